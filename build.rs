@@ -0,0 +1,11 @@
+fn main() {
+    // The gRPC interface is generated from the protobuf definition only if it's enabled
+    #[cfg(feature = "grpc")]
+    {
+        std::env::set_var(
+            "PROTOC",
+            protoc_bin_vendored::protoc_bin_path().expect("protoc not available"),
+        );
+        tonic_build::compile_protos("proto/crunchy.proto").expect("could not compile protos");
+    }
+}
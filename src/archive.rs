@@ -0,0 +1,672 @@
+// Time-series archive of states. The first state is stored as a whole and each next one only as
+// changes relative to the previous one (nodes added and removed, changed connections and metric
+// changes above a threshold), which is much smaller for slowly changing networks.
+//
+// Nodes of a reconstructed state are ordered as nodes kept from the previous state (in their
+// previous order) followed by the added nodes. Each node has the same connections as in the
+// archived state, but their order may differ.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    net::SocketAddr,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use ziggurat_core_crawler::summary::NetworkType;
+use ziggurat_core_geoip::geoip::GeoInfo;
+
+use crate::{
+    atomic_file,
+    geo_cluster::GeoCluster,
+    geo_summary::GeoSummary,
+    ips::{graph_utils::GraphDistances, statistics::GroupStatistics},
+    nodes::{update_degrees, ConnectionSanitization, HistogramSummary, Node},
+    provenance::StateMeta,
+    rankings::Rankings,
+    schema::{parse_state, STATE_SCHEMA_VERSION},
+    CrunchyState,
+};
+
+/// Changes of a node present in both states. Connections are indices of nodes in the
+/// reconstructed state.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NodeDelta {
+    /// Index of the node in the reconstructed state
+    pub index: usize,
+    /// New network type (if changed)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network_type: Option<NetworkType>,
+    /// New betweenness (if changed by more than the threshold)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub betweenness: Option<f64>,
+    /// New closeness (if changed by more than the threshold)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub closeness: Option<f64>,
+    /// New eigenvector centrality (if changed by more than the threshold)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eigenvector: Option<f64>,
+    /// New connections
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub connections_added: Vec<usize>,
+    /// Connections which no longer exist
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub connections_removed: Vec<usize>,
+    /// New location (if changed)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geolocation: Option<GeoInfo>,
+    /// Set if the node no longer has a location
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub geolocation_removed: bool,
+    /// Current eccentricity
+    pub eccentricity: Option<u32>,
+    /// Current relocation flag
+    pub location_changed: bool,
+    /// Current ASN
+    #[serde(default)]
+    pub asn: Option<u32>,
+    /// Current uptime
+    #[serde(default)]
+    pub uptime: Option<f64>,
+    /// Current stability
+    #[serde(default)]
+    pub stability: Option<f64>,
+    /// Current protocol version
+    #[serde(default)]
+    pub protocol_version: Option<u32>,
+    /// Current user agent
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    /// Current geographic cluster
+    #[serde(default)]
+    pub geo_cluster_id: Option<usize>,
+    /// Current normalized betweenness
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub betweenness_normalized: Option<f64>,
+    /// Current normalized closeness
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub closeness_normalized: Option<f64>,
+    /// Current hosting flag
+    #[serde(default)]
+    pub is_hosting: bool,
+    /// Current Tor flag
+    #[serde(default)]
+    pub is_tor: bool,
+    /// Current VPN flag
+    #[serde(default)]
+    pub is_vpn: bool,
+}
+
+/// Changes of the state relative to the previous one. Histograms, distances, group statistics and
+/// the geographical summary are small, so they are stored as a whole.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StateDelta {
+    pub elapsed: f64,
+    /// Addresses of nodes which are no longer present
+    pub removed: Vec<SocketAddr>,
+    /// New nodes (with connections indexed as in the reconstructed state)
+    pub added: Vec<Node>,
+    /// Changes of nodes present in both states
+    pub changed: Vec<NodeDelta>,
+    pub histograms: Vec<HistogramSummary>,
+    pub distances: GraphDistances,
+    pub group_statistics: Vec<GroupStatistics>,
+    #[serde(default)]
+    pub geo_summary: GeoSummary,
+    #[serde(default)]
+    pub geo_clusters: Vec<GeoCluster>,
+    #[serde(default)]
+    pub rankings: Rankings,
+    #[serde(default)]
+    pub sanitization: ConnectionSanitization,
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default)]
+    pub meta: Option<StateMeta>,
+}
+
+/// Series of states stored as the first state and deltas of the next ones.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct StateArchive {
+    /// Metric changes not greater than this are not stored
+    pub metric_threshold: f64,
+    /// The first archived state
+    pub base: Option<CrunchyState>,
+    /// Changes of each next state
+    pub deltas: Vec<StateDelta>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+fn same_geolocation(a: &Option<GeoInfo>, b: &Option<GeoInfo>) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+impl StateArchive {
+    pub fn new(metric_threshold: f64) -> Self {
+        Self {
+            metric_threshold,
+            ..Default::default()
+        }
+    }
+
+    /// Load the archive from a JSON file. The base state is upgraded like any other state if it
+    /// was written by an earlier version (states reconstructed from deltas get the current
+    /// schema).
+    pub fn load(path: &Path) -> Result<Self, io::Error> {
+        let archive_string = fs::read_to_string(path)?;
+        Self::parse(&archive_string)
+    }
+
+    fn parse(archive_string: &str) -> Result<Self, io::Error> {
+        let invalid_data = |e: serde_json::Error| io::Error::new(io::ErrorKind::InvalidData, e);
+        let mut archive: Value = serde_json::from_str(archive_string).map_err(invalid_data)?;
+        let base = match archive.get_mut("base").map(Value::take) {
+            None | Some(Value::Null) => None,
+            Some(base) => Some(parse_state(base)?),
+        };
+        let mut archive: StateArchive = serde_json::from_value(archive).map_err(invalid_data)?;
+        archive.base = base;
+        Ok(archive)
+    }
+
+    /// Save the archive to a JSON file (replaced atomically).
+    pub fn save(&self, path: &Path) -> Result<(), io::Error> {
+        atomic_file::write(path, serde_json::to_string(self).unwrap())
+    }
+
+    /// Number of archived states.
+    pub fn len(&self) -> usize {
+        self.base.as_ref().map_or(0, |_| self.deltas.len() + 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.base.is_none()
+    }
+
+    /// Add the next state to the archive. The delta is computed against the reconstructed latest
+    /// state, so metric changes skipped because of the threshold don't accumulate.
+    pub fn push(&mut self, state: &CrunchyState) {
+        match self.reconstruct(self.len().saturating_sub(1)) {
+            Some(latest) => {
+                let delta = state_delta(&latest, state, self.metric_threshold);
+                self.deltas.push(delta);
+            }
+            None => self.base = Some(state.clone()),
+        }
+    }
+
+    /// Reconstruct the archived state with the given index (0 is the first one).
+    pub fn reconstruct(&self, idx: usize) -> Option<CrunchyState> {
+        if idx >= self.len() {
+            return None;
+        }
+
+        let mut state = self.base.clone()?;
+        for delta in self.deltas.iter().take(idx) {
+            state = apply_delta(&state, delta);
+        }
+        Some(state)
+    }
+
+    /// Reconstruct up to `count` latest archived states (oldest first).
+    pub fn recent(&self, count: usize) -> Vec<CrunchyState> {
+        let mut states = Vec::with_capacity(count);
+        let mut state = match &self.base {
+            Some(base) if count > 0 => base.clone(),
+            _ => return states,
+        };
+
+        let first = self.len().saturating_sub(count);
+        for (idx, delta) in self.deltas.iter().enumerate() {
+            if idx >= first {
+                states.push(state.clone());
+            }
+            state = apply_delta(&state, delta);
+        }
+        states.push(state);
+        states
+    }
+}
+
+/// Addresses of nodes kept from the previous state followed by added nodes - the node order of
+/// the reconstructed state.
+fn reconstructed_order(previous: &[Node], removed: &HashSet<SocketAddr>) -> Vec<SocketAddr> {
+    previous
+        .iter()
+        .map(|node| node.addr)
+        .filter(|addr| !removed.contains(addr))
+        .collect()
+}
+
+/// Compute changes between two states. Betweenness, closeness and eigenvector changes not greater
+/// than the threshold are skipped.
+pub fn state_delta(previous: &CrunchyState, state: &CrunchyState, threshold: f64) -> StateDelta {
+    let current = state
+        .nodes
+        .iter()
+        .enumerate()
+        .map(|(idx, node)| (node.addr, idx))
+        .collect::<HashMap<SocketAddr, usize>>();
+    let removed = previous
+        .nodes
+        .iter()
+        .map(|node| node.addr)
+        .filter(|addr| !current.contains_key(addr))
+        .collect::<HashSet<SocketAddr>>();
+
+    let mut order = reconstructed_order(&previous.nodes, &removed);
+    let kept_count = order.len();
+    let kept = order.iter().copied().collect::<HashSet<SocketAddr>>();
+    order.extend(
+        state
+            .nodes
+            .iter()
+            .map(|node| node.addr)
+            .filter(|addr| !kept.contains(addr)),
+    );
+    let new_index = order
+        .iter()
+        .enumerate()
+        .map(|(idx, addr)| (*addr, idx))
+        .collect::<HashMap<SocketAddr, usize>>();
+    let reindex = |node: &Node| -> Vec<usize> {
+        node.connections
+            .iter()
+            .map(|c| new_index[&state.nodes[*c].addr])
+            .collect()
+    };
+
+    let previous_index = previous
+        .nodes
+        .iter()
+        .enumerate()
+        .map(|(idx, node)| (node.addr, idx))
+        .collect::<HashMap<SocketAddr, usize>>();
+    let mut changed = Vec::new();
+    for (index, addr) in order.iter().take(kept_count).enumerate() {
+        let old = &previous.nodes[previous_index[addr]];
+        let new = &state.nodes[current[addr]];
+
+        let old_connections = old
+            .connections
+            .iter()
+            .filter_map(|c| new_index.get(&previous.nodes[*c].addr).copied())
+            .collect::<HashSet<usize>>();
+        let new_connections = reindex(new);
+        let new_set = new_connections.iter().copied().collect::<HashSet<usize>>();
+
+        let mut delta = NodeDelta {
+            index,
+            network_type: (old.network_type != new.network_type).then_some(new.network_type),
+            betweenness: ((new.betweenness - old.betweenness).abs() > threshold)
+                .then_some(new.betweenness),
+            closeness: ((new.closeness - old.closeness).abs() > threshold).then_some(new.closeness),
+            eigenvector: ((new.eigenvector - old.eigenvector).abs() > threshold)
+                .then_some(new.eigenvector),
+            connections_added: new_connections
+                .into_iter()
+                .filter(|c| !old_connections.contains(c))
+                .collect(),
+            connections_removed: old_connections.difference(&new_set).copied().collect(),
+            geolocation: None,
+            geolocation_removed: false,
+            eccentricity: new.eccentricity,
+            location_changed: new.location_changed,
+            asn: new.asn,
+            uptime: new.uptime,
+            stability: new.stability,
+            protocol_version: new.protocol_version,
+            user_agent: new.user_agent.clone(),
+            geo_cluster_id: new.geo_cluster_id,
+            betweenness_normalized: new.betweenness_normalized,
+            closeness_normalized: new.closeness_normalized,
+            is_hosting: new.is_hosting,
+            is_tor: new.is_tor,
+            is_vpn: new.is_vpn,
+        };
+        delta.connections_removed.sort();
+        if !same_geolocation(&old.geolocation, &new.geolocation) {
+            delta.geolocation = new.geolocation.clone();
+            delta.geolocation_removed = new.geolocation.is_none();
+        }
+
+        let unchanged = delta.network_type.is_none()
+            && delta.betweenness.is_none()
+            && delta.closeness.is_none()
+            && delta.eigenvector.is_none()
+            && delta.connections_added.is_empty()
+            && delta.connections_removed.is_empty()
+            && delta.geolocation.is_none()
+            && !delta.geolocation_removed
+            && old.eccentricity == new.eccentricity
+            && old.location_changed == new.location_changed
+            && old.asn == new.asn
+            && old.uptime == new.uptime
+            && old.stability == new.stability
+            && old.protocol_version == new.protocol_version
+            && old.user_agent == new.user_agent
+            && old.geo_cluster_id == new.geo_cluster_id
+            && old.betweenness_normalized == new.betweenness_normalized
+            && old.closeness_normalized == new.closeness_normalized
+            && old.is_hosting == new.is_hosting
+            && old.is_tor == new.is_tor
+            && old.is_vpn == new.is_vpn;
+        if !unchanged {
+            changed.push(delta);
+        }
+    }
+
+    let added = order
+        .iter()
+        .skip(kept_count)
+        .map(|addr| {
+            let node = &state.nodes[current[addr]];
+            Node {
+                connections: reindex(node),
+                ..node.clone()
+            }
+        })
+        .collect();
+
+    StateDelta {
+        elapsed: state.elapsed,
+        removed: previous
+            .nodes
+            .iter()
+            .map(|node| node.addr)
+            .filter(|addr| removed.contains(addr))
+            .collect(),
+        added,
+        changed,
+        histograms: state.histograms.clone(),
+        distances: state.distances.clone(),
+        group_statistics: state.group_statistics.clone(),
+        geo_summary: state.geo_summary.clone(),
+        geo_clusters: state.geo_clusters.clone(),
+        rankings: state.rankings.clone(),
+        sanitization: state.sanitization.clone(),
+        seed: state.seed,
+        meta: state.meta.clone(),
+    }
+}
+
+/// Set uptime of each node of the state - the fraction of the recent earlier states (oldest
+/// first) and the state itself in which the node was seen. Without earlier states uptime stays
+/// unknown.
+pub fn observe_uptime(history: &[CrunchyState], state: &mut CrunchyState) {
+    let presence = presence(history, state);
+    for (node, presence) in state.nodes.iter_mut().zip(presence) {
+        node.uptime = Some(presence);
+    }
+}
+
+/// Set stability of each node of the state - the fraction of all the given earlier states and
+/// the state itself in which the node was seen, unlike uptime not limited to recent runs.
+/// Without earlier states stability stays unknown.
+pub fn observe_stability(snapshots: &[CrunchyState], state: &mut CrunchyState) {
+    let presence = presence(snapshots, state);
+    for (node, presence) in state.nodes.iter_mut().zip(presence) {
+        node.stability = Some(presence);
+    }
+}
+
+/// Fraction of the earlier states and the state itself in which each node of the state was seen
+/// (none without earlier states).
+fn presence(history: &[CrunchyState], state: &CrunchyState) -> Vec<f64> {
+    if history.is_empty() {
+        return Vec::new();
+    }
+
+    let mut seen = state
+        .nodes
+        .iter()
+        .map(|node| (node.addr, 1))
+        .collect::<HashMap<SocketAddr, usize>>();
+    for past in history {
+        for node in past.nodes.iter() {
+            if let Some(count) = seen.get_mut(&node.addr) {
+                *count += 1;
+            }
+        }
+    }
+
+    let runs = (history.len() + 1) as f64;
+    state
+        .nodes
+        .iter()
+        .map(|node| seen[&node.addr] as f64 / runs)
+        .collect()
+}
+
+/// Reconstruct the state from the previous state and its delta.
+pub fn apply_delta(previous: &CrunchyState, delta: &StateDelta) -> CrunchyState {
+    let removed = delta
+        .removed
+        .iter()
+        .copied()
+        .collect::<HashSet<SocketAddr>>();
+    let new_index = reconstructed_order(&previous.nodes, &removed)
+        .into_iter()
+        .enumerate()
+        .map(|(idx, addr)| (addr, idx))
+        .collect::<HashMap<SocketAddr, usize>>();
+
+    let mut nodes = previous
+        .nodes
+        .iter()
+        .filter(|node| !removed.contains(&node.addr))
+        .map(|node| Node {
+            connections: node
+                .connections
+                .iter()
+                .filter_map(|c| new_index.get(&previous.nodes[*c].addr).copied())
+                .collect(),
+            ..node.clone()
+        })
+        .collect::<Vec<Node>>();
+
+    for change in delta.changed.iter() {
+        let node = &mut nodes[change.index];
+        if let Some(network_type) = change.network_type {
+            node.network_type = network_type;
+        }
+        if let Some(betweenness) = change.betweenness {
+            node.betweenness = betweenness;
+        }
+        if let Some(closeness) = change.closeness {
+            node.closeness = closeness;
+        }
+        if let Some(eigenvector) = change.eigenvector {
+            node.eigenvector = eigenvector;
+        }
+        node.connections
+            .retain(|c| !change.connections_removed.contains(c));
+        node.connections
+            .extend(change.connections_added.iter().copied());
+        if change.geolocation.is_some() || change.geolocation_removed {
+            node.geolocation = change.geolocation.clone();
+        }
+        node.eccentricity = change.eccentricity;
+        node.location_changed = change.location_changed;
+        node.asn = change.asn;
+        node.uptime = change.uptime;
+        node.stability = change.stability;
+        node.protocol_version = change.protocol_version;
+        node.user_agent = change.user_agent.clone();
+        node.geo_cluster_id = change.geo_cluster_id;
+        node.betweenness_normalized = change.betweenness_normalized;
+        node.closeness_normalized = change.closeness_normalized;
+        node.is_hosting = change.is_hosting;
+        node.is_tor = change.is_tor;
+        node.is_vpn = change.is_vpn;
+    }
+    nodes.extend(delta.added.iter().cloned());
+    // Connections changed, so did the degrees
+    update_degrees(&mut nodes);
+
+    CrunchyState {
+        schema_version: STATE_SCHEMA_VERSION,
+        elapsed: delta.elapsed,
+        nodes,
+        histograms: delta.histograms.clone(),
+        distances: delta.distances.clone(),
+        group_statistics: delta.group_statistics.clone(),
+        geo_summary: delta.geo_summary.clone(),
+        geo_clusters: delta.geo_clusters.clone(),
+        rankings: delta.rankings.clone(),
+        sanitization: delta.sanitization.clone(),
+        seed: delta.seed,
+        meta: delta.meta.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(host: u8, betweenness: f64, connections: Vec<usize>) -> Node {
+        Node {
+            addr: SocketAddr::new([10, 0, 0, host].into(), 1234),
+            betweenness,
+            connections,
+            ..Default::default()
+        }
+    }
+
+    fn state(nodes: Vec<Node>) -> CrunchyState {
+        CrunchyState {
+            schema_version: STATE_SCHEMA_VERSION,
+            nodes,
+            ..Default::default()
+        }
+    }
+
+    /// Connections of each node as addresses, so states with different node order can be compared
+    fn connections_by_addr(state: &CrunchyState) -> HashMap<SocketAddr, HashSet<SocketAddr>> {
+        state
+            .nodes
+            .iter()
+            .map(|node| {
+                let peers = node
+                    .connections
+                    .iter()
+                    .map(|c| state.nodes[*c].addr)
+                    .collect();
+                (node.addr, peers)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn state_archive_test() {
+        // 1 - 2 - 3
+        let first = state(vec![
+            node(1, 0.0, vec![1]),
+            node(2, 1.0, vec![0, 2]),
+            node(3, 0.0, vec![1]),
+        ]);
+        // 1 is gone, 4 joins: 4 - 3 - 2
+        let second = state(vec![
+            node(4, 0.0, vec![1]),
+            node(3, 1.0, vec![0, 2]),
+            node(2, 0.95, vec![1]),
+        ]);
+
+        let mut archive = StateArchive::new(0.1);
+        archive.push(&first);
+        archive.push(&second);
+        assert_eq!(archive.len(), 2);
+        assert!(archive.reconstruct(2).is_none());
+
+        let delta = &archive.deltas[0];
+        assert_eq!(delta.removed, vec![first.nodes[0].addr]);
+        assert_eq!(delta.added.len(), 1);
+        // Connections to removed nodes are dropped implicitly, so only 3 has changed
+        assert_eq!(delta.changed.len(), 1);
+
+        // The archive survives serialization
+        let archive = StateArchive::parse(&serde_json::to_string(&archive).unwrap()).unwrap();
+        let reconstructed = archive.reconstruct(1).unwrap();
+        assert_eq!(reconstructed.nodes.len(), 3);
+        assert_eq!(
+            connections_by_addr(&reconstructed),
+            connections_by_addr(&second)
+        );
+        assert!(reconstructed
+            .nodes
+            .iter()
+            .all(|node| node.degree == node.connections.len()));
+
+        // Changes above the threshold are stored, smaller ones are not
+        let node_3 = reconstructed
+            .nodes
+            .iter()
+            .find(|n| n.addr == second.nodes[1].addr)
+            .unwrap();
+        assert_eq!(node_3.betweenness, 1.0);
+        let node_2 = reconstructed
+            .nodes
+            .iter()
+            .find(|n| n.addr == second.nodes[2].addr)
+            .unwrap();
+        assert_eq!(node_2.betweenness, 1.0);
+
+        assert_eq!(
+            connections_by_addr(&archive.reconstruct(0).unwrap()),
+            connections_by_addr(&first)
+        );
+
+        // Node 2 and 3 were seen in both states, 4 only in the second one
+        let mut latest = second.clone();
+        observe_uptime(&[], &mut latest);
+        assert_eq!(latest.nodes[0].uptime, None);
+        observe_uptime(std::slice::from_ref(&first), &mut latest);
+        let uptimes = latest.nodes.iter().map(|n| n.uptime).collect::<Vec<_>>();
+        assert_eq!(uptimes, vec![Some(0.5), Some(1.0), Some(1.0)]);
+
+        // Stability counts all the snapshots given, uptime only the recent ones
+        observe_stability(&[first.clone(), first.clone(), second.clone()], &mut latest);
+        let stabilities = latest.nodes.iter().map(|n| n.stability).collect::<Vec<_>>();
+        assert_eq!(stabilities, vec![Some(0.5), Some(1.0), Some(1.0)]);
+        observe_stability(&[first.clone(), first.clone()], &mut latest);
+        let stabilities = latest.nodes.iter().map(|n| n.stability).collect::<Vec<_>>();
+        assert_eq!(stabilities, vec![Some(1.0 / 3.0), Some(1.0), Some(1.0)]);
+
+        let recent = archive.recent(1);
+        assert_eq!(recent.len(), 1);
+        assert_eq!(
+            connections_by_addr(&recent[0]),
+            connections_by_addr(&second)
+        );
+        assert_eq!(archive.recent(5).len(), 2);
+        assert!(archive.recent(0).is_empty());
+    }
+
+    #[test]
+    fn load_v1_archive_test() {
+        // Version 1 states have neither the schema version nor degrees of nodes
+        let mut archive = StateArchive::new(0.1);
+        archive.push(&state(vec![node(1, 0.0, vec![1]), node(2, 0.0, vec![0])]));
+        let mut v1 = serde_json::to_value(&archive).unwrap();
+        let base = v1["base"].as_object_mut().unwrap();
+        base.remove("schema_version");
+        for node in base["nodes"].as_array_mut().unwrap() {
+            node.as_object_mut().unwrap().remove("degree");
+        }
+
+        let archive = StateArchive::parse(&v1.to_string()).unwrap();
+        let base = archive.reconstruct(0).unwrap();
+        assert_eq!(base.schema_version, STATE_SCHEMA_VERSION);
+        assert!(base.nodes.iter().all(|node| node.degree == 1));
+        assert!(
+            StateArchive::parse(r#"{"metric_threshold":0.1,"base":null,"deltas":[]}"#)
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,93 @@
+// Mapping of IP addresses to autonomous system numbers. The database is a TSV file in the format
+// published by iptoasn.com: range start, range end, AS number, country code and AS description.
+
+use std::{fs, io, net::IpAddr, path::Path};
+
+/// Range of IP addresses announced by a single autonomous system.
+struct AsnRange {
+    start: IpAddr,
+    end: IpAddr,
+    asn: u32,
+}
+
+/// IP to ASN database with ranges sorted by their start address.
+#[derive(Default)]
+pub struct AsnDatabase {
+    ranges: Vec<AsnRange>,
+}
+
+impl AsnDatabase {
+    /// Load the database from the TSV file.
+    pub fn load(path: &Path) -> Result<Self, io::Error> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parse the database from TSV lines. Ranges not announced by anyone (AS number 0) are
+    /// skipped.
+    pub fn parse(content: &str) -> Result<Self, io::Error> {
+        let invalid = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid ASN database line: {line}"),
+            )
+        };
+
+        let mut ranges = Vec::new();
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let mut columns = line.split('\t');
+            let mut next = || columns.next().map(str::trim).ok_or_else(|| invalid(line));
+            let start = next()?.parse::<IpAddr>().map_err(|_| invalid(line))?;
+            let end = next()?.parse::<IpAddr>().map_err(|_| invalid(line))?;
+            let asn = next()?.parse::<u32>().map_err(|_| invalid(line))?;
+            if asn != 0 {
+                ranges.push(AsnRange { start, end, asn });
+            }
+        }
+        ranges.sort_by_key(|range| range.start);
+
+        Ok(Self { ranges })
+    }
+
+    /// Find the AS number announcing the given IP.
+    pub fn lookup(&self, ip: IpAddr) -> Option<u32> {
+        let position = self.ranges.partition_point(|range| range.start <= ip);
+        let range = self.ranges.get(position.checked_sub(1)?)?;
+        (ip <= range.end).then_some(range.asn)
+    }
+
+    /// Number of known ranges.
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// Check if there are no known ranges.
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn asn_database_test() {
+        let database = AsnDatabase::parse(
+            "1.0.0.0\t1.0.0.255\t13335\tUS\tCLOUDFLARENET\n\
+             1.0.1.0\t1.0.3.255\t0\tNone\tNot routed\n\
+             1.0.4.0\t1.0.7.255\t38803\tAU\tGTELECOM\n\
+             2001:200::\t2001:200:ffff:ffff:ffff:ffff:ffff:ffff\t2500\tJP\tWIDE-BB\n",
+        )
+        .unwrap();
+        assert_eq!(database.len(), 3);
+
+        assert_eq!(database.lookup("1.0.0.1".parse().unwrap()), Some(13335));
+        assert_eq!(database.lookup("1.0.5.10".parse().unwrap()), Some(38803));
+        assert_eq!(database.lookup("1.0.2.1".parse().unwrap()), None);
+        assert_eq!(database.lookup("0.1.2.3".parse().unwrap()), None);
+        assert_eq!(database.lookup("8.8.8.8".parse().unwrap()), None);
+        assert_eq!(database.lookup("2001:200::1".parse().unwrap()), Some(2500));
+
+        assert!(AsnDatabase::parse("1.0.0.0\t1.0.0.255\tAS1").is_err());
+    }
+}
@@ -0,0 +1,68 @@
+// Files written at once - the contents go to a temporary file in the same directory which then
+// replaces the target, so readers (e.g. the explorer serving the state) never see a truncated
+// file, even if crunchy is killed while writing.
+
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+/// Write the contents to the file, replacing it atomically.
+pub fn write(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let temp_path = temp_path(path)?;
+    let result = write_and_rename(&temp_path, path, contents.as_ref());
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+fn write_and_rename(temp_path: &Path, path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = File::create(temp_path)?;
+    file.write_all(contents)?;
+    // The contents must be on the disk before the rename makes them visible
+    file.sync_all()?;
+    fs::rename(temp_path, path)
+}
+
+/// Hidden file next to the target - renaming across file systems is not atomic.
+fn temp_path(path: &Path) -> io::Result<PathBuf> {
+    let file_name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a file path", path.display()),
+        )
+    })?;
+    Ok(path.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        process::id()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn write_test() {
+        let dir = env::temp_dir().join(format!("crunchy-atomic-file-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("state.json");
+
+        write(&path, "{}").unwrap();
+        write(&path, "{\"nodes\":[]}").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"nodes\":[]}");
+        // No temporary file is left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        assert!(write(&dir.join("missing").join("state.json"), "{}").is_err());
+        assert!(write(Path::new("/"), "{}").is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
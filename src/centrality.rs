@@ -0,0 +1,110 @@
+// Centrality measures are computed by spectre. Computations on very large or pathological graphs
+// (e.g. a single giant star) can panic or produce non-finite values. Instead of aborting a long
+// run halfway through, such values are replaced with a fallback and a warning is printed.
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    panic::{self, AssertUnwindSafe},
+};
+
+use serde::{Deserialize, Serialize};
+use spectre::{edge::Edge, graph::Graph};
+
+use crate::{config::ClosenessVariant, csr::CsrGraph, ips::graph_utils::harmonic_closeness};
+
+/// Value used for a centrality measure that could not be computed for a node.
+pub const FALLBACK_CENTRALITY: f64 = 0.0;
+
+/// Run a centrality computation, replacing non-finite values with the fallback value. If the
+/// computation panics, an empty map is returned so every node gets the fallback value.
+pub fn guarded<T, F>(measure: &str, compute: F) -> HashMap<T, f64>
+where
+    T: Eq + Hash,
+    F: FnOnce() -> HashMap<T, f64>,
+{
+    let mut values = match panic::catch_unwind(AssertUnwindSafe(compute)) {
+        Ok(values) => values,
+        Err(_) => {
+            eprintln!(
+                "WARNING: {measure} centrality computation failed, using {FALLBACK_CENTRALITY} for all nodes"
+            );
+            return HashMap::new();
+        }
+    };
+
+    let mut non_finite = 0;
+    for value in values.values_mut().filter(|value| !value.is_finite()) {
+        *value = FALLBACK_CENTRALITY;
+        non_finite += 1;
+    }
+    if non_finite > 0 {
+        eprintln!(
+            "WARNING: {measure} centrality is not finite for {non_finite} nodes, using {FALLBACK_CENTRALITY} for them"
+        );
+    }
+
+    values
+}
+
+/// Get the centrality value of a node or the fallback value if it is missing.
+pub fn value_or_fallback<T: Eq + Hash>(values: &HashMap<T, f64>, key: &T) -> f64 {
+    values.get(key).copied().unwrap_or(FALLBACK_CENTRALITY)
+}
+
+/// Centrality measures of the graph nodes by their index
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Centralities {
+    pub betweenness: HashMap<usize, f64>,
+    pub closeness: HashMap<usize, f64>,
+    pub eigenvector: HashMap<usize, f64>,
+}
+
+/// Compute all centrality measures of the graph with the edges (each one listed once).
+pub fn compute_centralities(
+    edges: &[(usize, usize)],
+    num_threads: usize,
+    closeness: ClosenessVariant,
+) -> Centralities {
+    let mut graph = Graph::new();
+    for (a, b) in edges.iter() {
+        graph.insert(Edge::new(*a, *b));
+    }
+
+    let closeness = match closeness {
+        ClosenessVariant::Standard => {
+            guarded("closeness", || graph.closeness_centrality(num_threads))
+        }
+        ClosenessVariant::Harmonic => {
+            let len = edges.iter().map(|(a, b)| a.max(b) + 1).max().unwrap_or(0);
+            let sources = (0..len).collect::<Vec<usize>>();
+            harmonic_closeness(&CsrGraph::from_edges(len, edges), &sources, num_threads)
+        }
+    };
+    Centralities {
+        betweenness: guarded("betweenness", || {
+            graph.betweenness_centrality(num_threads, false)
+        }),
+        closeness,
+        eigenvector: guarded("eigenvector", || graph.eigenvalue_centrality()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guarded_test() {
+        let values = guarded("test", || {
+            HashMap::from([(1, 0.5), (2, f64::NAN), (3, f64::INFINITY)])
+        });
+        assert_eq!(value_or_fallback(&values, &1), 0.5);
+        assert_eq!(value_or_fallback(&values, &2), FALLBACK_CENTRALITY);
+        assert_eq!(value_or_fallback(&values, &3), FALLBACK_CENTRALITY);
+        assert_eq!(value_or_fallback(&values, &4), FALLBACK_CENTRALITY);
+
+        let values: HashMap<i32, f64> = guarded("test", || panic!("overflow"));
+        assert!(values.is_empty());
+    }
+}
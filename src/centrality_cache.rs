@@ -0,0 +1,158 @@
+// Cache of centrality results. Betweenness and closeness take most of the processing time of a
+// large crawl, yet the graph doesn't change when the same sample is processed again (e.g. to tune
+// IPS weights). Results are stored together with the hash of the graph they were computed for,
+// and computed again as soon as the hash differs.
+
+use std::{
+    fs, io,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    atomic_file,
+    centrality::{compute_centralities, Centralities},
+    config::ClosenessVariant,
+};
+
+/// Centrality results of one graph
+#[derive(Serialize, Deserialize)]
+struct CachedCentralities {
+    /// Hash of the graph the results were computed for, see [`graph_hash`]
+    graph_hash: String,
+    centralities: Centralities,
+}
+
+/// Centrality results of the last processed graph, kept in memory and optionally persisted in a
+/// file between runs.
+#[derive(Default)]
+pub struct CentralityCache {
+    entry: Mutex<Option<CachedCentralities>>,
+    /// Set when the entry changed since the cache was loaded or saved
+    updated: AtomicBool,
+}
+
+impl CentralityCache {
+    /// Load the cache from the file.
+    pub fn load(path: &Path) -> Result<CentralityCache, io::Error> {
+        let entry = serde_json::from_slice(&fs::read(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(CentralityCache {
+            entry: Mutex::new(Some(entry)),
+            updated: AtomicBool::new(false),
+        })
+    }
+
+    /// Save the cache to the file (replaced atomically), unless nothing changed.
+    pub fn save(&self, path: &Path) -> Result<(), io::Error> {
+        if !self.updated.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        match self.entry.lock().unwrap().as_ref() {
+            Some(entry) => atomic_file::write(path, serde_json::to_string(entry).unwrap()),
+            None => Ok(()),
+        }
+    }
+
+    /// Centralities of the graph with the edges - cached ones if they were computed for the same
+    /// graph (and closeness variant), computed (and cached in place of the previous ones)
+    /// otherwise. The cache isn't locked during the computation.
+    pub(crate) fn centralities(
+        &self,
+        edges: &[(usize, usize)],
+        num_threads: usize,
+        closeness: ClosenessVariant,
+    ) -> Centralities {
+        let graph_hash = graph_hash(edges, closeness);
+        if let Some(cached) = self
+            .entry
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|cached| cached.graph_hash == graph_hash)
+        {
+            return cached.centralities.clone();
+        }
+
+        let centralities = compute_centralities(edges, num_threads, closeness);
+        *self.entry.lock().unwrap() = Some(CachedCentralities {
+            graph_hash,
+            centralities: centralities.clone(),
+        });
+        self.updated.store(true, Ordering::Relaxed);
+        centralities
+    }
+}
+
+/// SHA-256 (hex) of the edges in their order, together with the version of crunchy and the
+/// closeness variant - results of other versions are not reused, as computations can change
+/// between them.
+fn graph_hash(edges: &[(usize, usize)], closeness: ClosenessVariant) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(env!("CARGO_PKG_VERSION"));
+    hasher.update(format!("{closeness:?}"));
+    for (a, b) in edges.iter() {
+        hasher.update((*a as u64).to_le_bytes());
+        hasher.update((*b as u64).to_le_bytes());
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+    use crate::config::ClosenessVariant::{Harmonic, Standard};
+
+    #[test]
+    fn centrality_cache_test() {
+        let path = env::temp_dir().join(format!("crunchy-centrality-{}.json", process::id()));
+        let star = [(0, 1), (0, 2), (0, 3)];
+        let path_graph = [(0, 1), (1, 2), (2, 3)];
+        assert_ne!(
+            graph_hash(&star, Standard),
+            graph_hash(&path_graph, Standard)
+        );
+        assert_ne!(graph_hash(&star, Standard), graph_hash(&star, Harmonic));
+
+        let cache = CentralityCache::default();
+        let computed = cache.centralities(&star, 1, Standard);
+        assert_eq!(computed, compute_centralities(&star, 1, Standard));
+        cache.save(&path).unwrap();
+
+        // The same graph is taken from the loaded cache
+        let cache = CentralityCache::load(&path).unwrap();
+        cache.entry.lock().unwrap().as_mut().unwrap().centralities = Centralities::default();
+        assert_eq!(
+            cache.centralities(&star, 1, Standard),
+            Centralities::default()
+        );
+        assert!(!cache.updated.load(Ordering::Relaxed));
+
+        // A changed graph replaces the entry
+        assert_eq!(
+            cache.centralities(&path_graph, 1, Standard),
+            compute_centralities(&path_graph, 1, Standard)
+        );
+        assert!(cache.updated.load(Ordering::Relaxed));
+        assert_eq!(
+            cache.entry.lock().unwrap().as_ref().unwrap().graph_hash,
+            graph_hash(&path_graph, Standard)
+        );
+
+        // The same graph with another closeness variant replaces the entry too
+        let harmonic = cache.centralities(&path_graph, 1, Harmonic);
+        assert_eq!(harmonic.closeness[&0], 1.0 + 0.5 + 1.0 / 3.0);
+        assert_eq!(harmonic.closeness[&1], 2.5);
+
+        fs::remove_file(&path).unwrap();
+        assert!(CentralityCache::load(&path).is_err());
+    }
+}
@@ -1,80 +1,937 @@
-use std::{fs, path::PathBuf, thread};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    thread,
+};
 
-use anyhow::Result;
-use serde::Deserialize;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
 use ziggurat_core_crawler::summary::NetworkType;
 
-use crate::ips::config::IPSConfiguration;
+use crate::{
+    histogram::HistogramScale,
+    ips::config::{IPSConfiguration, MultiCriteriaAnalysisWeights, WeightProfile},
+    rotation::{is_templated, TIMESTAMP_PLACEHOLDER},
+    stdio,
+};
 
 /// Default number of days to keep each entry in cache
 pub const DEFAULT_KEEP_IN_CACHE_DAYS: u16 = 14;
+/// Default number of GeoIP lookups running at the same time
+pub const DEFAULT_LOOKUP_CONCURRENCY: usize = 8;
+/// Default number of consecutive failed lookups after which a GeoIP provider is disabled
+pub const DEFAULT_MAX_CONSECUTIVE_FAILURES: usize = 10;
+
+/// Prefix of environment variables overriding configuration fields
+pub const ENV_PREFIX: &str = "CRUNCHY_";
+/// Separator of sections in names of environment variables overriding nested fields
+pub const ENV_SECTION_SEPARATOR: &str = "__";
+/// Value shown instead of API keys in a redacted configuration
+pub const REDACTED: &str = "<redacted>";
 
 /// Main configuration structure
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CrunchyConfiguration {
     /// Path to input file
     pub input_file_path: Option<PathBuf>,
+    /// Format of the input file
+    #[serde(default)]
+    pub input_format: InputFormat,
     /// Path where state JSON file will be written
     pub state_file_path: Option<PathBuf>,
+    /// Path where run report JSON file will be written
+    pub report_file_path: Option<PathBuf>,
+    /// Path to the cache of centrality results, so processing the same sample again (e.g. with
+    /// different IPS weights) skips their computation - nothing is cached if not set
+    #[serde(default)]
+    pub centrality_cache_file_path: Option<PathBuf>,
+    /// Path to the archive where each state is appended as changes relative to the previous one
+    #[serde(default)]
+    pub archive_file_path: Option<PathBuf>,
+    /// Betweenness and closeness changes not greater than this are not stored in the archive
+    #[serde(default)]
+    pub archive_metric_threshold: f64,
+    /// Earlier state files (oldest first) taken as runs preceding the archived ones - node
+    /// uptime and betweenness trends can be derived from them without the archive
+    #[serde(default)]
+    pub previous_state_file_paths: Vec<PathBuf>,
+    /// Number of latest runs (including the current one) in which betweenness trends are
+    /// analyzed - needs the archive or previous states
+    #[serde(default = "default_heat_window")]
+    pub heat_window: usize,
+    /// Maximum number of emerging hot nodes written to the run report
+    #[serde(default = "default_emerging_hot_nodes_count")]
+    pub emerging_hot_nodes_count: usize,
+    /// Configuration of the attack simulation (`--simulate`)
+    #[serde(default)]
+    pub simulation: SimulationConfiguration,
+    /// Configuration of the comparison with random graphs (`--null-model`)
+    #[serde(default)]
+    pub null_model: NullModelConfiguration,
+    /// Configuration of the weight sensitivity analysis (`ips sweep`)
+    #[serde(default)]
+    pub sweep: SweepConfiguration,
     /// Configuration for GeoIP module
     pub geoip_config: GeoIPConfiguration,
     /// Configuration for Intelligent Peer Sharing module
     pub ips_config: IPSConfiguration,
     /// Optional node filtering
     pub network_type_filter: Option<NetworkType>,
-    /// Number of threads to use
+    /// Number of threads to use (all available cores if not set)
+    #[serde(default = "default_num_threads", alias = "threads")]
     pub num_threads: usize,
+    /// Add histograms weighted by node degree alongside the plain node-count ones
+    #[serde(default)]
+    pub weighted_histograms: bool,
+    /// Bins and ranges of histograms
+    #[serde(default)]
+    pub histograms: HistogramConfiguration,
+    /// Path where histograms are written as a separate JSON file (not written if not set)
+    #[serde(default)]
+    pub histograms_file_path: Option<PathBuf>,
+    /// Leave histograms out of the state file - needs `histograms_file_path`
+    #[serde(default)]
+    pub exclude_histograms_from_state: bool,
+    /// Number of cities with the most nodes kept in the geographical summary of the state
+    #[serde(default = "default_geo_summary_top_cities")]
+    pub geo_summary_top_cities: usize,
+    /// Closeness computed for nodes (and used by IPS)
+    #[serde(default)]
+    pub closeness_variant: ClosenessVariant,
+    /// Raw or normalized betweenness and closeness (or both) in the state
+    #[serde(default)]
+    pub centrality_output: CentralityOutput,
+    /// Number of nodes in each ranking of the state
+    #[serde(default = "default_rankings_top_nodes")]
+    pub rankings_top_nodes: usize,
+    /// Geographic clustering of nodes
+    #[serde(default)]
+    pub geo_clustering: GeoClusteringConfiguration,
+    /// Seed of all pseudo-random choices, recorded in all outputs so results can be regenerated
+    /// (the command line tool picks a random one if not set, 0 is used otherwise)
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Make the state and peer lists byte-identical for the same input and seed - the state
+    /// doesn't record the processing time and outputs are stamped with the crawl time recorded in
+    /// the sample
+    #[serde(default)]
+    pub reproducible: bool,
+    /// Time (unix seconds) reproducible runs stamp their outputs with instead of the crawl time
+    /// recorded in the sample (samples without it are stamped with the unix epoch)
+    #[serde(default)]
+    pub reproducible_timestamp: Option<u64>,
+    /// Run IPS and report its impact and the proposed changes without writing the peer list
+    #[serde(default)]
+    pub ips_dry_run: bool,
+    /// Write the state and the peer lists (in JSON format) pretty-printed with sorted keys
+    /// instead of compact
+    #[serde(default)]
+    pub pretty_json: bool,
+    /// Number of the latest runs whose outputs are kept - older files matching output paths
+    /// with the `{timestamp}` placeholder are removed (nothing is removed if not set)
+    #[serde(default)]
+    pub keep_last: Option<usize>,
+}
+
+/// Default number of latest runs in which betweenness trends are analyzed
+pub const DEFAULT_HEAT_WINDOW: usize = 5;
+
+fn default_heat_window() -> usize {
+    DEFAULT_HEAT_WINDOW
+}
+
+/// Default maximum number of reported emerging hot nodes
+pub const DEFAULT_EMERGING_HOT_NODES_COUNT: usize = 10;
+
+fn default_emerging_hot_nodes_count() -> usize {
+    DEFAULT_EMERGING_HOT_NODES_COUNT
+}
+
+/// Default number of cities kept in the geographical summary
+pub const DEFAULT_GEO_SUMMARY_TOP_CITIES: usize = 20;
+
+fn default_geo_summary_top_cities() -> usize {
+    DEFAULT_GEO_SUMMARY_TOP_CITIES
+}
+
+/// Default number of nodes in each ranking
+pub const DEFAULT_RANKINGS_TOP_NODES: usize = 10;
+
+fn default_rankings_top_nodes() -> usize {
+    DEFAULT_RANKINGS_TOP_NODES
+}
+
+/// Number of threads used by default - all available cores
+pub fn default_num_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
 }
 
 /// Configuration for GeoIP module
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GeoIPConfiguration {
     /// Path to the GeoIP cache
     pub geocache_file_path: PathBuf,
-    /// Number of days to keep each entry in cache
+    /// Storage backend of the GeoIP cache
+    #[serde(default)]
+    pub geocache_backend: GeoCacheBackend,
+    /// Number of days to keep each entry in cache (for providers without their own setting)
     pub keep_in_cache_days: Option<u16>,
+    /// Number of previous locations to keep for each IP (history is disabled if not set)
+    pub location_history_size: Option<usize>,
+    /// Number of GeoIP lookups running at the same time
+    pub lookup_concurrency: Option<usize>,
+    /// Whether the first answer is taken or answers of all providers are merged
+    #[serde(default)]
+    pub lookup_mode: GeoLookupMode,
+    /// Maximum number of entries kept in the cache, least recently used are evicted first
+    /// (unlimited if not set)
+    pub max_cache_entries: Option<usize>,
+    /// Save the cache after that many new entries (and when the cache is dropped with unsaved
+    /// entries)
+    pub autosave_entries: Option<usize>,
+    /// Save the cache if there are new entries and that many seconds passed since the last save
+    /// (and when the cache is dropped with unsaved entries)
+    pub autosave_interval_secs: Option<u64>,
+    /// Number of consecutive failed lookups after which a provider is disabled for the rest of
+    /// the run (0 never disables providers)
+    pub max_consecutive_failures: Option<usize>,
+    /// Path to the IP to ASN database (TSV file in the iptoasn.com format)
+    pub asn_db_path: Option<PathBuf>,
+    /// Path to the IP2Location PX database (CSV file) classifying hosting, VPN and Tor addresses
+    #[serde(default)]
+    pub proxy_db_path: Option<PathBuf>,
+    /// Paths to lists of Tor exit addresses (one address or CIDR range per line)
+    #[serde(default)]
+    pub tor_list_paths: Vec<PathBuf>,
+    /// Paths to lists of VPN addresses (one address or CIDR range per line)
+    #[serde(default)]
+    pub vpn_list_paths: Vec<PathBuf>,
+    /// Paths to lists of hosting addresses (one address or CIDR range per line)
+    #[serde(default)]
+    pub hosting_list_paths: Vec<PathBuf>,
+    /// Use only the local database and the cache - online providers are never asked
+    #[serde(default)]
+    pub offline: bool,
     /// Enable IP2Location database
     pub ip2location_enable: bool,
     /// Path to the IP2Location database
     pub ip2location_db_path: Option<PathBuf>,
     /// Path to the IP2Location database for IPv6 if applicable
     pub ip2location_ipv6_db_path: Option<PathBuf>,
+    /// Order in which IP2Location database is asked (lowest first, 0 if not set)
+    pub ip2location_priority: Option<u32>,
+    /// Number of days to keep entries resolved by IP2Location database
+    pub ip2location_keep_in_cache_days: Option<u16>,
     /// Enable ipapi.co provider
     pub ipapico_enable: bool,
-    /// API key for ipapi.co provider
+    /// API key for ipapi.co provider - `${VAR}` is replaced with the environment variable
     pub ipapico_api_key: Option<String>,
+    /// Maximum number of requests per second sent to ipapi.co provider (unlimited if not set)
+    pub ipapico_requests_per_second: Option<f64>,
+    /// Order in which ipapi.co provider is asked (lowest first, 1 if not set)
+    pub ipapico_priority: Option<u32>,
+    /// Number of days to keep entries resolved by ipapi.co provider
+    pub ipapico_keep_in_cache_days: Option<u16>,
     /// Enable ipapi.com provider
     pub ipapicom_enable: bool,
-    /// API key for ipapi.com provider
+    /// API key for ipapi.com provider - `${VAR}` is replaced with the environment variable
     pub ipapicom_api_key: Option<String>,
+    /// Maximum number of requests per second sent to ipapi.com provider (unlimited if not set)
+    pub ipapicom_requests_per_second: Option<f64>,
+    /// Order in which ipapi.com provider is asked (lowest first, 2 if not set)
+    pub ipapicom_priority: Option<u32>,
+    /// Number of days to keep entries resolved by ipapi.com provider
+    pub ipapicom_keep_in_cache_days: Option<u16>,
+}
+
+impl GeoIPConfiguration {
+    /// Problems of the GeoIP configuration - providers are enabled without the data they need.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.ip2location_enable && self.ip2location_db_path.is_none() {
+            problems.push(
+                "geoip_config.ip2location_enable is set without geoip_config.ip2location_db_path"
+                    .to_owned(),
+            );
+        }
+        if self.ipapico_enable && self.ipapico_api_key.is_none() {
+            problems.push(
+                "geoip_config.ipapico_enable is set without geoip_config.ipapico_api_key (can be empty)"
+                    .to_owned(),
+            );
+        }
+        if self.ipapicom_enable && self.ipapicom_api_key.is_none() {
+            problems.push(
+                "geoip_config.ipapicom_enable is set without geoip_config.ipapicom_api_key"
+                    .to_owned(),
+            );
+        }
+        problems
+    }
+
+    /// Replace `${VAR}` references in API keys with values of the variables, so the keys
+    /// themselves don't have to be in the configuration file.
+    fn interpolate_api_keys(&mut self, vars: &BTreeMap<String, String>) -> Result<()> {
+        for (name, key) in [
+            ("ipapico_api_key", &mut self.ipapico_api_key),
+            ("ipapicom_api_key", &mut self.ipapicom_api_key),
+        ] {
+            if let Some(key) = key {
+                *key = interpolate(key, vars).map_err(|e| anyhow!("geoip_config.{name}: {e}"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Format of the input file
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputFormat {
+    /// JSON-RPC response of the ziggurat crawler with the network summary
+    #[default]
+    Ziggurat,
+    /// JSON object mapping address of each node to its Bitcoin `getpeerinfo` result
+    GetPeerInfo,
+    /// CSV file with a pair of connected socket addresses on each line
+    EdgeList,
+}
+
+/// How betweenness and closeness of nodes are written to the state
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CentralityOutput {
+    /// Values as computed
+    #[default]
+    Raw,
+    /// Values divided by their theoretical maxima for the number of nodes, within [0, 1]
+    Normalized,
+    /// Raw values, with the normalized ones in `betweenness_normalized` and
+    /// `closeness_normalized`
+    Both,
+}
+
+/// Variant of closeness centrality
+#[derive(Debug, Default, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClosenessVariant {
+    /// Average distance to other nodes in the node's component
+    #[default]
+    Standard,
+    /// Sum of inverse distances to all other nodes - unreachable nodes add nothing, so it's well
+    /// defined on disconnected networks
+    Harmonic,
+}
+
+/// Storage backend of the GeoIP cache
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GeoCacheBackend {
+    /// Whole cache kept in a JSON file, rewritten on every save
+    #[default]
+    Json,
+    /// SQLite database updated incrementally on every lookup (requires `sqlite` feature)
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+}
+
+/// How answers of GeoIP providers are used
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GeoLookupMode {
+    /// Providers are asked in the order of their priority and the first answer is taken
+    #[default]
+    First,
+    /// All providers are asked and their answers are merged with a confidence score
+    Merge,
 }
 
 /// GeoLocationMode enum - indicates if location should be taken into account and if so what
 /// should be preferred (closer or distant nodes).
-#[derive(Debug, PartialEq, Clone, Deserialize)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum GeoLocationMode {
     Off,
     PreferCloser,
     PreferDistant,
 }
 
+/// Default number of histogram slots
+pub const DEFAULT_HISTOGRAM_BINS: usize = 256;
+
+/// Configuration of histograms
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistogramConfiguration {
+    /// Number of slots of each histogram
+    pub bins: usize,
+    /// How values are mapped to slots
+    pub scale: HistogramScale,
+    /// Fixed ranges of metrics (betweenness, closeness or degree), so slots are the same across
+    /// snapshots - bounds which are not set are derived from the values
+    pub ranges: BTreeMap<String, HistogramRange>,
+}
+
+impl Default for HistogramConfiguration {
+    fn default() -> HistogramConfiguration {
+        HistogramConfiguration {
+            bins: DEFAULT_HISTOGRAM_BINS,
+            scale: HistogramScale::default(),
+            ranges: BTreeMap::new(),
+        }
+    }
+}
+
+/// Fixed range of a histogram - values outside of it are counted in the first or the last slot
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistogramRange {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+/// Configuration of the geographic clustering (DBSCAN)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GeoClusteringConfiguration {
+    /// Whether nodes are clustered at all
+    pub enable: bool,
+    /// Maximum distance (in km) between neighboring nodes of a cluster
+    pub radius_km: f64,
+    /// Number of nodes within the radius (including the node itself) making the node a core of
+    /// a cluster
+    pub min_nodes: usize,
+}
+
+impl Default for GeoClusteringConfiguration {
+    fn default() -> GeoClusteringConfiguration {
+        GeoClusteringConfiguration {
+            enable: false,
+            radius_km: 50.0,
+            min_nodes: 5,
+        }
+    }
+}
+
+/// Configuration of the attack simulation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimulationConfiguration {
+    /// Percentage of nodes removed in each step (at least one node)
+    pub step_percentage: f64,
+    /// Number of steps
+    pub steps: usize,
+}
+
+impl Default for SimulationConfiguration {
+    fn default() -> SimulationConfiguration {
+        SimulationConfiguration {
+            step_percentage: 1.0,
+            steps: 20,
+        }
+    }
+}
+
+/// Configuration of the comparison with random graphs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NullModelConfiguration {
+    /// Number of random graphs generated for each model
+    pub samples: usize,
+}
+
+impl Default for NullModelConfiguration {
+    fn default() -> NullModelConfiguration {
+        NullModelConfiguration { samples: 10 }
+    }
+}
+
+/// Configuration of the weight sensitivity analysis
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SweepConfiguration {
+    /// Values tried for each MCDA weight (by its name in `mcda_weights`) - IPS runs for every
+    /// combination, weights not listed keep their configured value
+    pub grid: BTreeMap<String, Vec<f64>>,
+    /// Number of nodes of the connected subgraph IPS runs on (the whole network if 0)
+    pub sample_nodes: usize,
+}
+
+impl Default for SweepConfiguration {
+    fn default() -> SweepConfiguration {
+        SweepConfiguration {
+            grid: BTreeMap::new(),
+            sample_nodes: 200,
+        }
+    }
+}
+
+/// Format of the configuration file
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum ConfigFormat {
+    #[default]
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Format given by the file extension (`.json`, `.yaml` or `.yml`), TOML otherwise.
+    pub fn from_path(path: &Path) -> ConfigFormat {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => ConfigFormat::Json,
+            Some(ext) if ext.eq_ignore_ascii_case("yaml") || ext.eq_ignore_ascii_case("yml") => {
+                ConfigFormat::Yaml
+            }
+            _ => ConfigFormat::Toml,
+        }
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "toml" => Ok(ConfigFormat::Toml),
+            "json" => Ok(ConfigFormat::Json),
+            "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+            _ => Err(format!(
+                "unknown configuration format {s} (toml, json or yaml)"
+            )),
+        }
+    }
+}
+
 impl CrunchyConfiguration {
-    pub fn new(conf_path: &str) -> Result<CrunchyConfiguration> {
+    /// Load the configuration file, with fields overridden by the environment variables and then
+    /// by the given overrides (named like the variables, e.g. from the command line). The
+    /// format is detected by the file extension unless given.
+    pub fn new(
+        conf_path: &str,
+        format: Option<ConfigFormat>,
+        overrides: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<CrunchyConfiguration> {
         let config_string = fs::read_to_string(conf_path)?;
-        let crunchy_config: CrunchyConfiguration = toml::from_str(&config_string)?;
-        Ok(crunchy_config)
+        let format = format.unwrap_or_else(|| ConfigFormat::from_path(Path::new(conf_path)));
+        Self::parse(&config_string, format, env::vars().chain(overrides))
+    }
+
+    /// Default configuration with fields overridden by the environment variables and then by
+    /// the given overrides, layered the same way as a loaded configuration file.
+    pub fn from_env(
+        overrides: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<CrunchyConfiguration> {
+        Self::with_defaults(env::vars().chain(overrides))
+    }
+
+    /// Default configuration with fields overridden by the `CRUNCHY_*` variables (see
+    /// [`CrunchyConfiguration::parse`]).
+    pub fn with_defaults(
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<CrunchyConfiguration> {
+        let mut config = match toml::Value::try_from(CrunchyConfiguration::default())? {
+            toml::Value::Table(config) => config,
+            _ => return Err(anyhow!("the configuration must be a map of fields")),
+        };
+        // Default weights would hide those of a profile set by the variables
+        if let Some(ips_config) = config.get_mut("ips_config").and_then(|v| v.as_table_mut()) {
+            ips_config.remove("mcda_weights");
+        }
+        Self::layer(config, vars)
+    }
+
+    /// Parse the configuration with fields overridden by the `CRUNCHY_*` variables, e.g.
+    /// `CRUNCHY_STATE_FILE_PATH` or `CRUNCHY_GEOIP_CONFIG__IPAPICO_API_KEY` (`__` separates
+    /// sections). Values are parsed as TOML (e.g. numbers, booleans or arrays) unless they
+    /// override a string or can't be parsed - then they are taken as strings. Strings which look
+    /// like other values can be quoted. Weights not set in `ips_config.mcda_weights` are taken
+    /// from `ips_config.profile` (if set), and `${VAR}` references in API keys are replaced with
+    /// the variables.
+    pub fn parse(
+        config_string: &str,
+        format: ConfigFormat,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<CrunchyConfiguration> {
+        // All formats go through a TOML table, so overrides and errors work the same way
+        let config: toml::Table = match format {
+            ConfigFormat::Toml => toml::from_str(config_string)?,
+            ConfigFormat::Json => to_table(serde_json::from_str(config_string)?)?,
+            ConfigFormat::Yaml => to_table(serde_yaml::from_str(config_string)?)?,
+        };
+        Self::layer(config, vars)
+    }
+
+    /// Override fields of the configuration table with the `CRUNCHY_*` variables, fill the
+    /// weights from the profile and interpolate the API keys.
+    fn layer(
+        mut config: toml::Table,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<CrunchyConfiguration> {
+        let vars = vars.into_iter().collect::<BTreeMap<String, String>>();
+        for (name, value) in vars.iter() {
+            if let Some(path) = name.strip_prefix(ENV_PREFIX) {
+                override_field(&mut config, path, value)?;
+            }
+        }
+        apply_weight_profile(&mut config)?;
+        let mut config: CrunchyConfiguration = toml::Value::Table(config).try_into()?;
+        config.geoip_config.interpolate_api_keys(&vars)?;
+        Ok(config)
+    }
+
+    /// Render the configuration in the given format, e.g. to show the values in effect.
+    pub fn render(&self, format: ConfigFormat) -> Result<String> {
+        Ok(match format {
+            ConfigFormat::Toml => toml::to_string_pretty(self)?,
+            ConfigFormat::Json => serde_json::to_string_pretty(self)?,
+            ConfigFormat::Yaml => serde_yaml::to_string(self)?,
+        })
+    }
+
+    /// Copy of the configuration with the API keys replaced, so it can be shown without leaking
+    /// them. Empty keys are kept, as they don't hide anything.
+    pub fn redacted(&self) -> CrunchyConfiguration {
+        let mut config = self.clone();
+        for key in [
+            &mut config.geoip_config.ipapico_api_key,
+            &mut config.geoip_config.ipapicom_api_key,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if !key.is_empty() {
+                *key = REDACTED.to_owned();
+            }
+        }
+        config
+    }
+
+    /// Check the configuration, returning all problems found - fields which are required by
+    /// other fields, and values out of their range.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+
+        if self.input_file_path.is_none() {
+            problems.push("input_file_path is not set".to_owned());
+        }
+        if self.state_file_path.is_none() {
+            problems.push("state_file_path is not set".to_owned());
+        }
+        if self.ips_config.peer_file_path.is_none() && !self.ips_dry_run {
+            problems.push(
+                "ips_config.peer_file_path is not set (needed unless ips_dry_run is set)"
+                    .to_owned(),
+            );
+        }
+        // A random seed can't be repeated without reading it from the outputs first
+        if self.reproducible && self.seed.is_none() {
+            problems.push(
+                "reproducible runs need a seed (--seed or `seed` in the configuration file)"
+                    .to_owned(),
+            );
+        }
+        // Only one output fits into stdout
+        let stdout_outputs = [
+            &self.state_file_path,
+            &self.report_file_path,
+            &self.histograms_file_path,
+            &self.ips_config.peer_file_path,
+        ]
+        .into_iter()
+        .filter(|path| matches!(path, Some(path) if stdio::is_stdio(path)))
+        .count();
+        if stdout_outputs > 1 {
+            problems.push(
+                "only one of state_file_path, report_file_path, histograms_file_path and \
+                 ips_config.peer_file_path can be written to stdout (`-`)"
+                    .to_owned(),
+            );
+        }
+        if matches!(&self.ips_config.peer_file_path, Some(path) if stdio::is_stdio(path))
+            && self.ips_networks().len() > 1
+        {
+            problems.push(
+                "ips_config.peer_file_path can't be stdout (`-`) with more than one network"
+                    .to_owned(),
+            );
+        }
+        // Checked before the run, so a missing state doesn't abort it after the input is processed
+        for path in self.previous_state_file_paths.iter() {
+            if !path.is_file() {
+                problems.push(format!(
+                    "previous_state_file_paths: {} is not a file",
+                    path.display()
+                ));
+            }
+        }
+        if self.exclude_histograms_from_state && self.histograms_file_path.is_none() {
+            problems.push(
+                "exclude_histograms_from_state needs histograms_file_path, or histograms are \
+                 not written at all"
+                    .to_owned(),
+            );
+        }
+        if self.histograms.bins == 0 {
+            problems.push("histograms.bins must be greater than 0".to_owned());
+        }
+        for (metric, range) in self.histograms.ranges.iter() {
+            if matches!((range.min, range.max), (Some(min), Some(max)) if min >= max) {
+                problems.push(format!(
+                    "histograms.ranges.{metric}: min must be lower than max"
+                ));
+            }
+        }
+        if !(self.geo_clustering.radius_km > 0.0 && self.geo_clustering.radius_km.is_finite()) {
+            problems.push("geo_clustering.radius_km must be a positive number".to_owned());
+        }
+        if self.geo_clustering.min_nodes == 0 {
+            problems.push("geo_clustering.min_nodes must be greater than 0".to_owned());
+        }
+        if !(self.simulation.step_percentage > 0.0 && self.simulation.step_percentage <= 100.0) {
+            problems.push("simulation.step_percentage must be within (0, 100]".to_owned());
+        }
+        if self.null_model.samples < 2 {
+            problems.push("null_model.samples must be at least 2".to_owned());
+        }
+        for (name, values) in self.sweep.grid.iter() {
+            if MultiCriteriaAnalysisWeights::default()
+                .weight_mut(name)
+                .is_none()
+            {
+                problems.push(format!("sweep.grid: unknown weight {name}"));
+            } else if values.is_empty() || values.iter().any(|value| !value.is_finite()) {
+                problems.push(format!(
+                    "sweep.grid.{name} must be a non-empty list of finite numbers"
+                ));
+            }
+        }
+        let output_paths = self.output_paths();
+        if self.keep_last == Some(0) {
+            problems.push("keep_last must be greater than 0".to_owned());
+        } else if self.keep_last.is_some() && !output_paths.iter().any(|p| is_templated(p)) {
+            problems.push(format!(
+                "keep_last needs an output path with the {TIMESTAMP_PLACEHOLDER} placeholder"
+            ));
+        }
+        for path in output_paths.iter() {
+            if path.parent().is_some_and(is_templated) {
+                problems.push(format!(
+                    "{}: {TIMESTAMP_PLACEHOLDER} can only be used in the file name",
+                    path.display()
+                ));
+            }
+        }
+
+        problems.extend(self.geoip_config.validate());
+        problems.extend(self.ips_config.validate());
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Configuration with each output path (the state, the report, histograms and files written
+    /// by IPS) replaced.
+    pub fn map_output_paths(&self, f: impl Fn(&Path) -> PathBuf) -> CrunchyConfiguration {
+        CrunchyConfiguration {
+            state_file_path: self.state_file_path.as_deref().map(&f),
+            report_file_path: self.report_file_path.as_deref().map(&f),
+            histograms_file_path: self.histograms_file_path.as_deref().map(&f),
+            ips_config: self.ips_config.map_output_paths(&f),
+            ..self.clone()
+        }
+    }
+
+    /// Paths of all outputs of a run - files written by IPS are given for each network type if
+    /// there are more of them.
+    pub fn output_paths(&self) -> Vec<PathBuf> {
+        let mut paths = [
+            &self.state_file_path,
+            &self.report_file_path,
+            &self.histograms_file_path,
+        ]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect::<Vec<PathBuf>>();
+        let networks = self.ips_networks();
+        if networks.len() > 1 {
+            for network in networks {
+                paths.extend(self.ips_config.for_network(network).output_paths());
+            }
+        } else {
+            paths.extend(self.ips_config.output_paths());
+        }
+        paths
+    }
+
+    /// Check if any output is written to stdout (its path is `-`).
+    pub fn writes_to_stdout(&self) -> bool {
+        let peer_file_path = self
+            .ips_config
+            .peer_file_path
+            .as_ref()
+            .filter(|_| !self.ips_dry_run);
+        [
+            self.state_file_path.as_ref(),
+            self.report_file_path.as_ref(),
+            self.histograms_file_path.as_ref(),
+            peer_file_path,
+        ]
+        .into_iter()
+        .any(|path| matches!(path, Some(path) if stdio::is_stdio(path)))
+    }
+
+    /// Network types IPS generates peer lists for - configured ones, or the filtered network
+    /// type, or Zcash if neither is set.
+    pub fn ips_networks(&self) -> Vec<NetworkType> {
+        if !self.ips_config.networks.is_empty() {
+            return self.ips_config.networks.clone();
+        }
+        vec![self.network_type_filter.unwrap_or(NetworkType::Zcash)]
+    }
+}
+
+/// Convert a JSON (or YAML) document to a TOML table. TOML has no null, so null fields are
+/// dropped - they are unset either way.
+fn to_table(mut value: serde_json::Value) -> Result<toml::Table> {
+    fn drop_nulls(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                map.retain(|_, value| !value.is_null());
+                map.values_mut().for_each(drop_nulls);
+            }
+            serde_json::Value::Array(values) => values.iter_mut().for_each(drop_nulls),
+            _ => (),
+        }
+    }
+
+    if !value.is_object() {
+        return Err(anyhow!("the configuration must be a map of fields"));
+    }
+    drop_nulls(&mut value);
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Fill the weights not set in `ips_config.mcda_weights` with those of `ips_config.profile`.
+fn apply_weight_profile(config: &mut toml::Table) -> Result<()> {
+    let ips_config = match config.get_mut("ips_config").and_then(|v| v.as_table_mut()) {
+        Some(ips_config) => ips_config,
+        None => return Ok(()),
+    };
+    let profile = match ips_config.get("profile") {
+        Some(profile) => profile
+            .as_str()
+            .ok_or_else(|| anyhow!("ips_config.profile must be a string"))?
+            .parse::<WeightProfile>()
+            .map_err(|e| anyhow!("ips_config.profile: {e}"))?,
+        None => return Ok(()),
+    };
+
+    let weights = ips_config
+        .entry("mcda_weights")
+        .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+        .as_table_mut()
+        .ok_or_else(|| anyhow!("ips_config.mcda_weights is not a section"))?;
+    if let toml::Value::Table(profile_weights) = toml::Value::try_from(profile.weights())? {
+        for (name, weight) in profile_weights {
+            weights.entry(name).or_insert(weight);
+        }
+    }
+    Ok(())
+}
+
+/// Replace each `${VAR}` in the value with the variable. Referencing a variable which isn't set
+/// is an error rather than an empty value, so a missing key doesn't go unnoticed.
+fn interpolate(value: &str, vars: &BTreeMap<String, String>) -> Result<String> {
+    let mut interpolated = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("unterminated ${{ in {value}"))?;
+        let name = &rest[start + 2..start + end];
+        let var = vars
+            .get(name)
+            .ok_or_else(|| anyhow!("environment variable {name} is not set"))?;
+        interpolated.push_str(&rest[..start]);
+        interpolated.push_str(var);
+        rest = &rest[start + end + 1..];
     }
+    interpolated.push_str(rest);
+    Ok(interpolated)
+}
+
+/// Set the field given by the path of lowercased keys, creating missing sections.
+fn override_field(config: &mut toml::Table, path: &str, value: &str) -> Result<()> {
+    let keys = path
+        .split(ENV_SECTION_SEPARATOR)
+        .map(str::to_lowercase)
+        .collect::<Vec<String>>();
+    // Splitting always gives at least one key
+    let (field, sections) = keys.split_last().unwrap();
+
+    let mut table = config;
+    for section in sections {
+        table = table
+            .entry(section.clone())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("{ENV_PREFIX}{path}: {section} is not a section"))?;
+    }
+
+    let parsed = match table.get(field) {
+        Some(toml::Value::String(_)) => None,
+        _ => toml::from_str::<toml::Table>(&format!("value = {value}"))
+            .ok()
+            .and_then(|mut parsed| parsed.remove("value")),
+    };
+    table.insert(
+        field.clone(),
+        parsed.unwrap_or_else(|| toml::Value::String(value.to_owned())),
+    );
+    Ok(())
 }
 
 impl Default for CrunchyConfiguration {
     fn default() -> CrunchyConfiguration {
         CrunchyConfiguration {
             input_file_path: Some(PathBuf::from("testdata/sample.json")),
+            input_format: InputFormat::default(),
             state_file_path: Some(PathBuf::from("testdata/state.json")),
+            report_file_path: Some(PathBuf::from("testdata/report.json")),
+            centrality_cache_file_path: None,
+            archive_file_path: None,
+            archive_metric_threshold: 0.0,
+            previous_state_file_paths: Vec::new(),
+            heat_window: DEFAULT_HEAT_WINDOW,
+            emerging_hot_nodes_count: DEFAULT_EMERGING_HOT_NODES_COUNT,
+            simulation: SimulationConfiguration::default(),
+            null_model: NullModelConfiguration::default(),
+            sweep: SweepConfiguration::default(),
             ips_config: IPSConfiguration::default(),
             geoip_config: GeoIPConfiguration::default(),
             network_type_filter: None,
-            num_threads: thread::available_parallelism().unwrap().get(),
+            num_threads: default_num_threads(),
+            weighted_histograms: false,
+            histograms: HistogramConfiguration::default(),
+            histograms_file_path: None,
+            exclude_histograms_from_state: false,
+            geo_summary_top_cities: DEFAULT_GEO_SUMMARY_TOP_CITIES,
+            closeness_variant: ClosenessVariant::default(),
+            centrality_output: CentralityOutput::default(),
+            rankings_top_nodes: DEFAULT_RANKINGS_TOP_NODES,
+            geo_clustering: GeoClusteringConfiguration::default(),
+            seed: None,
+            reproducible: false,
+            reproducible_timestamp: None,
+            ips_dry_run: false,
+            pretty_json: false,
+            keep_last: None,
         }
     }
 }
@@ -83,14 +940,325 @@ impl Default for GeoIPConfiguration {
     fn default() -> GeoIPConfiguration {
         GeoIPConfiguration {
             geocache_file_path: PathBuf::from("testdata/geoip-cache.json"),
+            geocache_backend: GeoCacheBackend::default(),
             keep_in_cache_days: Some(DEFAULT_KEEP_IN_CACHE_DAYS),
+            location_history_size: None,
+            lookup_concurrency: Some(DEFAULT_LOOKUP_CONCURRENCY),
+            lookup_mode: GeoLookupMode::default(),
+            max_cache_entries: None,
+            autosave_entries: None,
+            autosave_interval_secs: None,
+            max_consecutive_failures: Some(DEFAULT_MAX_CONSECUTIVE_FAILURES),
+            asn_db_path: None,
+            proxy_db_path: None,
+            tor_list_paths: Vec::new(),
+            vpn_list_paths: Vec::new(),
+            hosting_list_paths: Vec::new(),
+            offline: false,
             ip2location_enable: true,
             ip2location_db_path: Some(PathBuf::from("IP2LOCATION-LITE-DB11.BIN")),
             ip2location_ipv6_db_path: Some(PathBuf::from("IP2LOCATION-LITE-DB11.IPV6.BIN")),
+            ip2location_priority: None,
+            ip2location_keep_in_cache_days: None,
             ipapico_enable: true,
             ipapico_api_key: Some(String::from("")),
+            ipapico_requests_per_second: None,
+            ipapico_priority: None,
+            ipapico_keep_in_cache_days: None,
             ipapicom_enable: true,
             ipapicom_api_key: Some(String::from("")),
+            ipapicom_requests_per_second: None,
+            ipapicom_priority: None,
+            ipapicom_keep_in_cache_days: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_with_overrides_test() {
+        let config_string = fs::read_to_string("testdata/config.toml").unwrap();
+        let vars = [
+            ("CRUNCHY_STATE_FILE_PATH", "2024"),
+            ("CRUNCHY_REPORT_FILE_PATH", "/srv/report.json"),
+            ("CRUNCHY_NUM_THREADS", "2"),
+            ("CRUNCHY_IPS_CONFIG__CHANGE_NO_MORE", "5"),
+            ("CRUNCHY_GEOIP_CONFIG__IPAPICO_API_KEY", "a1b2c3"),
+            ("CRUNCHY_GEOIP_CONFIG__IPAPICOM_API_KEY", "\"1234\""),
+            ("CRUNCHY_HISTOGRAMS__BINS", "64"),
+            ("HOME", "/root"),
+        ]
+        .map(|(name, value)| (name.to_owned(), value.to_owned()));
+
+        let config = CrunchyConfiguration::parse(&config_string, ConfigFormat::Toml, vars).unwrap();
+        // Numbers overriding strings stay strings
+        assert_eq!(config.state_file_path, Some(PathBuf::from("2024")));
+        assert_eq!(
+            config.report_file_path,
+            Some(PathBuf::from("/srv/report.json"))
+        );
+        assert_eq!(config.num_threads, 2);
+        assert_eq!(config.ips_config.change_no_more, 5);
+        assert_eq!(
+            config.geoip_config.ipapico_api_key.as_deref(),
+            Some("a1b2c3")
+        );
+        assert_eq!(
+            config.geoip_config.ipapicom_api_key.as_deref(),
+            Some("1234")
+        );
+        // Missing sections are created
+        assert_eq!(config.histograms.bins, 64);
+
+        let invalid = [("CRUNCHY_NUM_THREADS__COUNT", "2")]
+            .map(|(name, value)| (name.to_owned(), value.to_owned()));
+        assert!(CrunchyConfiguration::parse(&config_string, ConfigFormat::Toml, invalid).is_err());
+    }
+
+    #[test]
+    fn with_defaults_test() {
+        let defaults = CrunchyConfiguration::with_defaults([]).unwrap();
+        assert_eq!(
+            format!("{defaults:?}"),
+            format!("{:?}", CrunchyConfiguration::default())
+        );
+
+        let vars = [
+            ("CRUNCHY_STATE_FILE_PATH", "/srv/state.json"),
+            ("CRUNCHY_IPS_CONFIG__PROFILE", "security"),
+            ("CRUNCHY_GEOIP_CONFIG__IPAPICO_API_KEY", "${IPAPICO_KEY}"),
+            ("IPAPICO_KEY", "a1b2c3"),
+        ]
+        .map(|(name, value)| (name.to_owned(), value.to_owned()));
+        let config = CrunchyConfiguration::with_defaults(vars).unwrap();
+        assert_eq!(
+            config.state_file_path,
+            Some(PathBuf::from("/srv/state.json"))
+        );
+        assert_eq!(
+            format!("{:?}", config.ips_config.mcda_weights),
+            format!("{:?}", WeightProfile::Security.weights())
+        );
+        assert_eq!(
+            config.geoip_config.ipapico_api_key.as_deref(),
+            Some("a1b2c3")
+        );
+    }
+
+    #[test]
+    fn weight_profile_test() {
+        // Default configuration without the weights
+        let rendered = CrunchyConfiguration::default()
+            .render(ConfigFormat::Toml)
+            .unwrap();
+        let mut table = toml::from_str::<toml::Table>(&rendered).unwrap();
+        let ips_config = table["ips_config"].as_table_mut().unwrap();
+        ips_config.remove("mcda_weights");
+        let config_string = toml::to_string(&table).unwrap();
+
+        let vars = [
+            ("CRUNCHY_IPS_CONFIG__PROFILE", "security"),
+            ("CRUNCHY_IPS_CONFIG__MCDA_WEIGHTS__DEGREE", "0.5"),
+        ]
+        .map(|(name, value)| (name.to_owned(), value.to_owned()));
+        let config = CrunchyConfiguration::parse(&config_string, ConfigFormat::Toml, vars).unwrap();
+        let weights = &config.ips_config.mcda_weights;
+        let security = WeightProfile::Security.weights();
+        // Weights which are set override the profile
+        assert_eq!(weights.degree, 0.5);
+        assert_eq!(weights.betweenness, security.betweenness);
+        assert_eq!(weights.asn_diversity, security.asn_diversity);
+
+        let vars = [("CRUNCHY_IPS_CONFIG__PROFILE", "geo-diverse")]
+            .map(|(name, value)| (name.to_owned(), value.to_owned()));
+        let config = CrunchyConfiguration::parse(&config_string, ConfigFormat::Toml, vars).unwrap();
+        assert_eq!(config.ips_config.profile, Some(WeightProfile::GeoDiverse));
+        assert_eq!(
+            config.ips_config.mcda_weights.country_diversity,
+            WeightProfile::GeoDiverse.weights().country_diversity
+        );
+
+        let vars = [("CRUNCHY_IPS_CONFIG__PROFILE", "fastest")]
+            .map(|(name, value)| (name.to_owned(), value.to_owned()));
+        assert!(CrunchyConfiguration::parse(&config_string, ConfigFormat::Toml, vars).is_err());
+        assert_eq!(
+            "Balanced".parse::<WeightProfile>(),
+            Ok(WeightProfile::Balanced)
+        );
+    }
+
+    #[test]
+    fn interpolate_api_keys_test() {
+        let config_string = fs::read_to_string("testdata/config.toml").unwrap();
+        let vars = [
+            ("IPAPICO_KEY", "a1b2c3"),
+            (
+                "CRUNCHY_GEOIP_CONFIG__IPAPICOM_API_KEY",
+                "key-${IPAPICO_KEY}-${IPAPICO_KEY}",
+            ),
+            ("CRUNCHY_GEOIP_CONFIG__IPAPICO_API_KEY", "${IPAPICO_KEY}"),
+        ]
+        .map(|(name, value)| (name.to_owned(), value.to_owned()));
+
+        let config = CrunchyConfiguration::parse(&config_string, ConfigFormat::Toml, vars).unwrap();
+        assert_eq!(
+            config.geoip_config.ipapico_api_key.as_deref(),
+            Some("a1b2c3")
+        );
+        assert_eq!(
+            config.geoip_config.ipapicom_api_key.as_deref(),
+            Some("key-a1b2c3-a1b2c3")
+        );
+        let redacted = config.redacted();
+        assert_eq!(
+            redacted.geoip_config.ipapicom_api_key.as_deref(),
+            Some(REDACTED)
+        );
+        assert!(!redacted
+            .render(ConfigFormat::Toml)
+            .unwrap()
+            .contains("a1b2c3"));
+
+        let vars = BTreeMap::new();
+        assert_eq!(interpolate("plain", &vars).unwrap(), "plain");
+        assert!(interpolate("${MISSING}", &vars).is_err());
+        assert!(interpolate("${UNTERMINATED", &vars).is_err());
+        let missing = [("CRUNCHY_GEOIP_CONFIG__IPAPICO_API_KEY", "${MISSING}")]
+            .map(|(name, value)| (name.to_owned(), value.to_owned()));
+        assert!(CrunchyConfiguration::parse(&config_string, ConfigFormat::Toml, missing).is_err());
+    }
+
+    #[test]
+    fn parse_json_and_yaml_test() {
+        let toml_string = fs::read_to_string("testdata/config.toml").unwrap();
+        let from_toml = CrunchyConfiguration::parse(&toml_string, ConfigFormat::Toml, []).unwrap();
+        let mut document = toml::from_str::<serde_json::Value>(&toml_string).unwrap();
+
+        let json_string = serde_json::to_string(&document).unwrap();
+        let from_json = CrunchyConfiguration::parse(&json_string, ConfigFormat::Json, []).unwrap();
+        assert_eq!(format!("{from_json:?}"), format!("{from_toml:?}"));
+
+        // Nulls are the same as missing fields
+        document["seed"] = serde_json::Value::Null;
+        let yaml_string = serde_yaml::to_string(&document).unwrap();
+        let vars =
+            [("CRUNCHY_NUM_THREADS", "4")].map(|(name, value)| (name.to_owned(), value.to_owned()));
+        let from_yaml =
+            CrunchyConfiguration::parse(&yaml_string, ConfigFormat::Yaml, vars).unwrap();
+        assert_eq!(from_yaml.state_file_path, from_toml.state_file_path);
+        assert_eq!(from_yaml.num_threads, 4);
+        assert_eq!(from_yaml.seed, None);
+
+        assert!(CrunchyConfiguration::parse("- 1", ConfigFormat::Yaml, []).is_err());
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("crunchy.yml")),
+            ConfigFormat::Yaml
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("crunchy.JSON")),
+            ConfigFormat::Json
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("crunchy.conf")),
+            ConfigFormat::Toml
+        );
+    }
+
+    #[test]
+    fn render_test() {
+        let config_string = fs::read_to_string("testdata/config.toml").unwrap();
+        let config = CrunchyConfiguration::parse(&config_string, ConfigFormat::Toml, []).unwrap();
+        let config = CrunchyConfiguration {
+            ips_config: IPSConfiguration {
+                pinned: vec!["10.0.0.0/8".parse().unwrap()],
+                networks: vec![NetworkType::Zcash],
+                ..config.ips_config
+            },
+            ..config
+        };
+
+        for format in [ConfigFormat::Toml, ConfigFormat::Json, ConfigFormat::Yaml] {
+            let rendered = config.render(format).unwrap();
+            let parsed = CrunchyConfiguration::parse(&rendered, format, []).unwrap();
+            assert_eq!(format!("{parsed:?}"), format!("{config:?}"));
+        }
+    }
+
+    #[test]
+    fn validate_test() {
+        assert_eq!(CrunchyConfiguration::default().validate(), Ok(()));
+
+        let config = CrunchyConfiguration {
+            state_file_path: None,
+            reproducible: true,
+            geoip_config: GeoIPConfiguration {
+                ip2location_db_path: None,
+                ..Default::default()
+            },
+            ips_config: IPSConfiguration {
+                change_at_least: 3,
+                change_no_more: 2,
+                adoption_rates: vec![50.0, 150.0],
+                networks: vec![NetworkType::Invalid],
+                candidate_pool_multiplier: 0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let problems = config.validate().unwrap_err();
+        assert_eq!(problems.len(), 7);
+        assert!(problems[0].starts_with("state_file_path"));
+
+        // The peer file isn't needed in dry runs
+        let mut config = CrunchyConfiguration {
+            ips_config: IPSConfiguration {
+                peer_file_path: None,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(config.validate().unwrap_err().len(), 1);
+        config.ips_dry_run = true;
+        assert!(config.validate().is_ok());
+
+        let stdout = Some(PathBuf::from(stdio::STDIO_PATH));
+        let config = CrunchyConfiguration {
+            input_file_path: stdout.clone(),
+            state_file_path: stdout.clone(),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+        assert!(config.writes_to_stdout());
+        let config = CrunchyConfiguration {
+            report_file_path: stdout.clone(),
+            reproducible: true,
+            seed: Some(1),
+            ..config
+        };
+        assert_eq!(config.validate().unwrap_err().len(), 1);
+        assert!(!CrunchyConfiguration::default().writes_to_stdout());
+
+        // Histograms left out of the state must be written somewhere
+        let mut config = CrunchyConfiguration {
+            exclude_histograms_from_state: true,
+            ..Default::default()
+        };
+        assert_eq!(config.validate().unwrap_err().len(), 1);
+        config.histograms_file_path = stdout;
+        assert!(config.validate().is_ok());
+        assert!(config.writes_to_stdout());
+
+        let mut config = CrunchyConfiguration::default();
+        config.sweep.grid = BTreeMap::from([
+            ("degree".to_owned(), vec![-0.5, 0.0]),
+            ("hops".to_owned(), vec![1.0]),
+            ("latency".to_owned(), vec![]),
+        ]);
+        let problems = config.validate().unwrap_err();
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].contains("hops"));
+    }
+}
@@ -0,0 +1,310 @@
+// Compressed sparse row (CSR) representation of the graph - connection lists of all nodes are
+// stored one after another in a single vector, together with the offset of each list. It takes
+// two allocations instead of one per node. Nodes are created from the crawl through it, and the
+// IPS state keeps the connections of its nodes only in it - they are moved out of the nodes when
+// the state is created, and a network changed by IPS is a new graph rather than a copy of the
+// nodes. Only peer lists being edited are kept as separate lists. Graph walks take any of these
+// forms through `Adjacency`, and the graph is written out as connection lists.
+
+use std::collections::HashSet;
+
+use serde::{Serialize, Serializer};
+
+use crate::Node;
+
+/// Graph of nodes identified by their index. Connection lists are kept as they are, including
+/// duplicates, self-connections and indices of missing nodes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsrGraph {
+    /// Start of the connections of each node in `targets`, followed by the number of all
+    /// connections
+    offsets: Vec<usize>,
+    targets: Vec<usize>,
+}
+
+impl Default for CsrGraph {
+    fn default() -> CsrGraph {
+        CsrGraph {
+            offsets: vec![0],
+            targets: Vec::new(),
+        }
+    }
+}
+
+impl CsrGraph {
+    /// Create the graph from the connection lists of nodes.
+    pub fn from_adjacency<'a>(lists: impl IntoIterator<Item = &'a [usize]>) -> CsrGraph {
+        let mut graph = CsrGraph::default();
+        for list in lists {
+            graph.targets.extend_from_slice(list);
+            graph.offsets.push(graph.targets.len());
+        }
+        graph
+    }
+
+    /// Create the graph from the connections of the nodes.
+    pub fn from_nodes(nodes: &[Node]) -> CsrGraph {
+        CsrGraph::from_adjacency(nodes.iter().map(|node| node.connections.as_slice()))
+    }
+
+    /// Create the graph from the peer lists.
+    pub fn from_lists(lists: &[Vec<usize>]) -> CsrGraph {
+        CsrGraph::from_adjacency(lists.iter().map(Vec::as_slice))
+    }
+
+    /// Move the connections of the nodes into the graph - nodes are left without them.
+    pub fn take_connections(nodes: &mut [Node]) -> CsrGraph {
+        let mut graph = CsrGraph::default();
+        for node in nodes.iter_mut() {
+            graph.targets.append(&mut node.connections);
+            graph.offsets.push(graph.targets.len());
+            node.connections = Vec::new();
+        }
+        graph
+    }
+
+    /// Create the graph of `len` nodes from undirected edges - each edge is added to the
+    /// connections of both its ends, so connections are listed in the order of the edges.
+    pub fn from_edges(len: usize, edges: &[(usize, usize)]) -> CsrGraph {
+        let mut offsets = vec![0; len + 1];
+        for (a, b) in edges.iter() {
+            offsets[a + 1] += 1;
+            offsets[b + 1] += 1;
+        }
+        for idx in 0..len {
+            offsets[idx + 1] += offsets[idx];
+        }
+
+        let mut next = offsets[..len].to_vec();
+        let mut targets = vec![0; offsets[len]];
+        for (a, b) in edges.iter() {
+            targets[next[*a]] = *b;
+            next[*a] += 1;
+            targets[next[*b]] = *a;
+            next[*b] += 1;
+        }
+        CsrGraph { offsets, targets }
+    }
+
+    /// Number of nodes
+    pub fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Connections of the node
+    pub fn neighbors(&self, idx: usize) -> &[usize] {
+        &self.targets[self.offsets[idx]..self.offsets[idx + 1]]
+    }
+
+    /// Graph with the connections added at the end of the connection lists of both their ends,
+    /// in the given order.
+    pub fn with_connections(&self, added: &[(usize, usize)]) -> CsrGraph {
+        if added.is_empty() {
+            return self.clone();
+        }
+        let mut lists = self.to_lists();
+        for (a, b) in added.iter() {
+            lists[*a].push(*b);
+            lists[*b].push(*a);
+        }
+        CsrGraph::from_lists(&lists)
+    }
+
+    /// Graph without any connection of the removed nodes. Nodes keep their indices, so the
+    /// removed ones are left without connections.
+    pub fn without_nodes(&self, removed: &HashSet<usize>) -> CsrGraph {
+        let mut graph = CsrGraph::default();
+        for idx in 0..self.len() {
+            if !removed.contains(&idx) {
+                graph.targets.extend(
+                    self.neighbors(idx)
+                        .iter()
+                        .filter(|peer| !removed.contains(peer)),
+                );
+            }
+            graph.offsets.push(graph.targets.len());
+        }
+        graph
+    }
+
+    /// Connections of each node as a separate list, so they can be edited.
+    pub fn to_lists(&self) -> Vec<Vec<usize>> {
+        (0..self.len())
+            .map(|idx| self.neighbors(idx).to_vec())
+            .collect()
+    }
+
+    /// Edges listed by the connections of their lower index end, in the order of connection
+    /// lists - each edge once if connections are symmetric.
+    pub fn edges(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (0..self.len()).flat_map(move |idx| {
+            self.neighbors(idx)
+                .iter()
+                .filter(move |peer| **peer > idx)
+                .map(move |peer| (idx, *peer))
+        })
+    }
+}
+
+/// The graph is written as the connection list of each node.
+impl Serialize for CsrGraph {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq((0..self.len()).map(|idx| self.neighbors(idx)))
+    }
+}
+
+/// Connection lists of nodes identified by their index. Traversals take a CSR graph, peer lists or
+/// the nodes themselves, so none of them has to be copied into another form for a traversal.
+pub trait Adjacency: Sync {
+    /// Number of nodes
+    fn node_count(&self) -> usize;
+
+    /// Connections of the node
+    fn connections(&self, idx: usize) -> &[usize];
+}
+
+impl Adjacency for CsrGraph {
+    fn node_count(&self) -> usize {
+        self.len()
+    }
+
+    fn connections(&self, idx: usize) -> &[usize] {
+        self.neighbors(idx)
+    }
+}
+
+impl Adjacency for [Node] {
+    fn node_count(&self) -> usize {
+        self.len()
+    }
+
+    fn connections(&self, idx: usize) -> &[usize] {
+        &self[idx].connections
+    }
+}
+
+impl Adjacency for Vec<Node> {
+    fn node_count(&self) -> usize {
+        self.len()
+    }
+
+    fn connections(&self, idx: usize) -> &[usize] {
+        &self[idx].connections
+    }
+}
+
+impl Adjacency for [Vec<usize>] {
+    fn node_count(&self) -> usize {
+        self.len()
+    }
+
+    fn connections(&self, idx: usize) -> &[usize] {
+        &self[idx]
+    }
+}
+
+impl Adjacency for Vec<Vec<usize>> {
+    fn node_count(&self) -> usize {
+        self.len()
+    }
+
+    fn connections(&self, idx: usize) -> &[usize] {
+        &self[idx]
+    }
+}
+
+/// Connections of the graph as undirected edges (lower index first) - a connection listed by
+/// either side is an edge, while self-connections and indices of missing nodes are skipped.
+/// Connections listed by both sides (or more times) give the edge more times, so the edges are
+/// usually collected into a set.
+pub fn undirected_edges<A: Adjacency + ?Sized>(
+    graph: &A,
+) -> impl Iterator<Item = (usize, usize)> + '_ {
+    (0..graph.node_count()).flat_map(move |idx| {
+        graph
+            .connections(idx)
+            .iter()
+            .filter(move |peer| **peer != idx && **peer < graph.node_count())
+            .map(move |peer| (idx.min(*peer), idx.max(*peer)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csr_graph_test() {
+        let lists = [vec![1, 2, 2], vec![0], vec![0, 0, 2], vec![]];
+        let graph = CsrGraph::from_adjacency(lists.iter().map(Vec::as_slice));
+        assert_eq!(graph.len(), 4);
+        for (idx, list) in lists.iter().enumerate() {
+            assert_eq!(graph.neighbors(idx), list.as_slice());
+        }
+        assert_eq!(
+            graph.edges().collect::<Vec<_>>(),
+            vec![(0, 1), (0, 2), (0, 2)]
+        );
+
+        let graph = CsrGraph::from_edges(4, &[(0, 2), (1, 2), (0, 1)]);
+        assert_eq!(graph.neighbors(0), &[2, 1]);
+        assert_eq!(graph.neighbors(1), &[2, 0]);
+        assert_eq!(graph.neighbors(2), &[0, 1]);
+        assert!(graph.neighbors(3).is_empty());
+
+        assert!(CsrGraph::default().is_empty());
+        assert_eq!(CsrGraph::from_edges(0, &[]), CsrGraph::default());
+
+        // Nodes are traversed the same way as the graph made of them
+        let nodes = lists
+            .iter()
+            .map(|connections| Node {
+                connections: connections.clone(),
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+        let graph = CsrGraph::from_nodes(&nodes);
+        assert_eq!(nodes.node_count(), graph.node_count());
+        for idx in 0..nodes.len() {
+            assert_eq!(
+                Adjacency::connections(nodes.as_slice(), idx),
+                graph.connections(idx)
+            );
+        }
+
+        // Connections can be moved out of the nodes, edited as lists and written as lists
+        let mut moved = nodes.clone();
+        assert_eq!(CsrGraph::take_connections(&mut moved), graph);
+        assert!(moved.iter().all(|node| node.connections.is_empty()));
+        assert_eq!(CsrGraph::from_lists(&graph.to_lists()), graph);
+        assert_eq!(
+            serde_json::to_string(&graph).unwrap(),
+            "[[1,2,2],[0],[0,0,2],[]]"
+        );
+
+        let added = graph.with_connections(&[(1, 3), (3, 3)]);
+        assert_eq!(added.neighbors(1), &[0, 3]);
+        assert_eq!(added.neighbors(3), &[1, 3, 3]);
+        assert_eq!(graph.with_connections(&[]), graph);
+
+        let remaining = graph.without_nodes(&HashSet::from([0]));
+        assert_eq!(remaining.len(), 4);
+        assert!(remaining.neighbors(0).is_empty() && remaining.neighbors(1).is_empty());
+        assert_eq!(remaining.neighbors(2), &[2]);
+
+        // Self-connections and missing nodes are skipped
+        let graph = CsrGraph::from_adjacency([[1, 5].as_slice(), &[0, 1], &[2]]);
+        assert_eq!(
+            undirected_edges(&graph).collect::<Vec<_>>(),
+            vec![(0, 1), (0, 1)]
+        );
+        assert_eq!(
+            undirected_edges(nodes.as_slice()).collect::<Vec<_>>(),
+            vec![(0, 1), (0, 2), (0, 2), (0, 1), (0, 2), (0, 2)]
+        );
+    }
+}
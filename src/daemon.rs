@@ -0,0 +1,236 @@
+// Helpers of the `daemon` command, which runs the pipeline on an interval instead of cron jobs.
+// Each run writes its outputs into its own directory named by the UTC time the run started
+// (e.g. `20261016T120000Z`), so the names sort in the order of runs, and old runs are removed
+// according to the retention policy. Caches, the archive and previous states are shared by all
+// runs and stay where they are configured.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::{config::CrunchyConfiguration, stdio};
+
+/// Length of the run directory names (`YYYYMMDDTHHMMSSZ`)
+const RUN_NAME_LEN: usize = 16;
+
+/// Which runs are kept, the rest is removed after each run. The latest run is always kept.
+#[derive(Debug, Clone, Default)]
+pub struct Retention {
+    /// Number of the latest runs kept
+    pub keep_runs: Option<usize>,
+    /// Runs started longer than this ago are removed
+    pub max_age: Option<Duration>,
+}
+
+/// Parse an interval given as a number with a unit - `s`, `m`, `h` or `d` (e.g. `90m` or `1h`).
+pub fn parse_interval(interval: &str) -> Result<Duration, String> {
+    let interval = interval.trim();
+    let unit = match interval.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        _ => return Err(format!("{interval}: missing unit (s, m, h or d)")),
+    };
+    let count = interval[..interval.len() - 1]
+        .parse::<u64>()
+        .map_err(|e| format!("{interval}: {e}"))?;
+    if count == 0 {
+        return Err(format!("{interval}: must be greater than 0"));
+    }
+    count
+        .checked_mul(unit)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("{interval}: too long"))
+}
+
+/// Name of the directory of a run started at the given time.
+pub fn run_name(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (year, month, day) = civil_date(secs / 86400);
+    let secs_of_day = secs % 86400;
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+/// Check if the name is a name of a run directory.
+pub(crate) fn is_run_name(name: &str) -> bool {
+    name.len() == RUN_NAME_LEN
+        && name.char_indices().all(|(idx, c)| match idx {
+            8 => c == 'T',
+            15 => c == 'Z',
+            _ => c.is_ascii_digit(),
+        })
+}
+
+/// Year, month and day of the date the given number of days after 1970-01-01 (in the proleptic
+/// Gregorian calendar).
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    // Days are counted from 0000-03-01, so leap days fall at the end of each year
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Configuration of a single run - all outputs (except those written to stdout) are written into
+/// the run directory, keeping their file names.
+pub fn run_configuration(config: &CrunchyConfiguration, run_dir: &Path) -> CrunchyConfiguration {
+    config.map_output_paths(|path| match path.file_name() {
+        Some(name) if !stdio::is_stdio(path) => run_dir.join(name),
+        _ => path.to_owned(),
+    })
+}
+
+/// Remove run directories not kept by the retention policy. Other entries of the directory are
+/// left alone. Returns the removed directories.
+pub fn prune_runs(dir: &Path, retention: &Retention, now: SystemTime) -> io::Result<Vec<PathBuf>> {
+    let mut runs = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| is_run_name(name))
+        .collect::<Vec<String>>();
+    // Names sort in the order of runs, the latest first
+    runs.sort_unstable_by(|a, b| b.cmp(a));
+
+    let oldest_kept = retention
+        .max_age
+        .and_then(|max_age| now.checked_sub(max_age))
+        .map(run_name);
+    let mut removed = Vec::new();
+    for (idx, name) in runs.iter().enumerate().skip(1) {
+        let too_many = retention.keep_runs.is_some_and(|keep| idx >= keep);
+        let too_old = oldest_kept.as_ref().is_some_and(|oldest| name < oldest);
+        if too_many || too_old {
+            let path = dir.join(name);
+            fs::remove_dir_all(&path)?;
+            removed.push(path);
+        }
+    }
+    Ok(removed)
+}
+
+/// Fetch the network summary from the crawler's JSON-RPC endpoint (`getmetrics`). Returns the
+/// response as received - it's the input of the `ziggurat` format.
+#[cfg(feature = "http")]
+pub async fn fetch_summary(url: &hyper::Uri) -> Result<Vec<u8>, String> {
+    use hyper::{header, Body, Client, Request};
+
+    let request = Request::post(url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            r#"{"jsonrpc":"2.0","id":0,"method":"getmetrics","params":null}"#,
+        ))
+        .map_err(|e| e.to_string())?;
+    let response = Client::new()
+        .request(request)
+        .await
+        .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("crawler responded with {}", response.status()));
+    }
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(body.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prune_runs_test() {
+        assert_eq!(parse_interval("1h"), Ok(Duration::from_secs(3600)));
+        assert_eq!(parse_interval("90m"), Ok(Duration::from_secs(5400)));
+        assert!(parse_interval("15").is_err());
+        assert!(parse_interval("0s").is_err());
+        assert!(parse_interval("h").is_err());
+        assert!(parse_interval(&format!("{}d", u64::MAX / 2)).is_err());
+
+        assert_eq!(run_name(SystemTime::UNIX_EPOCH), "19700101T000000Z");
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        assert_eq!(run_name(time), "20240229T123456Z");
+        assert!(is_run_name(&run_name(time)));
+        assert!(!is_run_name("20240229-123456Z"));
+
+        let dir = std::env::temp_dir().join(format!("crunchy-daemon-{}", std::process::id()));
+        let hour = Duration::from_secs(3600);
+        for hours in 0..5 {
+            fs::create_dir_all(dir.join(run_name(time - hour * hours))).unwrap();
+        }
+        fs::create_dir_all(dir.join("archive")).unwrap();
+
+        // Runs of the last 2.5 hours are kept
+        let retention = Retention {
+            keep_runs: None,
+            max_age: Some(hour * 5 / 2),
+        };
+        assert_eq!(prune_runs(&dir, &retention, time).unwrap().len(), 2);
+        // Of those only the latest 2 are kept
+        let retention = Retention {
+            keep_runs: Some(2),
+            max_age: None,
+        };
+        let removed = prune_runs(&dir, &retention, time).unwrap();
+        assert_eq!(removed, vec![dir.join(run_name(time - hour * 2))]);
+        // The latest run is kept however old it is
+        let retention = Retention {
+            keep_runs: Some(1),
+            max_age: Some(hour),
+        };
+        prune_runs(&dir, &retention, time + hour * 10).unwrap();
+        let mut left = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<String>>();
+        left.sort_unstable();
+        assert_eq!(left, vec![run_name(time), "archive".to_owned()]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn run_configuration_test() {
+        let mut config = CrunchyConfiguration {
+            state_file_path: Some(PathBuf::from("out/state.json")),
+            report_file_path: Some(PathBuf::from(stdio::STDIO_PATH)),
+            archive_file_path: Some(PathBuf::from("out/archive.json")),
+            ..Default::default()
+        };
+        config.ips_config.peer_file_path = Some(PathBuf::from("out/peers.json"));
+
+        let run = run_configuration(&config, Path::new("runs/20261016T120000Z"));
+        assert_eq!(
+            run.state_file_path,
+            Some(PathBuf::from("runs/20261016T120000Z/state.json"))
+        );
+        assert_eq!(run.report_file_path, config.report_file_path);
+        assert_eq!(run.archive_file_path, config.archive_file_path);
+        assert_eq!(
+            run.ips_config.statistics_file_path,
+            Some(PathBuf::from("runs/20261016T120000Z/statistics.json"))
+        );
+        assert_eq!(run.ips_config.log_path, None);
+    }
+}
@@ -0,0 +1,276 @@
+// Geographic clusters of nodes (e.g. datacenters or hosting regions) found with DBSCAN - nodes
+// with enough located nodes within the radius are cluster cores, clusters grow over cores
+// within the radius of each other, and nodes near no core are left out. Neighbors are looked up
+// in a grid of points on the unit sphere, so only nodes in nearby cells are measured.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use ziggurat_core_geoip::geoip::Coordinates;
+
+use crate::{config::GeoClusteringConfiguration, Node};
+
+/// Lowest Earth radius (in m) distances can be computed with - grid cells are large enough for
+/// any radius above it
+const MIN_EARTH_RADIUS_M: f64 = 6_350_000.0;
+
+/// Geographic cluster of nodes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GeoCluster {
+    /// Cluster ID set on its nodes - clusters are numbered from the largest one
+    pub id: usize,
+    /// Number of nodes in the cluster
+    pub nodes: usize,
+    /// Average latitude of the nodes
+    pub latitude: f64,
+    /// Average longitude of the nodes (of their directions, so a cluster across the
+    /// antimeridian isn't placed on the other side of the Earth)
+    pub longitude: f64,
+}
+
+/// Point on the unit sphere
+fn unit_vector(coordinates: Coordinates) -> [f64; 3] {
+    let (latitude, longitude) = (
+        coordinates.latitude.to_radians(),
+        coordinates.longitude.to_radians(),
+    );
+    [
+        latitude.cos() * longitude.cos(),
+        latitude.cos() * longitude.sin(),
+        latitude.sin(),
+    ]
+}
+
+/// Grid of located nodes by their points on the unit sphere. The straight-line distance of two
+/// points is shorter than the arc between them, so nodes within the radius are in the same or
+/// adjacent cells.
+struct Grid {
+    cell_size: f64,
+    cells: HashMap<[i64; 3], Vec<usize>>,
+}
+
+impl Grid {
+    fn new(points: &[[f64; 3]], radius_m: f64) -> Self {
+        let mut grid = Grid {
+            cell_size: radius_m / MIN_EARTH_RADIUS_M,
+            cells: HashMap::new(),
+        };
+        for (idx, point) in points.iter().enumerate() {
+            grid.cells.entry(grid.cell(point)).or_default().push(idx);
+        }
+        grid
+    }
+
+    fn cell(&self, point: &[f64; 3]) -> [i64; 3] {
+        point.map(|coordinate| (coordinate / self.cell_size).floor() as i64)
+    }
+
+    /// Nodes in the cell of the point and the cells around it
+    fn candidates(&self, point: &[f64; 3]) -> impl Iterator<Item = usize> + '_ {
+        let [x, y, z] = self.cell(point);
+        (-1..=1)
+            .flat_map(move |dx| (-1..=1).flat_map(move |dy| (-1..=1).map(move |dz| [dx, dy, dz])))
+            .filter_map(move |[dx, dy, dz]| self.cells.get(&[x + dx, y + dy, z + dz]))
+            .flatten()
+            .copied()
+    }
+}
+
+/// Find geographic clusters of located nodes, setting the cluster ID of each node (none for
+/// nodes outside any cluster).
+pub fn cluster_nodes(nodes: &mut [Node], config: &GeoClusteringConfiguration) -> Vec<GeoCluster> {
+    let located = nodes
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, node)| {
+            let geolocation = node.geolocation.as_ref()?;
+            Some((idx, geolocation.coordinates?))
+        })
+        .collect::<Vec<(usize, Coordinates)>>();
+    let radius_m = config.radius_km * 1000.0;
+    let points = located
+        .iter()
+        .map(|(_, coordinates)| unit_vector(*coordinates))
+        .collect::<Vec<[f64; 3]>>();
+    let grid = Grid::new(&points, radius_m);
+    let neighbors = |point: usize| {
+        grid.candidates(&points[point])
+            .filter(|other| located[point].1.distance_to(located[*other].1) <= radius_m)
+            .collect::<Vec<usize>>()
+    };
+
+    // Clusters of the located nodes, in the order they are found. Each node is queued at most
+    // once - a queued node is labeled by the cluster it was queued for.
+    let mut labels: Vec<Option<usize>> = vec![None; located.len()];
+    let mut visited = vec![false; located.len()];
+    let mut queued = vec![false; located.len()];
+    let mut sizes = Vec::new();
+    for point in 0..located.len() {
+        if visited[point] {
+            continue;
+        }
+        visited[point] = true;
+        let point_neighbors = neighbors(point);
+        if point_neighbors.len() < config.min_nodes {
+            continue;
+        }
+
+        let cluster = sizes.len();
+        sizes.push(0);
+        labels[point] = Some(cluster);
+        sizes[cluster] += 1;
+        queued[point] = true;
+        let mut queue = Vec::new();
+        let mut enqueue = |queue: &mut Vec<usize>, others: Vec<usize>| {
+            for other in others {
+                if !queued[other] {
+                    queued[other] = true;
+                    queue.push(other);
+                }
+            }
+        };
+        enqueue(&mut queue, point_neighbors);
+        while let Some(other) = queue.pop() {
+            if !visited[other] {
+                visited[other] = true;
+                let other_neighbors = neighbors(other);
+                if other_neighbors.len() >= config.min_nodes {
+                    enqueue(&mut queue, other_neighbors);
+                }
+            }
+            if labels[other].is_none() {
+                labels[other] = Some(cluster);
+                sizes[cluster] += 1;
+            }
+        }
+    }
+
+    // The largest cluster gets the lowest ID, the order of finding breaks ties
+    let mut order = (0..sizes.len()).collect::<Vec<usize>>();
+    order.sort_by(|a, b| sizes[*b].cmp(&sizes[*a]).then_with(|| a.cmp(b)));
+    let mut ids = vec![0; sizes.len()];
+    for (id, cluster) in order.iter().enumerate() {
+        ids[*cluster] = id;
+    }
+
+    // Longitudes are summed as directions (cosine and sine)
+    let mut longitudes = vec![(0.0, 0.0); sizes.len()];
+    let mut clusters = order
+        .iter()
+        .enumerate()
+        .map(|(id, cluster)| GeoCluster {
+            id,
+            nodes: sizes[*cluster],
+            latitude: 0.0,
+            longitude: 0.0,
+        })
+        .collect::<Vec<GeoCluster>>();
+    for node in nodes.iter_mut() {
+        node.geo_cluster_id = None;
+    }
+    for ((idx, coordinates), label) in located.iter().zip(labels) {
+        if let Some(cluster) = label {
+            let id = ids[cluster];
+            nodes[*idx].geo_cluster_id = Some(id);
+            clusters[id].latitude += coordinates.latitude / clusters[id].nodes as f64;
+            let longitude = coordinates.longitude.to_radians();
+            longitudes[id].0 += longitude.cos();
+            longitudes[id].1 += longitude.sin();
+        }
+    }
+    for (cluster, (cos, sin)) in clusters.iter_mut().zip(longitudes) {
+        cluster.longitude = f64::atan2(sin, cos).to_degrees();
+    }
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::geo_info;
+
+    fn node(coordinates: Option<(f64, f64)>) -> Node {
+        Node {
+            geolocation: geo_info(None, None, coordinates),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn cluster_nodes_test() {
+        let frankfurt = (50.11, 8.68);
+        let near_frankfurt = (50.2, 8.6);
+        let tokyo = (35.68, 139.69);
+        let mut nodes = vec![
+            node(Some(tokyo)),
+            node(Some(frankfurt)),
+            node(Some(near_frankfurt)),
+            node(Some(tokyo)),
+            node(Some(frankfurt)),
+            node(Some((0.0, 0.0))),
+            node(None),
+            Node::default(),
+        ];
+        let config = GeoClusteringConfiguration {
+            enable: true,
+            radius_km: 50.0,
+            min_nodes: 2,
+        };
+
+        let clusters = cluster_nodes(&mut nodes, &config);
+        assert_eq!(clusters.len(), 2);
+        assert_eq!((clusters[0].id, clusters[0].nodes), (0, 3));
+        assert_eq!((clusters[1].id, clusters[1].nodes), (1, 2));
+        assert!((clusters[0].latitude - 50.14).abs() < 1e-9);
+        assert!((clusters[1].longitude - 139.69).abs() < 1e-9);
+
+        let ids = nodes
+            .iter()
+            .map(|node| node.geo_cluster_id)
+            .collect::<Vec<Option<usize>>>();
+        assert_eq!(
+            ids,
+            vec![
+                Some(1),
+                Some(0),
+                Some(0),
+                Some(1),
+                Some(0),
+                None,
+                None,
+                None
+            ]
+        );
+
+        // Nothing is dense enough
+        let config = GeoClusteringConfiguration {
+            enable: true,
+            radius_km: 50.0,
+            min_nodes: 4,
+        };
+        assert!(cluster_nodes(&mut nodes, &config).is_empty());
+        assert!(nodes.iter().all(|node| node.geo_cluster_id.is_none()));
+    }
+
+    #[test]
+    fn cluster_across_antimeridian_test() {
+        // Nodes about 11 km apart on both sides of the antimeridian and a chain of nodes 40 km
+        // apart along the equator - each node reaches only its neighbors, so the chain is
+        // clustered over the neighbors of its cores
+        let mut nodes = vec![node(Some((0.0, 179.95))), node(Some((0.0, -179.95)))];
+        nodes.extend((0..10).map(|i| node(Some((0.0, i as f64 * 0.36)))));
+        let config = GeoClusteringConfiguration {
+            enable: true,
+            radius_km: 50.0,
+            min_nodes: 2,
+        };
+
+        let clusters = cluster_nodes(&mut nodes, &config);
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].nodes, 10);
+        assert!((clusters[0].longitude - 1.62).abs() < 1e-9);
+        assert_eq!(clusters[1].nodes, 2);
+        assert!((clusters[1].longitude.abs() - 180.0).abs() < 1e-9);
+        assert!(clusters[1].latitude.abs() < 1e-9);
+    }
+}
@@ -0,0 +1,165 @@
+// Node counts per country, city and ASN, and of classified (hosting, VPN and Tor) addresses. The
+// aggregation is done once here, from the locations resolved while creating nodes, so consumers
+// of the state don't have to repeat it.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Node;
+
+/// Number of nodes in a country or a city
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LocationCount {
+    pub name: String,
+    pub nodes: usize,
+}
+
+/// Number of nodes in an autonomous system
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AsnCount {
+    pub asn: u32,
+    pub nodes: usize,
+}
+
+/// Geographical distribution of nodes - each list is ordered by the number of nodes (the most
+/// first), names break ties.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct GeoSummary {
+    /// Nodes per country
+    pub countries: Vec<LocationCount>,
+    /// Nodes per city ("city, country"), only the top cities
+    pub cities: Vec<LocationCount>,
+    /// Nodes per ASN
+    pub asns: Vec<AsnCount>,
+    /// Number of nodes without a known country
+    pub unknown_country: usize,
+    /// Number of nodes without a known ASN
+    pub unknown_asn: usize,
+    /// Number of nodes on hosting (data center) addresses
+    #[serde(default)]
+    pub hosting: usize,
+    /// Number of nodes on Tor exit addresses
+    #[serde(default)]
+    pub tor: usize,
+    /// Number of nodes on VPN addresses
+    #[serde(default)]
+    pub vpn: usize,
+}
+
+/// Count nodes per country, city (only `top_cities` of them are kept), ASN and address class.
+pub fn create_geo_summary(nodes: &[Node], top_cities: usize) -> GeoSummary {
+    let mut countries = HashMap::new();
+    let mut cities = HashMap::new();
+    let mut asns = HashMap::new();
+    let mut summary = GeoSummary::default();
+
+    for node in nodes.iter() {
+        let geolocation = node.geolocation.as_ref();
+        match geolocation.and_then(|geo| geo.country.clone()) {
+            Some(country) => {
+                if let Some(city) = geolocation.and_then(|geo| geo.city.as_ref()) {
+                    *cities.entry(format!("{city}, {country}")).or_insert(0) += 1;
+                }
+                *countries.entry(country).or_insert(0) += 1;
+            }
+            None => summary.unknown_country += 1,
+        }
+        match node.asn {
+            Some(asn) => *asns.entry(asn).or_insert(0) += 1,
+            None => summary.unknown_asn += 1,
+        }
+        summary.hosting += usize::from(node.is_hosting);
+        summary.tor += usize::from(node.is_tor);
+        summary.vpn += usize::from(node.is_vpn);
+    }
+
+    summary.countries = location_counts(countries);
+    summary.cities = location_counts(cities);
+    summary.cities.truncate(top_cities);
+    summary.asns = asns
+        .into_iter()
+        .map(|(asn, nodes)| AsnCount { asn, nodes })
+        .collect();
+    summary
+        .asns
+        .sort_by(|a, b| b.nodes.cmp(&a.nodes).then_with(|| a.asn.cmp(&b.asn)));
+    summary
+}
+
+fn location_counts(counts: HashMap<String, usize>) -> Vec<LocationCount> {
+    let mut counts = counts
+        .into_iter()
+        .map(|(name, nodes)| LocationCount { name, nodes })
+        .collect::<Vec<LocationCount>>();
+    counts.sort_by(|a, b| b.nodes.cmp(&a.nodes).then_with(|| a.name.cmp(&b.name)));
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::geo_info;
+
+    fn node(country: Option<&str>, city: Option<&str>, asn: Option<u32>) -> Node {
+        Node {
+            geolocation: geo_info(country, city, None),
+            asn,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn create_geo_summary_test() {
+        let nodes = vec![
+            node(Some("Germany"), Some("Berlin"), Some(24940)),
+            node(Some("Germany"), Some("Munich"), Some(24940)),
+            node(Some("Germany"), Some("Berlin"), Some(16509)),
+            node(Some("Japan"), Some("Tokyo"), None),
+            node(Some("Japan"), None, Some(2500)),
+            node(None, None, Some(16509)),
+            Node {
+                is_hosting: true,
+                is_vpn: true,
+                ..Default::default()
+            },
+        ];
+
+        let summary = create_geo_summary(&nodes, 2);
+        let location = |name: &str, nodes| LocationCount {
+            name: name.to_owned(),
+            nodes,
+        };
+        assert_eq!(
+            summary.countries,
+            vec![location("Germany", 3), location("Japan", 2)]
+        );
+        assert_eq!(
+            summary.cities,
+            vec![
+                location("Berlin, Germany", 2),
+                location("Munich, Germany", 1)
+            ]
+        );
+        assert_eq!(
+            summary.asns,
+            vec![
+                AsnCount {
+                    asn: 16509,
+                    nodes: 2
+                },
+                AsnCount {
+                    asn: 24940,
+                    nodes: 2
+                },
+                AsnCount {
+                    asn: 2500,
+                    nodes: 1
+                },
+            ]
+        );
+        assert_eq!(summary.unknown_country, 2);
+        assert_eq!(summary.unknown_asn, 2);
+        assert_eq!((summary.hosting, summary.tor, summary.vpn), (1, 0, 1));
+    }
+}
@@ -0,0 +1,330 @@
+// Transfer of GeoIP cache entries between machines (`geocache` commands). Entries are exported
+// as a CSV table which can be inspected or edited with common tools and imported again, and
+// caches built on different machines can be merged - the newest entry of each IP wins and
+// entries already expired are left out. Location history and usage times of entries are not
+// part of the CSV table - a replaced entry keeps them, with its location added to the history.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    time::{Duration, SystemTime},
+};
+
+use serde_json::json;
+use ziggurat_core_geoip::geoip::GeoInfo;
+
+use crate::geoip_cache::{CachedIp, LocationRecord};
+
+/// Columns of the exported CSV table
+pub const CSV_HEADER: &str =
+    "ip,last_updated,provider,confidence,country,city,latitude,longitude,timezone,isp";
+
+/// Result of merging cache entries
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MergeSummary {
+    /// Entries of IPs which were not in the cache
+    pub added: usize,
+    /// Entries which replaced older entries of the same IP
+    pub updated: usize,
+    /// Entries which were not newer than the cached ones
+    pub skipped: usize,
+    /// Entries which were left out as they have already expired
+    pub expired: usize,
+}
+
+/// CSV table of the entries sorted by IP. The update time is written as seconds since the Unix
+/// epoch, missing values are left empty.
+pub fn entries_to_csv(entries: &HashMap<IpAddr, CachedIp>) -> String {
+    let mut ips = entries.keys().collect::<Vec<&IpAddr>>();
+    ips.sort_unstable();
+
+    let mut csv = String::from(CSV_HEADER);
+    csv.push('\n');
+    for ip in ips {
+        let entry = &entries[ip];
+        let info = &entry.info;
+        let text = |value: &Option<String>| value.as_deref().map_or_else(String::new, csv_field);
+        let number = |value: Option<f64>| value.map_or_else(String::new, |value| value.to_string());
+        let fields = [
+            ip.to_string(),
+            unix_secs(entry.last_updated).to_string(),
+            text(&entry.provider),
+            number(entry.confidence),
+            text(&info.country),
+            text(&info.city),
+            number(info.coordinates.map(|coordinates| coordinates.latitude)),
+            number(info.coordinates.map(|coordinates| coordinates.longitude)),
+            text(&info.timezone),
+            text(&info.isp),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Parse entries from a CSV table written by `entries_to_csv()`. The header is required, so
+/// columns can't be mixed up. Returns the number of the line and the problem if the table is
+/// invalid.
+pub fn entries_from_csv(csv: &str) -> Result<HashMap<IpAddr, CachedIp>, String> {
+    let mut lines = csv.lines().enumerate();
+    match lines.next() {
+        Some((_, header)) if header.trim_end() == CSV_HEADER => (),
+        _ => return Err(format!("line 1: expected header \"{CSV_HEADER}\"")),
+    }
+
+    let mut entries = HashMap::new();
+    for (idx, line) in lines {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (ip, entry) = parse_entry(line).map_err(|e| format!("line {}: {e}", idx + 1))?;
+        entries.insert(ip, entry);
+    }
+    Ok(entries)
+}
+
+/// Merge the entries into the cache, keeping the newest entry of each IP. Entries for which
+/// `is_expired` holds are not merged. A replaced entry keeps the time it was last used, and its
+/// history is given by `update_history` from the replaced entry and the new location.
+pub fn merge_entries(
+    cache: &mut HashMap<IpAddr, CachedIp>,
+    entries: HashMap<IpAddr, CachedIp>,
+    is_expired: impl Fn(&CachedIp) -> bool,
+    mut update_history: impl FnMut(IpAddr, CachedIp, &GeoInfo) -> Vec<LocationRecord>,
+) -> MergeSummary {
+    let mut summary = MergeSummary::default();
+    for (ip, mut entry) in entries {
+        if is_expired(&entry) {
+            summary.expired += 1;
+            continue;
+        }
+        match cache.remove(&ip) {
+            None => summary.added += 1,
+            Some(cached) if cached.last_updated < entry.last_updated => {
+                summary.updated += 1;
+                entry.last_used = entry.last_used.max(cached.last_used);
+                entry.history = update_history(ip, cached, &entry.info);
+            }
+            Some(cached) => {
+                summary.skipped += 1;
+                cache.insert(ip, cached);
+                continue;
+            }
+        }
+        cache.insert(ip, entry);
+    }
+    summary
+}
+
+/// Check if the entry is older than the given number of days at the given time.
+pub fn expired_at(entry: &CachedIp, keep_in_cache_days: u16, now: SystemTime) -> bool {
+    now.duration_since(entry.last_updated)
+        .is_ok_and(|age| age >= Duration::from_secs(60 * 60 * 24 * keep_in_cache_days as u64))
+}
+
+fn parse_entry(line: &str) -> Result<(IpAddr, CachedIp), String> {
+    let fields = split_csv_line(line)?;
+    if fields.len() != CSV_HEADER.split(',').count() {
+        return Err(format!(
+            "expected {} fields, found {}",
+            CSV_HEADER.split(',').count(),
+            fields.len()
+        ));
+    }
+    let text = |idx: usize| Some(fields[idx].clone()).filter(|value| !value.is_empty());
+    let number = |idx: usize, name: &str| match text(idx) {
+        Some(value) => value
+            .parse::<f64>()
+            .map(Some)
+            .map_err(|_| format!("invalid {name} \"{value}\"")),
+        None => Ok(None),
+    };
+
+    let ip = fields[0]
+        .parse::<IpAddr>()
+        .map_err(|_| format!("invalid IP address \"{}\"", fields[0]))?;
+    let last_updated = fields[1]
+        .parse::<u64>()
+        .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+        .map_err(|_| format!("invalid last_updated \"{}\"", fields[1]))?;
+    let coordinates = match (number(6, "latitude")?, number(7, "longitude")?) {
+        (Some(latitude), Some(longitude)) => {
+            Some(json!({"latitude": latitude, "longitude": longitude}))
+        }
+        (None, None) => None,
+        _ => return Err("latitude and longitude must be set together".to_owned()),
+    };
+    let info: GeoInfo = serde_json::from_value(json!({
+        "country": text(4),
+        "city": text(5),
+        "coordinates": coordinates,
+        "timezone": text(8),
+        "isp": text(9),
+    }))
+    .map_err(|e| e.to_string())?;
+
+    Ok((
+        ip,
+        CachedIp {
+            last_updated,
+            info,
+            provider: text(2),
+            confidence: number(3, "confidence")?,
+            last_used: None,
+            history: Vec::new(),
+        },
+    ))
+}
+
+/// Quote the value if it contains a separator or a quote. Line breaks are replaced with spaces,
+/// so each entry stays on its own line.
+fn csv_field(value: &str) -> String {
+    let value = value.replace(['\n', '\r'], " ");
+    if value.contains([',', '"']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+/// Split a CSV line into its fields, unquoting quoted fields.
+fn split_csv_line(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quoted field".to_owned());
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cached_ip(secs_since_epoch: u64, city: &str, provider: Option<&str>) -> CachedIp {
+        serde_json::from_value(json!({
+            "last_updated": {"secs_since_epoch": secs_since_epoch, "nanos_since_epoch": 0},
+            "info": {
+                "country": "Germany",
+                "city": city,
+                "coordinates": {"latitude": 49.01, "longitude": 8.39},
+                "timezone": "+01:00",
+                "isp": null
+            },
+            "provider": provider,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn csv_test() {
+        let ip_a: IpAddr = "10.0.0.2".parse().unwrap();
+        let ip_b: IpAddr = "10.0.0.1".parse().unwrap();
+        let mut entries = HashMap::from([
+            (ip_a, cached_ip(1000, "Karlsruhe", Some("ipapi.co"))),
+            (ip_b, cached_ip(2000, "Frankfurt, \"Main\"", None)),
+        ]);
+        entries.get_mut(&ip_a).unwrap().confidence = Some(0.5);
+        entries.get_mut(&ip_b).unwrap().info.coordinates = None;
+
+        let csv = entries_to_csv(&entries);
+        let lines = csv.lines().collect::<Vec<&str>>();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(
+            lines[1],
+            "10.0.0.1,2000,,,Germany,\"Frankfurt, \"\"Main\"\"\",,,+01:00,"
+        );
+        assert_eq!(
+            lines[2],
+            "10.0.0.2,1000,ipapi.co,0.5,Germany,Karlsruhe,49.01,8.39,+01:00,"
+        );
+
+        let imported = entries_from_csv(&csv).unwrap();
+        assert_eq!(entries_to_csv(&imported), csv);
+        assert_eq!(
+            imported[&ip_b].info.city.as_deref(),
+            Some("Frankfurt, \"Main\"")
+        );
+        assert_eq!(imported[&ip_a].last_updated, entries[&ip_a].last_updated);
+
+        assert!(entries_from_csv("ip,city\n").is_err());
+        let invalid = format!("{CSV_HEADER}\n10.0.0.1,2000,,,,,49.0,,,\n");
+        assert_eq!(
+            entries_from_csv(&invalid).err().unwrap(),
+            "line 2: latitude and longitude must be set together"
+        );
+    }
+
+    #[test]
+    fn merge_entries_test() {
+        let ip = |i: u8| IpAddr::from([10, 0, 0, i]);
+        let mut cache = HashMap::from([
+            (ip(1), cached_ip(1000, "Karlsruhe", None)),
+            (ip(2), cached_ip(3000, "Karlsruhe", None)),
+        ]);
+        let used_at = SystemTime::UNIX_EPOCH + Duration::from_secs(5000);
+        cache.get_mut(&ip(1)).unwrap().last_used = Some(used_at);
+        let entries = HashMap::from([
+            (ip(1), cached_ip(2000, "Berlin", None)),
+            (ip(2), cached_ip(2000, "Berlin", None)),
+            (ip(3), cached_ip(2000, "Berlin", None)),
+            (ip(4), cached_ip(10, "Berlin", None)),
+        ]);
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(60 * 60 * 24 + 1000);
+        let summary = merge_entries(
+            &mut cache,
+            entries,
+            |entry| expired_at(entry, 1, now),
+            |_, previous, _| {
+                let mut history = previous.history;
+                history.push(LocationRecord {
+                    seen_at: previous.last_updated,
+                    info: previous.info,
+                });
+                history
+            },
+        );
+        assert_eq!(
+            summary,
+            MergeSummary {
+                added: 1,
+                updated: 1,
+                skipped: 1,
+                expired: 1,
+            }
+        );
+        // The newest entry of each IP is kept
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache[&ip(1)].info.city.as_deref(), Some("Berlin"));
+        assert_eq!(cache[&ip(2)].info.city.as_deref(), Some("Karlsruhe"));
+        assert!(!cache.contains_key(&ip(4)));
+
+        // The replaced location is in the history and the entry is as recently used as before
+        let history = &cache[&ip(1)].history;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].info.city.as_deref(), Some("Karlsruhe"));
+        assert_eq!(cache[&ip(1)].last_used, Some(used_at));
+        assert!(cache[&ip(2)].history.is_empty());
+    }
+}
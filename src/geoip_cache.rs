@@ -1,14 +1,21 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs, io,
     net::IpAddr,
-    path::PathBuf,
-    sync::Arc,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
     time::{Duration, SystemTime},
 };
 
+use futures::{stream, StreamExt};
 use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
+use tokio::{
+    sync::{Mutex as AsyncMutex, RwLock},
+    time::{self, Instant},
+};
 use ziggurat_core_geoip::{
     geoip::{GeoIPService, GeoInfo},
     providers::{
@@ -17,12 +24,49 @@ use ziggurat_core_geoip::{
     },
 };
 
-use crate::config::{GeoIPConfiguration, DEFAULT_KEEP_IN_CACHE_DAYS};
+use crate::{
+    asn::AsnDatabase,
+    atomic_file,
+    config::{
+        GeoCacheBackend, GeoIPConfiguration, GeoLookupMode, DEFAULT_KEEP_IN_CACHE_DAYS,
+        DEFAULT_LOOKUP_CONCURRENCY, DEFAULT_MAX_CONSECUTIVE_FAILURES,
+    },
+    geocache_transfer::{expired_at, merge_entries, MergeSummary},
+    ip_class::{IpClass, IpClassDatabase, IpClasses},
+};
+
+/// Maximum distance between locations returned by two providers to consider them in agreement.
+const AGREEMENT_DISTANCE_M: f64 = 100_000.0;
+
+/// Minimum distance between two locations of the same IP to consider the node relocated.
+/// Smaller differences are usually just inaccuracies between providers or database updates.
+const RELOCATION_DISTANCE_M: f64 = 50_000.0;
 
+/// Single cache entry.
 #[derive(Clone, Serialize, Deserialize)]
-struct CachedIp {
+pub struct CachedIp {
     pub last_updated: SystemTime,
     pub info: GeoInfo,
+    /// Name of the provider which resolved the location
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// Part of the answering providers which agree on the location (only if answers of all
+    /// providers are merged)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f64>,
+    /// Last time the entry was used (tracked only if the cache size is limited)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used: Option<SystemTime>,
+    /// Previously resolved locations (oldest first)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<LocationRecord>,
+}
+
+/// Location resolved for the IP at some point in time.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LocationRecord {
+    pub seen_at: SystemTime,
+    pub info: GeoInfo,
 }
 
 #[derive(Default, Clone, Serialize, Deserialize)]
@@ -30,101 +74,730 @@ struct GeoCache {
     pub entries: HashMap<IpAddr, CachedIp>,
 }
 
+/// Storage used to persist cache entries between runs.
+pub trait GeoCacheStorage: Send + Sync {
+    /// Load all entries from the storage.
+    fn load(&self) -> Result<HashMap<IpAddr, CachedIp>, io::Error>;
+    /// Persist all entries to the storage.
+    fn save(&self, entries: &HashMap<IpAddr, CachedIp>) -> Result<(), io::Error>;
+    /// Persist a single new or updated entry. Storages which can't be updated incrementally
+    /// persist entries only on `save()`.
+    fn store(&self, _ip: IpAddr, _entry: &CachedIp) -> Result<(), io::Error> {
+        Ok(())
+    }
+    /// Remove a single entry from the storage.
+    fn remove(&self, _ip: IpAddr) -> Result<(), io::Error> {
+        Ok(())
+    }
+}
+
+/// Storage keeping the cache in a JSON file.
+pub struct JsonFileStorage {
+    /// Path to the cache file.
+    path: PathBuf,
+}
+
+impl JsonFileStorage {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl GeoCacheStorage for JsonFileStorage {
+    fn load(&self) -> Result<HashMap<IpAddr, CachedIp>, io::Error> {
+        let cache_string = fs::read_to_string(&self.path)?;
+        serde_json::from_str(&cache_string)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn save(&self, entries: &HashMap<IpAddr, CachedIp>) -> Result<(), io::Error> {
+        let cache_string = serde_json::to_string(entries).unwrap();
+        atomic_file::write(&self.path, cache_string)
+    }
+}
+
+/// Storage keeping the cache in a SQLite database. Each entry is written as soon as it's resolved,
+/// so the cache survives crashes and large caches are not rewritten on every save.
+#[cfg(feature = "sqlite")]
+pub struct SqliteStorage {
+    connection: Mutex<rusqlite::Connection>,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStorage {
+    /// Open (or create) the cache database.
+    pub fn open(path: &Path) -> Result<Self, io::Error> {
+        let connection = rusqlite::Connection::open(path).map_err(sqlite_error)?;
+        connection
+            .execute(
+                "CREATE TABLE IF NOT EXISTS geo_cache (ip TEXT PRIMARY KEY, entry TEXT NOT NULL)",
+                [],
+            )
+            .map_err(sqlite_error)?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+#[cfg(feature = "sqlite")]
+impl GeoCacheStorage for SqliteStorage {
+    fn load(&self) -> Result<HashMap<IpAddr, CachedIp>, io::Error> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare("SELECT ip, entry FROM geo_cache")
+            .map_err(sqlite_error)?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .map_err(sqlite_error)?;
+
+        let mut entries = HashMap::new();
+        for row in rows {
+            let (ip, entry) = row.map_err(sqlite_error)?;
+            let ip = ip
+                .parse()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let entry = serde_json::from_str(&entry)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            entries.insert(ip, entry);
+        }
+        Ok(entries)
+    }
+
+    /// Entries are already persisted by `store()` - nothing to do.
+    fn save(&self, _entries: &HashMap<IpAddr, CachedIp>) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    fn store(&self, ip: IpAddr, entry: &CachedIp) -> Result<(), io::Error> {
+        let entry = serde_json::to_string(entry).unwrap();
+        self.connection
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT OR REPLACE INTO geo_cache (ip, entry) VALUES (?1, ?2)",
+                (ip.to_string(), entry),
+            )
+            .map_err(sqlite_error)?;
+        Ok(())
+    }
+
+    fn remove(&self, ip: IpAddr) -> Result<(), io::Error> {
+        self.connection
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM geo_cache WHERE ip = ?1", (ip.to_string(),))
+            .map_err(sqlite_error)?;
+        Ok(())
+    }
+}
+
+/// Storage that doesn't persist anything - the cache lives only as long as the process.
+#[derive(Default)]
+pub struct MemoryStorage;
+
+impl GeoCacheStorage for MemoryStorage {
+    fn load(&self) -> Result<HashMap<IpAddr, CachedIp>, io::Error> {
+        Ok(HashMap::new())
+    }
+
+    fn save(&self, _entries: &HashMap<IpAddr, CachedIp>) -> Result<(), io::Error> {
+        Ok(())
+    }
+}
+
+/// Counters describing how lookups were resolved.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct GeoIPStatistics {
+    /// Number of all lookups
+    pub lookups: usize,
+    /// Number of lookups answered from the cache
+    pub cache_hits: usize,
+    /// Number of cache entries dropped because they were too old
+    pub expired_entries: usize,
+    /// Number of least recently used cache entries dropped to keep the cache size limit
+    #[serde(default)]
+    pub evicted_entries: usize,
+    /// Number of lookups answered by providers
+    pub provider_hits: usize,
+    /// Number of lookups no one could answer
+    pub misses: usize,
+    /// Providers disabled during the run because of repeated failures
+    pub disabled_providers: Vec<String>,
+}
+
+/// Parts of provider error messages which mean the provider has no location for the IP. Such
+/// answers show the provider works, so they aren't failures.
+const LOOKUP_MISS_MESSAGES: [&str; 4] = ["not found", "no data", "reserved range", "private range"];
+
+/// Check if the provider error only says the IP couldn't be located (as opposed to a transport
+/// or HTTP error).
+fn is_lookup_miss(error: &str) -> bool {
+    let error = error.to_lowercase();
+    LOOKUP_MISS_MESSAGES
+        .iter()
+        .any(|message| error.contains(message))
+}
+
+/// Tracks consecutive failures of a provider.
+#[derive(Default)]
+struct FailureTracker {
+    /// Number of lookups that failed in a row
+    consecutive_failures: AtomicUsize,
+    /// Set once the provider failed too many times in a row
+    disabled: AtomicBool,
+}
+
+impl FailureTracker {
+    fn is_disabled(&self) -> bool {
+        self.disabled.load(Ordering::Relaxed)
+    }
+
+    fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+    }
+
+    /// Record a failed lookup. Returns true if the provider has just been disabled (limit 0 means
+    /// the provider is never disabled).
+    fn record_failure(&self, limit: usize) -> bool {
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        limit > 0 && failures >= limit && !self.disabled.swap(true, Ordering::Relaxed)
+    }
+}
+
+/// Spreads requests evenly so that no more than the given number of requests per second is sent.
+struct RateLimiter {
+    /// Minimum time between two requests
+    interval: Duration,
+    /// Time when the next request can be sent
+    next_slot: AsyncMutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(requests_per_second: f64) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / requests_per_second),
+            next_slot: AsyncMutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait until the next request can be sent.
+    async fn acquire(&self) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().await;
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + self.interval;
+            slot
+        };
+        time::sleep_until(slot).await;
+    }
+}
+
+/// Settings of a single GeoIP provider.
+#[derive(Default, Clone)]
+pub struct ProviderSettings {
+    /// Providers with lower priority are asked first (providers with equal priority are asked in
+    /// the order they were added)
+    pub priority: u32,
+    /// Maximum number of requests per second sent to the provider (unlimited if not set)
+    pub requests_per_second: Option<f64>,
+    /// Number of days to keep entries resolved by the provider (cache default if not set)
+    pub keep_in_cache_days: Option<u16>,
+}
+
+/// GeoIP provider with its failure tracking.
+struct Provider {
+    /// Name used in warnings and statistics
+    name: String,
+    service: Box<dyn GeoIPService + Send + Sync>,
+    failures: FailureTracker,
+    /// Limits requests sent to the provider (if configured)
+    rate_limiter: Option<RateLimiter>,
+    /// Order in which providers are asked (lowest first)
+    priority: u32,
+    /// How many days to keep entries resolved by the provider
+    keep_in_cache_days: u16,
+}
+
 /// GeoIP cache responsible for getting and caching results.
 pub struct GeoIPCache {
     /// Available providers and their configuration.
-    providers: Vec<Box<dyn GeoIPService>>,
-    /// Path to the cache file.
-    cache_file: PathBuf,
+    providers: Vec<Provider>,
+    /// Storage where cache entries are persisted.
+    storage: Box<dyn GeoCacheStorage>,
     /// Cache entries.
     cache: Arc<RwLock<GeoCache>>,
-    /// How many days to keep the cache entries.
+    /// How many days to keep the cache entries of providers without their own setting.
     keep_in_cache_days: u16,
+    /// How many previous locations to keep for each IP (0 disables the history).
+    location_history_size: usize,
+    /// Maximum number of entries kept in the cache (0 means unlimited).
+    max_cache_entries: usize,
+    /// Number of new entries after which the cache is saved (0 disables it).
+    autosave_entries: usize,
+    /// Time after which the cache is saved if there are new entries.
+    autosave_interval: Option<Duration>,
+    /// Number of entries added since the last save.
+    unsaved_entries: AtomicUsize,
+    /// Time of the last save.
+    last_save: Mutex<Instant>,
+    /// Number of lookups running at the same time in `lookup_all()`.
+    lookup_concurrency: usize,
+    /// Whether the first answer is taken or answers of all providers are merged.
+    lookup_mode: GeoLookupMode,
+    /// Number of consecutive failures after which a provider is disabled (0 never disables).
+    max_consecutive_failures: usize,
+    /// IPs which changed their location since the previous lookup.
+    relocated: Mutex<HashSet<IpAddr>>,
+    /// IP to ASN database (if configured).
+    asn_db: Option<AsnDatabase>,
+    /// IP classification database (empty if not configured).
+    ip_class_db: IpClassDatabase,
+    /// Lookup counters.
+    statistics: Mutex<GeoIPStatistics>,
 }
 
 impl GeoIPCache {
-    /// Create a new GeoIP cache.
-    pub fn new(config: &GeoIPConfiguration) -> Self {
+    /// Create a new GeoIP cache stored in the file from configuration using the configured
+    /// backend. Fails if the storage can't be opened.
+    pub fn new(config: &GeoIPConfiguration) -> Result<Self, io::Error> {
+        let storage: Box<dyn GeoCacheStorage> = match config.geocache_backend {
+            GeoCacheBackend::Json => {
+                Box::new(JsonFileStorage::new(config.geocache_file_path.clone()))
+            }
+            #[cfg(feature = "sqlite")]
+            GeoCacheBackend::Sqlite => Box::new(SqliteStorage::open(&config.geocache_file_path)?),
+        };
+        Ok(Self::with_storage(config, storage))
+    }
+
+    /// Create a new GeoIP cache using the given storage.
+    pub fn with_storage(config: &GeoIPConfiguration, storage: Box<dyn GeoCacheStorage>) -> Self {
         Self {
             providers: Vec::new(),
-            cache_file: config.geocache_file_path.clone(),
+            storage,
             cache: Arc::new(RwLock::new(GeoCache::default())),
             keep_in_cache_days: config
                 .keep_in_cache_days
                 .unwrap_or(DEFAULT_KEEP_IN_CACHE_DAYS),
+            location_history_size: config.location_history_size.unwrap_or_default(),
+            max_cache_entries: config.max_cache_entries.unwrap_or_default(),
+            autosave_entries: config.autosave_entries.unwrap_or_default(),
+            autosave_interval: config
+                .autosave_interval_secs
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            unsaved_entries: AtomicUsize::new(0),
+            last_save: Mutex::new(Instant::now()),
+            lookup_concurrency: config
+                .lookup_concurrency
+                .unwrap_or(DEFAULT_LOOKUP_CONCURRENCY)
+                .max(1),
+            lookup_mode: config.lookup_mode.clone(),
+            max_consecutive_failures: config
+                .max_consecutive_failures
+                .unwrap_or(DEFAULT_MAX_CONSECUTIVE_FAILURES),
+            relocated: Mutex::new(HashSet::new()),
+            asn_db: config
+                .asn_db_path
+                .as_ref()
+                .and_then(|path| match AsnDatabase::load(path) {
+                    Ok(asn_db) => Some(asn_db),
+                    Err(e) => {
+                        eprintln!(
+                            "WARNING: Could not load ASN database {}: {e}",
+                            path.display()
+                        );
+                        None
+                    }
+                }),
+            ip_class_db: load_ip_class_database(config),
+            statistics: Mutex::new(GeoIPStatistics::default()),
         }
     }
 
-    /// Add a new provider to the list of providers. The providers will be called in the order they
-    /// are added.
-    pub fn add_provider(&mut self, provider: Box<dyn GeoIPService>) {
-        self.providers.push(provider);
+    /// Add a new provider to the list of providers. The providers will be called in the order of
+    /// their priority, and in the order they are added if priorities are equal.
+    pub fn add_provider(
+        &mut self,
+        name: &str,
+        provider: Box<dyn GeoIPService + Send + Sync>,
+        settings: ProviderSettings,
+    ) {
+        let position = self
+            .providers
+            .partition_point(|p| p.priority <= settings.priority);
+        self.providers.insert(
+            position,
+            Provider {
+                name: name.to_owned(),
+                service: provider,
+                failures: FailureTracker::default(),
+                rate_limiter: settings
+                    .requests_per_second
+                    .filter(|rps| *rps > 0.0)
+                    .map(RateLimiter::new),
+                priority: settings.priority,
+                keep_in_cache_days: settings
+                    .keep_in_cache_days
+                    .unwrap_or(self.keep_in_cache_days),
+            },
+        );
+    }
+
+    /// How many days to keep an entry resolved by the given provider. Entries of unknown (e.g.
+    /// no longer configured) providers are kept for the cache default.
+    fn keep_in_cache_days(&self, provider: Option<&str>) -> u16 {
+        provider
+            .and_then(|name| self.providers.iter().find(|p| p.name == name))
+            .map_or(self.keep_in_cache_days, |p| p.keep_in_cache_days)
     }
 
-    /// Load the cache from the file.
+    /// Load the cache from the storage.
     pub async fn load(&self) -> Result<(), io::Error> {
-        let cache_string = fs::read_to_string(&self.cache_file)?;
+        let entries = self.storage.load()?;
 
         let mut cache = self.cache.write().await;
-        cache.entries = serde_json::from_str(&cache_string).unwrap();
+        cache.entries = entries;
         Ok(())
     }
 
-    /// Save the cache to the file.
+    /// Save the cache to the storage. Least recently used entries above the cache size limit are
+    /// evicted first.
     pub async fn save(&self) -> Result<(), io::Error> {
-        let cache = self.cache.read().await;
-        let cache_string = serde_json::to_string(&cache.entries).unwrap();
-        fs::write(&self.cache_file, cache_string)
+        let mut cache = self.cache.write().await;
+        self.save_entries(&mut cache.entries)
+    }
+
+    /// All entries of the cache.
+    pub async fn entries(&self) -> HashMap<IpAddr, CachedIp> {
+        self.cache.read().await.entries.clone()
+    }
+
+    /// Merge entries (e.g. of a cache built on another machine) into the cache and save it. The
+    /// newest entry of each IP is kept, while entries already expired by the TTL of their provider
+    /// are left out. Replaced entries keep their location history and usage time.
+    pub async fn merge(
+        &self,
+        entries: HashMap<IpAddr, CachedIp>,
+    ) -> Result<MergeSummary, io::Error> {
+        let now = SystemTime::now();
+        let ips = entries.keys().copied().collect::<Vec<IpAddr>>();
+        let mut cache = self.cache.write().await;
+        let summary = merge_entries(
+            &mut cache.entries,
+            entries,
+            |entry| {
+                expired_at(
+                    entry,
+                    self.keep_in_cache_days(entry.provider.as_deref()),
+                    now,
+                )
+            },
+            |ip, previous, current| self.update_history(ip, previous, current),
+        );
+        for ip in ips {
+            if let Some(entry) = cache.entries.get(&ip) {
+                self.storage.store(ip, entry)?;
+            }
+        }
+        self.save_entries(&mut cache.entries)?;
+        Ok(summary)
+    }
+
+    /// Evict entries above the size limit and persist the rest.
+    fn save_entries(&self, entries: &mut HashMap<IpAddr, CachedIp>) -> Result<(), io::Error> {
+        self.evict_least_recently_used(entries);
+        self.unsaved_entries.store(0, Ordering::Relaxed);
+        *self.last_save.lock().unwrap() = Instant::now();
+        self.storage.save(entries)
+    }
+
+    /// Autosave is enabled if any of its triggers is configured.
+    fn autosave_enabled(&self) -> bool {
+        self.autosave_entries > 0 || self.autosave_interval.is_some()
+    }
+
+    /// Count a new entry and save the cache if enough entries were added or enough time passed
+    /// since the last save, so freshly fetched locations are not lost if the run crashes.
+    async fn autosave(&self) {
+        let unsaved = self.unsaved_entries.fetch_add(1, Ordering::Relaxed) + 1;
+        let entries_due = self.autosave_entries > 0 && unsaved >= self.autosave_entries;
+        let interval_due = self
+            .autosave_interval
+            .is_some_and(|interval| self.last_save.lock().unwrap().elapsed() >= interval);
+        if !entries_due && !interval_due {
+            return;
+        }
+
+        if let Err(e) = self.save().await {
+            eprintln!("WARNING: Could not autosave the GeoIP cache: {e}");
+        }
+    }
+
+    /// Remove the least recently used entries so that no more than `max_cache_entries` are kept.
+    /// Entries never used since they were resolved count as used at the time of resolving.
+    fn evict_least_recently_used(&self, entries: &mut HashMap<IpAddr, CachedIp>) {
+        let excess = match self.max_cache_entries {
+            0 => 0,
+            max => entries.len().saturating_sub(max),
+        };
+        if excess == 0 {
+            return;
+        }
+
+        let mut by_last_use = entries
+            .iter()
+            .map(|(ip, entry)| (entry.last_used.unwrap_or(entry.last_updated), *ip))
+            .collect::<Vec<_>>();
+        by_last_use.sort_unstable();
+
+        for (_, ip) in by_last_use.into_iter().take(excess) {
+            entries.remove(&ip);
+            if let Err(e) = self.storage.remove(ip) {
+                eprintln!("Could not remove cache entry for {ip}: {e}");
+            }
+        }
+        self.statistics.lock().unwrap().evicted_entries += excess;
+    }
+
+    /// Mark the entry as used now, so it's not evicted before entries unused for a longer time.
+    async fn touch(&self, ip: IpAddr) {
+        let mut rw_cache = self.cache.write().await;
+        if let Some(entry) = rw_cache.entries.get_mut(&ip) {
+            entry.last_used = Some(SystemTime::now());
+            if let Err(e) = self.storage.store(ip, entry) {
+                eprintln!("Could not store cache entry for {ip}: {e}");
+            }
+        }
     }
 
     /// Function look in cache and if not found, it will call the providers to fetch new data and
     /// store it into cache.
     pub async fn lookup(&self, ip: IpAddr) -> Option<GeoInfo> {
+        self.statistics.lock().unwrap().lookups += 1;
+
         if let Some(info) = self.check_cache(ip).await {
+            self.statistics.lock().unwrap().cache_hits += 1;
             return Some(info);
         }
 
+        let resolved = match self.lookup_mode {
+            GeoLookupMode::First => self.lookup_first(ip).await,
+            GeoLookupMode::Merge => self.lookup_merged(ip).await,
+        };
+
+        if let Some((provider, info, confidence)) = resolved {
+            let mut rw_cache = self.cache.write().await;
+            let history = match rw_cache.entries.remove(&ip) {
+                Some(previous) => self.update_history(ip, previous, &info),
+                None => Vec::new(),
+            };
+            let cache_entry = CachedIp {
+                last_updated: SystemTime::now(),
+                info,
+                provider: Some(provider),
+                confidence,
+                last_used: None,
+                history,
+            };
+            if let Err(e) = self.storage.store(ip, &cache_entry) {
+                eprintln!("Could not store cache entry for {ip}: {e}");
+            }
+            rw_cache.entries.insert(ip, cache_entry.clone());
+            drop(rw_cache);
+            self.statistics.lock().unwrap().provider_hits += 1;
+            if self.autosave_enabled() {
+                self.autosave().await;
+            }
+            return Some(cache_entry.info);
+        }
+
+        self.statistics.lock().unwrap().misses += 1;
+        None
+    }
+
+    /// Ask providers in the order of their priority and take the first answer. Returns the name
+    /// of the provider which answered.
+    async fn lookup_first(&self, ip: IpAddr) -> Option<(String, GeoInfo, Option<f64>)> {
+        for provider in self.providers.iter() {
+            if let Some(info) = self.ask_provider(provider, ip).await {
+                return Some((provider.name.clone(), info, None));
+            }
+        }
+        None
+    }
+
+    /// Ask all providers and merge their answers. Returns the name of the provider whose location
+    /// was taken and the confidence of the merged location.
+    async fn lookup_merged(&self, ip: IpAddr) -> Option<(String, GeoInfo, Option<f64>)> {
+        let mut answers = Vec::new();
         for provider in self.providers.iter() {
-            let entry = provider.lookup(ip).await;
-            if let Ok(ip_geo_info) = entry {
-                let mut rw_cache = self.cache.write().await;
-                let cache_entry = CachedIp {
-                    last_updated: SystemTime::now(),
-                    info: ip_geo_info.geo_info,
-                };
-                rw_cache.entries.insert(ip, cache_entry.clone());
-                return Some(cache_entry.info);
+            if let Some(info) = self.ask_provider(provider, ip).await {
+                answers.push((provider.name.clone(), info));
+            }
+        }
+
+        merge_locations(answers)
+            .map(|(provider, info, confidence)| (provider, info, Some(confidence)))
+    }
+
+    /// Ask a single provider, keeping track of its failures - only errors other than a missing
+    /// location count. Disabled providers are not asked.
+    async fn ask_provider(&self, provider: &Provider, ip: IpAddr) -> Option<GeoInfo> {
+        // Don't pay for lookups that keep failing
+        if provider.failures.is_disabled() {
+            return None;
+        }
+
+        if let Some(rate_limiter) = &provider.rate_limiter {
+            rate_limiter.acquire().await;
+        }
+
+        match provider.service.lookup(ip).await {
+            Ok(ip_geo_info) => {
+                provider.failures.record_success();
+                return Some(ip_geo_info.geo_info);
             }
+            Err(e) if is_lookup_miss(&e.to_string()) => {
+                provider.failures.record_success();
+                return None;
+            }
+            Err(_) => (),
         }
 
+        if provider
+            .failures
+            .record_failure(self.max_consecutive_failures)
+        {
+            eprintln!(
+                "WARNING: GeoIP provider {} failed {} times in a row and is disabled for the rest of the run",
+                provider.name, self.max_consecutive_failures
+            );
+            self.statistics
+                .lock()
+                .unwrap()
+                .disabled_providers
+                .push(provider.name.clone());
+        }
         None
     }
 
+    /// Look up all given IPs running up to `lookup_concurrency` lookups at the same time. Returns
+    /// locations of all resolved IPs.
+    pub async fn lookup_all(&self, ips: &[IpAddr]) -> HashMap<IpAddr, GeoInfo> {
+        // Each IP is looked up only once even if there are more nodes using it
+        let unique_ips = ips.iter().copied().collect::<HashSet<IpAddr>>();
+
+        stream::iter(unique_ips)
+            .map(|ip| async move { (ip, self.lookup(ip).await) })
+            .buffer_unordered(self.lookup_concurrency)
+            .filter_map(|(ip, info)| async move { info.map(|info| (ip, info)) })
+            .collect()
+            .await
+    }
+
+    /// Check if the IP changed its location since the previous lookup.
+    pub fn location_changed(&self, ip: IpAddr) -> bool {
+        self.relocated.lock().unwrap().contains(&ip)
+    }
+
+    /// Find the autonomous system announcing the given IP (if the ASN database is configured).
+    pub fn asn(&self, ip: IpAddr) -> Option<u32> {
+        self.asn_db.as_ref()?.lookup(ip)
+    }
+
+    /// Classes of the given IP - hosting, VPN or Tor (none if no classification is configured).
+    pub fn classify(&self, ip: IpAddr) -> IpClasses {
+        self.ip_class_db.classify(ip)
+    }
+
+    /// Move the previous entry to the location history and detect if the node was relocated.
+    pub fn update_history(
+        &self,
+        ip: IpAddr,
+        previous: CachedIp,
+        current: &GeoInfo,
+    ) -> Vec<LocationRecord> {
+        if self.location_history_size == 0 {
+            return Vec::new();
+        }
+
+        if let (Some(previous_location), Some(current_location)) =
+            (previous.info.coordinates, current.coordinates)
+        {
+            if previous_location.distance_to(current_location) > RELOCATION_DISTANCE_M {
+                self.relocated.lock().unwrap().insert(ip);
+            }
+        }
+
+        let mut history = previous.history;
+        history.push(LocationRecord {
+            seen_at: previous.last_updated,
+            info: previous.info,
+        });
+        let excess = history.len().saturating_sub(self.location_history_size);
+        history.drain(..excess);
+        history
+    }
+
+    /// Get lookup counters collected so far.
+    pub fn statistics(&self) -> GeoIPStatistics {
+        self.statistics.lock().unwrap().clone()
+    }
+
     async fn check_cache(&self, ip: IpAddr) -> Option<GeoInfo> {
         let mut remove_entry = false;
+        let mut hit = None;
         {
             let cache = self.cache.read().await;
             let res = cache.entries.get(&ip);
             if let Some(entry) = res {
                 // Check if the entry is not too old.
+                let keep_in_cache_days = self.keep_in_cache_days(entry.provider.as_deref());
                 if entry.last_updated.elapsed().unwrap()
-                    < Duration::from_secs(60 * 60 * 24 * self.keep_in_cache_days as u64)
+                    < Duration::from_secs(60 * 60 * 24 * keep_in_cache_days as u64)
                 {
-                    return Some(entry.info.clone());
+                    hit = Some(entry.info.clone());
+                } else {
+                    remove_entry = true;
                 }
-                remove_entry = true;
             }
         }
 
+        if hit.is_some() {
+            // Usage matters only for eviction
+            if self.max_cache_entries > 0 {
+                self.touch(ip).await;
+            }
+            return hit;
+        }
+
         if remove_entry {
-            let mut rw_cache = self.cache.write().await;
-            rw_cache.entries.remove(&ip);
+            // Expired entries are kept if the history is enabled - they will be moved to the
+            // history once the new location is resolved.
+            if self.location_history_size == 0 {
+                let mut rw_cache = self.cache.write().await;
+                rw_cache.entries.remove(&ip);
+                if let Err(e) = self.storage.remove(ip) {
+                    eprintln!("Could not remove cache entry for {ip}: {e}");
+                }
+            }
+            self.statistics.lock().unwrap().expired_entries += 1;
         }
 
         None
     }
 
-    /// Configure the providers based on the configuration.
+    /// Configure the providers based on the configuration. Online providers are skipped in the
+    /// offline mode.
     pub fn configure_providers(&mut self, config: &GeoIPConfiguration) {
         if config.ip2location_enable {
             let ipv6db = config
@@ -132,29 +805,452 @@ impl GeoIPCache {
                 .as_ref()
                 .map(|path| path.as_path().display().to_string());
 
-            self.add_provider(Box::new(Ip2LocationService::new(
-                config
-                    .ip2location_db_path
-                    .as_ref()
-                    .unwrap()
-                    .to_str()
-                    .unwrap(),
-                ipv6db,
-            )));
+            self.add_provider(
+                "ip2location",
+                Box::new(Ip2LocationService::new(
+                    config
+                        .ip2location_db_path
+                        .as_ref()
+                        .unwrap()
+                        .to_str()
+                        .unwrap(),
+                    ipv6db,
+                )),
+                ProviderSettings {
+                    priority: config.ip2location_priority.unwrap_or(0),
+                    requests_per_second: None,
+                    keep_in_cache_days: config.ip2location_keep_in_cache_days,
+                },
+            );
+        }
+
+        if config.ipapico_enable && !config.offline {
+            self.add_provider(
+                "ipapi.co",
+                Box::new(IpGeolocateService::new(
+                    BackendProvider::IpApiCo,
+                    config.ipapico_api_key.as_ref().unwrap().as_str(),
+                )),
+                ProviderSettings {
+                    priority: config.ipapico_priority.unwrap_or(1),
+                    requests_per_second: config.ipapico_requests_per_second,
+                    keep_in_cache_days: config.ipapico_keep_in_cache_days,
+                },
+            );
         }
 
-        if config.ipapico_enable {
-            self.add_provider(Box::new(IpGeolocateService::new(
-                BackendProvider::IpApiCo,
-                config.ipapico_api_key.as_ref().unwrap().as_str(),
-            )));
+        if config.ipapicom_enable && !config.offline {
+            self.add_provider(
+                "ipapi.com",
+                Box::new(IpGeolocateService::new(
+                    BackendProvider::IpApiCom,
+                    config.ipapicom_api_key.as_ref().unwrap().as_str(),
+                )),
+                ProviderSettings {
+                    priority: config.ipapicom_priority.unwrap_or(2),
+                    requests_per_second: config.ipapicom_requests_per_second,
+                    keep_in_cache_days: config.ipapicom_keep_in_cache_days,
+                },
+            );
         }
+    }
+}
 
-        if config.ipapicom_enable {
-            self.add_provider(Box::new(IpGeolocateService::new(
-                BackendProvider::IpApiCom,
-                config.ipapicom_api_key.as_ref().unwrap().as_str(),
-            )));
+/// Load the configured IP classification sources. Sources which can't be loaded are skipped
+/// with a warning, like the ASN database.
+fn load_ip_class_database(config: &GeoIPConfiguration) -> IpClassDatabase {
+    let mut database = IpClassDatabase::default();
+    let warn = |path: &Path, e: io::Error| {
+        eprintln!(
+            "WARNING: Could not load IP classification {}: {e}",
+            path.display()
+        )
+    };
+    if let Some(path) = &config.proxy_db_path {
+        if let Err(e) = database.load_px(path) {
+            warn(path, e);
+        }
+    }
+    for (paths, class) in [
+        (&config.tor_list_paths, IpClass::Tor),
+        (&config.vpn_list_paths, IpClass::Vpn),
+        (&config.hosting_list_paths, IpClass::Hosting),
+    ] {
+        for path in paths.iter() {
+            if let Err(e) = database.load_list(path, class) {
+                warn(path, e);
+            }
         }
     }
+    database
+}
+
+/// Merge locations returned by providers (in the order of their priority). The location agreeing
+/// with most of the other answers is taken, with coordinates averaged over the agreeing answers
+/// and missing fields filled in from them. Returns the name of the provider whose location was
+/// taken and the confidence - part of the answers agreeing with the merged location.
+fn merge_locations(answers: Vec<(String, GeoInfo)>) -> Option<(String, GeoInfo, f64)> {
+    let agreeing = |info: &GeoInfo| -> Vec<&GeoInfo> {
+        answers
+            .iter()
+            .map(|(_, other)| other)
+            .filter(|other| match (info.coordinates, other.coordinates) {
+                (Some(a), Some(b)) => a.distance_to(b) <= AGREEMENT_DISTANCE_M,
+                _ => false,
+            })
+            .collect()
+    };
+
+    // The first of the answers with most agreement wins
+    let (best, _) =
+        answers
+            .iter()
+            .enumerate()
+            .fold(
+                (0, 0),
+                |(best, best_count), (idx, (_, info))| match agreeing(info).len() {
+                    count if count > best_count => (idx, count),
+                    _ => (best, best_count),
+                },
+            );
+    let (provider, info) = answers.get(best)?;
+    let agreeing = agreeing(info);
+
+    let mut merged = info.clone();
+    if let Some(mut coordinates) = info.coordinates {
+        let count = agreeing.len() as f64;
+        let coordinates_of = || agreeing.iter().filter_map(|other| other.coordinates);
+        coordinates.latitude = coordinates_of().map(|c| c.latitude).sum::<f64>() / count;
+        coordinates.longitude = coordinates_of().map(|c| c.longitude).sum::<f64>() / count;
+        merged.coordinates = Some(coordinates);
+    }
+    for other in agreeing.iter() {
+        merged.country = merged.country.or_else(|| other.country.clone());
+        merged.city = merged.city.or_else(|| other.city.clone());
+        merged.timezone = merged.timezone.or_else(|| other.timezone.clone());
+        merged.isp = merged.isp.or_else(|| other.isp.clone());
+    }
+
+    // An answer without coordinates agrees at least with itself
+    let confidence = agreeing.len().max(1) as f64 / answers.len() as f64;
+    Some((provider.clone(), merged, confidence))
+}
+
+impl Drop for GeoIPCache {
+    /// Save entries added since the last save if autosave is enabled.
+    fn drop(&mut self) {
+        if !self.autosave_enabled() || *self.unsaved_entries.get_mut() == 0 {
+            return;
+        }
+
+        if let Ok(mut cache) = self.cache.try_write() {
+            if let Err(e) = self.save_entries(&mut cache.entries) {
+                eprintln!("WARNING: Could not save the GeoIP cache: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::geo_info;
+
+    fn cached_ip(secs_since_epoch: u64, latitude: f64, longitude: f64) -> CachedIp {
+        CachedIp {
+            last_updated: SystemTime::UNIX_EPOCH + Duration::from_secs(secs_since_epoch),
+            info: geo_info(
+                Some("Germany"),
+                Some("Karlsruhe"),
+                Some((latitude, longitude)),
+            )
+            .unwrap(),
+            provider: None,
+            confidence: None,
+            last_used: None,
+            history: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn update_history_test() {
+        let config = GeoIPConfiguration {
+            location_history_size: Some(2),
+            ..Default::default()
+        };
+        let cache = GeoIPCache::with_storage(&config, Box::new(MemoryStorage));
+        let ip = "1.1.1.1".parse().unwrap();
+
+        // Small location difference doesn't mean relocation
+        let mut previous = cached_ip(1, 49.0, 8.38);
+        let current = cached_ip(2, 49.01, 8.39);
+        previous.history = cache.update_history(ip, previous.clone(), &current.info);
+        assert_eq!(previous.history.len(), 1);
+        assert!(!cache.location_changed(ip));
+
+        // Frankfurt to Singapore
+        let current = cached_ip(3, 1.35, 103.82);
+        previous.history = cache.update_history(ip, previous.clone(), &current.info);
+        previous.history = cache.update_history(ip, previous.clone(), &current.info);
+        assert_eq!(previous.history.len(), 2);
+        assert!(cache.location_changed(ip));
+    }
+
+    #[test]
+    fn failure_tracker_test() {
+        let tracker = FailureTracker::default();
+
+        // Success resets the counter
+        assert!(!tracker.record_failure(3));
+        assert!(!tracker.record_failure(3));
+        tracker.record_success();
+        assert!(!tracker.record_failure(3));
+        assert!(!tracker.record_failure(3));
+        assert!(!tracker.is_disabled());
+
+        // Provider is disabled only once
+        assert!(tracker.record_failure(3));
+        assert!(tracker.is_disabled());
+        assert!(!tracker.record_failure(3));
+
+        // Limit 0 never disables the provider
+        let tracker = FailureTracker::default();
+        for _ in 0..100 {
+            assert!(!tracker.record_failure(0));
+        }
+        assert!(!tracker.is_disabled());
+    }
+
+    #[test]
+    fn is_lookup_miss_test() {
+        assert!(is_lookup_miss("IP address Not Found"));
+        assert!(is_lookup_miss("reserved range"));
+        assert!(!is_lookup_miss("error sending request: connection refused"));
+        assert!(!is_lookup_miss("HTTP status 429"));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_storage_test() {
+        let storage = SqliteStorage::open(Path::new(":memory:")).unwrap();
+        let ip_a = "1.1.1.1".parse().unwrap();
+        let ip_b = "2001:db8::1".parse().unwrap();
+
+        storage.store(ip_a, &cached_ip(1, 49.0, 8.38)).unwrap();
+        storage.store(ip_b, &cached_ip(1, 1.35, 103.82)).unwrap();
+        storage.store(ip_a, &cached_ip(2, 49.01, 8.39)).unwrap();
+        let entries = storage.load().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[&ip_a].last_updated,
+            cached_ip(2, 49.01, 8.39).last_updated
+        );
+
+        storage.remove(ip_b).unwrap();
+        let entries = storage.load().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(entries.contains_key(&ip_a));
+    }
+
+    #[test]
+    fn provider_priority_test() {
+        let config = GeoIPConfiguration {
+            keep_in_cache_days: Some(14),
+            ..Default::default()
+        };
+        let mut cache = GeoIPCache::with_storage(&config, Box::new(MemoryStorage));
+        for (name, priority, keep_in_cache_days) in
+            [("a", 2, Some(90)), ("b", 1, None), ("c", 2, Some(7))]
+        {
+            cache.add_provider(
+                name,
+                Box::new(IpGeolocateService::new(BackendProvider::IpApiCo, "")),
+                ProviderSettings {
+                    priority,
+                    keep_in_cache_days,
+                    ..Default::default()
+                },
+            );
+        }
+
+        let names = cache
+            .providers
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["b", "a", "c"]);
+
+        assert_eq!(cache.keep_in_cache_days(Some("a")), 90);
+        assert_eq!(cache.keep_in_cache_days(Some("b")), 14);
+        assert_eq!(cache.keep_in_cache_days(Some("c")), 7);
+        assert_eq!(cache.keep_in_cache_days(Some("removed")), 14);
+        assert_eq!(cache.keep_in_cache_days(None), 14);
+    }
+
+    #[test]
+    fn configure_providers_offline_test() {
+        let mut config = GeoIPConfiguration {
+            ip2location_enable: false,
+            ..Default::default()
+        };
+        let mut cache = GeoIPCache::with_storage(&config, Box::new(MemoryStorage));
+        cache.configure_providers(&config);
+        assert_eq!(cache.providers.len(), 2);
+
+        config.offline = true;
+        let mut cache = GeoIPCache::with_storage(&config, Box::new(MemoryStorage));
+        cache.configure_providers(&config);
+        assert!(cache.providers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rate_limiter_test() {
+        let rate_limiter = RateLimiter::new(20.0);
+        let start = Instant::now();
+        for _ in 0..5 {
+            rate_limiter.acquire().await;
+        }
+        // First request is sent immediately, the other four wait 50 ms each
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn lookup_all_test() {
+        let cache =
+            GeoIPCache::with_storage(&GeoIPConfiguration::default(), Box::new(MemoryStorage));
+        let ip_a: IpAddr = "1.1.1.1".parse().unwrap();
+        let ip_b: IpAddr = "8.8.8.8".parse().unwrap();
+        cache.cache.write().await.entries.insert(
+            ip_a,
+            CachedIp {
+                last_updated: SystemTime::now(),
+                ..cached_ip(1, 49.0, 8.38)
+            },
+        );
+
+        // No providers - only the cached IP is resolved, duplicates are looked up once
+        let locations = cache.lookup_all(&[ip_a, ip_b, ip_a]).await;
+        assert_eq!(locations.len(), 1);
+        assert!(locations.contains_key(&ip_a));
+        let statistics = cache.statistics();
+        assert_eq!(statistics.lookups, 2);
+        assert_eq!(statistics.cache_hits, 1);
+        assert_eq!(statistics.misses, 1);
+    }
+
+    #[tokio::test]
+    async fn evict_least_recently_used_test() {
+        let config = GeoIPConfiguration {
+            max_cache_entries: Some(2),
+            ..Default::default()
+        };
+        let cache = GeoIPCache::with_storage(&config, Box::new(MemoryStorage));
+        let ips: Vec<IpAddr> = vec![
+            "1.1.1.1".parse().unwrap(),
+            "2.2.2.2".parse().unwrap(),
+            "3.3.3.3".parse().unwrap(),
+        ];
+        for (age, ip) in (1..=3).rev().zip(ips.iter()) {
+            cache.cache.write().await.entries.insert(
+                *ip,
+                CachedIp {
+                    last_updated: SystemTime::now() - Duration::from_secs(age),
+                    ..cached_ip(1, 49.0, 8.38)
+                },
+            );
+        }
+
+        // The oldest entry is used again, so the second one is evicted
+        assert!(cache.lookup(ips[0]).await.is_some());
+        assert!(cache.save().await.is_ok());
+        let entries = &cache.cache.read().await.entries;
+        assert_eq!(entries.len(), 2);
+        assert!(entries.contains_key(&ips[0]));
+        assert!(entries.contains_key(&ips[2]));
+        assert_eq!(cache.statistics().evicted_entries, 1);
+    }
+
+    #[test]
+    fn merge_locations_test() {
+        let location = |latitude, longitude| cached_ip(1, latitude, longitude).info;
+
+        // Frankfurt and Karlsruhe are far apart, but both agree with Mannheim
+        let answers = vec![
+            (String::from("a"), location(1.35, 103.82)),
+            (String::from("b"), location(50.11, 8.68)),
+            (String::from("c"), location(49.49, 8.47)),
+            (String::from("d"), location(49.0, 8.38)),
+        ];
+        let (provider, info, confidence) = merge_locations(answers).unwrap();
+        assert_eq!(provider, "c");
+        assert_eq!(confidence, 0.75);
+        let coordinates = info.coordinates.unwrap();
+        assert!((coordinates.latitude - 49.533).abs() < 0.001);
+
+        // Nobody agrees - the first answer is taken
+        let answers = vec![
+            (String::from("a"), location(1.35, 103.82)),
+            (String::from("b"), location(50.11, 8.68)),
+        ];
+        let (provider, _, confidence) = merge_locations(answers).unwrap();
+        assert_eq!(provider, "a");
+        assert_eq!(confidence, 0.5);
+
+        assert!(merge_locations(Vec::new()).is_none());
+    }
+
+    /// Storage counting how many times the cache was saved.
+    struct CountingStorage(Arc<AtomicUsize>);
+
+    impl GeoCacheStorage for CountingStorage {
+        fn load(&self) -> Result<HashMap<IpAddr, CachedIp>, io::Error> {
+            Ok(HashMap::new())
+        }
+
+        fn save(&self, _entries: &HashMap<IpAddr, CachedIp>) -> Result<(), io::Error> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn autosave_test() {
+        let config = GeoIPConfiguration {
+            autosave_entries: Some(3),
+            ..Default::default()
+        };
+        let saves = Arc::new(AtomicUsize::new(0));
+        let cache = GeoIPCache::with_storage(&config, Box::new(CountingStorage(saves.clone())));
+
+        cache.autosave().await;
+        cache.autosave().await;
+        assert_eq!(saves.load(Ordering::Relaxed), 0);
+        cache.autosave().await;
+        assert_eq!(saves.load(Ordering::Relaxed), 1);
+
+        // Entries added since the last save are saved on drop
+        cache.autosave().await;
+        drop(cache);
+        assert_eq!(saves.load(Ordering::Relaxed), 2);
+
+        // Nothing to save
+        let cache = GeoIPCache::with_storage(&config, Box::new(CountingStorage(saves.clone())));
+        drop(cache);
+        assert_eq!(saves.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn update_history_disabled_test() {
+        let config = GeoIPConfiguration {
+            location_history_size: None,
+            ..Default::default()
+        };
+        let cache = GeoIPCache::with_storage(&config, Box::new(MemoryStorage));
+        let ip = "1.1.1.1".parse().unwrap();
+
+        let previous = cached_ip(1, 49.0, 8.38);
+        let current = cached_ip(2, 1.35, 103.82);
+        assert!(cache.update_history(ip, previous, &current.info).is_empty());
+        assert!(!cache.location_changed(ip));
+    }
 }
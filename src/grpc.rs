@@ -0,0 +1,298 @@
+// gRPC interface streaming node data of the latest state and processing new crawler summaries.
+// Streaming avoids building one large JSON document for consumers that handle nodes one by one.
+
+use std::{net::SocketAddr, pin::Pin, sync::Arc};
+
+use futures::{stream, Stream};
+use tokio::sync::RwLock;
+use tonic::{transport::Server, Request, Response, Status};
+use ziggurat_core_crawler::summary::NetworkSummary;
+
+use crate::{
+    centrality_cache::CentralityCache,
+    config::CrunchyConfiguration,
+    create_state,
+    geoip_cache::GeoIPCache,
+    nodes::{HistogramSummary, Node},
+    search::SearchIndex,
+    CrunchyState,
+};
+
+/// Types generated from `proto/crunchy.proto`
+pub mod proto {
+    tonic::include_proto!("crunchy");
+}
+
+use proto::crunchy_server::{Crunchy, CrunchyServer};
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// gRPC service holding the latest state
+pub struct CrunchyService {
+    config: CrunchyConfiguration,
+    geo_cache: GeoIPCache,
+    /// Centralities of the last processed summary, so the same crawl sent again is not
+    /// recomputed
+    centrality_cache: CentralityCache,
+    /// Latest state - replaced as a whole, so running streams keep their snapshot
+    latest: RwLock<Arc<Snapshot>>,
+}
+
+/// State together with its search index, so both are always replaced at once
+struct Snapshot {
+    state: CrunchyState,
+    index: SearchIndex,
+}
+
+impl Snapshot {
+    fn new(state: CrunchyState) -> Self {
+        Self {
+            index: SearchIndex::new(&state),
+            state,
+        }
+    }
+}
+
+impl CrunchyService {
+    pub fn new(config: CrunchyConfiguration, geo_cache: GeoIPCache, state: CrunchyState) -> Self {
+        Self {
+            config,
+            geo_cache,
+            centrality_cache: CentralityCache::default(),
+            latest: RwLock::new(Arc::new(Snapshot::new(state))),
+        }
+    }
+}
+
+impl From<&Node> for proto::Node {
+    fn from(node: &Node) -> Self {
+        Self {
+            addr: node.addr.to_string(),
+            network_type: format!("{:?}", node.network_type),
+            betweenness: node.betweenness,
+            closeness: node.closeness,
+            connections: node.connections.iter().map(|c| *c as u64).collect(),
+            geolocation: node.geolocation.as_ref().map(|geo| proto::GeoLocation {
+                country: geo.country.clone(),
+                city: geo.city.clone(),
+                latitude: geo.coordinates.map(|c| c.latitude),
+                longitude: geo.coordinates.map(|c| c.longitude),
+                timezone: geo.timezone.clone(),
+                isp: geo.isp.clone(),
+            }),
+            eccentricity: node.eccentricity,
+            location_changed: node.location_changed,
+            asn: node.asn,
+            uptime: node.uptime,
+            stability: node.stability,
+            eigenvector: node.eigenvector,
+            degree: node.degree as u64,
+            protocol_version: node.protocol_version,
+            user_agent: node.user_agent.clone(),
+            geo_cluster_id: node.geo_cluster_id.map(|id| id as u64),
+            betweenness_normalized: node.betweenness_normalized,
+            closeness_normalized: node.closeness_normalized,
+            is_hosting: node.is_hosting,
+            is_tor: node.is_tor,
+            is_vpn: node.is_vpn,
+        }
+    }
+}
+
+impl From<&HistogramSummary> for proto::Histogram {
+    fn from(histogram: &HistogramSummary) -> Self {
+        Self {
+            label: histogram.label.clone(),
+            counts: histogram.counts.iter().map(|c| *c as u64).collect(),
+            max_count: histogram.max_count as u64,
+            median: histogram.median,
+            min: histogram.min,
+            max: histogram.max,
+            scale: format!("{:?}", histogram.scale).to_lowercase(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Crunchy for CrunchyService {
+    type StreamNodesStream = ResponseStream<proto::Node>;
+    type StreamHistogramsStream = ResponseStream<proto::Histogram>;
+    type SearchStream = ResponseStream<proto::Node>;
+
+    async fn stream_nodes(
+        &self,
+        _request: Request<proto::StateRequest>,
+    ) -> Result<Response<Self::StreamNodesStream>, Status> {
+        let snapshot = self.latest.read().await.clone();
+        let nodes = (0..snapshot.state.nodes.len())
+            .map(move |idx| proto::Node::from(&snapshot.state.nodes[idx]));
+        Ok(Response::new(Box::pin(stream::iter(nodes.map(Ok)))))
+    }
+
+    async fn search(
+        &self,
+        request: Request<proto::SearchRequest>,
+    ) -> Result<Response<Self::SearchStream>, Status> {
+        let snapshot = self.latest.read().await.clone();
+        let indices = snapshot
+            .index
+            .search(&snapshot.state, &request.get_ref().query)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let nodes = indices
+            .into_iter()
+            .map(move |idx| proto::Node::from(&snapshot.state.nodes[idx]));
+        Ok(Response::new(Box::pin(stream::iter(nodes.map(Ok)))))
+    }
+
+    async fn stream_histograms(
+        &self,
+        _request: Request<proto::StateRequest>,
+    ) -> Result<Response<Self::StreamHistogramsStream>, Status> {
+        let snapshot = self.latest.read().await.clone();
+        let histograms = (0..snapshot.state.histograms.len())
+            .map(move |idx| proto::Histogram::from(&snapshot.state.histograms[idx]));
+        Ok(Response::new(Box::pin(stream::iter(histograms.map(Ok)))))
+    }
+
+    async fn process_summary(
+        &self,
+        request: Request<proto::SummaryRequest>,
+    ) -> Result<Response<proto::SummaryReply>, Status> {
+        let summary: NetworkSummary = serde_json::from_str(&request.get_ref().summary_json)
+            .map_err(|e| Status::invalid_argument(format!("invalid network summary: {e}")))?;
+
+        let state = create_state(
+            &summary,
+            &self.config,
+            &self.geo_cache,
+            &self.centrality_cache,
+        )
+        .await;
+        if let Err(e) = self.geo_cache.save().await {
+            eprintln!("Could not save cache file: {e}");
+        }
+
+        let reply = proto::SummaryReply {
+            nodes_count: state.nodes.len() as u64,
+            elapsed: state.elapsed,
+        };
+        *self.latest.write().await = Arc::new(Snapshot::new(state));
+        Ok(Response::new(reply))
+    }
+}
+
+/// Serve the gRPC interface on the given address, starting with the given state.
+pub async fn serve(
+    addr: SocketAddr,
+    config: CrunchyConfiguration,
+    geo_cache: GeoIPCache,
+    state: CrunchyState,
+) -> Result<(), tonic::transport::Error> {
+    Server::builder()
+        .add_service(CrunchyServer::new(CrunchyService::new(
+            config, geo_cache, state,
+        )))
+        .serve(addr)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::geoip_cache::MemoryStorage;
+
+    fn service() -> CrunchyService {
+        let config = CrunchyConfiguration::default();
+        let geo_cache = GeoIPCache::with_storage(&config.geoip_config, Box::new(MemoryStorage));
+        let state = CrunchyState {
+            nodes: vec![
+                Node {
+                    connections: vec![1],
+                    eccentricity: Some(1),
+                    ..Default::default()
+                },
+                Node {
+                    connections: vec![0],
+                    ..Default::default()
+                },
+            ],
+            histograms: vec![HistogramSummary {
+                label: String::from("degree"),
+                counts: vec![2],
+                max_count: 2,
+                median: 1.0,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        CrunchyService::new(config, geo_cache, state)
+    }
+
+    #[tokio::test]
+    async fn stream_nodes_test() {
+        let service = service();
+
+        let nodes = service
+            .stream_nodes(Request::new(proto::StateRequest {}))
+            .await
+            .unwrap()
+            .into_inner()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(nodes.len(), 2);
+        let node = nodes[0].as_ref().unwrap();
+        assert_eq!(node.addr, "0.0.0.0:0");
+        assert_eq!(node.connections, vec![1]);
+        assert_eq!(node.eccentricity, Some(1));
+
+        let histograms = service
+            .stream_histograms(Request::new(proto::StateRequest {}))
+            .await
+            .unwrap()
+            .into_inner()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(histograms.len(), 1);
+        assert_eq!(histograms[0].as_ref().unwrap().label, "degree");
+    }
+
+    #[tokio::test]
+    async fn search_test() {
+        let service = service();
+
+        let nodes = service
+            .search(Request::new(proto::SearchRequest {
+                query: String::from("eccentricity:1"),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].as_ref().unwrap().eccentricity, Some(1));
+
+        let status = service
+            .search(Request::new(proto::SearchRequest {
+                query: String::from("color:red"),
+            }))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn process_invalid_summary_test() {
+        let service = service();
+        let status = service
+            .process_summary(Request::new(proto::SummaryRequest {
+                summary_json: String::from("{"),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+}
@@ -0,0 +1,118 @@
+// Betweenness heat evolution across recent runs. A single state shows which nodes are hot now,
+// but nodes whose betweenness keeps rising run after run are becoming hot - they can be found
+// (and avoided by IPS) before they turn into single points of failure.
+
+use std::{collections::HashMap, net::SocketAddr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::CrunchyState;
+
+/// Node whose betweenness rises across recent runs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HotNode {
+    pub addr: SocketAddr,
+    /// Betweenness in each run the node was present in (oldest first)
+    pub betweenness: Vec<f64>,
+    /// Average change of betweenness per run (least squares slope)
+    pub trend: f64,
+}
+
+/// Find up to `count` nodes of the latest state with the fastest rising betweenness across the
+/// earlier states (oldest first) and the latest one. Only nodes seen in at least two runs with a
+/// positive trend are reported, fastest rising first.
+pub fn emerging_hot_nodes(
+    history: &[CrunchyState],
+    state: &CrunchyState,
+    count: usize,
+) -> Vec<HotNode> {
+    let mut samples = state
+        .nodes
+        .iter()
+        .map(|node| (node.addr, Vec::new()))
+        .collect::<HashMap<SocketAddr, Vec<(f64, f64)>>>();
+    for (run, past) in history.iter().chain(std::iter::once(state)).enumerate() {
+        for node in past.nodes.iter() {
+            if let Some(samples) = samples.get_mut(&node.addr) {
+                samples.push((run as f64, node.betweenness));
+            }
+        }
+    }
+
+    let mut hot_nodes = samples
+        .into_iter()
+        .filter(|(_, samples)| samples.len() > 1)
+        .map(|(addr, samples)| HotNode {
+            addr,
+            trend: slope(&samples),
+            betweenness: samples.into_iter().map(|(_, b)| b).collect(),
+        })
+        .filter(|node| node.trend > 0.0)
+        .collect::<Vec<HotNode>>();
+
+    // Address breaks ties, so the result doesn't depend on the hash map order
+    hot_nodes.sort_by(|a, b| {
+        b.trend
+            .total_cmp(&a.trend)
+            .then_with(|| a.addr.cmp(&b.addr))
+    });
+    hot_nodes.truncate(count);
+    hot_nodes
+}
+
+/// Least squares slope of the points (at least two with different x).
+fn slope(points: &[(f64, f64)]) -> f64 {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let covariance = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum::<f64>();
+    let variance = points
+        .iter()
+        .map(|(x, _)| (x - mean_x).powi(2))
+        .sum::<f64>();
+
+    covariance / variance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Node;
+
+    fn state(betweenness: &[(u8, f64)]) -> CrunchyState {
+        CrunchyState {
+            nodes: betweenness
+                .iter()
+                .map(|(host, betweenness)| Node {
+                    addr: SocketAddr::new([10, 0, 0, *host].into(), 1234),
+                    betweenness: *betweenness,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn emerging_hot_nodes_test() {
+        let history = vec![
+            state(&[(1, 0.1), (2, 0.5), (3, 0.2), (4, 0.3)]),
+            state(&[(1, 0.2), (2, 0.4), (3, 0.25), (4, 0.3)]),
+        ];
+        // Node 5 is new, so there is no trend for it
+        let latest = state(&[(1, 0.3), (2, 0.3), (3, 0.3), (4, 0.3), (5, 0.9)]);
+
+        let hot_nodes = emerging_hot_nodes(&history, &latest, 10);
+        assert_eq!(hot_nodes.len(), 2);
+        assert_eq!(hot_nodes[0].addr, latest.nodes[0].addr);
+        assert!((hot_nodes[0].trend - 0.1).abs() < 1e-9);
+        assert_eq!(hot_nodes[0].betweenness, vec![0.1, 0.2, 0.3]);
+        assert_eq!(hot_nodes[1].addr, latest.nodes[2].addr);
+
+        assert_eq!(emerging_hot_nodes(&history, &latest, 1).len(), 1);
+        assert!(emerging_hot_nodes(&[], &latest, 10).is_empty());
+    }
+}
@@ -1,7 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+use crate::numeric::weighted_median;
+
+/// How values are mapped to histogram slots
+#[derive(Debug, Default, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistogramScale {
+    /// Slots are evenly spaced values
+    #[default]
+    Linear,
+    /// Slots are evenly spaced log10 of values, which suits heavy-tailed distributions - values
+    /// not greater than zero are counted in the first slot
+    Log,
+}
+
+impl HistogramScale {
+    /// Map the value to the scale (None if it can't be mapped).
+    fn apply(&self, value: f64) -> Option<f64> {
+        match self {
+            HistogramScale::Linear => Some(value),
+            HistogramScale::Log => (value > 0.0).then(|| value.log10()),
+        }
+    }
+
+    /// Map the value back from the scale.
+    fn invert(&self, value: f64) -> f64 {
+        match self {
+            HistogramScale::Linear => value,
+            HistogramScale::Log => 10f64.powf(value),
+        }
+    }
+}
+
 /// Structure used to create a distribution of node
 /// centrality values for use in a displayed histogram
 
-#[derive(Default, Clone)]
+#[derive(Clone)]
 pub struct Histogram {
     /// Minimum value of a factor.
     pub min: f64,
@@ -9,35 +43,81 @@ pub struct Histogram {
     pub max: f64,
     /// store values
     pub values: Vec<f64>,
+    /// weight of each stored value (how much it adds to its slot count)
+    pub weights: Vec<usize>,
+    /// Fixed minimum of the range (values below it are counted in the first slot)
+    pub fixed_min: Option<f64>,
+    /// Fixed maximum of the range (values above it are counted in the last slot)
+    pub fixed_max: Option<f64>,
+    /// How values are mapped to slots
+    pub scale: HistogramScale,
 }
 
-impl Histogram {
-    pub fn default() -> Self {
+impl Default for Histogram {
+    fn default() -> Self {
         Self {
             min: f64::MAX,
             max: f64::MIN,
             values: Vec::new(),
+            weights: Vec::new(),
+            fixed_min: None,
+            fixed_max: None,
+            scale: HistogramScale::Linear,
         }
     }
+}
 
+impl Histogram {
     // Store a single value
     pub fn add(&mut self, value: f64) {
+        self.add_weighted(value, 1);
+    }
+
+    // Store a single value counted the given number of times
+    pub fn add_weighted(&mut self, value: f64, weight: usize) {
         self.values.push(value);
+        self.weights.push(weight);
+    }
+
+    // Median of stored values, each counted as many times as its weight
+    pub fn median(&self) -> Option<f64> {
+        weighted_median(&self.values, &self.weights)
     }
 
     // Compute the histogram tables, returning that table along
     // with the value of the maximum count in a single slot.
+    // Min and max are kept as values even if slots are computed on another scale.
     pub fn compute(&mut self, num_counts: usize) -> (Vec<usize>, usize) {
-        for value in self.values.iter() {
-            if *value < self.min {
-                self.min = *value;
+        let scale = self.scale;
+        let scaled = self
+            .values
+            .iter()
+            .map(|value| scale.apply(*value))
+            .collect::<Vec<Option<f64>>>();
+
+        let mut min = scale.apply(self.min).unwrap_or(f64::MAX);
+        let mut max = scale.apply(self.max).unwrap_or(f64::MIN);
+        for value in scaled.iter().flatten() {
+            if *value < min {
+                min = *value;
             }
-            if *value > self.max {
-                self.max = *value;
+            if *value > max {
+                max = *value;
             }
         }
+        // Fixed range keeps the slots the same across snapshots
+        if let Some(fixed_min) = self.fixed_min.and_then(|min| scale.apply(min)) {
+            min = fixed_min;
+        }
+        if let Some(fixed_max) = self.fixed_max.and_then(|max| scale.apply(max)) {
+            max = fixed_max;
+        }
+        if min <= max {
+            self.min = scale.invert(min);
+            self.max = scale.invert(max);
+        }
 
-        let delta = self.max - self.min;
+        let delta = max - min;
         let mut counts = vec![0; num_counts];
         let mut max_count: usize = 0;
 
@@ -45,13 +125,14 @@ impl Histogram {
             return (counts, max_count);
         }
 
-        for value in self.values.iter() {
-            let part = (*value - self.min) / delta;
+        for (value, weight) in scaled.iter().zip(self.weights.iter()) {
+            // Values which can't be scaled are below any scaled value
+            let part = value.map_or(0.0, |value| (value - min) / delta);
             let mut slot = (part * num_counts as f64).floor() as usize;
             if slot >= num_counts {
                 slot = num_counts - 1;
             }
-            counts[slot] += 1;
+            counts[slot] += weight;
         }
 
         for count in counts.iter() {
@@ -95,6 +176,63 @@ mod tests {
         assert_eq!(counts[117], 3);
     }
 
+    #[test]
+    fn histogram_weighted_test() {
+        let mut histogram = Histogram {
+            ..Histogram::default()
+        };
+
+        histogram.add_weighted(0.0, 1);
+        histogram.add_weighted(0.2, 5);
+        histogram.add_weighted(0.9, 2);
+        histogram.add(1.0);
+        let (counts, max_count) = histogram.compute(10);
+        assert_eq!(max_count, 5);
+        assert_eq!(counts[0], 1);
+        assert_eq!(counts[2], 5);
+        assert_eq!(counts[9], 3);
+        assert_eq!(counts.iter().sum::<usize>(), 9);
+        assert_eq!(histogram.median().unwrap(), 0.2);
+    }
+
+    #[test]
+    fn histogram_fixed_range_test() {
+        let mut histogram = Histogram {
+            fixed_min: Some(0.0),
+            fixed_max: Some(10.0),
+            ..Histogram::default()
+        };
+
+        histogram.add(-3.0);
+        histogram.add(2.5);
+        histogram.add(9.9);
+        histogram.add(42.0);
+        let (counts, max_count) = histogram.compute(4);
+        assert_eq!((histogram.min, histogram.max), (0.0, 10.0));
+        assert_eq!(counts, vec![1, 1, 0, 2]);
+        assert_eq!(max_count, 2);
+    }
+
+    #[test]
+    fn histogram_log_scale_test() {
+        let mut histogram = Histogram {
+            scale: HistogramScale::Log,
+            ..Histogram::default()
+        };
+
+        histogram.add(0.0);
+        histogram.add(1.0);
+        histogram.add(9.0);
+        histogram.add(10.0);
+        histogram.add(999.0);
+        histogram.add(1000.0);
+        let (counts, max_count) = histogram.compute(3);
+        assert_eq!((histogram.min, histogram.max), (1.0, 1000.0));
+        // Slots cover 1-10, 10-100 and 100-1000, zero is counted in the first one
+        assert_eq!(counts, vec![3, 1, 2]);
+        assert_eq!(max_count, 3);
+    }
+
     #[test]
     fn histogram_zero_delta_test() {
         let mut histogram = Histogram {
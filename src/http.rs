@@ -0,0 +1,513 @@
+// HTTP server publishing results of the latest run (the `serve` command), so consumers like the
+// explorer fetch them directly instead of files copied around by cron jobs. Resources are
+// rendered once per run and served with caching headers - an `ETag` (conditional requests get
+// `304 Not Modified`), `Last-Modified`, and `Cache-Control` lasting until the next scheduled run.
+
+use std::{
+    convert::Infallible,
+    future::Future,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use hyper::{
+    body::Bytes,
+    header::{self, HeaderValue},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+
+use crate::{
+    ips::{config::PeerOutputFormat, peer::PeerList},
+    search::SearchIndex,
+    to_json, CrunchyState, Node,
+};
+
+/// Results of one run of the pipeline
+pub struct Publication {
+    pub state: CrunchyState,
+    /// Peer lists by the network name, the first one is also served at `/peers` (none for dry
+    /// runs or if IPS failed)
+    pub peers: Vec<(String, PeerList)>,
+    /// Time the results were generated
+    pub generated_at: SystemTime,
+}
+
+/// How results are served
+#[derive(Debug, Clone, Default)]
+pub struct HttpOptions {
+    /// Time between scheduled runs - responses may be cached until the next one (they must be
+    /// revalidated if not set)
+    pub interval: Option<Duration>,
+    /// Serve peer lists even after they expired
+    pub allow_stale: bool,
+    pub peer_output_format: PeerOutputFormat,
+    pub pretty_json: bool,
+}
+
+/// Rendered response body with its entity tag
+struct Resource {
+    body: Bytes,
+    content_type: &'static str,
+    etag: String,
+}
+
+impl Resource {
+    fn new(body: String, content_type: &'static str) -> Self {
+        Self {
+            etag: format!("\"{:x}\"", Sha256::digest(body.as_bytes())),
+            body: body.into(),
+            content_type,
+        }
+    }
+}
+
+/// Results of the latest run with their rendered resources, replaced as a whole
+struct Snapshot {
+    state: CrunchyState,
+    index: SearchIndex,
+    state_resource: Resource,
+    histograms: Resource,
+    /// Network name, rendered peer list and its expiration
+    peers: Vec<(String, Resource, SystemTime)>,
+    generated_at: SystemTime,
+    /// Time the snapshot was published - the next run is scheduled from it
+    published_at: SystemTime,
+}
+
+/// Outcome of the latest runs, reported by `/healthz`
+#[derive(Default, Serialize)]
+struct Health {
+    /// Number of successful runs
+    runs: usize,
+    /// Error of the latest run, if it failed
+    last_error: Option<String>,
+}
+
+/// HTTP service holding results of the latest run
+pub struct HttpService {
+    options: HttpOptions,
+    latest: RwLock<Option<Arc<Snapshot>>>,
+    health: RwLock<Health>,
+}
+
+impl HttpService {
+    pub fn new(options: HttpOptions) -> Self {
+        Self {
+            options,
+            latest: RwLock::new(None),
+            health: RwLock::new(Health::default()),
+        }
+    }
+
+    /// Replace served results with results of a new run.
+    pub async fn publish(&self, publication: Publication) {
+        let pretty = self.options.pretty_json;
+        let peer_content_type = match self.options.peer_output_format {
+            PeerOutputFormat::Json | PeerOutputFormat::JsonArray => JSON,
+            PeerOutputFormat::Addr | PeerOutputFormat::AddNode => TEXT,
+        };
+        // Peer lists are valid from their publication - the same as from their generation for
+        // regular runs, while reproducible ones are stamped with the crawl time
+        let published_at = SystemTime::now();
+        let peers = publication
+            .peers
+            .iter()
+            .map(|(network, peerlist)| {
+                let body = peerlist.render(&self.options.peer_output_format, pretty);
+                (
+                    network.clone(),
+                    Resource::new(body, peer_content_type),
+                    published_at + peerlist.validity(),
+                )
+            })
+            .collect();
+        let snapshot = Snapshot {
+            index: SearchIndex::new(&publication.state),
+            state_resource: Resource::new(to_json(&publication.state, pretty), JSON),
+            histograms: Resource::new(to_json(&publication.state.histograms, pretty), JSON),
+            state: publication.state,
+            peers,
+            generated_at: publication.generated_at,
+            published_at,
+        };
+
+        *self.latest.write().await = Some(Arc::new(snapshot));
+        let mut health = self.health.write().await;
+        health.runs += 1;
+        health.last_error = None;
+    }
+
+    /// Record a failed run - results of the previous one are still served.
+    pub async fn fail(&self, error: String) {
+        self.health.write().await.last_error = Some(error);
+    }
+
+    /// Respond to the request.
+    pub async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        if request.method() != Method::GET && request.method() != Method::HEAD {
+            let mut response = plain(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed");
+            response
+                .headers_mut()
+                .insert(header::ALLOW, HeaderValue::from_static("GET, HEAD"));
+            return response;
+        }
+
+        let path = request.uri().path();
+        let snapshot = self.latest.read().await.clone();
+        let mut response = match (path, snapshot) {
+            ("/healthz", snapshot) => self.health(snapshot.as_deref()).await,
+            ("/state" | "/histograms" | "/peers" | "/search", None) => {
+                plain(StatusCode::SERVICE_UNAVAILABLE, "No results yet")
+            }
+            (_, None) if path.starts_with("/peers/") => {
+                plain(StatusCode::SERVICE_UNAVAILABLE, "No results yet")
+            }
+            ("/state", Some(snapshot)) => {
+                self.resource(&request, &snapshot, &snapshot.state_resource, None)
+            }
+            ("/histograms", Some(snapshot)) => {
+                self.resource(&request, &snapshot, &snapshot.histograms, None)
+            }
+            ("/peers", Some(snapshot)) => self.peers(&request, &snapshot, None),
+            (_, Some(snapshot)) if path.starts_with("/peers/") => {
+                self.peers(&request, &snapshot, Some(&path["/peers/".len()..]))
+            }
+            ("/search", Some(snapshot)) => self.search(&request, &snapshot),
+            _ => plain(StatusCode::NOT_FOUND, "Not found"),
+        };
+
+        if request.method() == Method::HEAD {
+            *response.body_mut() = Body::empty();
+        }
+        response
+    }
+
+    /// Status of the service - unavailable until the first run succeeds.
+    async fn health(&self, snapshot: Option<&Snapshot>) -> Response<Body> {
+        #[derive(Serialize)]
+        struct HealthOutput<'a> {
+            status: &'static str,
+            #[serde(flatten)]
+            health: &'a Health,
+            generated_at: Option<SystemTime>,
+            nodes: usize,
+        }
+
+        let health = self.health.read().await;
+        let output = HealthOutput {
+            status: if snapshot.is_some() { "ok" } else { "starting" },
+            health: &health,
+            generated_at: snapshot.map(|snapshot| snapshot.generated_at),
+            nodes: snapshot.map_or(0, |snapshot| snapshot.state.nodes.len()),
+        };
+        let status = if snapshot.is_some() {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+
+        let mut response = Response::new(Body::from(to_json(&output, self.options.pretty_json)));
+        *response.status_mut() = status;
+        let headers = response.headers_mut();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(JSON));
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        response
+    }
+
+    /// Peer list of the network (the first one if not set). Expired peer lists are based on an
+    /// outdated crawl, so they are refused unless stale ones are allowed.
+    fn peers(
+        &self,
+        request: &Request<Body>,
+        snapshot: &Snapshot,
+        network: Option<&str>,
+    ) -> Response<Body> {
+        let found = match network {
+            Some(network) => snapshot.peers.iter().find(|(name, ..)| name == network),
+            None => snapshot.peers.first(),
+        };
+        let (resource, expires_at) = match found {
+            Some((_, resource, expires_at)) => (resource, *expires_at),
+            None => return plain(StatusCode::NOT_FOUND, "No peer list"),
+        };
+        if SystemTime::now() > expires_at && !self.options.allow_stale {
+            let mut response = plain(StatusCode::SERVICE_UNAVAILABLE, "Peer list has expired");
+            response
+                .headers_mut()
+                .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+            return response;
+        }
+        self.resource(request, snapshot, resource, Some(expires_at))
+    }
+
+    /// Nodes matching the query given by the `q` parameter.
+    fn search(&self, request: &Request<Body>, snapshot: &Snapshot) -> Response<Body> {
+        let query = request
+            .uri()
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(name, _)| *name == "q")
+            .map(|(_, value)| {
+                percent_decode_str(&value.replace('+', " "))
+                    .decode_utf8_lossy()
+                    .into_owned()
+            })
+            .unwrap_or_default();
+        match snapshot.index.search(&snapshot.state, &query) {
+            Ok(indices) => {
+                let nodes = indices
+                    .iter()
+                    .map(|idx| &snapshot.state.nodes[*idx])
+                    .collect::<Vec<&Node>>();
+                let resource = Resource::new(to_json(&nodes, self.options.pretty_json), JSON);
+                self.resource(request, snapshot, &resource, None)
+            }
+            Err(e) => plain(StatusCode::BAD_REQUEST, &format!("Invalid query: {e}")),
+        }
+    }
+
+    /// Response with the resource, or `304 Not Modified` if the client has it already.
+    fn resource(
+        &self,
+        request: &Request<Body>,
+        snapshot: &Snapshot,
+        resource: &Resource,
+        expires_at: Option<SystemTime>,
+    ) -> Response<Body> {
+        let not_modified = request
+            .headers()
+            .get_all(header::IF_NONE_MATCH)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|tag| {
+                let tag = tag.trim();
+                tag == "*" || tag.trim_start_matches("W/") == resource.etag
+            });
+
+        let mut response = if not_modified {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NOT_MODIFIED;
+            response
+        } else {
+            let mut response = Response::new(Body::from(resource.body.clone()));
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(resource.content_type),
+            );
+            response
+        };
+
+        let headers = response.headers_mut();
+        headers.insert(header::ETAG, HeaderValue::from_str(&resource.etag).unwrap());
+        headers.insert(
+            header::LAST_MODIFIED,
+            HeaderValue::from_str(&httpdate::fmt_http_date(snapshot.generated_at)).unwrap(),
+        );
+        headers.insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_str(&self.cache_control(snapshot, expires_at)).unwrap(),
+        );
+        response
+    }
+
+    /// Responses can be cached until the next scheduled run (and not past the expiration of the
+    /// resource), otherwise they must be revalidated.
+    fn cache_control(&self, snapshot: &Snapshot, expires_at: Option<SystemTime>) -> String {
+        let next_run = self
+            .options
+            .interval
+            .map(|interval| snapshot.published_at + interval);
+        let fresh_until = match (next_run, expires_at) {
+            (Some(next_run), Some(expires_at)) => Some(next_run.min(expires_at)),
+            (Some(next_run), None) => Some(next_run),
+            (None, _) => None,
+        };
+        match fresh_until {
+            Some(until) => {
+                let max_age = until
+                    .duration_since(SystemTime::now())
+                    .unwrap_or_default()
+                    .as_secs();
+                format!("public, max-age={max_age}")
+            }
+            None => "no-cache".to_owned(),
+        }
+    }
+}
+
+const JSON: &str = "application/json";
+const TEXT: &str = "text/plain; charset=utf-8";
+
+fn plain(status: StatusCode, message: &str) -> Response<Body> {
+    let mut response = Response::new(Body::from(format!("{message}\n")));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(TEXT));
+    response
+}
+
+/// Bind the address and return the server running the service - it fails right away if the
+/// address can't be bound.
+pub fn serve(
+    addr: SocketAddr,
+    service: Arc<HttpService>,
+) -> Result<impl Future<Output = Result<(), hyper::Error>>, hyper::Error> {
+    let make_service = make_service_fn(move |_| {
+        let service = service.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let service = service.clone();
+                async move { Ok::<_, Infallible>(service.handle(request).await) }
+            }))
+        }
+    });
+    Ok(Server::try_bind(&addr)?.serve(make_service))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ips::peer::Peer;
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    async fn body(response: Response<Body>) -> String {
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    fn publication(validity: Duration) -> Publication {
+        let node = |addr: &str| Node {
+            addr: addr.parse().unwrap(),
+            ..Default::default()
+        };
+        let peer = Peer {
+            ip: "127.0.0.1:8233".parse().unwrap(),
+            list: vec![],
+            estimated_latency_ms: vec![],
+            details: vec![],
+        };
+        Publication {
+            state: CrunchyState {
+                nodes: vec![node("127.0.0.1:8233"), node("10.0.0.1:8233")],
+                ..Default::default()
+            },
+            peers: vec![("zcash".to_owned(), PeerList::new(vec![peer], validity, 7))],
+            generated_at: SystemTime::UNIX_EPOCH,
+        }
+    }
+
+    #[tokio::test]
+    async fn http_service_test() {
+        let service = HttpService::new(HttpOptions {
+            interval: Some(Duration::from_secs(600)),
+            ..Default::default()
+        });
+        let response = service.handle(get("/healthz")).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let response = service.handle(get("/state")).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        service
+            .publish(publication(Duration::from_secs(3600)))
+            .await;
+        let response = service.handle(get("/healthz")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(body(response).await.contains(r#""status":"ok""#));
+
+        let response = service.handle(get("/state")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        let etag = headers[header::ETAG].clone();
+        assert_eq!(headers[header::CONTENT_TYPE], JSON);
+        assert_eq!(
+            headers[header::LAST_MODIFIED],
+            "Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+        let cache_control = headers[header::CACHE_CONTROL].to_str().unwrap().to_owned();
+        assert!(cache_control.starts_with("public, max-age="));
+        let state: CrunchyState = serde_json::from_str(&body(response).await).unwrap();
+        assert_eq!(state.nodes.len(), 2);
+
+        // The client has the current version
+        let request = Request::get("/state")
+            .header(header::IF_NONE_MATCH, etag.clone())
+            .body(Body::empty())
+            .unwrap();
+        let response = service.handle(request).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], etag);
+        assert!(body(response).await.is_empty());
+
+        let response = service.handle(get("/peers/zcash")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let peerlist: PeerList = serde_json::from_str(&body(response).await).unwrap();
+        assert_eq!(peerlist.seed, 7);
+        assert_eq!(service.handle(get("/peers")).await.status(), StatusCode::OK);
+        assert_eq!(
+            service.handle(get("/peers/yggdrasil")).await.status(),
+            StatusCode::NOT_FOUND
+        );
+
+        let response = service.handle(get("/search?q=addr%3A10.")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let nodes: Vec<Node> = serde_json::from_str(&body(response).await).unwrap();
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(
+            service.handle(get("/search?q=bogus:1")).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+
+        let response = service.handle(get("/histograms")).await;
+        assert_eq!(body(response).await, "[]");
+        assert_eq!(
+            service.handle(get("/unknown")).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        let request = Request::post("/state").body(Body::empty()).unwrap();
+        assert_eq!(
+            service.handle(request).await.status(),
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+
+        // Expired peer lists are refused, unless stale ones are allowed
+        service.publish(publication(Duration::ZERO)).await;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(
+            service.handle(get("/peers")).await.status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        let service = HttpService::new(HttpOptions {
+            allow_stale: true,
+            ..Default::default()
+        });
+        service.publish(publication(Duration::ZERO)).await;
+        let response = service.handle(get("/peers")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+
+        // Reproduced peer lists stamped with an old crawl time are valid from their publication
+        let service = HttpService::new(HttpOptions::default());
+        let mut reproduced = publication(Duration::from_secs(3600));
+        reproduced.peers[0].1 = PeerList::with_generation_time(
+            Vec::new(),
+            SystemTime::UNIX_EPOCH,
+            Duration::from_secs(3600),
+            7,
+        );
+        service.publish(reproduced).await;
+        assert_eq!(service.handle(get("/peers")).await.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,378 @@
+// Input adapters converting network topologies from different sources into the network summary
+// used by the rest of crunchy, so networks not crawled by ziggurat can be analyzed without
+// conversion scripts.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, Read},
+    net::SocketAddr,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use serde::Deserialize;
+use ziggurat_core_crawler::summary::{NetworkSummary, NetworkType};
+
+use crate::{config::InputFormat, stdio, CrunchyState, JsonRpcResponse};
+
+/// Data announced by a node which the network summary has no place for
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct NodeMetadata {
+    pub protocol_version: Option<u32>,
+    pub user_agent: Option<String>,
+}
+
+/// Metadata of nodes by their addresses
+pub type NodesMetadata = HashMap<SocketAddr, NodeMetadata>;
+
+/// Network summary read from the input together with what else the input records
+pub struct Input {
+    pub summary: NetworkSummary,
+    pub metadata: NodesMetadata,
+    /// Time of the crawl recorded in the input, if any
+    pub crawled_at: Option<SystemTime>,
+}
+
+impl From<JsonRpcResponse> for Input {
+    fn from(response: JsonRpcResponse) -> Input {
+        Input {
+            summary: response.result,
+            metadata: NodesMetadata::new(),
+            crawled_at: response
+                .timestamp
+                .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+        }
+    }
+}
+
+/// Source of the network topology which can be converted into the network summary.
+pub trait InputAdapter {
+    /// Convert the input content into the network summary.
+    fn parse(&self, content: &str) -> Result<NetworkSummary, io::Error>;
+
+    /// Collect metadata of nodes from the input content. The crawler summary only counts
+    /// protocol versions and user agents of the whole network, so by default there is none.
+    fn metadata(&self, _content: &str) -> Result<NodesMetadata, io::Error> {
+        Ok(NodesMetadata::new())
+    }
+}
+
+/// JSON-RPC response of the ziggurat crawler with the network summary as its result, or the bare
+/// network summary.
+pub struct ZigguratAdapter;
+
+impl InputAdapter for ZigguratAdapter {
+    fn parse(&self, content: &str) -> Result<NetworkSummary, io::Error> {
+        Ok(JsonRpcResponse::parse(content)?.result)
+    }
+}
+
+/// Bitcoin `getpeerinfo` results of multiple nodes, as a JSON object mapping address of each
+/// node to its `getpeerinfo` result. Only outbound peers are used - inbound peers are seen from
+/// an ephemeral port, which doesn't identify the node.
+pub struct GetPeerInfoAdapter;
+
+/// Single entry of the `getpeerinfo` result (other fields are ignored).
+#[derive(Deserialize)]
+struct PeerInfo {
+    addr: String,
+    #[serde(default)]
+    inbound: bool,
+    /// Protocol version announced by the peer
+    #[serde(default)]
+    version: Option<u32>,
+    /// User agent announced by the peer
+    #[serde(default)]
+    subver: Option<String>,
+}
+
+impl InputAdapter for GetPeerInfoAdapter {
+    fn parse(&self, content: &str) -> Result<NetworkSummary, io::Error> {
+        // Ordered map keeps node indices the same for every run
+        let dumps: BTreeMap<SocketAddr, Vec<PeerInfo>> =
+            serde_json::from_str(content).map_err(invalid_data)?;
+
+        let mut builder = TopologyBuilder::default();
+        for (addr, peers) in dumps.iter() {
+            builder.add_node(*addr);
+            // Peers with addresses which are not IP based (e.g. onion) are skipped
+            for peer in peers.iter().filter(|peer| !peer.inbound) {
+                if let Ok(peer_addr) = peer.addr.parse::<SocketAddr>() {
+                    builder.add_connection(*addr, peer_addr);
+                }
+            }
+        }
+
+        let mut summary = builder.build();
+        summary.num_good_nodes = dumps.len();
+        Ok(summary)
+    }
+
+    // Each node is described by the nodes connected to it - as with connections, only outbound
+    // peers are identified by their addresses
+    fn metadata(&self, content: &str) -> Result<NodesMetadata, io::Error> {
+        let dumps: BTreeMap<SocketAddr, Vec<PeerInfo>> =
+            serde_json::from_str(content).map_err(invalid_data)?;
+
+        let mut metadata = NodesMetadata::new();
+        for peer in dumps.values().flatten().filter(|peer| !peer.inbound) {
+            if let Ok(peer_addr) = peer.addr.parse::<SocketAddr>() {
+                let entry = metadata.entry(peer_addr).or_default();
+                entry.protocol_version = peer.version.or(entry.protocol_version);
+                entry.user_agent = peer.subver.clone().or(entry.user_agent.take());
+            }
+        }
+        Ok(metadata)
+    }
+}
+
+/// CSV file with a single connection (two socket addresses) on each line. The header line, empty
+/// lines and lines starting with `#` are skipped.
+pub struct EdgeListAdapter;
+
+impl InputAdapter for EdgeListAdapter {
+    fn parse(&self, content: &str) -> Result<NetworkSummary, io::Error> {
+        let mut builder = TopologyBuilder::default();
+        for (line_idx, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let addrs = line
+                .split(',')
+                .map(|column| column.trim().parse::<SocketAddr>())
+                .collect::<Result<Vec<_>, _>>();
+            match addrs.as_deref() {
+                Ok([a, b]) => builder.add_connection(*a, *b),
+                Err(_) if line_idx == 0 => continue,
+                _ => return Err(invalid_data(format!("invalid edge list line: {line}"))),
+            }
+        }
+
+        Ok(builder.build())
+    }
+}
+
+/// Adapter for the given input format.
+pub fn adapter(format: &InputFormat) -> Box<dyn InputAdapter> {
+    match format {
+        InputFormat::Ziggurat => Box::new(ZigguratAdapter),
+        InputFormat::GetPeerInfo => Box::new(GetPeerInfoAdapter),
+        InputFormat::EdgeList => Box::new(EdgeListAdapter),
+    }
+}
+
+/// Load the network summary from the file in the given format.
+pub fn load_summary(path: &Path, format: &InputFormat) -> Result<NetworkSummary, io::Error> {
+    load_input(path, format).map(|input| input.summary)
+}
+
+/// Load the network summary together with the metadata of nodes and the crawl time from the file
+/// (or stdin if the path is `-`) in the given format.
+pub fn load_input(path: &Path, format: &InputFormat) -> Result<Input, io::Error> {
+    read_input(stdio::reader(path)?, format)
+}
+
+/// Read the network summary together with the metadata of nodes and the crawl time in the given
+/// format. With the `streaming-json` feature the crawler response is parsed while it's read.
+pub fn read_input<R: Read>(mut reader: R, format: &InputFormat) -> Result<Input, io::Error> {
+    #[cfg(feature = "streaming-json")]
+    if *format == InputFormat::Ziggurat {
+        return crate::streaming_json::read_response_streaming(reader).map(Input::from);
+    }
+
+    let mut content = String::new();
+    reader.read_to_string(&mut content)?;
+    // Only the crawler response records the crawl time
+    if *format == InputFormat::Ziggurat {
+        return JsonRpcResponse::parse(&content).map(Input::from);
+    }
+    let adapter = adapter(format);
+    Ok(Input {
+        summary: adapter.parse(&content)?,
+        metadata: adapter.metadata(&content)?,
+        crawled_at: None,
+    })
+}
+
+/// Set protocol versions and user agents of the state nodes from the metadata.
+pub fn apply_metadata(metadata: &NodesMetadata, state: &mut CrunchyState) {
+    for node in state.nodes.iter_mut() {
+        if let Some(node_metadata) = metadata.get(&node.addr) {
+            node.protocol_version = node_metadata.protocol_version;
+            node.user_agent = node_metadata.user_agent.clone();
+        }
+    }
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Collects nodes and connections in the order they are seen. Connections are symmetric and
+/// duplicates (including connections of a node to itself) are dropped.
+#[derive(Default)]
+struct TopologyBuilder {
+    addrs: Vec<SocketAddr>,
+    indices: HashMap<SocketAddr, usize>,
+    connections: Vec<Vec<usize>>,
+}
+
+impl TopologyBuilder {
+    fn add_node(&mut self, addr: SocketAddr) -> usize {
+        *self.indices.entry(addr).or_insert_with(|| {
+            self.addrs.push(addr);
+            self.connections.push(Vec::new());
+            self.addrs.len() - 1
+        })
+    }
+
+    fn add_connection(&mut self, a: SocketAddr, b: SocketAddr) {
+        let a = self.add_node(a);
+        let b = self.add_node(b);
+        if a != b && !self.connections[a].contains(&b) {
+            self.connections[a].push(b);
+            self.connections[b].push(a);
+        }
+    }
+
+    fn build(self) -> NetworkSummary {
+        NetworkSummary {
+            num_known_nodes: self.addrs.len(),
+            num_known_connections: self.connections.iter().map(Vec::len).sum::<usize>() / 2,
+            node_network_types: vec![NetworkType::Unknown; self.addrs.len()],
+            node_addrs: self.addrs,
+            nodes_indices: self.connections,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_peer_info_adapter_test() {
+        let summary = GetPeerInfoAdapter
+            .parse(
+                r#"{
+                    "10.0.0.1:8333": [
+                        {"addr": "10.0.0.2:8333", "inbound": false, "version": 70016, "subver": "/Satoshi:25.0.0/"},
+                        {"addr": "10.0.0.3:51234", "inbound": true},
+                        {"addr": "abcdefgh.onion:8333", "inbound": false}
+                    ],
+                    "10.0.0.2:8333": [
+                        {"addr": "10.0.0.1:8333", "inbound": false},
+                        {"addr": "[2001:db8::1]:8333", "inbound": false}
+                    ]
+                }"#,
+            )
+            .unwrap();
+
+        assert_eq!(summary.num_good_nodes, 2);
+        assert_eq!(summary.node_addrs.len(), 3);
+        assert_eq!(summary.nodes_indices, vec![vec![1], vec![0, 2], vec![1]]);
+        assert_eq!(summary.num_known_connections, 2);
+    }
+
+    #[test]
+    fn get_peer_info_metadata_test() {
+        let content = r#"{
+            "10.0.0.1:8333": [
+                {"addr": "10.0.0.2:8333", "inbound": false, "version": 70016, "subver": "/Satoshi:25.0.0/"},
+                {"addr": "10.0.0.3:51234", "inbound": true, "version": 70015}
+            ],
+            "10.0.0.2:8333": [
+                {"addr": "10.0.0.1:8333", "inbound": false, "version": 70015}
+            ]
+        }"#;
+        let metadata = GetPeerInfoAdapter.metadata(content).unwrap();
+
+        let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(
+            metadata[&addr("10.0.0.2:8333")],
+            NodeMetadata {
+                protocol_version: Some(70016),
+                user_agent: Some("/Satoshi:25.0.0/".to_owned()),
+            }
+        );
+        assert_eq!(
+            metadata[&addr("10.0.0.1:8333")].protocol_version,
+            Some(70015)
+        );
+        assert_eq!(metadata[&addr("10.0.0.1:8333")].user_agent, None);
+
+        let mut state = CrunchyState {
+            nodes: vec![crate::Node {
+                addr: addr("10.0.0.2:8333"),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        apply_metadata(&metadata, &mut state);
+        assert_eq!(state.nodes[0].protocol_version, Some(70016));
+
+        assert!(EdgeListAdapter.metadata("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn edge_list_adapter_test() {
+        let summary = EdgeListAdapter
+            .parse(
+                "source,target\n\
+                 # comment\n\
+                 10.0.0.1:8333, 10.0.0.2:8333\n\
+                 10.0.0.2:8333,10.0.0.1:8333\n\
+                 \n\
+                 10.0.0.3:8333,10.0.0.1:8333\n",
+            )
+            .unwrap();
+
+        assert_eq!(summary.node_addrs.len(), 3);
+        assert_eq!(summary.nodes_indices, vec![vec![1, 2], vec![0], vec![0]]);
+        assert_eq!(summary.node_network_types, vec![NetworkType::Unknown; 3]);
+
+        assert!(EdgeListAdapter
+            .parse("10.0.0.1:8333,10.0.0.2:8333\n10.0.0.1:8333\n")
+            .is_err());
+    }
+
+    #[test]
+    fn ziggurat_adapter_test() {
+        let summary =
+            load_summary(Path::new("testdata/sample.json"), &InputFormat::Ziggurat).unwrap();
+        assert_eq!(summary.node_addrs.len(), 6103);
+
+        // The bare summary is accepted as well
+        let bare = serde_json::to_string(&summary).unwrap();
+        let parsed = ZigguratAdapter.parse(&bare).unwrap();
+        assert_eq!(parsed.node_addrs, summary.node_addrs);
+        assert_eq!(parsed.nodes_indices, summary.nodes_indices);
+        assert!(ZigguratAdapter
+            .parse(r#"{"jsonrpc": "2.0", "id": 1}"#)
+            .is_err());
+    }
+
+    #[test]
+    fn read_input_test() {
+        let content = "10.0.0.1:8333,10.0.0.2:8333\n10.0.0.1:8333,10.0.0.3:8333\n";
+        let input = read_input(content.as_bytes(), &InputFormat::EdgeList).unwrap();
+        assert_eq!(input.summary.node_addrs.len(), 3);
+        assert!(input.metadata.is_empty());
+        assert_eq!(input.crawled_at, None);
+
+        // The crawl time is read in the same pass
+        let summary = serde_json::to_string(&input.summary).unwrap();
+        let content = format!(r#"{{"jsonrpc":"2.0","id":1,"result":{summary},"timestamp":100}}"#);
+        let input = read_input(content.as_bytes(), &InputFormat::Ziggurat).unwrap();
+        assert_eq!(
+            input.crawled_at,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(100))
+        );
+
+        assert!(read_input("{".as_bytes(), &InputFormat::Ziggurat).is_err());
+    }
+}
@@ -0,0 +1,238 @@
+// Classification of IP addresses as hosting (data center), VPN or Tor exit addresses, so it's
+// visible how much of the network runs on rented infrastructure or hides behind anonymizers.
+// Classes come from the IP2Location PX database (CSV file: range start, range end as numbers,
+// proxy type, ...) and from plain lists of addresses or CIDR ranges, one per line (e.g. the Tor
+// bulk exit list), where empty lines and lines starting with `#` are skipped.
+
+use std::{
+    fs, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::Path,
+};
+
+use crate::ips::ip_range::IpRange;
+
+/// Class of an IP address
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IpClass {
+    /// Data center or hosting provider
+    Hosting,
+    /// VPN exit
+    Vpn,
+    /// Tor exit
+    Tor,
+}
+
+/// Classes an IP address belongs to
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct IpClasses {
+    pub hosting: bool,
+    pub vpn: bool,
+    pub tor: bool,
+}
+
+/// Range of IP addresses of a single class.
+struct ClassRange {
+    start: IpAddr,
+    end: IpAddr,
+    class: IpClass,
+}
+
+/// IP classification database - ranges of the PX database and listed ranges of each class,
+/// sorted by their start address.
+#[derive(Default)]
+pub struct IpClassDatabase {
+    ranges: Vec<ClassRange>,
+    listed: Vec<(IpClass, Vec<(IpAddr, IpAddr)>)>,
+}
+
+impl IpClassDatabase {
+    /// Add ranges of the IP2Location PX database (CSV file).
+    pub fn load_px(&mut self, path: &Path) -> Result<(), io::Error> {
+        self.parse_px(&fs::read_to_string(path)?)
+    }
+
+    /// Add ranges of the IP2Location PX database from CSV lines. Only VPN, Tor and data center
+    /// (`DCH`) ranges are kept - other proxy types are not classified.
+    pub fn parse_px(&mut self, content: &str) -> Result<(), io::Error> {
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let mut columns = line
+                .split(',')
+                .map(|column| column.trim().trim_matches('"'));
+            let mut next = || columns.next().ok_or_else(|| invalid("PX database", line));
+            let start = px_address(next()?).ok_or_else(|| invalid("PX database", line))?;
+            let end = px_address(next()?).ok_or_else(|| invalid("PX database", line))?;
+            let class = match next()? {
+                "DCH" => IpClass::Hosting,
+                "VPN" => IpClass::Vpn,
+                "TOR" => IpClass::Tor,
+                _ => continue,
+            };
+            self.ranges.push(ClassRange { start, end, class });
+        }
+        self.ranges.sort_by_key(|range| range.start);
+        Ok(())
+    }
+
+    /// Add addresses and ranges of the list file, all of the given class.
+    pub fn load_list(&mut self, path: &Path, class: IpClass) -> Result<(), io::Error> {
+        self.parse_list(&fs::read_to_string(path)?, class)
+    }
+
+    /// Add addresses and ranges listed one per line, all of the given class.
+    pub fn parse_list(&mut self, content: &str, class: IpClass) -> Result<(), io::Error> {
+        let mut ranges = Vec::new();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let range = line
+                .parse::<IpRange>()
+                .map_err(|_| invalid("address list", line))?;
+            ranges.push(range.bounds());
+        }
+
+        let listed = match self.listed.iter().position(|(listed, _)| *listed == class) {
+            Some(idx) => &mut self.listed[idx].1,
+            None => {
+                self.listed.push((class, Vec::new()));
+                &mut self.listed.last_mut().unwrap().1
+            }
+        };
+        // Lists contain nested entries (e.g. an address and its subnet) and may overlap each
+        // other, so the ranges of a class are merged to keep them from overlapping
+        ranges.append(listed);
+        ranges.sort();
+        for (start, end) in ranges {
+            match listed.last_mut() {
+                Some((_, last_end)) if start <= *last_end => *last_end = end.max(*last_end),
+                _ => listed.push((start, end)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Classes of the given IP.
+    pub fn classify(&self, ip: IpAddr) -> IpClasses {
+        let mut classes = IpClasses::default();
+        let mut add = |class| match class {
+            IpClass::Hosting => classes.hosting = true,
+            IpClass::Vpn => classes.vpn = true,
+            IpClass::Tor => classes.tor = true,
+        };
+
+        // Ranges don't overlap, so only the last one starting before the IP can contain it
+        let position = self.ranges.partition_point(|range| range.start <= ip);
+        if let Some(range) = position.checked_sub(1).map(|idx| &self.ranges[idx]) {
+            if ip <= range.end {
+                add(range.class);
+            }
+        }
+        for (class, ranges) in self.listed.iter() {
+            let position = ranges.partition_point(|(start, _)| *start <= ip);
+            if let Some((_, end)) = position.checked_sub(1).map(|idx| &ranges[idx]) {
+                if ip <= *end {
+                    add(*class);
+                }
+            }
+        }
+        classes
+    }
+
+    /// Number of known ranges, with overlapping listed entries counted once.
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+            + self
+                .listed
+                .iter()
+                .map(|(_, ranges)| ranges.len())
+                .sum::<usize>()
+    }
+
+    /// Check if there are no known ranges.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Address given as a number in the PX database - IPv6 databases contain IPv4 addresses as
+/// IPv4-mapped ones.
+fn px_address(number: &str) -> Option<IpAddr> {
+    let number = number.parse::<u128>().ok()?;
+    Some(match u32::try_from(number) {
+        Ok(number) => IpAddr::V4(Ipv4Addr::from(number)),
+        Err(_) => {
+            let addr = Ipv6Addr::from(number);
+            match addr.to_ipv4_mapped() {
+                Some(addr) => IpAddr::V4(addr),
+                None => IpAddr::V6(addr),
+            }
+        }
+    })
+}
+
+fn invalid(source: &str, line: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid {source} line: {line}"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ip_class_database_test() {
+        let mut database = IpClassDatabase::default();
+        database
+            .parse_px(
+                "\"16777216\",\"16777471\",\"DCH\",\"AU\",\"Australia\"\n\
+                 \"16777472\",\"16777727\",\"PUB\",\"CN\",\"China\"\n\
+                 \"16778240\",\"16779263\",\"VPN\",\"AU\",\"Australia\"\n\
+                 \"281470698586368\",\"281470698586623\",\"TOR\",\"DE\",\"Germany\"\n\
+                 \"42540528726795050063891204319802818560\",\"42540528806023212578155541913346768895\",\"DCH\",\"JP\",\"Japan\"\n",
+            )
+            .unwrap();
+        database
+            .parse_list("# Tor exits\n\n1.0.4.7\n185.220.101.0/24\n", IpClass::Tor)
+            .unwrap();
+        // Nested and overlapping entries are merged with the ones already listed
+        database
+            .parse_list("185.220.101.7\n185.220.100.0/23\n", IpClass::Tor)
+            .unwrap();
+        assert_eq!(database.len(), 6);
+
+        let classify = |ip: &str| database.classify(ip.parse().unwrap());
+        assert_eq!(
+            classify("1.0.0.10"),
+            IpClasses {
+                hosting: true,
+                ..Default::default()
+            }
+        );
+        // Public proxies are not classified
+        assert_eq!(classify("1.0.1.10"), IpClasses::default());
+        assert_eq!(
+            classify("1.0.4.7"),
+            IpClasses {
+                vpn: true,
+                tor: true,
+                ..Default::default()
+            }
+        );
+        // IPv4-mapped range of the IPv6 database
+        assert!(classify("1.1.1.1").tor);
+        assert!(classify("185.220.101.42").tor);
+        assert!(classify("185.220.100.1").tor);
+        assert!(!classify("185.220.102.1").tor);
+        assert!(classify("2001:200::1").hosting);
+        assert_eq!(classify("8.8.8.8"), IpClasses::default());
+
+        assert!(database.parse_px("1,2\n").is_err());
+        assert!(database
+            .parse_list("not an address\n", IpClass::Vpn)
+            .is_err());
+        assert!(IpClassDatabase::default().is_empty());
+    }
+}
@@ -0,0 +1,218 @@
+// Simulation of partial adoption of the generated peer lists. In the real world only some nodes
+// apply the recommendations, so the network statistics are also computed for networks where
+// only a given part of the nodes changed their peers.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashSet},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    csr::Adjacency,
+    ips::{
+        config::{AdoptionSelection, CanaryConfiguration},
+        statistics::{StatisticDelta, Statistics},
+    },
+    numeric::Rng,
+    Node,
+};
+
+/// Expected network statistics when only a part of the nodes adopts the peer lists
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AdoptionSimulation {
+    /// Percentage of nodes assumed to adopt the peer lists
+    pub adoption_rate: f64,
+    /// Number of nodes which adopted the peer lists
+    pub adopting_nodes: usize,
+    /// Statistics for the partially adopted network
+    pub statistics: Statistics,
+    /// Change of each statistic between the initial and the partially adopted network
+    pub statistics_delta: BTreeMap<String, StatisticDelta>,
+}
+
+/// Choose indices of nodes adopting the peer lists for the given adoption rate (in percent).
+/// The order of adoption doesn't depend on the rate, so nodes adopting at a lower rate adopt
+/// at all higher rates too. The pseudo-random order depends only on the seed.
+pub fn adopting_nodes(
+    nodes: &[Node],
+    adoption_rate: f64,
+    selection: &AdoptionSelection,
+    seed: u64,
+) -> HashSet<usize> {
+    let count = (nodes.len() as f64 * adoption_rate.clamp(0.0, 100.0) / 100.0).round() as usize;
+
+    let mut order = (0..nodes.len()).collect::<Vec<usize>>();
+    match selection {
+        AdoptionSelection::Random => Rng(seed).shuffle(&mut order),
+        AdoptionSelection::Tier => order.sort_by_key(|idx| Reverse(nodes[*idx].degree)),
+    }
+
+    order.into_iter().take(count).collect()
+}
+
+/// Choose indices of canary nodes - nodes with any of the configured tags and the configured
+/// percentage of nodes in the adoption order, so the canary matches the simulated adoption.
+pub fn canary_nodes(
+    nodes: &[Node],
+    canary: &CanaryConfiguration,
+    selection: &AdoptionSelection,
+    seed: u64,
+) -> HashSet<usize> {
+    let mut canary_nodes = adopting_nodes(nodes, canary.percentage, selection, seed);
+    canary_nodes.extend(
+        nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| {
+                let network_type = format!("{:?}", node.network_type);
+                canary
+                    .tags
+                    .iter()
+                    .any(|tag| tag.eq_ignore_ascii_case(&network_type))
+            })
+            .map(|(idx, _)| idx),
+    );
+    canary_nodes
+}
+
+/// Build peer lists of the network where only the adopting nodes changed their peers.
+/// Connections are decided by adopting nodes - a connection exists if an adopting node recommends
+/// it, or if neither node adopts and the connection existed originally.
+pub fn partially_adopted(
+    original: &(impl Adjacency + ?Sized),
+    recommended: &(impl Adjacency + ?Sized),
+    adopting: &HashSet<usize>,
+) -> Vec<Vec<usize>> {
+    let mut peer_lists = (0..original.node_count())
+        .map(|idx| {
+            if adopting.contains(&idx) {
+                recommended.connections(idx).to_vec()
+            } else {
+                original
+                    .connections(idx)
+                    .iter()
+                    .copied()
+                    .filter(|peer| !adopting.contains(peer))
+                    .collect()
+            }
+        })
+        .collect::<Vec<Vec<usize>>>();
+
+    // Add connections recommended by adopting nodes to the peers which don't adopt
+    for idx in adopting {
+        for peer in recommended.connections(*idx) {
+            if !adopting.contains(peer) {
+                peer_lists[*peer].push(*idx);
+            }
+        }
+    }
+
+    for peers in peer_lists.iter_mut() {
+        peers.sort();
+        peers.dedup();
+    }
+
+    peer_lists
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use ziggurat_core_crawler::summary::NetworkType;
+
+    use super::*;
+    use crate::nodes::update_degrees;
+
+    fn node(port: u16, connections: Vec<usize>) -> Node {
+        Node {
+            addr: SocketAddr::new("127.0.0.1".parse().unwrap(), port),
+            connections,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn adopting_nodes_test() {
+        let mut nodes = vec![
+            node(1, vec![1]),
+            node(2, vec![0, 2, 3]),
+            node(3, vec![1, 3]),
+            node(4, vec![1, 2]),
+        ];
+        update_degrees(&mut nodes);
+
+        let tier = adopting_nodes(&nodes, 50.0, &AdoptionSelection::Tier, 0);
+        assert_eq!(tier.len(), 2);
+        assert!(tier.contains(&1));
+        assert!(adopting_nodes(&nodes, 0.0, &AdoptionSelection::Tier, 0).is_empty());
+        assert_eq!(
+            adopting_nodes(&nodes, 100.0, &AdoptionSelection::Tier, 0).len(),
+            4
+        );
+
+        let half = adopting_nodes(&nodes, 50.0, &AdoptionSelection::Random, 0);
+        let most = adopting_nodes(&nodes, 75.0, &AdoptionSelection::Random, 0);
+        assert_eq!(half.len(), 2);
+        assert_eq!(most.len(), 3);
+        assert!(half.is_subset(&most));
+        assert_eq!(
+            half,
+            adopting_nodes(&nodes, 50.0, &AdoptionSelection::Random, 0)
+        );
+
+        // Another seed gives another order
+        let nodes = (0..20).map(|port| node(port, vec![])).collect::<Vec<_>>();
+        assert_ne!(
+            adopting_nodes(&nodes, 50.0, &AdoptionSelection::Random, 1),
+            adopting_nodes(&nodes, 50.0, &AdoptionSelection::Random, 2)
+        );
+    }
+
+    #[test]
+    fn canary_nodes_test() {
+        let mut nodes = (0..10).map(|port| node(port, vec![])).collect::<Vec<_>>();
+        nodes[7].network_type = NetworkType::Ripple;
+
+        let canary = CanaryConfiguration {
+            percentage: 20.0,
+            ..Default::default()
+        };
+        let selected = canary_nodes(&nodes, &canary, &AdoptionSelection::Random, 0);
+        assert_eq!(
+            selected,
+            adopting_nodes(&nodes, 20.0, &AdoptionSelection::Random, 0)
+        );
+
+        let canary = CanaryConfiguration {
+            tags: vec![String::from("ripple")],
+            ..Default::default()
+        };
+        let selected = canary_nodes(&nodes, &canary, &AdoptionSelection::Random, 0);
+        assert_eq!(selected, HashSet::from([7]));
+    }
+
+    #[test]
+    fn partially_adopted_test() {
+        // 0 - 1 - 2 changed to 0 - 2 - 1
+        let original = vec![node(1, vec![1]), node(2, vec![0, 2]), node(3, vec![1])];
+        let recommended = vec![node(1, vec![2]), node(2, vec![2]), node(3, vec![0, 1])];
+
+        // Nobody adopts
+        let peer_lists = partially_adopted(&original, &recommended, &HashSet::new());
+        assert_eq!(peer_lists[0], vec![1]);
+        assert_eq!(peer_lists[1], vec![0, 2]);
+
+        // Node 0 drops 1 and connects to 2, others stay as they were
+        let peer_lists = partially_adopted(&original, &recommended, &HashSet::from([0]));
+        assert_eq!(peer_lists, vec![vec![2], vec![2], vec![0, 1]]);
+
+        // Everybody adopts
+        let peer_lists = partially_adopted(&original, &recommended, &HashSet::from([0, 1, 2]));
+        for (peers, recommended) in peer_lists.iter().zip(recommended.iter()) {
+            assert_eq!(*peers, recommended.connections);
+        }
+    }
+}
@@ -16,43 +16,184 @@
 // Especially, there could be a need to add some modifiers to the ranking.
 
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
-    fs,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt, fs,
     fs::File,
-    io,
     io::Write,
     net::SocketAddr,
+    ops::Range,
+    path::PathBuf,
+    thread,
+    time::Instant,
 };
 
+use serde::{Deserialize, Serialize};
+use spectre::{edge::Edge, graph::Graph};
 use ziggurat_core_crawler::summary::NetworkType;
 
 use crate::{
-    config::GeoLocationMode,
+    centrality::{guarded, value_or_fallback, FALLBACK_CENTRALITY},
+    config::{ClosenessVariant, GeoLocationMode},
+    csr::{Adjacency, CsrGraph},
     ips::{
-        config::IPSConfiguration,
+        adoption::{adopting_nodes, canary_nodes, partially_adopted, AdoptionSimulation},
+        config::{BridgeDetection, CandidateSort, IPSConfiguration, Optimizer},
+        constraints::{
+            asn_limit_reached, churn_exceeded, comparable_rating, ip_limit_reached, is_bridge,
+            peer_list_too_long, protocol_version_too_low, same_asn, ConnectionLimits, GeoThrottle,
+        },
+        genetic::GeneticOptimizer,
         graph_utils::{
-            construct_graph, filter_network, find_bridges, find_lowest_betweenness, remove_node,
+            changed_components, construct_graph, detect_islands, extract_island, filter_network,
+            find_bridges, find_exact_bridges, find_lowest_betweenness, harmonic_closeness,
         },
+        latency::estimate_rtt_ms,
         normalization::NormalizationFactors,
-        peer::Peer,
+        pareto::{ConnectionChanges, ParetoFront, ParetoSolution},
+        peer::{FactorContribution, Peer, PeerDetails, RatingFactor},
+        security::{
+            bridge_connections, island_membership, HotNode, Repair, RepairReason, SecurityReport,
+        },
         statistics::{
-            degree_centrality_avg, generate_statistics, print_statistics, print_statistics_delta,
+            degree_centrality_avg, generate_full_statistics, generate_statistics, print_statistics,
+            print_statistics_delta, statistics_delta, StatisticDelta, Statistics,
         },
     },
-    CrunchyState, Node,
+    nodes::{shared_ip_nodes, update_degrees_from},
+    numeric::percentile,
+    stdio, CrunchyState, Node,
 };
 
 /// Intelligent Peer Sharing (IPS) module structure
 #[derive(Default, Clone)]
 pub struct Ips {
     config: IPSConfiguration,
+    /// Seed of all pseudo-random choices
+    seed: u64,
+    /// Nodes never recommended as new peers
+    avoided: HashSet<SocketAddr>,
+    /// Geographic clusters whose nodes get no new peers from the same cluster
+    mega_geo_clusters: HashSet<usize>,
+    /// Closeness computed for the nodes
+    closeness_variant: ClosenessVariant,
+    report: IpsReport,
+}
+
+/// Findings and outcomes of the last IPS run
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct IpsReport {
+    /// Number of nodes connected to themselves
+    pub self_connections: usize,
+    /// Number of connections not present on the other side
+    pub asymmetric_connections: usize,
+    /// Size of each island found in the network
+    pub island_sizes: Vec<usize>,
+    /// Number of islands containing a significant part of the network
+    pub massive_islands: usize,
+    /// Number of small islands joined to the largest island
+    #[serde(default)]
+    pub repaired_islands: usize,
+    /// Number of connections added to join small islands to the largest island
+    #[serde(default)]
+    pub island_repair_connections: usize,
+    /// Indicates if the network stayed integral after removing the hottest nodes
+    pub integrity_preserved: bool,
+    /// Number of bridges found
+    pub bridges: usize,
+    /// Number of pinned nodes whose connections are protected like bridges
+    #[serde(default)]
+    pub pinned_nodes: usize,
+    /// Number of blacklisted nodes never suggested as peers
+    #[serde(default)]
+    pub blacklisted_nodes: usize,
+    /// Number of nodes never suggested as peers due to their protocol version
+    #[serde(default)]
+    pub outdated_nodes: usize,
+    /// Number of nodes sharing their IP address with another node
+    #[serde(default)]
+    pub shared_ip_nodes: usize,
+    /// Number of geographic mega-clusters whose nodes get no new peers from the same cluster
+    #[serde(default)]
+    pub mega_geo_clusters: usize,
+    /// Number of connections added to peer lists spanning too few countries
+    #[serde(default)]
+    pub geo_spread_repairs: usize,
+    /// Number of peer lists still spanning too few countries after the repair
+    #[serde(default)]
+    pub geo_spread_violations: usize,
+    /// Number of articulation points found (only for exact bridge detection)
+    pub articulation_points: Option<usize>,
+    /// Statistics for the initial network
+    pub initial_statistics: Option<Statistics>,
+    /// Statistics for the final network
+    pub final_statistics: Option<Statistics>,
+    /// Change of each statistic between the initial and the final network
+    pub statistics_delta: BTreeMap<String, StatisticDelta>,
+    /// Intermediate states written to the debug dump directory
+    #[serde(default)]
+    pub debug_dumps: Vec<PathBuf>,
+    /// Reports of massive islands analyzed independently
+    #[serde(default)]
+    pub island_reports: Vec<IpsReport>,
+    /// Expected statistics for each configured adoption rate
+    #[serde(default)]
+    pub adoption_simulations: Vec<AdoptionSimulation>,
+    /// Number of candidates skipped as their country or city got too many recommendations
+    #[serde(default)]
+    pub throttled_candidates: usize,
+    /// Number of candidates from the node's own autonomous system passed over for comparable
+    /// ones from other autonomous systems
+    #[serde(default)]
+    pub same_asn_avoided: usize,
+    /// Best fitness of each generation of the genetic optimizer (the initial population first)
+    #[serde(default)]
+    pub genetic_fitness: Vec<f64>,
+    /// Nodes in the canary - only their peer lists should be emitted
+    #[serde(default)]
+    pub canary_nodes: Vec<SocketAddr>,
+    /// Expected statistics when only the canary nodes adopt the peer lists
+    #[serde(default)]
+    pub canary_simulation: Option<AdoptionSimulation>,
+    /// Findings and repairs of the security phase - written to their own file, as they list
+    /// nodes and connections
+    #[serde(skip)]
+    pub security: SecurityReport,
+    /// Networks of the Pareto front found by the genetic optimizer - written to their own file,
+    /// as they list connections
+    #[serde(skip)]
+    pub pareto_front: Vec<ParetoSolution>,
+}
+
+/// Errors which prevent IPS from generating peer lists
+#[derive(Debug)]
+pub enum IpsError {
+    /// There is more than one massive island in the network
+    MultipleMassiveIslands(usize),
+    /// There are no nodes of the network type
+    NoNodes(NetworkType),
+}
+
+impl fmt::Display for IpsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpsError::MultipleMassiveIslands(count) => write!(
+                f,
+                "there are {count} massive islands in the network - it is not possible to merge them automatically"
+            ),
+            IpsError::NoNodes(network) => write!(f, "there are no {network:?} nodes"),
+        }
+    }
 }
 
+impl std::error::Error for IpsError {}
+
 /// State structure containing all the information about the graph and nodes at some point
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Serialize)]
 pub struct IpsState {
-    /// Nodes present in the network
+    /// Nodes present in the network (their connections are kept only in the graph)
     pub nodes: Vec<Node>,
+    /// Connections of the nodes
+    pub graph: CsrGraph,
     /// Peer list for each node in the network
     pub peer_list: Vec<Peer>,
     /// Degrees of each node in the network
@@ -80,6 +221,24 @@ struct PeerEntry {
     pub rating: f64,
 }
 
+/// Ratings of all peers computed for a single node
+struct RatedPeers {
+    /// Peer entries indexed the same way as state.nodes
+    ratings: Vec<PeerEntry>,
+    /// Indices of peers sorted by rating (highest first)
+    order: Vec<usize>,
+    /// Contribution of each factor depending on the node to the ratings (only if peer details
+    /// are enabled)
+    factors: Vec<(RatingFactor, Vec<f64>)>,
+}
+
+/// Contributions of the factors depending on the node to the rating of its peers, by node and
+/// peer index
+type PeerFactors = HashMap<(usize, usize), Vec<(RatingFactor, f64)>>;
+
+/// Rating update by a factor depending on the node (given with its peers)
+type UpdateRating = fn(&Ips, &Node, &[usize], &[Node], &mut [PeerEntry]);
+
 const NORMALIZE_TO_VALUE: f64 = 100.0;
 const NORMALIZE_HALF: f64 = NORMALIZE_TO_VALUE / 2.0;
 const NORMALIZE_2_3: f64 = NORMALIZE_TO_VALUE * 2.0 / 3.0;
@@ -91,9 +250,59 @@ const ERR_GET_EIGENVECTOR: &str = "failed to get eigenvector";
 const MASSIVE_ISLAND_PERCENTAGE: f64 = 0.1;
 const NODES_TO_BE_REMOVED_PERCENTAGE: f64 = 0.1;
 
+/// Number of nodes rated by each thread before peers are selected for them.
+const MCDA_BATCH_PER_THREAD: usize = 4;
+
+/// Number of factors listed as dominating the rating of a peer
+const DOMINANT_FACTORS: usize = 2;
+
+/// Number of candidates scanned beyond the pool size looking for alternatives to candidates from
+/// the node's own autonomous system.
+const SAME_ASN_LOOK_AHEAD: usize = 32;
+
+impl RatedPeers {
+    /// Contributions of the factors depending on the node to the rating of the peer
+    fn factors_of(&self, peer: usize) -> Vec<(RatingFactor, f64)> {
+        self.factors
+            .iter()
+            .map(|(factor, contributions)| (*factor, contributions[peer]))
+            .collect()
+    }
+}
+
 impl Ips {
     pub fn new(config: IPSConfiguration) -> Ips {
-        Ips { config }
+        Ips {
+            config,
+            seed: 0,
+            avoided: HashSet::new(),
+            mega_geo_clusters: HashSet::new(),
+            closeness_variant: ClosenessVariant::default(),
+            report: IpsReport::default(),
+        }
+    }
+
+    /// Use the given seed for all pseudo-random choices.
+    pub fn with_seed(mut self, seed: u64) -> Ips {
+        self.seed = seed;
+        self
+    }
+
+    /// Compute the given variant of closeness (the same as for the state).
+    pub fn with_closeness_variant(mut self, variant: ClosenessVariant) -> Ips {
+        self.closeness_variant = variant;
+        self
+    }
+
+    /// Never recommend the given nodes as new peers (e.g. nodes getting hotter across runs).
+    pub fn avoiding(mut self, nodes: HashSet<SocketAddr>) -> Ips {
+        self.avoided = nodes;
+        self
+    }
+
+    /// Report with findings of the last run
+    pub fn report(&self) -> &IpsReport {
+        &self.report
     }
 
     /// Generate peer list - main function with The Algorithm
@@ -102,31 +311,34 @@ impl Ips {
         state: &CrunchyState,
         network: NetworkType,
         num_threads: usize,
-    ) -> Vec<Peer> {
+    ) -> Result<Vec<Peer>, IpsError> {
         // Set up logging
         let output = match self.config.log_path {
             Some(ref path) => File::create(path).map(|f| Box::new(f) as Box<dyn Write>),
-            None => Ok(Box::new(io::stdout()) as Box<dyn Write>),
+            None => Ok(stdio::messages()),
         };
 
         let mut o = output.unwrap_or_else(|e| {
-            println!("Failed to open the log file: {e}");
-            Box::new(io::stdout()) as Box<dyn Write>
+            eprintln!("Failed to open the log file: {e}");
+            stdio::messages()
         });
 
         // Sanity check that each node is really connected to its peers and the peers also
         // have the node in their connections.
         writeln!(o, "IPS algorithm started...").unwrap();
-        let start_time = std::time::Instant::now();
+        let start_time = Instant::now();
+        self.report = IpsReport::default();
 
         writeln!(o, "Checking for nodes connected to themselves...").unwrap();
         for (idx, node) in state.nodes.iter().enumerate() {
             if node.connections.contains(&idx) {
                 writeln!(o, "{} is connected to itself.", node.addr).unwrap();
+                self.report.self_connections += 1;
             }
 
             for peer in &node.connections {
                 if !state.nodes[*peer].connections.contains(&idx) {
+                    self.report.asymmetric_connections += 1;
                     writeln!(
                         o,
                         "{} is not connected to {} but {} have a connection to it",
@@ -138,27 +350,59 @@ impl Ips {
         }
 
         let network_nodes = filter_network(&state.nodes, network);
+        self.dump_debug_state(&mut o, "filtered-nodes", &network_nodes);
+        if network_nodes.is_empty() {
+            return Err(IpsError::NoNodes(network));
+        }
 
         writeln!(
             o,
             "Network contains {} nodes and {} connections",
             network_nodes.len(),
-            network_nodes
-                .iter()
-                .fold(0, |acc, n| acc + n.connections.len())
+            network_nodes.iter().fold(0, |acc, n| acc + n.degree)
         )
         .unwrap();
 
-        writeln!(o, "Generating initial network state and its statistics... ").unwrap();
+        let generate_full = network_nodes.len() != state.nodes.len();
+        self.optimize(
+            &mut o,
+            network_nodes,
+            generate_full,
+            num_threads,
+            start_time,
+        )
+    }
 
-        // This is the working set of factors.
-        let mut working_state = self.generate_state(&network_nodes, true, num_threads);
-        let mut final_state = working_state.clone();
+    /// Generate peer lists for the given nodes - security checks and the MCDA optimization.
+    /// If `generate_full` is false, betweenness and closeness of the nodes are assumed to be
+    /// up to date.
+    fn optimize(
+        &mut self,
+        o: &mut Box<dyn Write>,
+        mut network_nodes: Vec<Node>,
+        generate_full: bool,
+        num_threads: usize,
+        start_time: Instant,
+    ) -> Result<Vec<Peer>, IpsError> {
+        writeln!(o, "Generating initial network state and its statistics... ").unwrap();
 
-        let initial_statistics = generate_statistics(&working_state);
+        // This is the working set of factors. Centrality measures computed for the whole state
+        // are still valid if filtering didn't remove any node. Connections are moved from the
+        // nodes to the graph of the state.
+        let graph = CsrGraph::take_connections(&mut network_nodes);
+        let mut working_state =
+            self.generate_state(network_nodes, graph, generate_full, num_threads);
+        // Connections of the given network are kept only to describe the peers
+        let given_graph = self
+            .config
+            .peer_details
+            .then(|| working_state.graph.clone());
+        self.dump_debug_state(o, "initial-state", &working_state);
+
+        let initial_statistics = generate_full_statistics(&working_state, num_threads);
 
         writeln!(o, "Statistics for the initial network:").unwrap();
-        print_statistics(&mut o, &initial_statistics);
+        print_statistics(o, &initial_statistics);
 
         writeln!(
             o,
@@ -167,16 +411,18 @@ impl Ips {
         )
         .unwrap();
 
-        if let Some(path) = &self.config.vanilla_peer_file_path {
-            let peerlist = serde_json::to_string(&working_state.peer_list).unwrap();
-            fs::write(path, peerlist).unwrap();
-        }
-
         // Phase 1: Security checks
 
         // Detect islands
-        let islands = self.detect_islands(&working_state.nodes);
+        let islands = self.detect_islands(&working_state.graph);
+        self.report.island_sizes = islands.iter().map(|island| island.len()).collect();
         if islands.len() > 1 {
+            self.report.security.islands = island_membership(
+                &working_state.nodes,
+                &islands,
+                (working_state.nodes.len() as f64 * MASSIVE_ISLAND_PERCENTAGE).round() as usize,
+            );
+
             // Check if we're talking about massive islands or just a few nodes
             let mut massive_islands_count = 0;
             for island in &islands {
@@ -188,12 +434,22 @@ impl Ips {
                     massive_islands_count += 1;
                 }
             }
+            self.report.massive_islands = massive_islands_count;
 
             if massive_islands_count > 1 {
                 // We need to break here. Merging big islands can be a very complex task especially
                 // when they started to live their lives and created their own blockchain history
                 // after separation.
-                panic!("There are more than one massive island in the network. It is not possible to merge them automatically.");
+                if !self.config.analyze_islands_independently {
+                    return Err(IpsError::MultipleMassiveIslands(massive_islands_count));
+                }
+
+                writeln!(
+                    o,
+                    "IPS detected {massive_islands_count} massive islands. Each of them will be analyzed independently."
+                )
+                .unwrap();
+                return self.optimize_islands(o, &working_state, &islands, num_threads, start_time);
             }
 
             writeln!(
@@ -201,27 +457,119 @@ impl Ips {
                 "IPS detected no massive islands. However, there are some disconnected nodes."
             )
             .unwrap();
+
+            if self.config.island_repair_connections > 0 {
+                let added = self.repair_islands(&working_state, &islands);
+                writeln!(
+                    o,
+                    "IPS joined {} small islands to the largest island with {} new connections. Recalculating graph...",
+                    self.report.repaired_islands, self.report.island_repair_connections
+                )
+                .unwrap();
+                let nodes = std::mem::take(&mut working_state.nodes);
+                let graph = working_state.graph.with_connections(&added);
+                working_state = self.generate_state(nodes, graph, true, num_threads);
+                self.dump_debug_state(o, "island-repair-state", &working_state);
+            }
         } else {
             // There are no islands
             writeln!(o, "IPS detected no islands").unwrap();
         }
 
-        if !self.check_and_fix_integrity_upon_removal(&mut working_state) {
+        // Peer lists start from the network as it is now, the connections added below to keep
+        // the network integral change only the working state
+        let mut peer_lists = working_state.graph.to_lists();
+
+        self.report.integrity_preserved =
+            self.check_and_fix_integrity_upon_removal(&mut working_state);
+        if !self.report.integrity_preserved {
             writeln!(o, "There were hot nodes that can be dangerous for the network! Recalculating graph...").unwrap();
-            working_state = self.generate_state(&working_state.nodes, true, num_threads);
+            let nodes = std::mem::take(&mut working_state.nodes);
+            let graph = std::mem::take(&mut working_state.graph);
+            working_state = self.generate_state(nodes, graph, true, num_threads);
         } else {
             // There are no hot nodes
             writeln!(o, "IPS detected no fragmentation possibility even when top nodes would be disconnected").unwrap();
         }
 
+        self.dump_debug_state(o, "security-phase-state", &working_state);
+
         // Now take the current params
         let degree_avg = degree_centrality_avg(&working_state.degrees);
 
         // Detect possible bridges
-        let bridges = find_bridges(
-            &working_state.nodes,
-            self.config.bridge_threshold_adjustment,
-        );
+        let mut bridges = match self.config.bridge_detection {
+            BridgeDetection::Heuristic => find_bridges(
+                &working_state.nodes,
+                &working_state.graph,
+                self.config.bridge_threshold_adjustment,
+            ),
+            BridgeDetection::Exact => {
+                let (bridges, articulation_points) = find_exact_bridges(&working_state.graph);
+                writeln!(
+                    o,
+                    "IPS detected {} bridges and {} articulation points",
+                    bridges.values().map(|peers| peers.len()).sum::<usize>() / 2,
+                    articulation_points.len()
+                )
+                .unwrap();
+                self.report.articulation_points = Some(articulation_points.len());
+                let mut articulation_points = articulation_points
+                    .iter()
+                    .map(|idx| working_state.nodes[*idx].addr)
+                    .collect::<Vec<_>>();
+                articulation_points.sort_unstable();
+                self.report.security.articulation_points = articulation_points;
+                bridges
+            }
+        };
+        self.report.bridges = bridges.values().map(|peers| peers.len()).sum::<usize>() / 2;
+        self.report.security.bridges = bridge_connections(&working_state.nodes, &bridges);
+
+        // Connections of pinned nodes must never be removed - protect them the same way
+        self.report.pinned_nodes =
+            self.protect_pinned(&working_state.nodes, &working_state.graph, &mut bridges);
+        if self.report.pinned_nodes > 0 {
+            writeln!(
+                o,
+                "IPS protects connections of {} pinned nodes",
+                self.report.pinned_nodes
+            )
+            .unwrap();
+        }
+
+        self.report.blacklisted_nodes = working_state
+            .nodes
+            .iter()
+            .filter(|node| self.config.blacklist.contains(node))
+            .count();
+        self.report.outdated_nodes = working_state
+            .nodes
+            .iter()
+            .filter(|node| {
+                protocol_version_too_low(node.protocol_version, self.config.min_protocol_version)
+            })
+            .count();
+        self.report.shared_ip_nodes = shared_ip_nodes(&working_state.nodes);
+
+        // Cluster sizes are counted within the network peer lists are generated for
+        let mut cluster_sizes = HashMap::new();
+        for id in working_state
+            .nodes
+            .iter()
+            .filter_map(|node| node.geo_cluster_id)
+        {
+            *cluster_sizes.entry(id).or_insert(0) += 1;
+        }
+        self.mega_geo_clusters = match self.config.mega_geo_cluster_size {
+            Some(min_size) => cluster_sizes
+                .into_iter()
+                .filter(|(_, size)| *size >= min_size)
+                .map(|(id, _)| id)
+                .collect(),
+            None => HashSet::new(),
+        };
+        self.report.mega_geo_clusters = self.mega_geo_clusters.len();
 
         // Phase 2: Generate peer list using MCDA optimization.
 
@@ -231,158 +579,111 @@ impl Ips {
         // location. Now we can compute each node's constant rating based on some graph params.
         let const_factors = self.calculate_const_factors(&working_state);
 
-        // Iterate over nodes to generate peerlist entry for each node
-        for (node_idx, node) in working_state.nodes.iter().enumerate() {
-            let node_addr = node.addr;
-
-            // Clone const factors for each node to be able to modify them
-            let mut peer_ratings = const_factors.clone();
-
-            let mut curr_peer_ratings: Vec<PeerEntry> = Vec::new();
-
-            // 1 - update ranks by location for specified node
-            // This need to be done every time as location ranking will change for differently
-            // located nodes.
-            if self.config.geolocation != GeoLocationMode::Off {
-                self.update_rating_by_location(node, &working_state.nodes, &mut peer_ratings);
-            }
-
-            // Load peerlist with current connections (we don't want to change everything)
-            for peer in &final_state.nodes[node_idx].connections {
-                // Remember current peer ratings
-                curr_peer_ratings.push(peer_ratings[*peer]);
-            }
-
-            // Get current node's degree for further computations
-            let degree = *working_state.degrees.get(&node_addr).expect(ERR_GET_DEGREE);
-
-            // 2 - Calculate desired vertex degree
-            // In the first iteration we will use degree average so all nodes should pursue to
-            // that level. That could be bad if graph's vertexes have very high (or low) degrees
-            // and therefore, delta is very high (or low) too. But until we have some better idea
-            // this one is the best we can do to keep up with the graph.
-            let desired_degree = degree_avg.round() as u32;
-
-            // 3 - Calculate how many peers to add or delete from peerlist
-            let mut peers_to_delete_count = if desired_degree < degree {
-                degree.saturating_sub(desired_degree)
-            } else {
-                // Check if config forces to change peerlist even if we have good degree.
-                // This should be always set to at least one to allow for some changes in graph -
-                // searching for better potential peers.
-                self.config.change_at_least
-            };
-
-            // Limit number of changes to config value
-            if peers_to_delete_count > self.config.change_no_more {
-                peers_to_delete_count = self.config.change_no_more;
-            }
-
-            // Calculating how many peers should be added. If we have more peers than desired degree
-            // we will add at least config.change_at_least peers.
-            let mut peers_to_add_count = if desired_degree > degree {
-                desired_degree
-                    .saturating_sub(degree)
-                    .saturating_add(peers_to_delete_count)
-            } else {
-                self.config.change_at_least
-            };
+        // Rating peers for each node depends only on the working state, so it can be done in
+        // parallel. Choosing peers depends on the changes made for the previously processed
+        // nodes, so it is done sequentially in batches, which keeps the result deterministic.
+        let batch_size = num_threads.max(1) * MCDA_BATCH_PER_THREAD;
+        let mut throttle = GeoThrottle::new(&self.config);
+        let mut peer_factors = PeerFactors::new();
+        for batch_start in (0..working_state.nodes.len()).step_by(batch_size) {
+            let batch_end = (batch_start + batch_size).min(working_state.nodes.len());
+            let rated_batch = self.rate_peers(
+                &working_state,
+                &const_factors,
+                batch_start..batch_end,
+                num_threads,
+            );
 
-            // Limit number of changes to config value
-            if peers_to_add_count > self.config.change_no_more {
-                peers_to_add_count = self.config.change_no_more;
+            for (node_idx, rated) in (batch_start..batch_end).zip(rated_batch) {
+                self.report.same_asn_avoided += self.select_peers(
+                    node_idx,
+                    rated,
+                    &working_state,
+                    &mut peer_lists,
+                    &bridges,
+                    degree_avg,
+                    &mut throttle,
+                    &mut peer_factors,
+                );
             }
+        }
+        self.report.throttled_candidates = throttle.throttled;
 
-            // Remove potential peers identified to have too high degree and have already
-            // been processed by the algorithm
-            peer_ratings.retain(|x| {
-                final_state.nodes[x.index].connections.len()
-                    < working_state.nodes[x.index].connections.len()
-            });
-
-            // Remove nodes that reached max conn limit
-            peer_ratings.retain(|x| {
-                final_state.nodes[x.index]
-                    .connections
-                    .len()
-                    .abs_diff(working_state.nodes[x.index].connections.len())
-                    <= self.config.change_no_more as usize
-            });
-
-            // Remove node itself to ensure we don't add it to peerlist
-            peer_ratings.retain(|x| x.index != node_idx);
-
-            // Sort peers by rating (highest first)
-            curr_peer_ratings.sort_by(|a, b| b.rating.partial_cmp(&a.rating).unwrap());
+        // The final network has the same nodes with their own centralities. The state is
+        // regenerated for each network evaluated from now on.
+        let mut final_state = IpsState {
+            nodes: working_state.nodes.clone(),
+            ..Default::default()
+        };
 
-            // 4 - Choose peers to delete from peerlist (based on ranking)
-            while peers_to_delete_count > 0 {
-                if let Some(peer) = curr_peer_ratings.pop() {
-                    // Check if we're not deleting a bridge
-                    if bridges.contains_key(&peer.index) && bridges[&peer.index].contains(&node_idx)
+        if self.config.optimizer == Optimizer::Genetic {
+            writeln!(o, "The genetic optimizer is starting...").unwrap();
+            let mut optimizer = GeneticOptimizer::new(
+                &self.config.genetic,
+                &working_state.nodes,
+                &working_state.graph,
+                &bridges,
+                self.config.change_no_more,
+                self.seed,
+            )
+            .with_max_peer_list_size(self.config.max_peer_list_size)
+            .with_limits(self.connection_limits(), GeoThrottle::new(&self.config))
+            .excluding(
+                (0..working_state.nodes.len())
+                    .filter(|idx| self.never_suggested(&working_state.nodes[*idx]))
+                    .collect(),
+            );
+            // Networks splitting the largest component can't be on the Pareto front
+            let track_pareto = self.config.pareto_file_path.is_some();
+            let mut front = ParetoFront::default();
+            let (evolved, fitness) =
+                optimizer.evolve(&[&working_state.graph, &peer_lists], |network| {
+                    self.regenerate_state(
+                        &working_state,
+                        &mut final_state,
+                        CsrGraph::from_lists(network),
+                        num_threads,
+                    );
+                    let statistics = generate_statistics(&final_state, num_threads);
+                    if track_pareto
+                        && statistics.component_size() >= initial_statistics.component_size()
                     {
-                        continue;
+                        let changes = ConnectionChanges::between(&working_state.graph, network);
+                        let objectives = statistics.objectives(changes.churn());
+                        front.insert(objectives, changes);
                     }
-                    curr_peer_ratings.retain(|x| x != &peer);
-                }
-                peers_to_delete_count -= 1;
-            }
-
-            // 5 - Find peers to add from selected peers (based on rating)
-            if peers_to_add_count > 0 {
-                // Sort peers by rating
-                peer_ratings.sort_by(|a, b| b.rating.partial_cmp(&a.rating).unwrap());
-
-                let mut candidates = peer_ratings
-                    .iter()
-                    .filter(|x| {
-                        // Check if we're not adding a node that is already connected to us
-                        if final_state.nodes[x.index].connections.contains(&node_idx) {
-                            return false;
-                        }
-
-                        // Check if we're not adding a node that is already connected to us
-                        if final_state.nodes[node_idx].connections.contains(&x.index) {
-                            return false;
-                        }
-
-                        true
-                    })
-                    .take((peers_to_add_count * 2) as usize) // Take twice as many candidates
-                    .copied()
-                    .collect::<Vec<_>>();
-
-                // Here we have 2*peers_to_add_count candidates to add sorted by ranking.
-                // We need to choose best ones from them - let's choose those with lowest
-                // betweenness factor - just to avoid creating "hot" nodes that have very high
-                // importance to the network which can be risky if such node goes down.
-                candidates.sort_by(|a, b| {
-                    working_state.nodes[a.index]
-                        .betweenness
-                        .partial_cmp(&working_state.nodes[b.index].betweenness)
-                        .unwrap()
+                    statistics.fitness()
                 });
-
-                for peer in candidates.iter().take(peers_to_add_count as usize) {
-                    curr_peer_ratings.push(*peer);
-                    final_state.nodes[peer.index].connections.push(node_idx);
-                }
-
-                // Write new node set
-                final_state.nodes[node_idx].connections = curr_peer_ratings
+            writeln!(
+                o,
+                "Best fitness of each generation: {}",
+                fitness
                     .iter()
-                    .map(|x| x.index)
-                    .collect::<Vec<usize>>()
-                    .to_vec();
-
-                // Eliminate duplicates, the node itself and shrink vector
-                final_state.nodes[node_idx].connections.sort();
-                final_state.nodes[node_idx].connections.dedup();
-                final_state.nodes[node_idx]
-                    .connections
-                    .retain(|x| *x != node_idx);
-                final_state.nodes[node_idx].connections.shrink_to_fit();
+                    .map(|f| f.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .unwrap();
+            if track_pareto {
+                writeln!(o, "Pareto front contains {} networks", front.len()).unwrap();
+                self.report.pareto_front = front.solutions(
+                    &working_state.nodes,
+                    &ConnectionChanges::between(&working_state.graph, &evolved),
+                );
             }
+            peer_lists = evolved;
+            self.report.genetic_fitness = fitness;
+        }
+
+        if let Some(min_countries) = self.config.min_peer_countries {
+            self.repair_geo_spread(
+                o,
+                &working_state,
+                &mut peer_lists,
+                &bridges,
+                min_countries,
+                &mut throttle,
+            );
         }
 
         writeln!(
@@ -392,18 +693,50 @@ impl Ips {
         )
         .unwrap();
 
-        final_state = self.generate_state(&final_state.nodes, true, num_threads);
+        self.regenerate_state(
+            &working_state,
+            &mut final_state,
+            CsrGraph::from_lists(&peer_lists),
+            num_threads,
+        );
 
-        let final_statistics = generate_statistics(&final_state);
+        let final_statistics = generate_full_statistics(&final_state, num_threads);
         writeln!(o, "Statistics for the final network:").unwrap();
-        print_statistics(&mut o, &final_statistics);
+        print_statistics(o, &final_statistics);
 
         writeln!(
             o,
             "Comparing if network parameters got changed on plus or minus:"
         )
         .unwrap();
-        print_statistics_delta(&mut o, &final_statistics, &initial_statistics);
+        print_statistics_delta(o, &final_statistics, &initial_statistics);
+
+        self.report.statistics_delta = statistics_delta(&final_statistics, &initial_statistics);
+
+        // Networks of the simulations are evaluated in the final state, which isn't needed anymore
+        let mut peers = std::mem::take(&mut final_state.peer_list);
+        self.simulate_adoption(
+            o,
+            &working_state,
+            &peer_lists,
+            &mut final_state,
+            &initial_statistics,
+            num_threads,
+        );
+        self.simulate_canary(
+            o,
+            &working_state,
+            &peer_lists,
+            &mut final_state,
+            &initial_statistics,
+            num_threads,
+        );
+        self.report.initial_statistics = Some(initial_statistics);
+        self.report.final_statistics = Some(final_statistics);
+
+        if let Some(given_graph) = given_graph {
+            self.describe_peers(&given_graph, &working_state, &peer_factors, &mut peers);
+        }
 
         writeln!(
             o,
@@ -412,7 +745,7 @@ impl Ips {
         )
         .unwrap();
 
-        final_state.peer_list
+        Ok(peers)
     }
 
     // Helper functions
@@ -421,95 +754,146 @@ impl Ips {
     /// betweenness factor.
     /// Return true if integrity is preserved, false otherwise. If false is returned the caller
     /// should try to regenerate the network.
-    fn check_and_fix_integrity_upon_removal(&self, state: &mut IpsState) -> bool {
-        let mut high_betweenness = state
-            .nodes
-            .iter()
-            .map(|x| x.betweenness)
-            .collect::<Vec<f64>>();
-
-        high_betweenness.sort_by(|a, b| b.partial_cmp(a).unwrap());
-
-        let mut test_state = state.clone();
-        let mut removed_idx = Vec::new();
-
+    fn check_and_fix_integrity_upon_removal(&mut self, state: &mut IpsState) -> bool {
         // Take some % of nodes with highest betweenness
+        let mut high_betweenness = (0..state.nodes.len()).collect::<Vec<usize>>();
+        high_betweenness.sort_by(|a, b| {
+            state.nodes[*b]
+                .betweenness
+                .partial_cmp(&state.nodes[*a].betweenness)
+                .unwrap()
+        });
         let nodes_to_remove =
             (high_betweenness.len() as f64 * NODES_TO_BE_REMOVED_PERCENTAGE).round() as usize;
-        for b in high_betweenness.iter().take(nodes_to_remove) {
-            let idx = test_state
-                .nodes
-                .iter()
-                .position(|x| x.betweenness == *b)
-                .unwrap();
-            remove_node(&mut test_state.nodes, idx);
-            removed_idx.push(idx);
+        high_betweenness.truncate(nodes_to_remove);
+        for idx in high_betweenness.iter() {
+            self.report.security.hot_nodes.push(HotNode {
+                addr: state.nodes[*idx].addr,
+                betweenness: state.nodes[*idx].betweenness,
+            });
         }
 
-        let islands = self.detect_islands(&test_state.nodes);
+        // Removed nodes keep their indices, each of them is left as an island of its own
+        let removed = high_betweenness.iter().copied().collect::<HashSet<usize>>();
+        let remaining = state.graph.without_nodes(&removed);
+        let islands = self.detect_islands(&remaining);
         let mut massive_island = 0;
         if islands.len() > 1 {
             // Consider network as not integral if there are more than 1 islands with at least
             // some % of nodes. Don't consider islands with less than some % of nodes as they would
             // probably have no meaning for the network itself.
+            let remaining_count = state.nodes.len() - removed.len();
             for island in islands.iter() {
+                if island.iter().all(|idx| removed.contains(idx)) {
+                    continue;
+                }
                 if island.len()
-                    > (test_state.nodes.len() as f64 * MASSIVE_ISLAND_PERCENTAGE).round() as usize
+                    > (remaining_count as f64 * MASSIVE_ISLAND_PERCENTAGE).round() as usize
                 {
                     massive_island += 1;
                 }
             }
         }
 
+        self.report.security.fragmentation_possible = massive_island > 1;
         if massive_island > 1 {
             // If we're able to fragment the network into more than 1 massive islands then try to fix it
             // by adding new connections between highest betweenness node's neighbors.
-            for node_idx in removed_idx {
-                let mut conns = state.nodes[node_idx].connections.clone();
+            let mut added: Vec<(usize, usize)> = Vec::new();
+            for node_idx in high_betweenness {
+                let mut conns = state.graph.neighbors(node_idx).to_vec();
+                for (a, b) in added.iter() {
+                    if *a == node_idx {
+                        conns.push(*b);
+                    } else if *b == node_idx {
+                        conns.push(*a);
+                    }
+                }
                 let node_a_idx = find_lowest_betweenness(&conns, state);
                 // Remove node_a_idx from conns
                 conns.retain(|x| *x != node_a_idx);
                 let node_b_idx = find_lowest_betweenness(&conns, state);
 
-                state.nodes[node_a_idx].connections.push(node_b_idx);
-                state.nodes[node_b_idx].connections.push(node_a_idx);
+                added.push((node_a_idx, node_b_idx));
+                self.report.security.repairs.push(Repair {
+                    reason: RepairReason::Fragmentation,
+                    nodes: [state.nodes[node_a_idx].addr, state.nodes[node_b_idx].addr],
+                });
             }
+            state.graph = state.graph.with_connections(&added);
             return false;
         }
 
         true
     }
 
-    /// Generate state for IPS
+    /// Generate state for IPS from the nodes and their connections.
     /// If generate_full is true, then it will generate full state for IPS. If false then
     /// it will not re-run betweenness and closeness centrality calculations.
-    fn generate_state(&self, nodes: &[Node], generate_full: bool, num_threads: usize) -> IpsState {
+    fn generate_state(
+        &self,
+        nodes: Vec<Node>,
+        graph: CsrGraph,
+        generate_full: bool,
+        num_threads: usize,
+    ) -> IpsState {
         let mut ips_state = IpsState {
-            nodes: nodes.to_vec(),
+            nodes,
+            graph,
             ..Default::default()
         };
+        self.update_state(&mut ips_state, generate_full, num_threads);
+        ips_state
+    }
+
+    /// Recalculate factors of the state after its graph changed. Centrality measures are
+    /// recalculated only if generate_full is true.
+    fn update_state(&self, ips_state: &mut IpsState, generate_full: bool, num_threads: usize) {
+        update_degrees_from(&mut ips_state.nodes, &ips_state.graph);
 
-        let mut graph = construct_graph(nodes);
+        let mut graph = construct_graph(&ips_state.nodes, &ips_state.graph);
 
         if generate_full {
-            let closeness = graph.closeness_centrality(num_threads);
-            let betweenness = graph.betweenness_centrality(num_threads, false);
+            let all = (0..ips_state.nodes.len()).collect::<Vec<usize>>();
+            let closeness = self.closeness(
+                &mut graph,
+                &ips_state.nodes,
+                &ips_state.graph,
+                &all,
+                num_threads,
+            );
+            let betweenness = guarded("betweenness", || {
+                graph.betweenness_centrality(num_threads, false)
+            });
 
             // Recalculate factors with new graph
             for node in ips_state.nodes.iter_mut() {
                 let addr = node.addr;
-                node.betweenness = *betweenness.get(&addr).expect("can't fetch betweenness");
-                node.closeness = *closeness.get(&addr).expect("can't fetch closeness");
+                node.betweenness = value_or_fallback(&betweenness, &addr);
+                node.closeness = value_or_fallback(&closeness, &addr);
             }
         }
 
-        ips_state.degrees = graph.degree_centrality();
-        ips_state.eigenvalues = graph.eigenvalue_centrality();
+        // Degrees come from the nodes, so IPS and the rest of crunchy count them the same way
+        ips_state.degrees = ips_state
+            .nodes
+            .iter()
+            .map(|node| (node.addr, node.degree as u32))
+            .collect();
+        ips_state.eigenvalues = guarded("eigenvector", || graph.eigenvalue_centrality());
+        for node in &ips_state.nodes {
+            ips_state
+                .eigenvalues
+                .entry(node.addr)
+                .or_insert(FALLBACK_CENTRALITY);
+        }
 
+        // Normalization factors can't be determined only for an empty network - all ratings are
+        // 0.0 then.
         ips_state.degree_factors = NormalizationFactors::determine(
             &ips_state.degrees.values().cloned().collect::<Vec<u32>>(),
         )
-        .expect("can't calculate degree factors");
+        .unwrap_or_default();
 
         ips_state.eigenvector_factors = NormalizationFactors::determine(
             &ips_state
@@ -518,154 +902,1317 @@ impl Ips {
                 .cloned()
                 .collect::<Vec<f64>>(),
         )
-        .expect("can't calculate eigenvector factors");
+        .unwrap_or_default();
 
-        let betweenness = &nodes.iter().map(|n| n.betweenness).collect::<Vec<f64>>();
-        ips_state.betweenness_factors = NormalizationFactors::determine(betweenness)
-            .expect("can't calculate betweenness factors");
+        let betweenness = &ips_state
+            .nodes
+            .iter()
+            .map(|n| n.betweenness)
+            .collect::<Vec<f64>>();
+        ips_state.betweenness_factors =
+            NormalizationFactors::determine(betweenness).unwrap_or_default();
 
-        let closeness = &nodes.iter().map(|n| n.closeness).collect::<Vec<f64>>();
+        let closeness = &ips_state
+            .nodes
+            .iter()
+            .map(|n| n.closeness)
+            .collect::<Vec<f64>>();
         ips_state.closeness_factors =
-            NormalizationFactors::determine(closeness).expect("can't calculate closeness factors");
+            NormalizationFactors::determine(closeness).unwrap_or_default();
 
-        ips_state.peer_list = Peer::generate_all_peerlists(nodes);
-
-        ips_state
+        ips_state.peer_list = Peer::generate_all_peerlists(
+            &ips_state.nodes,
+            &ips_state.graph,
+            self.config.latency_per_hop_ms,
+        );
     }
 
-    /// Calculates const factors for each node.
-    fn calculate_const_factors(&self, state: &IpsState) -> Vec<PeerEntry> {
-        let mut const_factors = Vec::with_capacity(state.nodes.len());
+    /// Regenerate the state (with the same nodes as the previous state) for the new graph,
+    /// reusing betweenness and closeness computed for the previous state. Both measures depend
+    /// only on paths inside the node's component, so only components with changed connections
+    /// are recomputed.
+    fn regenerate_state(
+        &self,
+        previous: &IpsState,
+        state: &mut IpsState,
+        graph: CsrGraph,
+        num_threads: usize,
+    ) {
+        for (node, previous) in state.nodes.iter_mut().zip(previous.nodes.iter()) {
+            node.betweenness = previous.betweenness;
+            node.closeness = previous.closeness;
+        }
 
-        for (index, node) in state.nodes.iter().enumerate() {
-            let addr = node.addr;
-            const_factors.push(PeerEntry {
-                addr,
-                index,
-                rating: self.rate_node(node, state),
+        let changed = changed_components(&previous.graph, &graph);
+        if !changed.is_empty() {
+            let nodes = &mut state.nodes;
+            let mut changed_graph = Graph::new();
+            for idx in changed.iter() {
+                let connections = graph.neighbors(*idx);
+                if connections.is_empty() {
+                    changed_graph.insert(Edge::new(nodes[*idx].addr, nodes[*idx].addr));
+                }
+                for peer_idx in connections.iter().filter(|i| **i < nodes.len()) {
+                    changed_graph.insert(Edge::new(nodes[*idx].addr, nodes[*peer_idx].addr));
+                }
+            }
+
+            let closeness =
+                self.closeness(&mut changed_graph, nodes, &graph, &changed, num_threads);
+            let betweenness = guarded("betweenness", || {
+                changed_graph.betweenness_centrality(num_threads, false)
             });
+
+            for idx in changed {
+                let node = &mut nodes[idx];
+                node.betweenness = value_or_fallback(&betweenness, &node.addr);
+                node.closeness = value_or_fallback(&closeness, &node.addr);
+            }
         }
-        const_factors
+
+        state.graph = graph;
+        self.update_state(state, false, num_threads);
     }
 
-    /// Update nodes rating based on location
-    fn update_rating_by_location(
+    /// Closeness of the `sources` nodes by their address. Standard closeness is computed for all
+    /// nodes of the graph, while harmonic closeness is computed on all the nodes (as nodes of
+    /// other components add nothing to it) only for the sources.
+    fn closeness(
         &self,
-        selected_node: &Node,
+        graph: &mut Graph<SocketAddr>,
         nodes: &[Node],
-        ratings: &mut [PeerEntry],
+        adjacency: &(impl Adjacency + ?Sized),
+        sources: &[usize],
+        num_threads: usize,
+    ) -> HashMap<SocketAddr, f64> {
+        match self.closeness_variant {
+            ClosenessVariant::Standard => {
+                guarded("closeness", || graph.closeness_centrality(num_threads))
+            }
+            ClosenessVariant::Harmonic => harmonic_closeness(adjacency, sources, num_threads)
+                .into_iter()
+                .map(|(idx, closeness)| (nodes[idx].addr, closeness))
+                .collect(),
+        }
+    }
+
+    /// Check if the node must never be suggested as a new peer - it's blacklisted or announces
+    /// a protocol version lower than the required one.
+    fn never_suggested(&self, node: &Node) -> bool {
+        self.config.blacklist.contains(node)
+            || protocol_version_too_low(node.protocol_version, self.config.min_protocol_version)
+    }
+
+    /// Limits of new connections taken from the configuration.
+    fn connection_limits(&self) -> ConnectionLimits {
+        ConnectionLimits {
+            max_peers_per_ip: self.config.max_peers_per_ip,
+            max_peers_per_asn: self.config.max_peers_per_asn,
+            mega_geo_clusters: self.mega_geo_clusters.clone(),
+        }
+    }
+
+    /// Make peer lists span at least `min_countries` countries where possible. A peer list
+    /// spanning fewer gets a peer from a missing country - the candidate with the lowest
+    /// betweenness, so no new hot node is created. If the list can't grow, the new peer replaces
+    /// a peer from a country present more than once, unless the replaced peer's own list (already
+    /// repaired) would lose a country it needs. Connections change only within the same limits
+    /// as in the MCDA procedure (including the recommendations already counted by the throttle),
+    /// so some peer lists may stay short of countries - they are reported in the log.
+    fn repair_geo_spread(
+        &mut self,
+        o: &mut Box<dyn Write>,
+        working_state: &IpsState,
+        peer_lists: &mut [Vec<usize>],
+        bridges: &HashMap<usize, HashSet<usize>>,
+        min_countries: usize,
+        throttle: &mut GeoThrottle,
     ) {
-        if selected_node.geolocation.is_none() {
+        let nodes = &working_state.nodes;
+        let country_of = |idx: usize| nodes[idx].geolocation.as_ref()?.country.clone();
+        if (0..nodes.len()).all(|idx| country_of(idx).is_none()) {
+            writeln!(
+                o,
+                "No node has a known country - the peer lists can't be spread"
+            )
+            .unwrap();
             return;
         }
+        let original_degree = |idx: usize| nodes[idx].degree;
+        let can_grow = |peer_lists: &[Vec<usize>], idx: usize| {
+            let degree = peer_lists[idx].len();
+            !churn_exceeded(original_degree(idx), degree + 1, self.config.change_no_more)
+                && !peer_list_too_long(degree + 1, self.config.max_peer_list_size)
+        };
+        // Number of peers in each country, leaving out the given peer
+        let countries_of = |peer_lists: &[Vec<usize>], idx: usize, without: Option<usize>| {
+            let mut countries = HashMap::new();
+            for peer in peer_lists[idx].iter() {
+                if Some(*peer) == without {
+                    continue;
+                }
+                if let Some(country) = country_of(*peer) {
+                    *countries.entry(country).or_insert(0) += 1;
+                }
+            }
+            countries
+        };
+        let limits = self.connection_limits();
+
+        for node_idx in 0..nodes.len() {
+            loop {
+                let connections = &peer_lists[node_idx];
+                let countries = countries_of(peer_lists, node_idx, None);
+                if countries.len() >= min_countries {
+                    break;
+                }
 
-        let selected_location =
-            if let Some(coordinates) = selected_node.geolocation.as_ref().unwrap().coordinates {
-                coordinates
-            } else {
-                return;
-            };
+                // The connection goes both ways, so both nodes count as recommended
+                let candidate = (0..nodes.len())
+                    .filter(|idx| *idx != node_idx && !connections.contains(idx))
+                    .filter(|idx| country_of(*idx).is_some_and(|c| !countries.contains_key(&c)))
+                    .filter(|idx| {
+                        !self.avoided.contains(&nodes[*idx].addr)
+                            && !self.never_suggested(&nodes[*idx])
+                            && can_grow(peer_lists, *idx)
+                            && throttle.allows(&nodes[*idx])
+                            && throttle.allows(&nodes[node_idx])
+                            && limits.allow(nodes, node_idx, *idx, [connections, &peer_lists[*idx]])
+                    })
+                    .min_by(|a, b| nodes[*a].betweenness.total_cmp(&nodes[*b].betweenness));
+                // Without room for another peer, one from a country present more than once goes
+                let replaced = if can_grow(peer_lists, node_idx) {
+                    Some(None)
+                } else {
+                    connections
+                        .iter()
+                        .copied()
+                        .filter(|peer| {
+                            !is_bridge(bridges, node_idx, *peer)
+                                && country_of(*peer).is_none_or(|c| countries[&c] > 1)
+                                && !churn_exceeded(
+                                    original_degree(*peer),
+                                    peer_lists[*peer].len().saturating_sub(1),
+                                    self.config.change_no_more,
+                                )
+                        })
+                        // Peers not processed yet are repaired on their turn
+                        .filter(|peer| {
+                            let spread = countries_of(peer_lists, *peer, Some(node_idx)).len();
+                            *peer > node_idx
+                                || spread >= min_countries
+                                || spread == countries_of(peer_lists, *peer, None).len()
+                        })
+                        .max_by_key(|peer| peer_lists[*peer].len())
+                        .map(Some)
+                };
+
+                match (candidate, replaced) {
+                    (Some(candidate), Some(replaced)) => {
+                        if let Some(peer) = replaced {
+                            peer_lists[node_idx].retain(|p| *p != peer);
+                            peer_lists[peer].retain(|p| *p != node_idx);
+                        }
+                        peer_lists[node_idx].push(candidate);
+                        peer_lists[candidate].push(node_idx);
+                        throttle.record(&nodes[candidate]);
+                        throttle.record(&nodes[node_idx]);
+                        self.report.geo_spread_repairs += 1;
+                    }
+                    _ => {
+                        writeln!(
+                            o,
+                            "{} has peers in only {} countries ({} required)",
+                            nodes[node_idx].addr,
+                            countries.len(),
+                            min_countries
+                        )
+                        .unwrap();
+                        self.report.geo_spread_violations += 1;
+                        break;
+                    }
+                }
+            }
+        }
+        writeln!(
+            o,
+            "Geographic spread: {} connections added, {} peer lists still span too few countries",
+            self.report.geo_spread_repairs, self.report.geo_spread_violations
+        )
+        .unwrap();
+    }
 
+    /// Add connections of the pinned nodes to the bridges, so they are never removed. Returns
+    /// the number of pinned nodes.
+    fn protect_pinned(
+        &self,
+        nodes: &[Node],
+        graph: &(impl Adjacency + ?Sized),
+        bridges: &mut HashMap<usize, HashSet<usize>>,
+    ) -> usize {
+        let mut pinned_count = 0;
         for (node_idx, node) in nodes.iter().enumerate() {
-            if node.geolocation.is_none() {
+            let ip = node.addr.ip();
+            if !self.config.pinned.iter().any(|range| range.contains(&ip)) {
                 continue;
             }
 
-            let geo_info = node.geolocation.as_ref().unwrap();
-            if geo_info.coordinates.is_none() {
-                continue;
+            pinned_count += 1;
+            for peer in graph.connections(node_idx) {
+                bridges.entry(*peer).or_default().insert(node_idx);
+                bridges.entry(node_idx).or_default().insert(*peer);
             }
+        }
+        pinned_count
+    }
 
-            let distance = selected_location.distance_to(geo_info.coordinates.unwrap());
-            let minmax_distance_m = self.config.geolocation_minmax_distance_km as f64 * 1000.0;
+    /// Join each small island to the largest island. Each new connection links the next best
+    /// rated node of the small island with the best rated node of the largest island (from the
+    /// point of view of the island node, so location is taken into account) which is not already
+    /// its peer. Returns the new connections.
+    fn repair_islands(
+        &mut self,
+        state: &IpsState,
+        islands: &[HashSet<usize>],
+    ) -> Vec<(usize, usize)> {
+        let nodes = &state.nodes;
+        let mut connections = Vec::new();
+        let const_factors = self.calculate_const_factors(state);
+        // Ties are broken by index, so repairs don't depend on the hash set order
+        let by_rating = |ratings: &[PeerEntry], island: &HashSet<usize>| {
+            let mut indices = island.iter().copied().collect::<Vec<usize>>();
+            indices.sort_by(|a, b| {
+                ratings[*b]
+                    .rating
+                    .total_cmp(&ratings[*a].rating)
+                    .then_with(|| a.cmp(b))
+            });
+            indices
+        };
 
-            // Map distance to some levels of rating - now they are taken arbitrarily but
-            // they should be somehow related to the distance.
-            let rating = if self.config.geolocation == GeoLocationMode::PreferCloser {
-                match distance {
-                    _ if distance < minmax_distance_m => NORMALIZE_TO_VALUE,
-                    _ if distance < 2.0 * minmax_distance_m => NORMALIZE_2_3,
-                    _ if distance < 3.0 * minmax_distance_m => NORMALIZE_1_3,
-                    _ => 0.0,
+        let largest = match islands.iter().max_by_key(|island| island.len()) {
+            Some(largest) => largest,
+            None => return connections,
+        };
+        let mut small_islands = islands
+            .iter()
+            .filter(|island| !std::ptr::eq(*island, largest))
+            .collect::<Vec<_>>();
+        small_islands.sort_by_key(|island| island.iter().min().copied());
+
+        for island in small_islands {
+            let sources = by_rating(&const_factors, island);
+            let mut added = 0;
+            for source in sources
+                .iter()
+                .cycle()
+                .take(self.config.island_repair_connections)
+            {
+                let mut ratings = const_factors.clone();
+                if self.config.geolocation != GeoLocationMode::Off {
+                    self.update_rating_by_location(
+                        &nodes[*source],
+                        state.graph.neighbors(*source),
+                        nodes,
+                        &mut ratings,
+                    );
                 }
-            } else {
-                match distance {
-                    _ if distance < 0.5 * minmax_distance_m => 0.0,
-                    _ if distance < minmax_distance_m => NORMALIZE_HALF,
-                    _ => NORMALIZE_TO_VALUE,
+
+                let target = by_rating(&ratings, largest).into_iter().find(|target| {
+                    !state.graph.neighbors(*source).contains(target)
+                        && !connections.contains(&(*source, *target))
+                        && !self.avoided.contains(&nodes[*target].addr)
+                        && !self.never_suggested(&nodes[*target])
+                });
+                if let Some(target) = target {
+                    connections.push((*source, target));
+                    self.report.security.repairs.push(Repair {
+                        reason: RepairReason::IslandRepair,
+                        nodes: [nodes[*source].addr, nodes[target].addr],
+                    });
+                    added += 1;
                 }
-            };
-            ratings[node_idx].rating += rating * self.config.mcda_weights.location;
+            }
+
+            if added > 0 {
+                self.report.repaired_islands += 1;
+                self.report.island_repair_connections += added;
+            }
         }
+
+        connections
     }
 
-    fn rate_node(&self, node: &Node, state: &IpsState) -> f64 {
-        // Calculate rating for node (if min == max for normalization factors then rating is
-        // not increased for that factor as lerp() returns 0.0).
-        // Rating is a combination of the following factors:
-        let mut rating = 0.0;
+    /// Generate peer lists for each massive island separately. Nodes of other (small) islands keep
+    /// their current peer lists.
+    fn optimize_islands(
+        &mut self,
+        o: &mut Box<dyn Write>,
+        state: &IpsState,
+        islands: &[HashSet<usize>],
+        num_threads: usize,
+        start_time: Instant,
+    ) -> Result<Vec<Peer>, IpsError> {
+        let nodes = &state.nodes;
+        let massive_island_size = (nodes.len() as f64 * MASSIVE_ISLAND_PERCENTAGE).round() as usize;
+        let mut peers = Vec::with_capacity(nodes.len());
+
+        for (island_idx, island) in islands.iter().enumerate() {
+            let island_nodes = extract_island(nodes, &state.graph, island);
+            if island.len() <= massive_island_size {
+                peers.append(&mut Peer::generate_all_peerlists(
+                    &island_nodes,
+                    &island_nodes,
+                    self.config.latency_per_hop_ms,
+                ));
+                continue;
+            }
 
-        let addr = node.addr;
-        let degree = *state.degrees.get(&addr).expect(ERR_GET_DEGREE);
-        let eigenvalue = *state.eigenvalues.get(&addr).expect(ERR_GET_EIGENVECTOR);
+            writeln!(
+                o,
+                "Analyzing island {island_idx} with {} nodes...",
+                island_nodes.len()
+            )
+            .unwrap();
+
+            let mut config = self.config.clone();
+            config.debug_dump_dir = config
+                .debug_dump_dir
+                .map(|dir| dir.join(format!("island-{island_idx}")));
 
-        // 1. Degree
-        rating += state.degree_factors.scale(degree as f64)
-            * NORMALIZE_TO_VALUE
-            * self.config.mcda_weights.degree;
+            let mut island_ips = Ips::new(config)
+                .with_seed(self.seed)
+                .with_closeness_variant(self.closeness_variant)
+                .avoiding(self.avoided.clone());
+            peers.append(&mut island_ips.optimize(
+                o,
+                island_nodes,
+                true,
+                num_threads,
+                start_time,
+            )?);
+            self.report
+                .debug_dumps
+                .extend(island_ips.report.debug_dumps.iter().cloned());
+            self.report
+                .canary_nodes
+                .extend(island_ips.report.canary_nodes.iter().copied());
+            self.report
+                .security
+                .merge(island_ips.report.security.clone());
+            self.report.island_reports.push(island_ips.report);
+        }
 
-        // 2. Betweenness
-        rating += state.betweenness_factors.scale(node.betweenness)
-            * NORMALIZE_TO_VALUE
-            * self.config.mcda_weights.betweenness;
+        Ok(peers)
+    }
 
-        // 3. Closeness
-        rating += state.closeness_factors.scale(node.closeness)
-            * NORMALIZE_TO_VALUE
-            * self.config.mcda_weights.closeness;
+    /// Compute statistics of networks where only the configured percentages of nodes adopted
+    /// their peer lists. Each network is evaluated in the scratch state.
+    fn simulate_adoption(
+        &mut self,
+        o: &mut Box<dyn Write>,
+        working_state: &IpsState,
+        recommended: &[Vec<usize>],
+        state: &mut IpsState,
+        initial_statistics: &Statistics,
+        num_threads: usize,
+    ) {
+        for adoption_rate in self.config.adoption_rates.clone() {
+            let adopting = adopting_nodes(
+                &working_state.nodes,
+                adoption_rate,
+                &self.config.adoption_selection,
+                self.seed,
+            );
+            let network = partially_adopted(&working_state.graph, recommended, &adopting);
+            let graph = CsrGraph::from_lists(&network);
+            self.regenerate_state(working_state, state, graph, num_threads);
+            let statistics = generate_statistics(state, num_threads);
 
-        // 4. Eigenvector
-        rating += state.eigenvector_factors.scale(eigenvalue)
-            * NORMALIZE_TO_VALUE
-            * self.config.mcda_weights.eigenvector;
+            writeln!(
+                o,
+                "Comparing network parameters when {adoption_rate}% of nodes ({}) adopt peer lists:",
+                adopting.len()
+            )
+            .unwrap();
+            print_statistics_delta(o, &statistics, initial_statistics);
 
-        rating
+            self.report.adoption_simulations.push(AdoptionSimulation {
+                adoption_rate,
+                adopting_nodes: adopting.len(),
+                statistics_delta: statistics_delta(&statistics, initial_statistics),
+                statistics,
+            });
+        }
     }
 
-    // Very simple algorithm to detect islands.
-    // Take first vertex and do BFS to find all connected vertices. If there are any unvisited vertices
-    // create new island and do BFS one more time. Repeat until all vertices are visited.
-    fn detect_islands(&self, nodes: &[Node]) -> Vec<HashSet<usize>> {
-        let mut islands = Vec::new();
-        let mut visited = vec![false; nodes.len()];
+    /// Choose the canary nodes (if configured) and compute statistics of the network where only
+    /// they adopted their peer lists. The network is evaluated in the scratch state.
+    fn simulate_canary(
+        &mut self,
+        o: &mut Box<dyn Write>,
+        working_state: &IpsState,
+        recommended: &[Vec<usize>],
+        state: &mut IpsState,
+        initial_statistics: &Statistics,
+        num_threads: usize,
+    ) {
+        let canary = match &self.config.canary {
+            Some(canary) => canary,
+            None => return,
+        };
 
-        for i in 0..nodes.len() {
-            if visited[i] {
-                continue;
+        let canary = canary_nodes(
+            &working_state.nodes,
+            canary,
+            &self.config.adoption_selection,
+            self.seed,
+        );
+        let network = partially_adopted(&working_state.graph, recommended, &canary);
+        let graph = CsrGraph::from_lists(&network);
+        self.regenerate_state(working_state, state, graph, num_threads);
+        let statistics = generate_statistics(state, num_threads);
+
+        writeln!(
+            o,
+            "Comparing network parameters when canary nodes ({}) adopt peer lists:",
+            canary.len()
+        )
+        .unwrap();
+        print_statistics_delta(o, &statistics, initial_statistics);
+
+        let mut canary = canary.into_iter().collect::<Vec<usize>>();
+        canary.sort();
+        self.report.canary_nodes = canary
+            .iter()
+            .map(|idx| working_state.nodes[*idx].addr)
+            .collect();
+        self.report.canary_simulation = Some(AdoptionSimulation {
+            adoption_rate: 100.0 * canary.len() as f64 / working_state.nodes.len().max(1) as f64,
+            adopting_nodes: canary.len(),
+            statistics_delta: statistics_delta(&statistics, initial_statistics),
+            statistics,
+        });
+    }
+
+    /// Write an intermediate artifact to the debug dump directory (if configured). Failures are
+    /// only logged as dumps are not needed to generate peer lists.
+    fn dump_debug_state<T: Serialize>(&mut self, o: &mut Box<dyn Write>, name: &str, value: &T) {
+        let dir = match &self.config.debug_dump_dir {
+            Some(dir) => dir,
+            None => return,
+        };
+
+        let path = dir.join(format!("{name}.json"));
+        let res = fs::create_dir_all(dir)
+            .and_then(|_| fs::write(&path, serde_json::to_string(value).unwrap()));
+        match res {
+            Ok(_) => self.report.debug_dumps.push(path),
+            Err(e) => writeln!(o, "Failed to write {}: {e}", path.display()).unwrap(),
+        }
+    }
+
+    /// Rate all peers for each node from the given range. Nodes are split between the given
+    /// number of threads.
+    fn rate_peers(
+        &self,
+        state: &IpsState,
+        const_factors: &[PeerEntry],
+        range: Range<usize>,
+        num_threads: usize,
+    ) -> Vec<RatedPeers> {
+        let chunk_size = (range.len() / num_threads.max(1)).max(1);
+        let node_indices = range.collect::<Vec<usize>>();
+        let nodes = &state.nodes;
+
+        thread::scope(|s| {
+            let handles = node_indices
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    s.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|node_idx| {
+                                // Clone const factors for each node to be able to modify them
+                                let mut ratings = const_factors.to_vec();
+                                let mut factors = Vec::new();
+
+                                // Update ranks by the factors depending on the node - this needs
+                                // to be done every time as e.g. location ranking will change for
+                                // differently located nodes.
+                                for (factor, enabled, update) in self.node_factors() {
+                                    if !enabled {
+                                        continue;
+                                    }
+                                    let node = &nodes[*node_idx];
+                                    let peers = state.graph.neighbors(*node_idx);
+                                    if !self.config.peer_details {
+                                        update(self, node, peers, nodes, &mut ratings);
+                                        continue;
+                                    }
+                                    let before =
+                                        ratings.iter().map(|x| x.rating).collect::<Vec<f64>>();
+                                    update(self, node, peers, nodes, &mut ratings);
+                                    let contributions = ratings
+                                        .iter()
+                                        .zip(before)
+                                        .map(|(x, before)| x.rating - before)
+                                        .collect();
+                                    factors.push((factor, contributions));
+                                }
+
+                                // Sort peers by rating (highest first). Sort is stable, so
+                                // peers with equal rating keep their order.
+                                let mut order = (0..ratings.len()).collect::<Vec<usize>>();
+                                order.sort_by(|a, b| {
+                                    ratings[*b].rating.total_cmp(&ratings[*a].rating)
+                                });
+
+                                RatedPeers {
+                                    ratings,
+                                    order,
+                                    factors,
+                                }
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().expect("peer rating thread panicked"))
+                .collect()
+        })
+    }
+
+    /// Choose peers to delete from and add to the node's peerlist and update the final state.
+    /// Factor contributions of the chosen peers are recorded if peer details are enabled - a new
+    /// connection is also recorded for the peer until it chooses its own peers. Returns the
+    /// number of candidates from the node's own autonomous system passed over for comparable
+    /// ones.
+    #[allow(clippy::too_many_arguments)]
+    fn select_peers(
+        &self,
+        node_idx: usize,
+        rated: RatedPeers,
+        working_state: &IpsState,
+        peer_lists: &mut [Vec<usize>],
+        bridges: &HashMap<usize, HashSet<usize>>,
+        degree_avg: f64,
+        throttle: &mut GeoThrottle,
+        peer_factors: &mut PeerFactors,
+    ) -> usize {
+        let node_addr = working_state.nodes[node_idx].addr;
+        let ip_of = |idx: &usize| working_state.nodes[*idx].addr.ip();
+        let asn_of = |idx: &usize| working_state.nodes[*idx].asn;
+        let mut curr_peer_ratings: Vec<PeerEntry> = Vec::new();
+        let mut same_asn_avoided = 0;
+
+        // Load peerlist with current connections (we don't want to change everything)
+        for peer in &peer_lists[node_idx] {
+            // Remember current peer ratings
+            curr_peer_ratings.push(rated.ratings[*peer]);
+        }
+
+        // Get current node's degree for further computations
+        let degree = *working_state.degrees.get(&node_addr).expect(ERR_GET_DEGREE);
+
+        // 2 - Calculate desired vertex degree
+        // In the first iteration we will use degree average so all nodes should pursue to
+        // that level. That could be bad if graph's vertexes have very high (or low) degrees
+        // and therefore, delta is very high (or low) too. But until we have some better idea
+        // this one is the best we can do to keep up with the graph.
+        let desired_degree = degree_avg.round() as u32;
+
+        // 3 - Calculate how many peers to add or delete from peerlist
+        let mut peers_to_delete_count = if desired_degree < degree {
+            degree.saturating_sub(desired_degree)
+        } else {
+            // Check if config forces to change peerlist even if we have good degree.
+            // This should be always set to at least one to allow for some changes in graph -
+            // searching for better potential peers.
+            self.config.change_at_least
+        };
+
+        // Limit number of changes to config value
+        if peers_to_delete_count > self.config.change_no_more {
+            peers_to_delete_count = self.config.change_no_more;
+        }
+
+        // Peer list size limit is a hard one - delete as many peers as needed to fit in
+        if let Some(max) = self.config.max_peer_list_size {
+            peers_to_delete_count = peers_to_delete_count.max(
+                curr_peer_ratings
+                    .len()
+                    .saturating_sub(max)
+                    .try_into()
+                    .unwrap_or(u32::MAX),
+            );
+        }
+
+        // Calculating how many peers should be added. If we have more peers than desired degree
+        // we will add at least config.change_at_least peers.
+        let mut peers_to_add_count = if desired_degree > degree {
+            desired_degree
+                .saturating_sub(degree)
+                .saturating_add(peers_to_delete_count)
+        } else {
+            self.config.change_at_least
+        };
+
+        // Limit number of changes to config value
+        if peers_to_add_count > self.config.change_no_more {
+            peers_to_add_count = self.config.change_no_more;
+        }
+
+        // Sort peers by rating (highest first)
+        curr_peer_ratings.sort_by(|a, b| b.rating.total_cmp(&a.rating));
+
+        // 4 - Choose peers to delete from peerlist (based on ranking)
+        let mut protected_peers = Vec::new();
+        while peers_to_delete_count > 0 {
+            if let Some(peer) = curr_peer_ratings.pop() {
+                // Check if we're not deleting a bridge
+                if is_bridge(bridges, node_idx, peer.index) {
+                    protected_peers.push(peer);
+                    continue;
+                }
+                curr_peer_ratings.retain(|x| x != &peer);
             }
+            peers_to_delete_count -= 1;
+        }
 
-            let mut island = HashSet::new();
-            let mut queue = VecDeque::new();
-            queue.push_back(i);
+        // Bridges were taken from the list only to be skipped - put them back
+        curr_peer_ratings.append(&mut protected_peers);
+
+        // 5 - Find peers to add from selected peers (based on rating). Connections go both ways,
+        // so nodes which are never suggested as peers don't get new ones either.
+        if peers_to_add_count > 0 && !self.never_suggested(&working_state.nodes[node_idx]) {
+            let node_asn = working_state.nodes[node_idx].asn;
+            let is_same_asn = |x: &PeerEntry| same_asn(node_asn, working_state.nodes[x.index].asn);
+            let pool_size = (peers_to_add_count * self.config.candidate_pool_multiplier) as usize;
+            let mut candidates = Vec::with_capacity(pool_size);
+            // Candidates from the node's own autonomous system with a comparable alternative
+            let mut deferred = HashSet::new();
+
+            // Peers are already sorted by rating (highest first)
+            let filtered = rated
+                .order
+                .iter()
+                .map(|index| rated.ratings[*index])
+                .filter(|x| {
+                    // Remove node itself to ensure we don't add it to peerlist
+                    if x.index == node_idx {
+                        return false;
+                    }
+
+                    let final_len = peer_lists[x.index].len();
+                    let working_len = working_state.nodes[x.index].degree;
+
+                    // Remove potential peers identified to have too high degree and have already
+                    // been processed by the algorithm
+                    if final_len >= working_len {
+                        return false;
+                    }
+
+                    // Remove nodes that reached max conn limit
+                    if churn_exceeded(working_len, final_len, self.config.change_no_more) {
+                        return false;
+                    }
+
+                    // Remove nodes whose peer list can't grow anymore
+                    if peer_list_too_long(final_len + 1, self.config.max_peer_list_size) {
+                        return false;
+                    }
+
+                    // Check if we're not adding a node that is already connected to us
+                    if peer_lists[x.index].contains(&node_idx) {
+                        return false;
+                    }
+
+                    // Check if we're not adding a node that is already connected to us
+                    if peer_lists[node_idx].contains(&x.index) {
+                        return false;
+                    }
+
+                    // Route recommendations away from nodes getting hotter
+                    if self.avoided.contains(&x.addr) {
+                        return false;
+                    }
+
+                    // Never suggest blacklisted or outdated nodes
+                    if self.never_suggested(&working_state.nodes[x.index]) {
+                        return false;
+                    }
+
+                    // Nodes behind one IP address are likely the same host - the node's own
+                    // address is skipped and the others can't crowd either peer list
+                    if let Some(max) = self.config.max_peers_per_ip {
+                        let node_ips = curr_peer_ratings.iter().map(|peer| ip_of(&peer.index));
+                        let peer_ips = peer_lists[x.index].iter().map(ip_of);
+                        if x.addr.ip() == node_addr.ip()
+                            || ip_limit_reached(node_ips, x.addr.ip(), Some(max))
+                            || ip_limit_reached(peer_ips, node_addr.ip(), Some(max))
+                        {
+                            return false;
+                        }
+                    }
+
+                    // No single provider may dominate either peer list
+                    if let Some(max) = self.config.max_peers_per_asn {
+                        let node_asns = curr_peer_ratings.iter().map(|peer| asn_of(&peer.index));
+                        let peer_asns = peer_lists[x.index].iter().map(asn_of);
+                        if asn_limit_reached(node_asns, asn_of(&x.index), Some(max))
+                            || asn_limit_reached(peer_asns, node_asn, Some(max))
+                        {
+                            return false;
+                        }
+                    }
 
-            while let Some(node_idx) = queue.pop_front() {
-                if visited[node_idx] {
+                    // Don't concentrate connections further within a mega-cluster
+                    if let Some(cluster) = working_state.nodes[node_idx].geo_cluster_id {
+                        if self.mega_geo_clusters.contains(&cluster)
+                            && working_state.nodes[x.index].geo_cluster_id == Some(cluster)
+                        {
+                            return false;
+                        }
+                    }
+
+                    true
+                });
+            // Don't funnel the network toward a single country or city. Checked only for
+            // candidates entering the pool, so only those count as throttled.
+            let mut admit = |x: &PeerEntry| {
+                let allowed = throttle.allows(&working_state.nodes[x.index]);
+                if !allowed {
+                    throttle.throttled += 1;
+                }
+                allowed
+            };
+
+            // Take more candidates than needed to have a choice. Connections within one
+            // autonomous system (likely one provider or datacenter) add little resilience - with
+            // the tolerance set, such candidates don't count to the pool if there is a comparable
+            // candidate from another one. Candidates come highest rated first, so the first one
+            // from another autonomous system is the best alternative. The search for alternatives
+            // looks only a fixed number of candidates past the pool.
+            match self.config.same_asn_tolerance {
+                None => candidates.extend(filtered.filter(|x| admit(x)).take(pool_size)),
+                Some(tolerance) => {
+                    let mut best_alternative = None;
+                    let mut pending = Vec::new();
+                    for x in filtered.take(pool_size + SAME_ASN_LOOK_AHEAD) {
+                        let pool_full = candidates.len() >= pool_size;
+                        if is_same_asn(&x) && best_alternative.is_none() && pool_full {
+                            // The pool is full - unless an alternative turns up
+                            continue;
+                        }
+                        if !admit(&x) {
+                            continue;
+                        }
+                        if !is_same_asn(&x) {
+                            if best_alternative.is_none() {
+                                best_alternative = Some(x.rating);
+                                deferred.extend(
+                                    pending
+                                        .drain(..)
+                                        .filter(|y: &PeerEntry| {
+                                            comparable_rating(y.rating, x.rating, tolerance)
+                                        })
+                                        .map(|y| y.index),
+                                );
+                            }
+                        } else if best_alternative.is_some() {
+                            deferred.insert(x.index);
+                        } else {
+                            pending.push(x);
+                        }
+                        candidates.push(x);
+                        if candidates.len() - deferred.len() >= pool_size && pending.is_empty() {
+                            break;
+                        }
+                    }
+                }
+            }
+
+            // Here we have multiplier*peers_to_add_count candidates to add sorted by ranking.
+            // We need to choose best ones from them - by default let's choose those with
+            // lowest betweenness factor - just to avoid creating "hot" nodes that have very
+            // high importance to the network which can be risky if such node goes down.
+            match self.config.candidate_sort {
+                CandidateSort::Rating => {}
+                CandidateSort::Betweenness => candidates.sort_by(|a, b| {
+                    working_state.nodes[a.index]
+                        .betweenness
+                        .total_cmp(&working_state.nodes[b.index].betweenness)
+                }),
+                CandidateSort::Degree => candidates
+                    .sort_by_key(|x| *working_state.degrees.get(&x.addr).expect(ERR_GET_DEGREE)),
+            }
+
+            // Deferred candidates which would be picked without the tolerance
+            let preferred = candidates
+                .iter()
+                .take(peers_to_add_count as usize)
+                .map(|x| x.index)
+                .filter(|index| deferred.contains(index))
+                .collect::<Vec<usize>>();
+
+            // Deferred candidates go last, so they are added only when alternatives run out.
+            // Sort is stable, so the order is kept otherwise.
+            candidates.sort_by_key(|x| deferred.contains(&x.index));
+
+            let mut added_count = 0;
+            for peer in candidates.iter() {
+                if added_count == peers_to_add_count
+                    || peer_list_too_long(
+                        curr_peer_ratings.len() + 1,
+                        self.config.max_peer_list_size,
+                    )
+                {
+                    break;
+                }
+                // Limits can be reached by candidates added before
+                let peer_node = &working_state.nodes[peer.index];
+                if !throttle.allows(peer_node) {
+                    throttle.throttled += 1;
                     continue;
                 }
+                if ip_limit_reached(
+                    curr_peer_ratings.iter().map(|peer| ip_of(&peer.index)),
+                    peer.addr.ip(),
+                    self.config.max_peers_per_ip,
+                ) || asn_limit_reached(
+                    curr_peer_ratings.iter().map(|peer| asn_of(&peer.index)),
+                    peer_node.asn,
+                    self.config.max_peers_per_asn,
+                ) {
+                    continue;
+                }
+                throttle.record(peer_node);
+
+                curr_peer_ratings.push(*peer);
+                peer_lists[peer.index].push(node_idx);
+                if !rated.factors.is_empty() {
+                    peer_factors
+                        .entry((peer.index, node_idx))
+                        .or_insert_with(|| rated.factors_of(peer.index));
+                }
+                added_count += 1;
+                deferred.remove(&peer.index);
+            }
+            same_asn_avoided = preferred
+                .iter()
+                .filter(|index| deferred.contains(index))
+                .count();
+
+            // Write new node set
+            peer_lists[node_idx] = curr_peer_ratings
+                .iter()
+                .map(|x| x.index)
+                .collect::<Vec<usize>>()
+                .to_vec();
+
+            // Eliminate duplicates, the node itself and shrink vector
+            peer_lists[node_idx].sort();
+            peer_lists[node_idx].dedup();
+            peer_lists[node_idx].retain(|x| *x != node_idx);
+            peer_lists[node_idx].shrink_to_fit();
+        }
+
+        // 6 - Remove connections to blacklisted nodes if requested (unless they are bridges)
+        if self.config.blacklist.remove_connections {
+            peer_lists[node_idx].retain(|peer| {
+                is_bridge(bridges, node_idx, *peer)
+                    || !self.config.blacklist.contains(&working_state.nodes[*peer])
+            });
+        }
+
+        // Bridges go first, then the best rated peers
+        let by_priority = |a: &usize, b: &usize| {
+            is_bridge(bridges, node_idx, *b)
+                .cmp(&is_bridge(bridges, node_idx, *a))
+                .then_with(|| {
+                    rated.ratings[*b]
+                        .rating
+                        .total_cmp(&rated.ratings[*a].rating)
+                })
+                .then_with(|| a.cmp(b))
+        };
+
+        // 7 - Enforce the limit of peers per IP address on existing peers as well, keeping
+        // bridges and the best rated peers
+        if let Some(max) = self.config.max_peers_per_ip {
+            let connections = &mut peer_lists[node_idx];
+            connections.sort_by(by_priority);
+            let mut kept = Vec::with_capacity(connections.len());
+            for peer in connections.iter() {
+                if is_bridge(bridges, node_idx, *peer)
+                    || !ip_limit_reached(kept.iter().map(ip_of), ip_of(peer), Some(max))
+                {
+                    kept.push(*peer);
+                }
+            }
+            kept.sort();
+            *connections = kept;
+        }
+
+        // 8 - Enforce the peer list size limit. Bridges could have been kept above it, or the
+        // list could have been left untouched - keep bridges first, then the best rated peers.
+        if let Some(max) = self.config.max_peer_list_size {
+            let connections = &mut peer_lists[node_idx];
+            if connections.len() > max {
+                connections.sort_by(by_priority);
+                connections.truncate(max);
+                connections.sort();
+            }
+        }
+
+        if !rated.factors.is_empty() {
+            for peer in peer_lists[node_idx].iter() {
+                peer_factors.insert((node_idx, *peer), rated.factors_of(*peer));
+            }
+        }
 
-                island.insert(node_idx);
+        same_asn_avoided
+    }
+
+    /// Describe each peer in the peer lists - its rating for the node as given by the MCDA
+    /// procedure in the working state, the factors contributing the most to it, and whether the
+    /// node was connected to it in the given nodes. Peers not chosen by the MCDA procedure are
+    /// described by the factors not depending on the node only.
+    fn describe_peers(
+        &self,
+        given_graph: &CsrGraph,
+        state: &IpsState,
+        peer_factors: &PeerFactors,
+        peers: &mut [Peer],
+    ) {
+        let indices = state
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(idx, node)| (node.addr, idx))
+            .collect::<HashMap<SocketAddr, usize>>();
+        let existing = state
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(idx, node)| {
+                let peers = given_graph
+                    .neighbors(idx)
+                    .iter()
+                    .filter_map(|peer| state.nodes.get(*peer).map(|peer| peer.addr))
+                    .collect::<HashSet<SocketAddr>>();
+                (node.addr, peers)
+            })
+            .collect::<HashMap<SocketAddr, HashSet<SocketAddr>>>();
+        let const_factors = state
+            .nodes
+            .iter()
+            .map(|node| self.rate_node_factors(node, state))
+            .collect::<Vec<_>>();
 
-                visited[node_idx] = true;
+        for peer in peers.iter_mut() {
+            let node_idx = match indices.get(&peer.ip) {
+                Some(idx) => *idx,
+                None => continue,
+            };
 
-                for j in 0..nodes[node_idx].connections.len() {
-                    if !visited[nodes[node_idx].connections[j]] {
-                        queue.push_back(nodes[node_idx].connections[j]);
+            peer.details = peer
+                .list
+                .iter()
+                .map(|addr| {
+                    let mut factors = indices
+                        .get(addr)
+                        .map(|idx| {
+                            let node_factors = peer_factors
+                                .get(&(node_idx, *idx))
+                                .into_iter()
+                                .flatten()
+                                .copied();
+                            const_factors[*idx]
+                                .iter()
+                                .copied()
+                                .chain(node_factors)
+                                .collect()
+                        })
+                        .unwrap_or_else(Vec::new);
+                    let rating = factors.iter().map(|(_, rating)| rating).sum();
+                    factors.retain(|(_, rating)| *rating != 0.0);
+                    factors.sort_by(|a, b| b.1.abs().total_cmp(&a.1.abs()));
+
+                    PeerDetails {
+                        rating,
+                        dominant_factors: factors
+                            .iter()
+                            .take(DOMINANT_FACTORS)
+                            .map(|(factor, contribution)| FactorContribution {
+                                factor: *factor,
+                                contribution: *contribution,
+                            })
+                            .collect(),
+                        existing: existing
+                            .get(&peer.ip)
+                            .is_some_and(|peers| peers.contains(addr)),
                     }
+                })
+                .collect();
+        }
+    }
+
+    /// Factors depending on the node, whether each is enabled and how it updates the ratings
+    fn node_factors(&self) -> [(RatingFactor, bool, UpdateRating); 4] {
+        let weights = &self.config.mcda_weights;
+        [
+            (
+                RatingFactor::Location,
+                self.config.geolocation != GeoLocationMode::Off,
+                Ips::update_rating_by_location,
+            ),
+            (
+                RatingFactor::AsnDiversity,
+                weights.asn_diversity != 0.0,
+                Ips::update_rating_by_asn,
+            ),
+            (
+                RatingFactor::CountryDiversity,
+                weights.country_diversity != 0.0,
+                Ips::update_rating_by_country,
+            ),
+            (
+                RatingFactor::Latency,
+                weights.latency != 0.0,
+                Ips::update_rating_by_latency,
+            ),
+        ]
+    }
+
+    /// Calculates const factors for each node.
+    fn calculate_const_factors(&self, state: &IpsState) -> Vec<PeerEntry> {
+        let mut const_factors = Vec::with_capacity(state.nodes.len());
+
+        for (index, node) in state.nodes.iter().enumerate() {
+            let addr = node.addr;
+            const_factors.push(PeerEntry {
+                addr,
+                index,
+                rating: self.rate_node(node, state),
+            });
+        }
+        const_factors
+    }
+
+    /// Update nodes rating based on location
+    fn update_rating_by_location(
+        &self,
+        selected_node: &Node,
+        _peers: &[usize],
+        nodes: &[Node],
+        ratings: &mut [PeerEntry],
+    ) {
+        if selected_node.geolocation.is_none() {
+            return;
+        }
+
+        let selected_location =
+            if let Some(coordinates) = selected_node.geolocation.as_ref().unwrap().coordinates {
+                coordinates
+            } else {
+                return;
+            };
+
+        let distances = nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(node_idx, node)| {
+                let coordinates = node.geolocation.as_ref()?.coordinates?;
+                Some((node_idx, selected_location.distance_to(coordinates)))
+            })
+            .collect::<Vec<(usize, f64)>>();
+
+        // Fixed threshold doesn't fit both dense clusters and remote nodes - if requested, the
+        // threshold is derived from the distribution of distances seen from the selected node.
+        let minmax_distance_m = match self.config.geolocation_distance_percentile {
+            Some(p) => {
+                let observed = distances.iter().map(|(_, d)| *d).collect::<Vec<f64>>();
+                percentile(&observed, p).unwrap_or_default()
+            }
+            None => self.config.geolocation_minmax_distance_km as f64 * 1000.0,
+        };
+
+        for (node_idx, distance) in distances {
+            // Map distance to some levels of rating - now they are taken arbitrarily but
+            // they should be somehow related to the distance.
+            let rating = if self.config.geolocation == GeoLocationMode::PreferCloser {
+                match distance {
+                    _ if distance < minmax_distance_m => NORMALIZE_TO_VALUE,
+                    _ if distance < 2.0 * minmax_distance_m => NORMALIZE_2_3,
+                    _ if distance < 3.0 * minmax_distance_m => NORMALIZE_1_3,
+                    _ => 0.0,
+                }
+            } else {
+                match distance {
+                    _ if distance < 0.5 * minmax_distance_m => 0.0,
+                    _ if distance < minmax_distance_m => NORMALIZE_HALF,
+                    _ => NORMALIZE_TO_VALUE,
+                }
+            };
+            ratings[node_idx].rating += rating * self.config.mcda_weights.location;
+        }
+    }
+
+    /// Update nodes rating based on their ASN - peers in the node's own ASN are penalized and
+    /// peers in ASNs not yet present in the node's peer list are rewarded.
+    fn update_rating_by_asn(
+        &self,
+        selected_node: &Node,
+        peers: &[usize],
+        nodes: &[Node],
+        ratings: &mut [PeerEntry],
+    ) {
+        let peer_asns = peers
+            .iter()
+            .filter_map(|peer| nodes[*peer].asn)
+            .collect::<HashSet<u32>>();
+
+        for (node_idx, node) in nodes.iter().enumerate() {
+            let asn = if let Some(asn) = node.asn {
+                asn
+            } else {
+                continue;
+            };
+
+            let rating = if selected_node.asn == Some(asn) {
+                -NORMALIZE_TO_VALUE
+            } else if !peer_asns.contains(&asn) {
+                NORMALIZE_TO_VALUE
+            } else {
+                0.0
+            };
+            ratings[node_idx].rating += rating * self.config.mcda_weights.asn_diversity;
+        }
+    }
+
+    /// Update nodes rating based on their country - peers in countries not yet present in the
+    /// node's peer list are rewarded.
+    fn update_rating_by_country(
+        &self,
+        _selected_node: &Node,
+        peers: &[usize],
+        nodes: &[Node],
+        ratings: &mut [PeerEntry],
+    ) {
+        let country_of = |node: &Node| node.geolocation.as_ref()?.country.clone();
+        let peer_countries = peers
+            .iter()
+            .filter_map(|peer| country_of(&nodes[*peer]))
+            .collect::<HashSet<String>>();
+
+        for (node_idx, node) in nodes.iter().enumerate() {
+            if let Some(country) = country_of(node) {
+                if !peer_countries.contains(&country) {
+                    ratings[node_idx].rating +=
+                        NORMALIZE_TO_VALUE * self.config.mcda_weights.country_diversity;
                 }
             }
-            islands.push(island);
         }
-        islands
+    }
+
+    /// Update nodes rating based on the estimated latency (RTT) to the node - the lower the
+    /// latency, the higher the rating (relative to the highest latency seen from the node).
+    fn update_rating_by_latency(
+        &self,
+        selected_node: &Node,
+        _peers: &[usize],
+        nodes: &[Node],
+        ratings: &mut [PeerEntry],
+    ) {
+        let latencies = nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(node_idx, node)| {
+                estimate_rtt_ms(selected_node, node, self.config.latency_per_hop_ms)
+                    .map(|rtt| (node_idx, rtt))
+            })
+            .collect::<Vec<(usize, f64)>>();
+
+        let max_latency = latencies.iter().map(|(_, rtt)| *rtt).fold(0.0, f64::max);
+        if max_latency == 0.0 {
+            return;
+        }
+
+        for (node_idx, rtt) in latencies {
+            ratings[node_idx].rating +=
+                (1.0 - rtt / max_latency) * NORMALIZE_TO_VALUE * self.config.mcda_weights.latency;
+        }
+    }
+
+    fn rate_node(&self, node: &Node, state: &IpsState) -> f64 {
+        self.rate_node_factors(node, state)
+            .iter()
+            .fold(0.0, |rating, (_, contribution)| rating + contribution)
+    }
+
+    /// Contribution of each factor to the node rating.
+    fn rate_node_factors(&self, node: &Node, state: &IpsState) -> [(RatingFactor, f64); 6] {
+        // Calculate rating for node (if min == max for normalization factors then rating is
+        // not increased for that factor as lerp() returns 0.0).
+        // Rating is a combination of the following factors:
+        let addr = node.addr;
+        let degree = *state.degrees.get(&addr).expect(ERR_GET_DEGREE);
+        let eigenvalue = *state.eigenvalues.get(&addr).expect(ERR_GET_EIGENVECTOR);
+
+        [
+            // 1. Degree
+            (
+                RatingFactor::Degree,
+                state.degree_factors.scale(degree as f64)
+                    * NORMALIZE_TO_VALUE
+                    * self.config.mcda_weights.degree,
+            ),
+            // 2. Betweenness
+            (
+                RatingFactor::Betweenness,
+                state.betweenness_factors.scale(node.betweenness)
+                    * NORMALIZE_TO_VALUE
+                    * self.config.mcda_weights.betweenness,
+            ),
+            // 3. Closeness
+            (
+                RatingFactor::Closeness,
+                state.closeness_factors.scale(node.closeness)
+                    * NORMALIZE_TO_VALUE
+                    * self.config.mcda_weights.closeness,
+            ),
+            // 4. Eigenvector
+            (
+                RatingFactor::Eigenvector,
+                state.eigenvector_factors.scale(eigenvalue)
+                    * NORMALIZE_TO_VALUE
+                    * self.config.mcda_weights.eigenvector,
+            ),
+            // 5. Uptime (flaky nodes are not rewarded, unknown uptime is not taken into account)
+            (
+                RatingFactor::Uptime,
+                node.uptime.map_or(0.0, |uptime| {
+                    uptime * NORMALIZE_TO_VALUE * self.config.mcda_weights.uptime
+                }),
+            ),
+            // 6. Stability (the same as uptime, over all the earlier snapshots given)
+            (
+                RatingFactor::Stability,
+                node.stability.map_or(0.0, |stability| {
+                    stability * NORMALIZE_TO_VALUE * self.config.mcda_weights.stability
+                }),
+            ),
+        ]
+    }
+
+    /// Detect islands (connected components) in the network.
+    fn detect_islands(&self, graph: &(impl Adjacency + ?Sized)) -> Vec<HashSet<usize>> {
+        detect_islands(graph)
     }
 }
 
@@ -680,36 +2227,289 @@ mod tests {
     use spectre::{edge::Edge, graph::Graph};
 
     use super::*;
+    use crate::{
+        ips::config::{BlacklistConfiguration, CanaryConfiguration, MultiCriteriaAnalysisWeights},
+        test_util::{addr, geo, geo_at, geo_info, ring_state},
+    };
 
     pub const ERR_PARSE_IP: &str = "failed to parse IP address";
 
     #[test]
-    fn rate_node_test() {
-        let ips_config = IPSConfiguration::default();
+    fn rate_node_test() {
+        let ips_config = IPSConfiguration::default();
+        let ips = Ips::new(ips_config);
+
+        let nodes = vec![
+            Node {
+                addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 1234),
+                connections: vec![1, 2],
+                ..Default::default()
+            },
+            Node {
+                addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 0, 0, 0)), 1234),
+                connections: vec![0, 2],
+                ..Default::default()
+            },
+            Node {
+                addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(2, 0, 0, 0)), 1234),
+                connections: vec![0, 1],
+                ..Default::default()
+            },
+        ];
+
+        let num_threads = thread::available_parallelism().unwrap().get();
+        let state = ips.generate_state(
+            nodes.clone(),
+            CsrGraph::from_nodes(&nodes),
+            true,
+            num_threads,
+        );
+
+        assert_eq!(ips.rate_node(nodes.first().unwrap(), &state), 10.0);
+    }
+
+    #[test]
+    fn rate_closeness_test() {
+        let ips = Ips::new(IPSConfiguration::default());
+        // Path 0 - 1 - 2 and a node without any connections
+        let nodes = [vec![1], vec![0, 2], vec![1], vec![]]
+            .into_iter()
+            .enumerate()
+            .map(|(idx, connections)| Node {
+                addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(idx as u8, 0, 0, 0)), 1234),
+                connections,
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+        let state = ips.generate_state(nodes.clone(), CsrGraph::from_nodes(&nodes), true, 2);
+        let closeness = |idx: usize| {
+            ips.rate_node_factors(&state.nodes[idx], &state)
+                .into_iter()
+                .find(|(factor, _)| *factor == RatingFactor::Closeness)
+                .unwrap()
+                .1
+        };
+
+        // Standard closeness is rated by its value as it always was
+        assert_eq!(state.closeness_factors.min, 0.0);
+        assert_eq!(state.closeness_factors.max, 1.5);
+        assert_eq!(
+            closeness(0),
+            NORMALIZE_TO_VALUE * ips.config.mcda_weights.closeness
+        );
+        assert_eq!(closeness(1), closeness(0) / 1.5);
+        assert_eq!(closeness(3), 0.0);
+    }
+
+    #[test]
+    fn rate_stability_test() {
+        let ips = Ips::new(IPSConfiguration {
+            mcda_weights: MultiCriteriaAnalysisWeights {
+                stability: 0.5,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        // Pair of nodes, one seen in half of the snapshots, the other with unknown stability
+        let nodes = [(vec![1], Some(0.5)), (vec![0], None)]
+            .into_iter()
+            .enumerate()
+            .map(|(idx, (connections, stability))| Node {
+                addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(idx as u8, 0, 0, 0)), 1234),
+                connections,
+                stability,
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+        let graph = CsrGraph::from_nodes(&nodes);
+        let state = ips.generate_state(nodes, graph, true, 2);
+        let stability = |idx: usize| {
+            ips.rate_node_factors(&state.nodes[idx], &state)
+                .into_iter()
+                .find(|(factor, _)| *factor == RatingFactor::Stability)
+                .unwrap()
+                .1
+        };
+
+        assert_eq!(stability(0), 0.5 * NORMALIZE_TO_VALUE * 0.5);
+        assert_eq!(stability(1), 0.0);
+    }
+
+    #[test]
+    fn update_rating_by_location_percentile_test() {
+        let node = |latitude: f64, longitude: f64| Node {
+            geolocation: geo_at(latitude, longitude),
+            ..Default::default()
+        };
+        // Dense cluster around Frankfurt and a few remote nodes
+        let nodes = vec![
+            node(50.11, 8.68),
+            node(50.0, 8.27),
+            node(49.49, 8.47),
+            node(48.14, 11.58),
+            node(52.52, 13.40),
+            node(35.68, 139.69),
+            node(-33.87, 151.21),
+        ];
+        let rate = |config: IPSConfiguration| {
+            let mut ratings = (0..nodes.len())
+                .map(|index| PeerEntry {
+                    addr: nodes[index].addr,
+                    index,
+                    rating: 0.0,
+                })
+                .collect::<Vec<_>>();
+            Ips::new(config).update_rating_by_location(&nodes[0], &[], &nodes, &mut ratings);
+            ratings.iter().map(|r| r.rating).collect::<Vec<_>>()
+        };
+
+        // All European nodes are within the fixed 1000 km threshold
+        let ips_config = IPSConfiguration::default();
+        let location_weight = ips_config.mcda_weights.location;
+        let ratings = rate(ips_config);
+        assert_eq!(ratings[4], NORMALIZE_TO_VALUE * location_weight);
+
+        // Only the closest third (including the node itself) stays within the threshold
+        let ratings = rate(IPSConfiguration {
+            geolocation_distance_percentile: Some(33.4),
+            ..Default::default()
+        });
+        assert_eq!(ratings[1], NORMALIZE_TO_VALUE * location_weight);
+        assert!(ratings[4] < NORMALIZE_TO_VALUE * location_weight);
+        assert_eq!(ratings[6], 0.0);
+    }
+
+    #[test]
+    fn geo_throttle_test() {
+        let node = |country: &str, city: &str| Node {
+            geolocation: geo_info(Some(country), Some(city), None),
+            ..Default::default()
+        };
+        let frankfurt = node("Germany", "Frankfurt");
+        let berlin = node("Germany", "Berlin");
+        let paris = node("France", "Paris");
+        let unknown = Node::default();
+
+        let mut throttle = GeoThrottle::new(&IPSConfiguration {
+            max_recommendations_per_country: Some(3),
+            max_recommendations_per_city: Some(2),
+            ..Default::default()
+        });
+        throttle.record(&frankfurt);
+        throttle.record(&frankfurt);
+        assert!(!throttle.allows(&frankfurt));
+        assert!(throttle.allows(&berlin));
+        throttle.record(&berlin);
+        assert!(!throttle.allows(&berlin));
+        assert!(throttle.allows(&paris));
+        throttle.record(&unknown);
+        assert!(throttle.allows(&unknown));
+
+        // No limits configured
+        let mut throttle = GeoThrottle::new(&IPSConfiguration::default());
+        for _ in 0..10 {
+            throttle.record(&frankfurt);
+        }
+        assert!(throttle.allows(&frankfurt));
+    }
+
+    #[test]
+    fn update_rating_by_country_test() {
+        let mut ips_config = IPSConfiguration::default();
+        ips_config.mcda_weights.country_diversity = 0.5;
+        let ips = Ips::new(ips_config);
+
+        let node = |connections: Vec<usize>, country: &str| Node {
+            connections,
+            geolocation: geo(country),
+            ..Default::default()
+        };
+        // Node 0 in Germany is connected to node 1 in France
+        let nodes = vec![
+            node(vec![1], "Germany"),
+            node(vec![0], "France"),
+            node(vec![], "France"),
+            node(vec![], "Japan"),
+            Node::default(),
+        ];
+        let mut ratings = (0..nodes.len())
+            .map(|index| PeerEntry {
+                addr: nodes[index].addr,
+                index,
+                rating: 0.0,
+            })
+            .collect::<Vec<_>>();
+
+        ips.update_rating_by_country(&nodes[0], &nodes[0].connections, &nodes, &mut ratings);
+        let ratings = ratings.iter().map(|r| r.rating).collect::<Vec<_>>();
+        assert_eq!(ratings, vec![50.0, 0.0, 0.0, 50.0, 0.0]);
+    }
+
+    #[test]
+    fn update_rating_by_latency_test() {
+        let mut ips_config = IPSConfiguration {
+            latency_per_hop_ms: 0.0,
+            ..Default::default()
+        };
+        ips_config.mcda_weights.latency = 0.5;
+        let ips = Ips::new(ips_config);
+
+        let node = |longitude: f64| Node {
+            geolocation: geo_at(0.0, longitude),
+            ..Default::default()
+        };
+        // Nodes on the equator - latency grows with the distance
+        let nodes = vec![
+            node(0.0),
+            node(0.0),
+            node(10.0),
+            node(20.0),
+            Node::default(),
+        ];
+        let mut ratings = (0..nodes.len())
+            .map(|index| PeerEntry {
+                addr: nodes[index].addr,
+                index,
+                rating: 0.0,
+            })
+            .collect::<Vec<_>>();
+
+        ips.update_rating_by_latency(&nodes[0], &[], &nodes, &mut ratings);
+        let ratings = ratings.iter().map(|r| r.rating.round()).collect::<Vec<_>>();
+        assert_eq!(ratings, vec![50.0, 50.0, 25.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn update_rating_by_asn_test() {
+        let mut ips_config = IPSConfiguration::default();
+        ips_config.mcda_weights.asn_diversity = 0.5;
         let ips = Ips::new(ips_config);
 
+        let node = |connections: Vec<usize>, asn: Option<u32>| Node {
+            connections,
+            asn,
+            ..Default::default()
+        };
+        // Node 0 in AS 1 is connected to node 1 in AS 2
         let nodes = vec![
-            Node {
-                addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 1234),
-                connections: vec![1, 2],
-                ..Default::default()
-            },
-            Node {
-                addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 0, 0, 0)), 1234),
-                connections: vec![0, 2],
-                ..Default::default()
-            },
-            Node {
-                addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(2, 0, 0, 0)), 1234),
-                connections: vec![0, 1],
-                ..Default::default()
-            },
+            node(vec![1], Some(1)),
+            node(vec![0], Some(2)),
+            node(vec![], Some(1)),
+            node(vec![], Some(2)),
+            node(vec![], Some(3)),
+            node(vec![], None),
         ];
+        let mut ratings = (0..nodes.len())
+            .map(|index| PeerEntry {
+                addr: nodes[index].addr,
+                index,
+                rating: 0.0,
+            })
+            .collect::<Vec<_>>();
 
-        let num_threads = thread::available_parallelism().unwrap().get();
-        let state = ips.generate_state(&nodes, true, num_threads);
-
-        assert_eq!(ips.rate_node(nodes.get(0).unwrap(), &state), 10.0);
+        ips.update_rating_by_asn(&nodes[0], &nodes[0].connections, &nodes, &mut ratings);
+        let ratings = ratings.iter().map(|r| r.rating).collect::<Vec<_>>();
+        assert_eq!(ratings, vec![-50.0, 0.0, -50.0, 0.0, 50.0, 0.0]);
     }
 
     #[tokio::test]
@@ -792,4 +2592,762 @@ mod tests {
 
         assert_eq!(islands.len(), nodes.len());
     }
+
+    fn two_islands_state() -> CrunchyState {
+        // Two fully connected islands with 6 nodes each
+        let mut nodes = Vec::new();
+        for island in 0..2 {
+            for i in 0..6 {
+                let first = island * 6;
+                nodes.push(Node {
+                    addr: SocketAddr::new(
+                        IpAddr::V4(Ipv4Addr::new(10, island as u8, 0, i as u8)),
+                        1234,
+                    ),
+                    network_type: NetworkType::Zcash,
+                    connections: (first..first + 6)
+                        .filter(|peer| *peer != first + i)
+                        .collect(),
+                    ..Default::default()
+                });
+            }
+        }
+
+        CrunchyState {
+            nodes,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn generate_massive_islands_error_test() {
+        let mut ips = Ips::new(IPSConfiguration {
+            peer_file_path: None,
+            ..Default::default()
+        });
+
+        let res = ips
+            .generate(&two_islands_state(), NetworkType::Zcash, 2)
+            .await;
+        assert!(matches!(res, Err(IpsError::MultipleMassiveIslands(2))));
+        assert_eq!(ips.report().massive_islands, 2);
+        // Islands are reported even though peer lists couldn't be generated
+        let islands = &ips.report().security.islands;
+        assert_eq!(islands.len(), 2);
+        assert!(islands.iter().all(|island| island.massive));
+        assert_eq!(islands[1].nodes.len(), 6);
+    }
+
+    #[tokio::test]
+    async fn generate_no_nodes_error_test() {
+        let mut ips = Ips::new(IPSConfiguration {
+            peer_file_path: None,
+            ..Default::default()
+        });
+
+        let res = ips
+            .generate(&two_islands_state(), NetworkType::Ripple, 2)
+            .await;
+        assert!(matches!(res, Err(IpsError::NoNodes(NetworkType::Ripple))));
+    }
+
+    #[tokio::test]
+    async fn generate_massive_islands_independently_test() {
+        let mut ips = Ips::new(IPSConfiguration {
+            peer_file_path: None,
+            analyze_islands_independently: true,
+            ..Default::default()
+        });
+
+        let state = two_islands_state();
+        let peers = ips.generate(&state, NetworkType::Zcash, 2).await.unwrap();
+        assert_eq!(peers.len(), 12);
+        assert_eq!(ips.report().island_reports.len(), 2);
+
+        // Peers are never chosen from the other island
+        for peer in peers {
+            let island = state.nodes.iter().position(|n| n.addr == peer.ip).unwrap() / 6;
+            for addr in peer.list {
+                assert_eq!(
+                    state.nodes.iter().position(|n| n.addr == addr).unwrap() / 6,
+                    island
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn generate_island_repair_test() {
+        // Fully connected island with 30 nodes and a small island with 2 nodes
+        let mut nodes = (0..32)
+            .map(|i| Node {
+                addr: addr(i),
+                network_type: NetworkType::Zcash,
+                connections: (0..30).filter(|peer| *peer != i).collect(),
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+        nodes[30].connections = vec![31];
+        nodes[31].connections = vec![30];
+        let state = CrunchyState {
+            nodes,
+            ..Default::default()
+        };
+
+        let mut ips = Ips::new(IPSConfiguration {
+            peer_file_path: None,
+            island_repair_connections: 2,
+            ..Default::default()
+        });
+        ips.generate(&state, NetworkType::Zcash, 2).await.unwrap();
+        let report = ips.report();
+        assert_eq!(report.island_sizes.len(), 2);
+        assert_eq!(report.repaired_islands, 1);
+        assert_eq!(report.island_repair_connections, 2);
+        let security = &report.security;
+        assert_eq!(
+            security.islands[1].nodes,
+            vec![state.nodes[30].addr, state.nodes[31].addr]
+        );
+        assert_eq!(security.repairs.len(), 2);
+        assert!(security
+            .repairs
+            .iter()
+            .all(|repair| repair.reason == RepairReason::IslandRepair));
+        assert_eq!(security.hot_nodes.len(), 3);
+        assert!(!security.fragmentation_possible);
+
+        // Both nodes of the small island get a peer from the largest island
+        let mut ips = Ips::new(IPSConfiguration {
+            peer_file_path: None,
+            island_repair_connections: 2,
+            ..Default::default()
+        });
+        let graph = CsrGraph::from_nodes(&state.nodes);
+        let working_state = ips.generate_state(state.nodes.clone(), graph, true, 2);
+        let islands = ips.detect_islands(&working_state.graph);
+        let added = ips.repair_islands(&working_state, &islands);
+        let graph = working_state.graph.with_connections(&added);
+        assert!(graph.neighbors(30).iter().any(|peer| *peer < 30));
+        assert!(graph.neighbors(31).iter().any(|peer| *peer < 30));
+        assert_eq!(ips.detect_islands(&graph).len(), 1);
+    }
+
+    #[test]
+    fn harmonic_closeness_state_test() {
+        // Path 0 - 1 - 2 and a separate pair 3 - 4
+        let connections = [vec![1], vec![0, 2], vec![1], vec![4], vec![3]];
+        let mut nodes = connections
+            .iter()
+            .enumerate()
+            .map(|(i, connections)| Node {
+                addr: addr(i),
+                connections: connections.clone(),
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+
+        let ips = Ips::new(IPSConfiguration {
+            peer_file_path: None,
+            ..Default::default()
+        })
+        .with_closeness_variant(ClosenessVariant::Harmonic);
+        let state = ips.generate_state(nodes.clone(), CsrGraph::from_nodes(&nodes), true, 2);
+        let closeness = |state: &IpsState| {
+            state
+                .nodes
+                .iter()
+                .map(|node| node.closeness)
+                .collect::<Vec<f64>>()
+        };
+        assert_eq!(closeness(&state), vec![1.5, 2.0, 1.5, 1.0, 1.0]);
+
+        // Joining the components recomputes closeness of all their nodes
+        nodes[2].connections.push(3);
+        nodes[3].connections.push(2);
+        let mut regenerated = IpsState {
+            nodes: state.nodes.clone(),
+            ..Default::default()
+        };
+        ips.regenerate_state(&state, &mut regenerated, CsrGraph::from_nodes(&nodes), 2);
+        assert_eq!(closeness(&regenerated)[0], 1.0 + 0.5 + 1.0 / 3.0 + 0.25);
+        assert_eq!(closeness(&regenerated)[2], 1.0 + 1.0 + 0.5 + 0.5);
+    }
+
+    #[test]
+    fn harmonic_closeness_prefers_central_node_test() {
+        // Path 0 - 1 - 2 - 3 - 4 with the central node 2
+        let nodes = (0..5)
+            .map(|i: usize| Node {
+                addr: addr(i),
+                connections: [i.checked_sub(1), Some(i + 1).filter(|peer| *peer < 5)]
+                    .into_iter()
+                    .flatten()
+                    .collect(),
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+        let config = IPSConfiguration {
+            peer_file_path: None,
+            mcda_weights: MultiCriteriaAnalysisWeights {
+                closeness: 1.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let ips = Ips::new(config).with_closeness_variant(ClosenessVariant::Harmonic);
+        let graph = CsrGraph::from_nodes(&nodes);
+        let state = ips.generate_state(nodes, graph, true, 2);
+        let ratings = state
+            .nodes
+            .iter()
+            .map(|node| ips.rate_node(node, &state))
+            .collect::<Vec<f64>>();
+        assert!(
+            ratings.iter().all(|rating| *rating <= ratings[2]),
+            "{ratings:?}"
+        );
+        assert!(ratings[2] > ratings[0], "{ratings:?}");
+    }
+
+    #[tokio::test]
+    async fn generate_max_peer_list_size_test() {
+        // Fully connected network with 30 nodes - each node has 29 peers
+        let state = CrunchyState {
+            nodes: (0..30)
+                .map(|i| Node {
+                    addr: addr(i),
+                    network_type: NetworkType::Zcash,
+                    connections: (0..30).filter(|peer| *peer != i).collect(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        let mut ips = Ips::new(IPSConfiguration {
+            peer_file_path: None,
+            max_peer_list_size: Some(8),
+            ..Default::default()
+        });
+        let peers = ips.generate(&state, NetworkType::Zcash, 2).await.unwrap();
+        assert_eq!(peers.len(), 30);
+        assert!(peers.iter().all(|peer| peer.list.len() <= 8));
+    }
+
+    #[tokio::test]
+    async fn generate_max_peers_per_ip_test() {
+        // Fully connected network with 30 nodes, three of them behind each IP address
+        let state = CrunchyState {
+            nodes: (0..30)
+                .map(|i| Node {
+                    addr: SocketAddr::new(
+                        IpAddr::V4(Ipv4Addr::new(10, 0, 0, (i / 3) as u8)),
+                        8233 + i as u16,
+                    ),
+                    network_type: NetworkType::Zcash,
+                    connections: (0..30).filter(|peer| *peer != i).collect(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        let mut ips = Ips::new(IPSConfiguration {
+            peer_file_path: None,
+            max_peers_per_ip: Some(1),
+            ..Default::default()
+        });
+        let peers = ips.generate(&state, NetworkType::Zcash, 2).await.unwrap();
+        assert_eq!(ips.report().shared_ip_nodes, 30);
+        for peer in peers.iter() {
+            let ips = peer
+                .list
+                .iter()
+                .map(|addr| addr.ip())
+                .collect::<HashSet<IpAddr>>();
+            assert_eq!(ips.len(), peer.list.len());
+        }
+    }
+
+    #[tokio::test]
+    async fn generate_max_peers_per_asn_test() {
+        // Fully connected 10 nodes (giving up peers) and a ring of 30 nodes (getting new ones)
+        // joined by a single connection. Neighbours in the ring are in the other one of two
+        // autonomous systems, so each ring node starts with two peers in the same one
+        let mut nodes = (0..40)
+            .map(|i| Node {
+                addr: addr(i),
+                network_type: NetworkType::Zcash,
+                connections: if i < 10 {
+                    (0..10).filter(|peer| *peer != i).collect()
+                } else {
+                    vec![10 + (i - 9) % 30, 10 + (i + 19) % 30]
+                },
+                asn: Some(if i < 10 { 7 } else { i as u32 % 2 }),
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+        nodes[0].connections.push(10);
+        nodes[10].connections.push(0);
+        let state = CrunchyState {
+            nodes,
+            ..Default::default()
+        };
+
+        let mut ips = Ips::new(IPSConfiguration {
+            peer_file_path: None,
+            max_peers_per_asn: Some(2),
+            ..Default::default()
+        });
+        let peers = ips.generate(&state, NetworkType::Zcash, 2).await.unwrap();
+        // New peers never take ring nodes over the limit
+        for peer in peers
+            .iter()
+            .filter(|peer| peer.ip.ip() >= state.nodes[10].addr.ip())
+        {
+            let mut counts = HashMap::new();
+            for addr in peer.list.iter() {
+                let idx = state
+                    .nodes
+                    .iter()
+                    .position(|node| node.addr == *addr)
+                    .unwrap();
+                *counts.entry(state.nodes[idx].asn).or_insert(0) += 1;
+            }
+            assert!(counts.values().all(|count| *count <= 2), "{counts:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn generate_same_asn_tolerance_test() {
+        // Fully connected 30 nodes (giving up peers) and a ring of 30 nodes (getting new ones)
+        // joined by a single connection, in two autonomous systems
+        let mut nodes = (0..60)
+            .map(|i| Node {
+                addr: addr(i),
+                network_type: NetworkType::Zcash,
+                connections: if i < 30 {
+                    (0..30).filter(|peer| *peer != i).collect()
+                } else {
+                    vec![30 + (i + 1) % 30, 30 + (i + 29) % 30]
+                },
+                asn: Some(i as u32 % 2),
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+        nodes[0].connections.push(30);
+        nodes[30].connections.push(0);
+        let state = CrunchyState {
+            nodes,
+            ..Default::default()
+        };
+        let same_asn_peers = |peers: &[Peer]| {
+            peers
+                .iter()
+                .flat_map(|peer| {
+                    peer.list
+                        .iter()
+                        .filter(move |addr| match (addr.ip(), peer.ip.ip()) {
+                            (IpAddr::V4(a), IpAddr::V4(b)) => {
+                                a.octets()[3] % 2 == b.octets()[3] % 2
+                            }
+                            _ => false,
+                        })
+                })
+                .count()
+        };
+
+        let mut ips = Ips::new(IPSConfiguration {
+            peer_file_path: None,
+            change_at_least: 1,
+            change_no_more: 10,
+            ..Default::default()
+        });
+        let peers = ips.generate(&state, NetworkType::Zcash, 2).await.unwrap();
+        let without_tolerance = same_asn_peers(&peers);
+        assert_eq!(ips.report().same_asn_avoided, 0);
+
+        let mut ips = Ips::new(IPSConfiguration {
+            peer_file_path: None,
+            change_at_least: 1,
+            change_no_more: 10,
+            same_asn_tolerance: Some(50.0),
+            ..Default::default()
+        });
+        let peers = ips.generate(&state, NetworkType::Zcash, 2).await.unwrap();
+        assert!(same_asn_peers(&peers) < without_tolerance);
+        assert!(ips.report().same_asn_avoided > 0);
+    }
+
+    #[test]
+    fn protect_pinned_test() {
+        let node = |addr: &str, connections: Vec<usize>| Node {
+            addr: addr.parse().unwrap(),
+            connections,
+            ..Default::default()
+        };
+        let nodes = vec![
+            node("10.0.0.1:8233", vec![1, 2]),
+            node("10.0.0.2:8233", vec![0]),
+            node("192.168.1.1:8233", vec![0]),
+        ];
+        let ips = Ips::new(IPSConfiguration {
+            pinned: vec!["10.0.0.2".parse().unwrap()],
+            ..Default::default()
+        });
+
+        let mut bridges = HashMap::new();
+        assert_eq!(ips.protect_pinned(&nodes, &nodes, &mut bridges), 1);
+        assert!(is_bridge(&bridges, 0, 1));
+        assert!(is_bridge(&bridges, 1, 0));
+        assert!(!is_bridge(&bridges, 0, 2));
+
+        // Ranges cover all nodes within them
+        let ips = Ips::new(IPSConfiguration {
+            pinned: vec!["10.0.0.0/24".parse().unwrap()],
+            ..Default::default()
+        });
+        let mut bridges = HashMap::new();
+        assert_eq!(ips.protect_pinned(&nodes, &nodes, &mut bridges), 2);
+        assert!(is_bridge(&bridges, 2, 0));
+    }
+
+    #[tokio::test]
+    async fn generate_blacklist_test() {
+        // Fully connected network with 30 nodes, the last 5 of them are blacklisted
+        let state = CrunchyState {
+            nodes: (0..30)
+                .map(|i| Node {
+                    addr: addr(i),
+                    network_type: NetworkType::Zcash,
+                    connections: (0..30).filter(|peer| *peer != i).collect(),
+                    asn: Some(if i < 25 { 1 } else { 666 }),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let blacklisted = |addr: &SocketAddr| match addr.ip() {
+            IpAddr::V4(ip) => ip.octets()[3] >= 25,
+            IpAddr::V6(_) => false,
+        };
+
+        let mut ips = Ips::new(IPSConfiguration {
+            peer_file_path: None,
+            blacklist: BlacklistConfiguration {
+                asns: vec![666],
+                remove_connections: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let peers = ips.generate(&state, NetworkType::Zcash, 2).await.unwrap();
+        assert_eq!(ips.report().blacklisted_nodes, 5);
+        // Even existing connections to blacklisted nodes are removed
+        for peer in peers.iter().filter(|peer| !blacklisted(&peer.ip)) {
+            assert!(!peer.list.iter().any(blacklisted));
+        }
+    }
+
+    #[tokio::test]
+    async fn generate_blacklist_no_new_connections_test() {
+        // Two cliques of 15 nodes joined by a node of each, nodes 20 and 21 are blacklisted
+        let state = CrunchyState {
+            nodes: (0..30)
+                .map(|i| Node {
+                    addr: addr(i),
+                    network_type: NetworkType::Zcash,
+                    connections: (0..30)
+                        .filter(|peer| *peer != i && (*peer < 15) == (i < 15))
+                        .chain(match i {
+                            0 => Some(15),
+                            15 => Some(0),
+                            _ => None,
+                        })
+                        .collect(),
+                    asn: Some(if i == 20 || i == 21 { 666 } else { 1 }),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        let mut ips = Ips::new(IPSConfiguration {
+            peer_file_path: None,
+            change_at_least: 2,
+            blacklist: BlacklistConfiguration {
+                asns: vec![666],
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let peers = ips.generate(&state, NetworkType::Zcash, 2).await.unwrap();
+        assert_eq!(ips.report().blacklisted_nodes, 2);
+        // Blacklisted nodes keep at most their existing connections - neither they get new
+        // peers, nor they are suggested to anyone
+        for peer in peers.iter() {
+            let idx = state
+                .nodes
+                .iter()
+                .position(|node| node.addr == peer.ip)
+                .unwrap();
+            let original = state.nodes[idx]
+                .connections
+                .iter()
+                .map(|peer| state.nodes[*peer].addr)
+                .collect::<Vec<SocketAddr>>();
+            for listed in peer.list.iter() {
+                if [20, 21].map(addr).contains(listed) || [20, 21].map(addr).contains(&peer.ip) {
+                    assert!(original.contains(listed), "{} -> {listed}", peer.ip);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn generate_min_protocol_version_test() {
+        // Ring of 30 nodes, the last 5 of them announce an old protocol version
+        let mut state = ring_state(30);
+        for (i, node) in state.nodes.iter_mut().enumerate() {
+            node.protocol_version = Some(if i < 25 { 170100 } else { 170018 });
+        }
+        let outdated = |addr: &SocketAddr| match addr.ip() {
+            IpAddr::V4(ip) => ip.octets()[3] >= 25,
+            IpAddr::V6(_) => false,
+        };
+
+        let mut ips = Ips::new(IPSConfiguration {
+            peer_file_path: None,
+            min_protocol_version: Some(170100),
+            ..Default::default()
+        });
+        let peers = ips.generate(&state, NetworkType::Zcash, 2).await.unwrap();
+        assert_eq!(ips.report().outdated_nodes, 5);
+        // Outdated nodes are only kept where they already were
+        for (idx, peer) in peers.iter().enumerate() {
+            let original = [addr((idx + 1) % 30), addr((idx + 29) % 30)];
+            assert!(peer
+                .list
+                .iter()
+                .all(|peer_addr| !outdated(peer_addr) || original.contains(peer_addr)));
+        }
+    }
+
+    #[tokio::test]
+    async fn generate_pareto_front_test() {
+        let state = ring_state(30);
+
+        let mut ips = Ips::new(IPSConfiguration {
+            peer_file_path: None,
+            optimizer: Optimizer::Genetic,
+            pareto_file_path: Some(PathBuf::from("pareto.json")),
+            ..Default::default()
+        })
+        .with_seed(7);
+        ips.generate(&state, NetworkType::Zcash, 2).await.unwrap();
+        let front = &ips.report().pareto_front;
+        assert!(!front.is_empty());
+        assert!(front.iter().filter(|solution| solution.selected).count() <= 1);
+        for solution in front.iter() {
+            assert_eq!(
+                solution.objectives.churn,
+                solution.added.len() + solution.removed.len()
+            );
+            assert!(front
+                .iter()
+                .all(|other| !other.objectives.dominates(&solution.objectives)));
+        }
+    }
+
+    #[tokio::test]
+    async fn generate_min_peer_countries_test() {
+        // Ring of 30 nodes in three countries, 10 consecutive nodes in each
+        let countries = ["Germany", "France", "Poland"];
+        let mut state = ring_state(30);
+        for (i, node) in state.nodes.iter_mut().enumerate() {
+            node.geolocation = geo(countries[i / 10]);
+        }
+        let country_of = |addr: &SocketAddr| match addr.ip() {
+            IpAddr::V4(ip) => countries[ip.octets()[3] as usize / 10],
+            IpAddr::V6(_) => unreachable!(),
+        };
+
+        let mut ips = Ips::new(IPSConfiguration {
+            peer_file_path: None,
+            min_peer_countries: Some(2),
+            ..Default::default()
+        });
+        let peers = ips.generate(&state, NetworkType::Zcash, 2).await.unwrap();
+        assert!(ips.report().geo_spread_repairs > 0);
+        assert_eq!(ips.report().geo_spread_violations, 0);
+        for peer in peers.iter() {
+            let spread = peer.list.iter().map(country_of).collect::<HashSet<_>>();
+            assert!(spread.len() >= 2, "{} {spread:?}", peer.ip);
+        }
+
+        // No repairs beyond the recommendations allowed per country
+        let mut ips = Ips::new(IPSConfiguration {
+            peer_file_path: None,
+            min_peer_countries: Some(2),
+            max_recommendations_per_country: Some(0),
+            ..Default::default()
+        });
+        ips.generate(&state, NetworkType::Zcash, 2).await.unwrap();
+        assert_eq!(ips.report().geo_spread_repairs, 0);
+        assert!(ips.report().geo_spread_violations > 0);
+    }
+
+    #[tokio::test]
+    async fn generate_mega_geo_cluster_test() {
+        // Ring of 30 nodes, the first 20 of them in one geographic cluster
+        let mut state = ring_state(30);
+        for (i, node) in state.nodes.iter_mut().enumerate() {
+            node.geo_cluster_id = (i < 20).then_some(0);
+        }
+        let clustered = |addr: &SocketAddr| match addr.ip() {
+            IpAddr::V4(ip) => ip.octets()[3] < 20,
+            IpAddr::V6(_) => false,
+        };
+
+        let mut ips = Ips::new(IPSConfiguration {
+            peer_file_path: None,
+            mega_geo_cluster_size: Some(20),
+            ..Default::default()
+        });
+        let peers = ips.generate(&state, NetworkType::Zcash, 2).await.unwrap();
+        assert_eq!(ips.report().mega_geo_clusters, 1);
+        // Nodes of the cluster get no new peers from the cluster
+        for peer in peers.iter().filter(|peer| clustered(&peer.ip)) {
+            let idx = state
+                .nodes
+                .iter()
+                .position(|node| node.addr == peer.ip)
+                .unwrap();
+            let original = [addr((idx + 1) % 30), addr((idx + 29) % 30)];
+            assert!(peer
+                .list
+                .iter()
+                .all(|peer_addr| !clustered(peer_addr) || original.contains(peer_addr)));
+        }
+
+        let mut ips = Ips::new(IPSConfiguration {
+            peer_file_path: None,
+            mega_geo_cluster_size: Some(21),
+            ..Default::default()
+        });
+        ips.generate(&state, NetworkType::Zcash, 2).await.unwrap();
+        assert_eq!(ips.report().mega_geo_clusters, 0);
+    }
+
+    #[tokio::test]
+    async fn generate_peer_details_test() {
+        // Ring of 30 nodes, every node has its own ASN
+        let mut state = ring_state(30);
+        for (i, node) in state.nodes.iter_mut().enumerate() {
+            node.asn = Some(i as u32);
+        }
+        let config = IPSConfiguration {
+            peer_file_path: None,
+            peer_details: true,
+            mcda_weights: MultiCriteriaAnalysisWeights {
+                asn_diversity: 0.5,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let peers = Ips::new(config.clone())
+            .generate(&state, NetworkType::Zcash, 2)
+            .await
+            .unwrap();
+        let mut new_peers = 0;
+        for (idx, peer) in peers.iter().enumerate() {
+            assert_eq!(peer.details.len(), peer.list.len());
+            let original = [addr((idx + 1) % 30), addr((idx + 29) % 30)];
+            for (peer_addr, details) in peer.list.iter().zip(peer.details.iter()) {
+                assert_eq!(details.existing, original.contains(peer_addr));
+                assert!(details.dominant_factors.len() <= DOMINANT_FACTORS);
+                if !details.existing {
+                    new_peers += 1;
+                    // Peers in other ASNs not yet in the peer list are rewarded
+                    let asn = &details.dominant_factors[0];
+                    assert_eq!(asn.factor, RatingFactor::AsnDiversity);
+                    assert_eq!(asn.contribution, NORMALIZE_TO_VALUE * 0.5);
+                }
+            }
+        }
+        assert!(new_peers > 0);
+
+        let peers = Ips::new(IPSConfiguration {
+            peer_details: false,
+            ..config
+        })
+        .generate(&state, NetworkType::Zcash, 2)
+        .await
+        .unwrap();
+        assert!(peers.iter().all(|peer| peer.details.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn generate_adoption_simulation_test() {
+        let mut ips = Ips::new(IPSConfiguration {
+            peer_file_path: None,
+            analyze_islands_independently: true,
+            adoption_rates: vec![0.0, 100.0],
+            ..Default::default()
+        });
+
+        ips.generate(&two_islands_state(), NetworkType::Zcash, 2)
+            .await
+            .unwrap();
+        let report = &ips.report().island_reports[0];
+        let simulations = &report.adoption_simulations;
+        assert_eq!(simulations.len(), 2);
+
+        // Nothing changes if no node adopts the peer lists
+        assert_eq!(simulations[0].adopting_nodes, 0);
+        assert!(simulations[0]
+            .statistics_delta
+            .values()
+            .all(|delta| delta.delta == 0.0));
+
+        // The final network is expected if all nodes adopt the peer lists (small-world and cut
+        // measures are computed only for the final network)
+        assert_eq!(simulations[1].adopting_nodes, 6);
+        for (name, delta) in report.statistics_delta.iter() {
+            if name.starts_with("small_world") || name.starts_with("rich_club") || name == "min_cut"
+            {
+                assert!(!simulations[1].statistics_delta.contains_key(name));
+                continue;
+            }
+            assert!((simulations[1].statistics_delta[name].delta - delta.delta).abs() < 0.0001);
+        }
+    }
+
+    #[tokio::test]
+    async fn generate_canary_test() {
+        let mut ips = Ips::new(IPSConfiguration {
+            peer_file_path: None,
+            analyze_islands_independently: true,
+            canary: Some(CanaryConfiguration {
+                percentage: 50.0,
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        let peers = ips
+            .generate(&two_islands_state(), NetworkType::Zcash, 2)
+            .await
+            .unwrap();
+        // Peer lists are generated for all nodes, canary nodes are chosen in each island
+        assert_eq!(peers.len(), 12);
+        let report = ips.report();
+        assert_eq!(report.canary_nodes.len(), 6);
+        let simulation = report.island_reports[0].canary_simulation.as_ref().unwrap();
+        assert_eq!(simulation.adopting_nodes, 3);
+        assert_eq!(simulation.adoption_rate, 50.0);
+    }
 }
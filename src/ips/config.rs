@@ -1,11 +1,15 @@
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use ziggurat_core_crawler::summary::NetworkType;
 
-use crate::config::GeoLocationMode;
+use crate::{config::GeoLocationMode, ips::ip_range::IpRange, stdio, Node};
 
 /// Multi-criteria analysis weights
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MultiCriteriaAnalysisWeights {
     /// Weight (importance) of the location factor
     pub location: f64,
@@ -17,13 +21,275 @@ pub struct MultiCriteriaAnalysisWeights {
     pub betweenness: f64,
     /// Weight (importance) of the closeness factor
     pub closeness: f64,
+    /// Weight (importance) of the ASN diversity factor - penalizes peers in the node's own ASN
+    /// and rewards ASNs not yet present in its peer list
+    #[serde(default)]
+    pub asn_diversity: f64,
+    /// Weight (importance) of the country diversity factor - rewards peers in countries not yet
+    /// present in the node's peer list
+    #[serde(default)]
+    pub country_diversity: f64,
+    /// Weight (importance) of the latency factor - rewards peers with low estimated RTT
+    #[serde(default)]
+    pub latency: f64,
+    /// Weight (importance) of the uptime factor - rewards peers seen in most of recent runs
+    #[serde(default)]
+    pub uptime: f64,
+    /// Weight (importance) of the stability factor - rewards peers seen in most of all the
+    /// earlier snapshots given
+    #[serde(default)]
+    pub stability: f64,
+}
+
+impl MultiCriteriaAnalysisWeights {
+    /// Weight of the factor with the given name (as in the configuration file).
+    pub fn weight_mut(&mut self, name: &str) -> Option<&mut f64> {
+        match name {
+            "location" => Some(&mut self.location),
+            "degree" => Some(&mut self.degree),
+            "eigenvector" => Some(&mut self.eigenvector),
+            "betweenness" => Some(&mut self.betweenness),
+            "closeness" => Some(&mut self.closeness),
+            "asn_diversity" => Some(&mut self.asn_diversity),
+            "country_diversity" => Some(&mut self.country_diversity),
+            "latency" => Some(&mut self.latency),
+            "uptime" => Some(&mut self.uptime),
+            "stability" => Some(&mut self.stability),
+            _ => None,
+        }
+    }
+}
+
+/// Predefined MCDA weights for common goals, so the weights don't have to be picked by hand
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WeightProfile {
+    /// Avoid hot nodes and prefer peers in other autonomous systems
+    Security,
+    /// Prefer close, central and reliable peers
+    Performance,
+    /// Prefer peers in other countries and autonomous systems
+    GeoDiverse,
+    /// A bit of everything
+    Balanced,
+}
+
+impl WeightProfile {
+    /// Name of the profile as used in the configuration and on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            WeightProfile::Security => "security",
+            WeightProfile::Performance => "performance",
+            WeightProfile::GeoDiverse => "geo-diverse",
+            WeightProfile::Balanced => "balanced",
+        }
+    }
+
+    /// Weights of the profile.
+    pub fn weights(&self) -> MultiCriteriaAnalysisWeights {
+        match self {
+            WeightProfile::Security => MultiCriteriaAnalysisWeights {
+                location: 0.0,
+                degree: -0.3,
+                eigenvector: -0.1,
+                betweenness: -0.4,
+                closeness: 0.0,
+                asn_diversity: 0.3,
+                country_diversity: 0.1,
+                latency: 0.0,
+                uptime: 0.2,
+                stability: 0.0,
+            },
+            WeightProfile::Performance => MultiCriteriaAnalysisWeights {
+                location: 0.2,
+                degree: 0.1,
+                eigenvector: 0.1,
+                betweenness: 0.0,
+                closeness: 0.3,
+                asn_diversity: 0.0,
+                country_diversity: 0.0,
+                latency: 0.4,
+                uptime: 0.2,
+                stability: 0.0,
+            },
+            WeightProfile::GeoDiverse => MultiCriteriaAnalysisWeights {
+                location: 0.3,
+                degree: -0.1,
+                eigenvector: 0.0,
+                betweenness: -0.1,
+                closeness: 0.1,
+                asn_diversity: 0.3,
+                country_diversity: 0.4,
+                latency: 0.0,
+                uptime: 0.1,
+                stability: 0.0,
+            },
+            WeightProfile::Balanced => MultiCriteriaAnalysisWeights {
+                location: 0.2,
+                degree: -0.2,
+                eigenvector: 0.1,
+                betweenness: -0.2,
+                closeness: 0.1,
+                asn_diversity: 0.1,
+                country_diversity: 0.1,
+                latency: 0.0,
+                uptime: 0.1,
+                stability: 0.0,
+            },
+        }
+    }
+}
+
+impl FromStr for WeightProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            WeightProfile::Security,
+            WeightProfile::Performance,
+            WeightProfile::GeoDiverse,
+            WeightProfile::Balanced,
+        ]
+        .into_iter()
+        .find(|profile| profile.name() == s.to_lowercase())
+        .ok_or_else(|| {
+            format!("unknown weight profile {s} (security, performance, geo-diverse or balanced)")
+        })
+    }
+}
+
+/// Method used to detect bridges in the network
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BridgeDetection {
+    /// Betweenness threshold based detection of bridge-like connections
+    #[default]
+    Heuristic,
+    /// Tarjan's algorithm finding edges that disconnect the graph when removed
+    Exact,
+}
+
+/// Criterion used to choose peers to add from the candidate pool
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CandidateSort {
+    /// Prefer candidates with lowest betweenness to avoid creating "hot" nodes
+    #[default]
+    Betweenness,
+    /// Prefer candidates with lowest degree
+    Degree,
+    /// Keep the MCDA rating order
+    Rating,
+}
+
+/// Optimizer generating the peer lists
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Optimizer {
+    /// Multi-criteria analysis choosing peers for each node separately
+    #[default]
+    Mcda,
+    /// Genetic algorithm evolving whole peer-list assignments, starting from the MCDA result
+    Genetic,
+}
+
+/// Configuration of the genetic optimizer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GeneticConfiguration {
+    /// Number of networks in each generation
+    pub population_size: usize,
+    /// Number of generations
+    pub generations: usize,
+    /// Number of connections replaced in each new network
+    pub mutations: usize,
+}
+
+/// Format of the peer list file
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PeerOutputFormat {
+    /// JSON with peer lists of all nodes and their validity window
+    #[default]
+    Json,
+    /// JSON array of peer lists without the validity window, as written before the window was
+    /// added (format version 1)
+    #[serde(rename = "json-array")]
+    JsonArray,
+    /// Plain text with a line for each node - its address followed by addresses of its peers
+    Addr,
+    /// Section of `addnode=` lines for each node, headed by a comment with its address, ready to
+    /// be pasted into the node's `zcash.conf`
+    AddNode,
+}
+
+/// How nodes adopting the peer lists are chosen in the adoption simulation
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AdoptionSelection {
+    /// Nodes adopt in pseudo-random order (the same for every run with the same seed)
+    #[default]
+    Random,
+    /// Nodes with the highest degree adopt first
+    Tier,
+}
+
+/// Canary rollout - peer lists are emitted only for a subset of nodes, while statistics are
+/// projected for the whole network
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CanaryConfiguration {
+    /// Percentage of nodes in the canary, chosen in the same order as adopting nodes in the
+    /// adoption simulation
+    pub percentage: f64,
+    /// Nodes with any of these tags (currently network types, e.g. "zcash") are always in the
+    /// canary
+    pub tags: Vec<String>,
+    /// If set, peer lists for all nodes are written there (always as JSON), so they can be
+    /// promoted later
+    pub full_peer_file_path: Option<PathBuf>,
+}
+
+/// Nodes which must never be suggested as peers
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlacklistConfiguration {
+    /// Addresses or CIDR ranges
+    pub addresses: Vec<IpRange>,
+    /// Countries (case insensitive)
+    pub countries: Vec<String>,
+    /// Autonomous system numbers
+    pub asns: Vec<u32>,
+    /// If set, existing connections to blacklisted nodes are removed as well (bridges are kept)
+    pub remove_connections: bool,
+}
+
+impl BlacklistConfiguration {
+    /// Check if the node is blacklisted.
+    pub fn contains(&self, node: &Node) -> bool {
+        let ip = node.addr.ip();
+        let country = node
+            .geolocation
+            .as_ref()
+            .and_then(|geo| geo.country.as_ref());
+
+        self.addresses.iter().any(|range| range.contains(&ip))
+            || matches!(node.asn, Some(asn) if self.asns.contains(&asn))
+            || matches!(country, Some(country) if self
+                .countries
+                .iter()
+                .any(|blacklisted| blacklisted.eq_ignore_ascii_case(country)))
+    }
 }
 
 /// Configuration for Intelligent Peer Sharing module
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IPSConfiguration {
     /// Path where peer list file will be written
     pub peer_file_path: Option<PathBuf>,
+    /// Format of the peer list file
+    #[serde(default)]
+    pub peer_output_format: PeerOutputFormat,
     /// Path where log file will be written (if none, all logs will be written to stdout)
     pub log_path: Option<PathBuf>,
     /// Indicates if configuration should be taken into account and if so what should be
@@ -31,30 +297,388 @@ pub struct IPSConfiguration {
     pub geolocation: GeoLocationMode,
     /// This is the max (or min) distance in km between peers
     pub geolocation_minmax_distance_km: u32,
+    /// If set, the max (or min) distance is not fixed but it's the given percentile (0-100) of
+    /// distances from the node to all located nodes - it adapts to dense clusters and remote nodes
+    #[serde(default)]
+    pub geolocation_distance_percentile: Option<f64>,
     /// Indicates how many peers must be changed for each node
     pub change_at_least: u32,
     /// Indicates maximum peers should be changed for each node
     pub change_no_more: u32,
     /// Indicates adjustment factor for bridge detection
     pub bridge_threshold_adjustment: f64,
-    /// Multi-criteria analysis weights
+    /// Bridge detection method
+    #[serde(default)]
+    pub bridge_detection: BridgeDetection,
+    /// How many times more candidates than peers to add are taken for the final selection
+    #[serde(default = "default_candidate_pool_multiplier")]
+    pub candidate_pool_multiplier: u32,
+    /// Criterion used to choose peers from the candidate pool
+    #[serde(default)]
+    pub candidate_sort: CandidateSort,
+    /// Maximum number of new connections within one run pointing at nodes in a single country
+    /// (unlimited if not set)
+    #[serde(default)]
+    pub max_recommendations_per_country: Option<usize>,
+    /// Maximum number of new connections within one run pointing at nodes in a single city
+    /// (unlimited if not set)
+    #[serde(default)]
+    pub max_recommendations_per_city: Option<usize>,
+    /// Geographic clusters with at least this many nodes (of the network) are mega-clusters -
+    /// nodes in them are not recommended new peers from their own cluster (no limit if not set)
+    #[serde(default)]
+    pub mega_geo_cluster_size: Option<usize>,
+    /// Maximum length of the generated peer list of any node, regardless of the number of
+    /// changes allowed (unlimited if not set)
+    #[serde(default)]
+    pub max_peer_list_size: Option<usize>,
+    /// Maximum number of peers sharing one IP address (nodes on different ports) in the peer
+    /// list of any node - nodes sharing the node's own address are never recommended (unlimited
+    /// if not set)
+    #[serde(default)]
+    pub max_peers_per_ip: Option<usize>,
+    /// Maximum number of peers in one autonomous system that new peers can bring the peer list
+    /// of any node to - peers with unknown ASN don't count (unlimited if not set)
+    #[serde(default)]
+    pub max_peers_per_asn: Option<usize>,
+    /// Minimum number of countries each peer list spans where possible - peer lists spanning
+    /// fewer are repaired after the optimization (not enforced if not set)
+    #[serde(default)]
+    pub min_peer_countries: Option<usize>,
+    /// Percentage by which the rating of a candidate from another autonomous system may be lower
+    /// than the rating of a candidate from the node's own one - new connections within one
+    /// provider are made only if there is no such alternative (not applied if not set)
+    #[serde(default)]
+    pub same_asn_tolerance: Option<f64>,
+    /// Addresses or CIDR ranges of nodes (e.g. official seeders or monitoring nodes) whose
+    /// connections are never removed
+    #[serde(default)]
+    pub pinned: Vec<IpRange>,
+    /// Nodes never suggested as peers
+    #[serde(default)]
+    pub blacklist: BlacklistConfiguration,
+    /// Nodes announcing a lower protocol version are never suggested as peers (nodes with
+    /// unknown version are)
+    #[serde(default)]
+    pub min_protocol_version: Option<u32>,
+    /// Network types peer lists are generated for (the filtered network type or Zcash if empty)
+    #[serde(default)]
+    pub networks: Vec<NetworkType>,
+    /// Constant added to the RTT estimated from the distance between nodes (in milliseconds) -
+    /// covers routing and processing on the way
+    #[serde(default = "default_latency_per_hop_ms")]
+    pub latency_per_hop_ms: f64,
+    /// Profile the weights not set in `mcda_weights` are taken from (applied when the
+    /// configuration is parsed)
+    #[serde(default)]
+    pub profile: Option<WeightProfile>,
+    /// Multi-criteria analysis weights (the default weights if neither they nor the profile are
+    /// set)
+    #[serde(default)]
     pub mcda_weights: MultiCriteriaAnalysisWeights,
-    /// If set, vanilla (original, before IPS) peer list should be generated in the specified file
+    /// Optimizer generating the peer lists
+    #[serde(default)]
+    pub optimizer: Optimizer,
+    /// Configuration of the genetic optimizer
+    #[serde(default)]
+    pub genetic: GeneticConfiguration,
+    /// If set, peers added and removed for each node (relative to the vanilla peer list) are
+    /// written to the specified file as JSON
+    #[serde(default)]
+    pub peers_delta_file_path: Option<PathBuf>,
+    /// Where the initial and final statistics (and their difference) are written as JSON
+    /// (`statistics.json` next to the peer file if not set, unless it's stdout)
+    #[serde(default)]
+    pub statistics_file_path: Option<PathBuf>,
+    /// Where hot nodes, bridges, islands and repairs found by the security phase are written as
+    /// JSON (`security_report.json` next to the peer file if not set, unless it's stdout)
+    #[serde(default)]
+    pub security_report_file_path: Option<PathBuf>,
+    /// If set, the genetic optimizer keeps every network not dominated in diameter, maximum
+    /// betweenness, average distance and churn, and this Pareto front is written as JSON
+    #[serde(default)]
+    pub pareto_file_path: Option<PathBuf>,
+    /// If set, vanilla (original, before IPS) peer list is written to the specified file in the
+    /// same format as the generated peer list
     pub vanilla_peer_file_path: Option<PathBuf>,
+    /// How often the network is crawled (in hours) - generated peer lists expire after that time
+    #[serde(default = "default_crawl_interval_hours")]
+    pub crawl_interval_hours: u32,
+    /// If set, a network split into more than one massive island is not treated as an error -
+    /// peer lists are generated for each massive island independently
+    #[serde(default)]
+    pub analyze_islands_independently: bool,
+    /// Number of new connections joining each small island to the largest island (islands are
+    /// not repaired if zero)
+    #[serde(default)]
+    pub island_repair_connections: usize,
+    /// If set, intermediate states of the algorithm are written as JSON files to that directory
+    #[serde(default)]
+    pub debug_dump_dir: Option<PathBuf>,
+    /// Percentages of nodes adopting the peer lists for which the network statistics are
+    /// simulated (no simulation if empty)
+    #[serde(default)]
+    pub adoption_rates: Vec<f64>,
+    /// How nodes adopting the peer lists are chosen in the adoption simulation
+    #[serde(default)]
+    pub adoption_selection: AdoptionSelection,
+    /// If set, nodes with rising betweenness across recent runs (emerging hot nodes) are not
+    /// recommended as new peers
+    #[serde(default)]
+    pub avoid_emerging_hot_nodes: bool,
+    /// If set, peer lists are emitted only for the canary subset of nodes
+    #[serde(default)]
+    pub canary: Option<CanaryConfiguration>,
+    /// If set, each peer in the peer list file comes with its MCDA rating, the factors
+    /// dominating it and whether the node is already connected to it
+    #[serde(default)]
+    pub peer_details: bool,
+}
+
+/// Default crawl cadence used for peer list expiration
+pub const DEFAULT_CRAWL_INTERVAL_HOURS: u32 = 24;
+
+fn default_crawl_interval_hours() -> u32 {
+    DEFAULT_CRAWL_INTERVAL_HOURS
+}
+
+/// Default candidate pool size multiplier
+pub const DEFAULT_CANDIDATE_POOL_MULTIPLIER: u32 = 2;
+
+fn default_candidate_pool_multiplier() -> u32 {
+    DEFAULT_CANDIDATE_POOL_MULTIPLIER
+}
+
+/// Default constant added to the estimated RTT
+pub const DEFAULT_LATENCY_PER_HOP_MS: f64 = 5.0;
+
+fn default_latency_per_hop_ms() -> f64 {
+    DEFAULT_LATENCY_PER_HOP_MS
 }
 
 impl Default for IPSConfiguration {
     fn default() -> IPSConfiguration {
         IPSConfiguration {
             peer_file_path: Some(PathBuf::from("testdata/peers.json")),
+            peer_output_format: PeerOutputFormat::default(),
             log_path: None,
             geolocation: GeoLocationMode::PreferCloser,
             geolocation_minmax_distance_km: 1000,
+            geolocation_distance_percentile: None,
             change_at_least: 1,
             change_no_more: 2,
+            candidate_pool_multiplier: DEFAULT_CANDIDATE_POOL_MULTIPLIER,
+            candidate_sort: CandidateSort::default(),
+            max_recommendations_per_country: None,
+            max_recommendations_per_city: None,
+            mega_geo_cluster_size: None,
+            max_peer_list_size: None,
+            max_peers_per_ip: None,
+            max_peers_per_asn: None,
+            min_peer_countries: None,
+            same_asn_tolerance: None,
+            pinned: Vec::new(),
+            blacklist: BlacklistConfiguration::default(),
+            min_protocol_version: None,
+            networks: Vec::new(),
+            latency_per_hop_ms: DEFAULT_LATENCY_PER_HOP_MS,
+            profile: None,
             mcda_weights: MultiCriteriaAnalysisWeights::default(),
+            optimizer: Optimizer::default(),
+            genetic: GeneticConfiguration::default(),
             bridge_threshold_adjustment: 1.25,
+            bridge_detection: BridgeDetection::default(),
             vanilla_peer_file_path: None,
+            statistics_file_path: None,
+            security_report_file_path: None,
+            pareto_file_path: None,
+            peers_delta_file_path: None,
+            crawl_interval_hours: DEFAULT_CRAWL_INTERVAL_HOURS,
+            analyze_islands_independently: false,
+            island_repair_connections: 0,
+            debug_dump_dir: None,
+            adoption_rates: Vec::new(),
+            adoption_selection: AdoptionSelection::default(),
+            avoid_emerging_hot_nodes: false,
+            canary: None,
+            peer_details: false,
+        }
+    }
+}
+
+impl IPSConfiguration {
+    /// Problems of the IPS configuration - values out of their range and weights which can't
+    /// rate nodes.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let percentage = |value: f64| (0.0..=100.0).contains(&value);
+
+        let weights = &self.mcda_weights;
+        let weights = [
+            weights.location,
+            weights.degree,
+            weights.eigenvector,
+            weights.betweenness,
+            weights.closeness,
+            weights.asn_diversity,
+            weights.country_diversity,
+            weights.latency,
+            weights.uptime,
+            weights.stability,
+        ];
+        if weights.iter().any(|weight| !weight.is_finite()) {
+            problems.push("ips_config.mcda_weights must be finite numbers".to_owned());
+        } else if weights.iter().all(|weight| *weight == 0.0) {
+            problems.push(
+                "ips_config.mcda_weights are all zero - all nodes would be rated the same"
+                    .to_owned(),
+            );
+        }
+        if self.change_at_least > self.change_no_more {
+            problems.push(format!(
+                "ips_config.change_at_least ({}) is greater than ips_config.change_no_more ({})",
+                self.change_at_least, self.change_no_more
+            ));
+        }
+        if self.candidate_pool_multiplier == 0 {
+            problems.push("ips_config.candidate_pool_multiplier must be greater than 0".to_owned());
+        }
+        if self.max_peer_list_size == Some(0) {
+            problems.push("ips_config.max_peer_list_size must be greater than 0".to_owned());
+        }
+        if self.max_peers_per_ip == Some(0) {
+            problems.push("ips_config.max_peers_per_ip must be greater than 0".to_owned());
+        }
+        if self.max_peers_per_asn == Some(0) {
+            problems.push("ips_config.max_peers_per_asn must be greater than 0".to_owned());
+        }
+        if self.min_peer_countries == Some(0) {
+            problems.push("ips_config.min_peer_countries must be greater than 0".to_owned());
+        }
+        if self
+            .same_asn_tolerance
+            .is_some_and(|tolerance| !(tolerance >= 0.0 && tolerance.is_finite()))
+        {
+            problems.push("ips_config.same_asn_tolerance must be a non-negative number".to_owned());
+        }
+        if matches!(self.geolocation_distance_percentile, Some(p) if !percentage(p)) {
+            problems.push(
+                "ips_config.geolocation_distance_percentile must be within [0, 100]".to_owned(),
+            );
+        }
+        if self.adoption_rates.iter().any(|rate| !percentage(*rate)) {
+            problems.push("ips_config.adoption_rates must be within [0, 100]".to_owned());
+        }
+        if matches!(&self.canary, Some(canary) if !percentage(canary.percentage)) {
+            problems.push("ips_config.canary.percentage must be within [0, 100]".to_owned());
+        }
+        if self.pareto_file_path.is_some() && self.optimizer != Optimizer::Genetic {
+            problems.push(
+                "ips_config.pareto_file_path needs the genetic optimizer (ips_config.optimizer)"
+                    .to_owned(),
+            );
+        }
+        if self.networks.contains(&NetworkType::Invalid) {
+            problems.push(
+                "ips_config.networks contains an invalid network type (check Readme for possible values)"
+                    .to_owned(),
+            );
+        }
+        problems
+    }
+
+    /// Path of the statistics file - the configured one or `statistics.json` next to the peer
+    /// file (none if the peer list goes to stdout).
+    pub fn statistics_path(&self) -> Option<PathBuf> {
+        self.statistics_file_path.clone().or_else(|| {
+            self.peer_file_path
+                .as_ref()
+                .filter(|path| !stdio::is_stdio(path))
+                .map(|path| path.with_file_name("statistics.json"))
+        })
+    }
+
+    /// Path of the security report - the configured one or `security_report.json` next to the
+    /// peer file (none if the peer list goes to stdout).
+    pub fn security_report_path(&self) -> Option<PathBuf> {
+        self.security_report_file_path.clone().or_else(|| {
+            self.peer_file_path
+                .as_ref()
+                .filter(|path| !stdio::is_stdio(path))
+                .map(|path| path.with_file_name("security_report.json"))
+        })
+    }
+
+    /// Configuration for one of many network types processed in a single run - network name is
+    /// appended to all files written by IPS (e.g. `peers.json` becomes `peers-zcash.json`), so
+    /// the runs don't overwrite each other.
+    pub fn for_network(&self, network: NetworkType) -> IPSConfiguration {
+        let name = network_name(network);
+        self.map_output_paths(|path| with_network_suffix(path, &name))
+    }
+
+    /// Configuration with the path of each file (or directory) written by IPS replaced - the
+    /// statistics file and the security report get their default paths first.
+    pub fn map_output_paths(&self, f: impl Fn(&Path) -> PathBuf) -> IPSConfiguration {
+        let mapped = |path: &Option<PathBuf>| path.as_deref().map(&f);
+
+        let mut config = self.clone();
+        config.peer_file_path = mapped(&self.peer_file_path);
+        config.log_path = mapped(&self.log_path);
+        config.vanilla_peer_file_path = mapped(&self.vanilla_peer_file_path);
+        config.statistics_file_path = mapped(&self.statistics_path());
+        config.security_report_file_path = mapped(&self.security_report_path());
+        config.pareto_file_path = mapped(&self.pareto_file_path);
+        config.peers_delta_file_path = mapped(&self.peers_delta_file_path);
+        config.debug_dump_dir = mapped(&self.debug_dump_dir);
+        if let Some(canary) = config.canary.as_mut() {
+            canary.full_peer_file_path = mapped(&canary.full_peer_file_path);
+        }
+        config
+    }
+
+    /// Paths of all files (and directories) written by IPS.
+    pub fn output_paths(&self) -> Vec<PathBuf> {
+        [
+            self.peer_file_path.clone(),
+            self.log_path.clone(),
+            self.vanilla_peer_file_path.clone(),
+            self.statistics_path(),
+            self.security_report_path(),
+            self.pareto_file_path.clone(),
+            self.peers_delta_file_path.clone(),
+            self.debug_dump_dir.clone(),
+            self.canary
+                .as_ref()
+                .and_then(|canary| canary.full_peer_file_path.clone()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+/// Lowercase name of the network type used in file names and reports.
+pub fn network_name(network: NetworkType) -> String {
+    format!("{network:?}").to_lowercase()
+}
+
+/// Append the network name to the file name (before the extension).
+pub fn with_network_suffix(path: &Path, name: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{stem}-{name}.{}", extension.to_string_lossy()),
+        None => format!("{stem}-{name}"),
+    };
+    path.with_file_name(file_name)
+}
+
+impl Default for GeneticConfiguration {
+    fn default() -> GeneticConfiguration {
+        GeneticConfiguration {
+            population_size: 8,
+            generations: 10,
+            mutations: 2,
         }
     }
 }
@@ -67,6 +691,67 @@ impl Default for MultiCriteriaAnalysisWeights {
             eigenvector: 0.1,
             betweenness: 0.25,
             closeness: 0.1,
+            asn_diversity: 0.0,
+            country_diversity: 0.0,
+            latency: 0.0,
+            uptime: 0.0,
+            stability: 0.0,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn for_network_test() {
+        let config = IPSConfiguration {
+            peer_file_path: Some(PathBuf::from("out/peers.json")),
+            log_path: Some(PathBuf::from("ips.log")),
+            debug_dump_dir: Some(PathBuf::from("ips-debug")),
+            pareto_file_path: Some(PathBuf::from("pareto.json")),
+            canary: Some(CanaryConfiguration {
+                full_peer_file_path: Some(PathBuf::from("peers-full.json")),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let config = config.for_network(NetworkType::Zcash);
+        assert_eq!(
+            config.peer_file_path,
+            Some(PathBuf::from("out/peers-zcash.json"))
+        );
+        assert_eq!(config.log_path, Some(PathBuf::from("ips-zcash.log")));
+        assert_eq!(
+            config.debug_dump_dir,
+            Some(PathBuf::from("ips-debug-zcash"))
+        );
+        assert_eq!(config.vanilla_peer_file_path, None);
+        assert_eq!(
+            config.statistics_file_path,
+            Some(PathBuf::from("out/statistics-zcash.json"))
+        );
+        assert_eq!(
+            config.security_report_file_path,
+            Some(PathBuf::from("out/security_report-zcash.json"))
+        );
+        assert_eq!(
+            config.pareto_file_path,
+            Some(PathBuf::from("pareto-zcash.json"))
+        );
+        assert_eq!(
+            config.canary.unwrap().full_peer_file_path,
+            Some(PathBuf::from("peers-full-zcash.json"))
+        );
+
+        // Nothing is written next to stdout
+        let config = IPSConfiguration {
+            peer_file_path: Some(PathBuf::from(stdio::STDIO_PATH)),
+            ..Default::default()
+        };
+        assert_eq!(config.statistics_path(), None);
+        assert_eq!(config.security_report_path(), None);
+    }
+}
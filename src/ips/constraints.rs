@@ -0,0 +1,231 @@
+// Constraints every change of the peer lists must satisfy, regardless of the optimizer used.
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+};
+
+use crate::{ips::config::IPSConfiguration, Node};
+
+/// Check if the connection between the node and its peer is a bridge - removing it could split
+/// the network, so it must be kept.
+pub fn is_bridge(bridges: &HashMap<usize, HashSet<usize>>, node: usize, peer: usize) -> bool {
+    matches!(bridges.get(&peer), Some(peers) if peers.contains(&node))
+}
+
+/// Check if the node's degree changed by more than the allowed number of peers (churn).
+pub fn churn_exceeded(original_degree: usize, degree: usize, change_no_more: u32) -> bool {
+    original_degree.abs_diff(degree) > change_no_more as usize
+}
+
+/// Check if the peer list is longer than the allowed maximum (unlimited if not set).
+pub fn peer_list_too_long(len: usize, max_peer_list_size: Option<usize>) -> bool {
+    matches!(max_peer_list_size, Some(max) if len > max)
+}
+
+/// Check if the peer list already has the allowed maximum of peers with the IP address
+/// (unlimited if not set).
+pub fn ip_limit_reached(
+    peer_ips: impl Iterator<Item = IpAddr>,
+    ip: IpAddr,
+    max_peers_per_ip: Option<usize>,
+) -> bool {
+    match max_peers_per_ip {
+        Some(max) => peer_ips.filter(|peer_ip| *peer_ip == ip).count() >= max,
+        None => false,
+    }
+}
+
+/// Check if the peer list already has the allowed maximum of peers in the autonomous system
+/// (unlimited if not set). Peers with unknown ASN are neither counted nor limited.
+pub fn asn_limit_reached(
+    peer_asns: impl Iterator<Item = Option<u32>>,
+    asn: Option<u32>,
+    max_peers_per_asn: Option<usize>,
+) -> bool {
+    match (asn, max_peers_per_asn) {
+        (Some(asn), Some(max)) => {
+            peer_asns.filter(|peer_asn| *peer_asn == Some(asn)).count() >= max
+        }
+        _ => false,
+    }
+}
+
+/// Check if both nodes are known to be in the same autonomous system (likely the same provider or
+/// datacenter).
+pub fn same_asn(asn: Option<u32>, other: Option<u32>) -> bool {
+    matches!((asn, other), (Some(asn), Some(other)) if asn == other)
+}
+
+/// Check if the alternative's rating is lower than the rating by no more than the tolerance
+/// (percentage of the rating's magnitude).
+pub fn comparable_rating(rating: f64, alternative: f64, tolerance: f64) -> bool {
+    alternative >= rating - rating.abs() * tolerance / 100.0
+}
+
+/// Check if the protocol version is known to be lower than the required minimum (unknown
+/// versions and missing minimum pass).
+pub fn protocol_version_too_low(version: Option<u32>, min_protocol_version: Option<u32>) -> bool {
+    matches!((version, min_protocol_version), (Some(version), Some(min)) if version < min)
+}
+
+/// Limits every new connection must respect, next to the per-node constraints above.
+#[derive(Debug, Default, Clone)]
+pub struct ConnectionLimits {
+    /// Maximum number of peers with one IP address in a peer list
+    pub max_peers_per_ip: Option<usize>,
+    /// Maximum number of peers from one autonomous system in a peer list
+    pub max_peers_per_asn: Option<usize>,
+    /// Geographic mega-clusters whose nodes get no new peers from the same cluster
+    pub mega_geo_clusters: HashSet<usize>,
+}
+
+impl ConnectionLimits {
+    /// Check if a new connection between the node and the peer keeps both peer lists (node
+    /// indices, the node and the peer themselves aren't counted) within the IP address and
+    /// autonomous system limits and doesn't concentrate a mega-cluster further.
+    pub fn allow(
+        &self,
+        nodes: &[Node],
+        node: usize,
+        peer: usize,
+        peer_lists: [&[usize]; 2],
+    ) -> bool {
+        let ip_of = |idx: &usize| nodes[*idx].addr.ip();
+        let asn_of = |idx: &usize| nodes[*idx].asn;
+
+        // Nodes behind one IP address are likely the same host
+        if self.max_peers_per_ip.is_some() && ip_of(&node) == ip_of(&peer) {
+            return false;
+        }
+        for (idx, (own, other)) in [(node, peer), (peer, node)].into_iter().enumerate() {
+            let peers = || {
+                peer_lists[idx]
+                    .iter()
+                    .filter(move |p| **p != own && **p != other)
+            };
+            if ip_limit_reached(peers().map(ip_of), ip_of(&other), self.max_peers_per_ip)
+                || asn_limit_reached(peers().map(asn_of), asn_of(&other), self.max_peers_per_asn)
+            {
+                return false;
+            }
+        }
+
+        !matches!(nodes[node].geo_cluster_id, Some(cluster)
+            if self.mega_geo_clusters.contains(&cluster)
+                && nodes[peer].geo_cluster_id == Some(cluster))
+    }
+}
+
+/// Counter of new connections pointing at each country and city, limiting how many of them can
+/// point at a single place within one run.
+#[derive(Default, Clone)]
+pub struct GeoThrottle {
+    max_per_country: Option<usize>,
+    max_per_city: Option<usize>,
+    per_country: HashMap<String, usize>,
+    /// Cities are counted together with their country as city names are not unique
+    per_city: HashMap<(String, String), usize>,
+    /// Number of candidates rejected because of the limits
+    pub throttled: usize,
+}
+
+impl GeoThrottle {
+    pub fn new(config: &IPSConfiguration) -> Self {
+        Self {
+            max_per_country: config.max_recommendations_per_country,
+            max_per_city: config.max_recommendations_per_city,
+            ..Default::default()
+        }
+    }
+
+    fn country(node: &Node) -> Option<String> {
+        node.geolocation.as_ref()?.country.clone()
+    }
+
+    fn city(node: &Node) -> Option<(String, String)> {
+        let geo_info = node.geolocation.as_ref()?;
+        Some((
+            geo_info.country.clone().unwrap_or_default(),
+            geo_info.city.clone()?,
+        ))
+    }
+
+    /// Check if another connection can point at the node. Nodes without known location are never
+    /// throttled.
+    pub fn allows(&self, node: &Node) -> bool {
+        let country_full = match (self.max_per_country, Self::country(node)) {
+            (Some(max), Some(country)) => {
+                self.per_country.get(&country).copied().unwrap_or(0) >= max
+            }
+            _ => false,
+        };
+        let city_full = match (self.max_per_city, Self::city(node)) {
+            (Some(max), Some(city)) => self.per_city.get(&city).copied().unwrap_or(0) >= max,
+            _ => false,
+        };
+        !country_full && !city_full
+    }
+
+    /// Count a new connection pointing at the node.
+    pub fn record(&mut self, node: &Node) {
+        if let Some(country) = Self::country(node) {
+            *self.per_country.entry(country).or_default() += 1;
+        }
+        if let Some(city) = Self::city(node) {
+            *self.per_city.entry(city).or_default() += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constraints_test() {
+        let bridges = HashMap::from([(1, HashSet::from([0])), (0, HashSet::from([1]))]);
+        assert!(is_bridge(&bridges, 0, 1));
+        assert!(is_bridge(&bridges, 1, 0));
+        assert!(!is_bridge(&bridges, 0, 2));
+        assert!(!is_bridge(&bridges, 2, 0));
+
+        assert!(!churn_exceeded(5, 7, 2));
+        assert!(!churn_exceeded(5, 3, 2));
+        assert!(churn_exceeded(5, 8, 2));
+        assert!(churn_exceeded(5, 2, 2));
+
+        assert!(!peer_list_too_long(8, None));
+        assert!(!peer_list_too_long(8, Some(8)));
+        assert!(peer_list_too_long(9, Some(8)));
+
+        let ip = |host: u8| IpAddr::from([10, 0, 0, host]);
+        let peer_ips = [ip(1), ip(2), ip(1)];
+        assert!(!ip_limit_reached(peer_ips.into_iter(), ip(1), None));
+        assert!(!ip_limit_reached(peer_ips.into_iter(), ip(1), Some(3)));
+        assert!(ip_limit_reached(peer_ips.into_iter(), ip(1), Some(2)));
+        assert!(!ip_limit_reached(peer_ips.into_iter(), ip(3), Some(1)));
+
+        let peer_asns = [Some(7), None, Some(7), Some(8)];
+        assert!(!asn_limit_reached(peer_asns.into_iter(), Some(7), None));
+        assert!(asn_limit_reached(peer_asns.into_iter(), Some(7), Some(2)));
+        assert!(!asn_limit_reached(peer_asns.into_iter(), Some(8), Some(2)));
+        assert!(!asn_limit_reached(peer_asns.into_iter(), None, Some(1)));
+
+        assert!(same_asn(Some(7), Some(7)));
+        assert!(!same_asn(Some(7), Some(8)));
+        assert!(!same_asn(None, None));
+        assert!(!same_asn(Some(7), None));
+
+        assert!(comparable_rating(50.0, 45.0, 10.0));
+        assert!(!comparable_rating(50.0, 44.0, 10.0));
+        assert!(comparable_rating(-50.0, -55.0, 10.0));
+        assert!(!comparable_rating(-50.0, -56.0, 10.0));
+        assert!(comparable_rating(50.0, 60.0, 0.0));
+
+        assert!(!protocol_version_too_low(Some(170100), None));
+        assert!(!protocol_version_too_low(None, Some(170100)));
+        assert!(!protocol_version_too_low(Some(170100), Some(170100)));
+        assert!(protocol_version_too_low(Some(170018), Some(170100)));
+    }
+}
@@ -0,0 +1,416 @@
+// Genetic-algorithm optimizer evolving whole peer-list assignments. The MCDA algorithm decides
+// for each node separately, so it can't see how changes of different nodes work together. Here
+// whole networks are scored (using the network statistics) and the best ones are combined and
+// mutated over a number of generations. Every network must satisfy the same constraints as the
+// MCDA result - bridges are never removed, no node's degree changes by more than allowed, no peer
+// list grows over the size limit and new connections respect the IP address, autonomous system,
+// mega-cluster and country/city limits. The minimum country spread is repaired afterwards for
+// both optimizers.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::{
+    csr::{undirected_edges, Adjacency},
+    ips::{
+        config::GeneticConfiguration,
+        constraints::{
+            churn_exceeded, is_bridge, peer_list_too_long, ConnectionLimits, GeoThrottle,
+        },
+    },
+    numeric::Rng,
+    Node,
+};
+
+/// Number of attempts to find a mutation satisfying the constraints.
+const MUTATION_ATTEMPTS: usize = 16;
+
+/// Connections of the network as undirected edges (lower index first).
+type Edges = BTreeSet<(usize, usize)>;
+
+/// Evolves peer-list assignments of the network.
+pub struct GeneticOptimizer<'a> {
+    config: &'a GeneticConfiguration,
+    /// Nodes of the network before any change - churn is checked against their degrees
+    original: &'a [Node],
+    bridges: &'a HashMap<usize, HashSet<usize>>,
+    change_no_more: u32,
+    /// Maximum length of any peer list (unlimited if not set)
+    max_peer_list_size: Option<usize>,
+    /// Nodes which must never get new connections
+    excluded: HashSet<usize>,
+    /// Limits of the new connections
+    limits: ConnectionLimits,
+    /// Counts new connections per country and city (a fresh copy is used for every network)
+    throttle: GeoThrottle,
+    /// Connections of the original network - the others are new
+    original_edges: Edges,
+    rng: Rng,
+}
+
+impl<'a> GeneticOptimizer<'a> {
+    /// Optimizer for the network of the original nodes with connections given by the graph.
+    pub fn new(
+        config: &'a GeneticConfiguration,
+        original: &'a [Node],
+        graph: &(impl Adjacency + ?Sized),
+        bridges: &'a HashMap<usize, HashSet<usize>>,
+        change_no_more: u32,
+        seed: u64,
+    ) -> Self {
+        Self {
+            config,
+            original,
+            bridges,
+            change_no_more,
+            max_peer_list_size: None,
+            excluded: HashSet::new(),
+            limits: ConnectionLimits::default(),
+            throttle: GeoThrottle::default(),
+            original_edges: undirected_edges(graph).collect(),
+            rng: Rng(seed),
+        }
+    }
+
+    /// Never connect the given nodes (e.g. blacklisted ones) to any new peer.
+    pub fn excluding(mut self, excluded: HashSet<usize>) -> Self {
+        self.excluded = excluded;
+        self
+    }
+
+    /// Never let any peer list grow over the given size.
+    pub fn with_max_peer_list_size(mut self, max_peer_list_size: Option<usize>) -> Self {
+        self.max_peer_list_size = max_peer_list_size;
+        self
+    }
+
+    /// Hold new connections to the limits (the MCDA ones) and count them in the throttle, which
+    /// must not have any connection recorded yet.
+    pub fn with_limits(mut self, limits: ConnectionLimits, throttle: GeoThrottle) -> Self {
+        self.limits = limits;
+        self.throttle = throttle;
+        self
+    }
+
+    /// Evolve the population starting from the given networks (e.g. the original network and the
+    /// MCDA result) and return peer lists of the best network found (higher score is better)
+    /// together with the best score of each generation (the initial population first). Networks
+    /// are scored by their peer lists.
+    pub fn evolve<F>(
+        &mut self,
+        initial: &[&dyn Adjacency],
+        mut score: F,
+    ) -> (Vec<Vec<usize>>, Vec<f64>)
+    where
+        F: FnMut(&[Vec<usize>]) -> f64,
+    {
+        let count = self.original.len();
+        // Networks which can't be scored are the worst ones
+        let mut score_edges = |edges: &Edges| {
+            let score = score(&peer_lists(edges, count));
+            if score.is_nan() {
+                f64::NEG_INFINITY
+            } else {
+                score
+            }
+        };
+
+        // Networks with too long peer lists (e.g. the original one) can't be the result
+        let mut population = initial
+            .iter()
+            .map(|network| undirected_edges(*network).collect::<Edges>())
+            .filter(|edges| !self.violates_size(edges))
+            .collect::<Vec<Edges>>();
+        // Fill the population with mutations of the initial networks
+        let size = self.config.population_size.max(population.len()).max(1);
+        let seeds = population.len();
+        while seeds > 0 && population.len() < size {
+            let mut edges = population[self.rng.below(seeds)].clone();
+            self.mutate(&mut edges);
+            population.push(edges);
+        }
+        let mut scored = population
+            .into_iter()
+            .map(|edges| {
+                let score = score_edges(&edges);
+                (edges, score)
+            })
+            .collect::<Vec<(Edges, f64)>>();
+        if scored.is_empty() {
+            return (peer_lists(&self.original_edges, count), Vec::new());
+        }
+        sort_by_score(&mut scored);
+
+        let mut history = vec![scored[0].1];
+        for _ in 0..self.config.generations {
+            // The best network always survives
+            let mut next = vec![scored[0].clone()];
+            while next.len() < size {
+                let a = self.tournament(&scored);
+                let b = self.tournament(&scored);
+                let mut child = self.crossover(&scored[a].0, &scored[b].0);
+                for _ in 0..self.config.mutations {
+                    self.mutate(&mut child);
+                }
+                let score = score_edges(&child);
+                next.push((child, score));
+            }
+            scored = next;
+            sort_by_score(&mut scored);
+            history.push(scored[0].1);
+        }
+
+        (peer_lists(&scored[0].0, count), history)
+    }
+
+    /// Choose the better of two random networks.
+    fn tournament(&mut self, scored: &[(Edges, f64)]) -> usize {
+        let a = self.rng.below(scored.len());
+        let b = self.rng.below(scored.len());
+        // Population is sorted, so the lower index is the better one
+        a.min(b)
+    }
+
+    /// Child keeps edges present in both parents and half of the others. If the child violates
+    /// the constraints, the first parent is taken instead.
+    fn crossover(&mut self, a: &Edges, b: &Edges) -> Edges {
+        let mut child = a.intersection(b).copied().collect::<Edges>();
+        for edge in a.symmetric_difference(b) {
+            if self.rng.coin() {
+                child.insert(*edge);
+            }
+        }
+        // Bridges are kept by both parents, but make sure they are never lost
+        for (peer, nodes) in self.bridges.iter() {
+            for node in nodes {
+                child.insert(edge(*node, *peer));
+            }
+        }
+
+        if self.violates_churn(&child) || self.violates_size(&child) || self.violates_limits(&child)
+        {
+            return a.clone();
+        }
+        child
+    }
+
+    /// Replace a random connection of a random node with a connection to another random node.
+    /// Nothing is changed if no such replacement satisfying the constraints is found.
+    fn mutate(&mut self, edges: &mut Edges) {
+        let count = self.original.len();
+        if edges.is_empty() || count < 3 {
+            return;
+        }
+
+        let degrees = degrees(edges, count);
+        let peer_lists = peer_lists(edges, count);
+        let throttle = self.throttle_of(edges);
+        for _ in 0..MUTATION_ATTEMPTS {
+            let (a, b) = *edges.iter().nth(self.rng.below(edges.len())).unwrap();
+            // Either side of the connection can be the one which keeps its degree
+            let (node, dropped) = if self.rng.coin() { (a, b) } else { (b, a) };
+            let target = self.rng.below(count);
+
+            if target == node
+                || target == dropped
+                || edges.contains(&edge(node, target))
+                || self.excluded.contains(&target)
+                || self.excluded.contains(&node)
+                || is_bridge(self.bridges, node, dropped)
+                || is_bridge(self.bridges, dropped, node)
+                || churn_exceeded(
+                    self.original[dropped].degree,
+                    degrees[dropped] - 1,
+                    self.change_no_more,
+                )
+                || churn_exceeded(
+                    self.original[target].degree,
+                    degrees[target] + 1,
+                    self.change_no_more,
+                )
+                || peer_list_too_long(degrees[target] + 1, self.max_peer_list_size)
+                || !self.allows_new(&peer_lists, &throttle, node, target)
+            {
+                continue;
+            }
+
+            edges.remove(&(a, b));
+            edges.insert(edge(node, target));
+            return;
+        }
+    }
+
+    fn violates_size(&self, edges: &Edges) -> bool {
+        degrees(edges, self.original.len())
+            .iter()
+            .any(|degree| peer_list_too_long(*degree, self.max_peer_list_size))
+    }
+
+    /// Check the limits for the new connection between the node and the peer.
+    fn allows_new(
+        &self,
+        peer_lists: &[Vec<usize>],
+        throttle: &GeoThrottle,
+        node: usize,
+        peer: usize,
+    ) -> bool {
+        self.limits.allow(
+            self.original,
+            node,
+            peer,
+            [&peer_lists[node], &peer_lists[peer]],
+        ) && throttle.allows(&self.original[node])
+            && throttle.allows(&self.original[peer])
+    }
+
+    /// Throttle with all new connections of the network counted at both ends.
+    fn throttle_of(&self, edges: &Edges) -> GeoThrottle {
+        let mut throttle = self.throttle.clone();
+        for (a, b) in edges.difference(&self.original_edges) {
+            throttle.record(&self.original[*a]);
+            throttle.record(&self.original[*b]);
+        }
+        throttle
+    }
+
+    fn violates_limits(&self, edges: &Edges) -> bool {
+        let peer_lists = peer_lists(edges, self.original.len());
+        let mut throttle = self.throttle.clone();
+        for (a, b) in edges.difference(&self.original_edges) {
+            if !self.allows_new(&peer_lists, &throttle, *a, *b) {
+                return true;
+            }
+            throttle.record(&self.original[*a]);
+            throttle.record(&self.original[*b]);
+        }
+        false
+    }
+
+    fn violates_churn(&self, edges: &Edges) -> bool {
+        degrees(edges, self.original.len())
+            .iter()
+            .zip(self.original.iter())
+            .any(|(degree, node)| churn_exceeded(node.degree, *degree, self.change_no_more))
+    }
+}
+
+fn edge(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
+
+fn degrees(edges: &Edges, count: usize) -> Vec<usize> {
+    let mut degrees = vec![0; count];
+    for (a, b) in edges {
+        degrees[*a] += 1;
+        degrees[*b] += 1;
+    }
+    degrees
+}
+
+fn peer_lists(edges: &Edges, count: usize) -> Vec<Vec<usize>> {
+    let mut peer_lists = vec![Vec::new(); count];
+    for (a, b) in edges {
+        peer_lists[*a].push(*b);
+        peer_lists[*b].push(*a);
+    }
+    peer_lists
+}
+
+/// Sort networks by their score, best first. Sort is stable, so the result is deterministic.
+fn sort_by_score(scored: &mut [(Edges, f64)]) {
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nodes::update_degrees;
+
+    /// Ring of nodes with a few chords.
+    fn network() -> Vec<Node> {
+        let count = 12;
+        let mut nodes = (0..count)
+            .map(|idx| {
+                let mut connections = vec![(idx + 1) % count, (idx + count - 1) % count];
+                if idx % 4 == 0 {
+                    connections.push((idx + count / 2) % count);
+                }
+                Node {
+                    connections,
+                    ..Default::default()
+                }
+            })
+            .collect::<Vec<Node>>();
+        update_degrees(&mut nodes);
+        nodes
+    }
+
+    /// Prefer networks where degrees are as even as possible.
+    fn score(peer_lists: &[Vec<usize>]) -> f64 {
+        -peer_lists
+            .iter()
+            .map(|peers| (peers.len() as f64 - 3.0).powi(2))
+            .sum::<f64>()
+    }
+
+    #[test]
+    fn evolve_test() {
+        let original = network();
+        let config = GeneticConfiguration {
+            population_size: 6,
+            generations: 5,
+            mutations: 2,
+        };
+        // Connection between 0 and 1 must be kept
+        let bridges = HashMap::from([(0, HashSet::from([1])), (1, HashSet::from([0]))]);
+
+        let mut optimizer = GeneticOptimizer::new(&config, &original, &original, &bridges, 1, 7);
+        let (peer_lists, history) = optimizer.evolve(&[&original], score);
+
+        assert_eq!(history.len(), 6);
+        // The best network always survives
+        assert!(history.windows(2).all(|w| w[1] >= w[0]));
+        assert_eq!(score(&peer_lists), *history.last().unwrap());
+
+        assert!(peer_lists[0].contains(&1));
+        for (peers, original) in peer_lists.iter().zip(original.iter()) {
+            assert!(!churn_exceeded(original.connections.len(), peers.len(), 1));
+        }
+
+        // Same seed gives the same result
+        let mut optimizer = GeneticOptimizer::new(&config, &original, &original, &bridges, 1, 7);
+        let (same_peer_lists, _) = optimizer.evolve(&[&original], score);
+        assert_eq!(peer_lists, same_peer_lists);
+
+        // Mutations never grow a peer list over the limit
+        let mut optimizer = GeneticOptimizer::new(&config, &original, &original, &bridges, 1, 7)
+            .with_max_peer_list_size(Some(3));
+        let (peer_lists, _) = optimizer.evolve(&[&original], score);
+        assert!(peer_lists.iter().all(|peers| peers.len() <= 3));
+
+        // No new connection within a mega-cluster covering the whole network
+        let mut clustered = original.clone();
+        for node in clustered.iter_mut() {
+            node.geo_cluster_id = Some(0);
+        }
+        let limits = ConnectionLimits {
+            mega_geo_clusters: HashSet::from([0]),
+            ..Default::default()
+        };
+        let mut optimizer = GeneticOptimizer::new(&config, &clustered, &clustered, &bridges, 1, 7)
+            .with_limits(limits, GeoThrottle::default());
+        let (peer_lists, _) = optimizer.evolve(&[&clustered], score);
+        let edges = undirected_edges(&peer_lists).collect::<Edges>();
+        assert!(edges.is_subset(&undirected_edges(&clustered).collect()));
+
+        // Networks which can't be scored are never the best ones
+        let mut optimizer = GeneticOptimizer::new(&config, &original, &original, &bridges, 1, 7);
+        let (_, history) = optimizer.evolve(&[&original], |peer_lists| {
+            if peer_lists[0].len() == 3 {
+                f64::NAN
+            } else {
+                score(peer_lists)
+            }
+        });
+        assert!(history.iter().all(|score| !score.is_nan()));
+    }
+}
@@ -1,13 +1,18 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     net::SocketAddr,
+    thread,
 };
 
+use serde::{Deserialize, Serialize};
 use spectre::{edge::Edge, graph::Graph};
 use ziggurat_core_crawler::summary::NetworkType;
 
 use crate::{
-    ips::{algorithm::IpsState, statistics::median},
+    csr::{undirected_edges, Adjacency},
+    ips::algorithm::IpsState,
+    nodes::update_degrees,
+    numeric::median,
     Node,
 };
 
@@ -28,7 +33,12 @@ use crate::{
 /// the median but taking value from some percentile (eg. 90th percentile) but this could lead to
 /// set threshold to find too many bridges in case of eg. balanced graph (if there are many nodes
 /// with similar betweenness centrality taking top 20% would result in finding fake bridges).
-pub fn find_bridges(nodes: &[Node], threshold_adjustment: f64) -> HashMap<usize, HashSet<usize>> {
+/// Connections of the nodes are given by the graph.
+pub fn find_bridges(
+    nodes: &[Node],
+    graph: &(impl Adjacency + ?Sized),
+    threshold_adjustment: f64,
+) -> HashMap<usize, HashSet<usize>> {
     let mut bridges = HashMap::new();
 
     // If there are less than 2 nodes there is no point in finding bridges.
@@ -48,7 +58,7 @@ pub fn find_bridges(nodes: &[Node], threshold_adjustment: f64) -> HashMap<usize,
             continue;
         }
 
-        for peer_idx in &node.connections {
+        for peer_idx in graph.connections(node_idx) {
             if nodes[*peer_idx].betweenness <= betweenness_threshold {
                 continue;
             }
@@ -73,25 +83,331 @@ pub fn find_bridges(nodes: &[Node], threshold_adjustment: f64) -> HashMap<usize,
     bridges
 }
 
-/// Reconstruct graph from nodes and their connection subfield. This step is used to run
-/// some graph algorithms on the graph (like betweenness centrality).
-pub fn construct_graph(nodes: &[Node]) -> Graph<SocketAddr> {
+/// Find bridges and articulation points in graph using Tarjan's algorithm.
+/// Unlike `find_bridges` this returns only real bridges - edges that disconnect the graph when
+/// removed. Articulation points are nodes whose removal disconnects the graph. DFS is done
+/// iteratively to avoid stack overflows on large networks.
+pub fn find_exact_bridges(
+    graph: &(impl Adjacency + ?Sized),
+) -> (HashMap<usize, HashSet<usize>>, HashSet<usize>) {
+    let mut bridges: HashMap<usize, HashSet<usize>> = HashMap::new();
+    let mut articulation_points = HashSet::new();
+
+    let mut discovery = vec![usize::MAX; graph.node_count()];
+    let mut low = vec![0; graph.node_count()];
+    let mut timer = 0;
+
+    for root in 0..graph.node_count() {
+        if discovery[root] != usize::MAX {
+            continue;
+        }
+
+        discovery[root] = timer;
+        low[root] = timer;
+        timer += 1;
+        let mut root_children = 0;
+
+        // Each frame holds: node, its DFS parent, next connection to visit and whether the edge
+        // to the parent was already skipped (so parallel edges are not treated as bridges).
+        let mut stack = vec![(root, usize::MAX, 0, false)];
+
+        while let Some(frame) = stack.last_mut() {
+            let (node_idx, parent_idx) = (frame.0, frame.1);
+
+            if frame.2 < graph.connections(node_idx).len() {
+                let peer_idx = graph.connections(node_idx)[frame.2];
+                frame.2 += 1;
+
+                if peer_idx >= graph.node_count() || peer_idx == node_idx {
+                    continue;
+                }
+
+                if peer_idx == parent_idx && !frame.3 {
+                    frame.3 = true;
+                    continue;
+                }
+
+                if discovery[peer_idx] == usize::MAX {
+                    discovery[peer_idx] = timer;
+                    low[peer_idx] = timer;
+                    timer += 1;
+                    if node_idx == root {
+                        root_children += 1;
+                    }
+                    stack.push((peer_idx, node_idx, 0, false));
+                } else {
+                    low[node_idx] = low[node_idx].min(discovery[peer_idx]);
+                }
+                continue;
+            }
+
+            stack.pop();
+            if parent_idx == usize::MAX {
+                continue;
+            }
+
+            low[parent_idx] = low[parent_idx].min(low[node_idx]);
+
+            if low[node_idx] > discovery[parent_idx] {
+                bridges.entry(parent_idx).or_default().insert(node_idx);
+                bridges.entry(node_idx).or_default().insert(parent_idx);
+            }
+
+            if parent_idx != root && low[node_idx] >= discovery[parent_idx] {
+                articulation_points.insert(parent_idx);
+            }
+        }
+
+        if root_children > 1 {
+            articulation_points.insert(root);
+        }
+    }
+
+    (bridges, articulation_points)
+}
+
+/// Count triangles in the graph and compute its global transitivity (the ratio of closed
+/// triplets to all connected triplets). Triangles imply alternative paths between nodes, so
+/// these measures show how much redundancy the network has.
+/// Each triangle is counted once by orienting edges from lower to higher ranked node (by degree).
+pub fn count_triangles(graph: &(impl Adjacency + ?Sized)) -> (u64, f64) {
+    let neighbours = (0..graph.node_count())
+        .map(|idx| {
+            let mut peers = graph
+                .connections(idx)
+                .iter()
+                .copied()
+                .filter(|peer_idx| *peer_idx != idx && *peer_idx < graph.node_count())
+                .collect::<Vec<usize>>();
+            peers.sort_unstable();
+            peers.dedup();
+            peers
+        })
+        .collect::<Vec<_>>();
+
+    let rank = |idx: usize| (neighbours[idx].len(), idx);
+    let forward = neighbours
+        .iter()
+        .enumerate()
+        .map(|(idx, peers)| {
+            peers
+                .iter()
+                .copied()
+                .filter(|peer_idx| rank(*peer_idx) > rank(idx))
+                .collect::<HashSet<usize>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut triangles = 0u64;
+    for (idx, peers) in forward.iter().enumerate() {
+        for peer_idx in peers {
+            triangles += forward[*peer_idx]
+                .iter()
+                .filter(|third_idx| forward[idx].contains(third_idx))
+                .count() as u64;
+        }
+    }
+
+    let triplets = neighbours
+        .iter()
+        .map(|peers| {
+            let degree = peers.len() as u64;
+            degree * degree.saturating_sub(1) / 2
+        })
+        .sum::<u64>();
+
+    let transitivity = if triplets == 0 {
+        0.0
+    } else {
+        3.0 * triangles as f64 / triplets as f64
+    };
+
+    (triangles, transitivity)
+}
+
+/// Rich-club coefficients of the graph for degree thresholds 1, 2, 4, 8... - the fraction of
+/// possible connections present among the nodes with degree higher than the threshold. The
+/// thresholds go up as long as at least two nodes are above them. High values for high
+/// thresholds mean the best connected nodes form a tightly connected core.
+pub fn rich_club_coefficients(graph: &(impl Adjacency + ?Sized)) -> BTreeMap<u32, f64> {
+    let neighbours = (0..graph.node_count())
+        .map(|idx| {
+            graph
+                .connections(idx)
+                .iter()
+                .copied()
+                .filter(|peer_idx| *peer_idx != idx && *peer_idx < graph.node_count())
+                .collect::<HashSet<usize>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut coefficients = BTreeMap::new();
+    let mut threshold = 1;
+    loop {
+        let rich = (0..graph.node_count())
+            .filter(|idx| neighbours[*idx].len() > threshold as usize)
+            .collect::<HashSet<usize>>();
+        if rich.len() < 2 {
+            break;
+        }
+        // Each connection is counted from both of its ends
+        let connections = rich
+            .iter()
+            .map(|idx| neighbours[*idx].intersection(&rich).count())
+            .sum::<usize>();
+        coefficients.insert(
+            threshold,
+            connections as f64 / (rich.len() * (rich.len() - 1)) as f64,
+        );
+        threshold *= 2;
+    }
+    coefficients
+}
+
+/// Minimum cut between two nodes - the smallest number of connections whose removal separates
+/// them. Unlike betweenness it shows how many connections actually hold the network together.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MinCut {
+    pub source: SocketAddr,
+    pub target: SocketAddr,
+    /// Number of connections in the cut
+    pub size: usize,
+}
+
+/// Find minimum cuts between each pair of the given number of nodes with the highest
+/// betweenness, the smallest cut first. Critical nodes are only a sample of the network, so the
+/// smallest cut found is an estimate (an upper bound) of the weakest point of the network. Pairs
+/// of nodes in different components are already separated and left out. Connections of the nodes
+/// are given by the graph.
+pub fn find_min_cuts(
+    nodes: &[Node],
+    graph: &(impl Adjacency + ?Sized),
+    count: usize,
+) -> Vec<MinCut> {
+    let mut component = vec![0; nodes.len()];
+    for (id, island) in detect_islands(graph).into_iter().enumerate() {
+        for idx in island {
+            component[idx] = id;
+        }
+    }
+
+    let mut critical = (0..nodes.len()).collect::<Vec<usize>>();
+    critical.sort_by(|a, b| {
+        nodes[*b]
+            .betweenness
+            .total_cmp(&nodes[*a].betweenness)
+            .then_with(|| a.cmp(b))
+    });
+    critical.truncate(count);
+
+    let network = FlowNetwork::new(graph);
+    let mut cuts = Vec::new();
+    for (idx, source) in critical.iter().enumerate() {
+        for target in critical[idx + 1..]
+            .iter()
+            .filter(|target| component[**target] == component[*source])
+        {
+            cuts.push(MinCut {
+                source: nodes[*source].addr,
+                target: nodes[*target].addr,
+                size: network.max_flow(*source, *target),
+            });
+        }
+    }
+    // Sort is stable, so cuts of the same size keep the order of betweenness
+    cuts.sort_by_key(|cut| cut.size);
+    cuts
+}
+
+/// Connections with unit capacity for the max-flow computation. Each connection is a pair of
+/// arcs which are reverse of each other (arc `i` and `i ^ 1`), so the flow can use it in either
+/// direction.
+struct FlowNetwork {
+    /// Node each arc points to
+    heads: Vec<usize>,
+    /// Arcs leaving each node
+    arcs: Vec<Vec<usize>>,
+}
+
+impl FlowNetwork {
+    fn new(graph: &(impl Adjacency + ?Sized)) -> Self {
+        // Connections are counted once, even if listed by both ends or more times
+        let mut edges = undirected_edges(graph).collect::<Vec<(usize, usize)>>();
+        edges.sort_unstable();
+        edges.dedup();
+
+        let mut network = FlowNetwork {
+            heads: Vec::with_capacity(edges.len() * 2),
+            arcs: vec![Vec::new(); graph.node_count()],
+        };
+        for (a, b) in edges {
+            network.arcs[a].push(network.heads.len());
+            network.heads.push(b);
+            network.arcs[b].push(network.heads.len());
+            network.heads.push(a);
+        }
+        network
+    }
+
+    /// Maximum flow between the nodes (Edmonds-Karp) - the size of the minimum cut separating
+    /// them, as all capacities are one.
+    fn max_flow(&self, source: usize, sink: usize) -> usize {
+        let mut capacity = vec![1u32; self.heads.len()];
+        let mut flow = 0;
+        loop {
+            // Shortest augmenting path, each node remembers the arc it was reached by
+            let mut via = vec![None; self.arcs.len()];
+            let mut visited = vec![false; self.arcs.len()];
+            let mut queue = VecDeque::from([source]);
+            visited[source] = true;
+            while let Some(node) = queue.pop_front() {
+                if node == sink {
+                    break;
+                }
+                for arc in self.arcs[node].iter() {
+                    let next = self.heads[*arc];
+                    if capacity[*arc] > 0 && !visited[next] {
+                        visited[next] = true;
+                        via[next] = Some(*arc);
+                        queue.push_back(next);
+                    }
+                }
+            }
+            if !visited[sink] {
+                return flow;
+            }
+
+            let mut node = sink;
+            while let Some(arc) = via[node] {
+                capacity[arc] -= 1;
+                capacity[arc ^ 1] += 1;
+                node = self.heads[arc ^ 1];
+            }
+            flow += 1;
+        }
+    }
+}
+
+/// Reconstruct graph from nodes and their connections given by the adjacency. This step is used
+/// to run some graph algorithms on the graph (like betweenness centrality).
+pub fn construct_graph(nodes: &[Node], adjacency: &(impl Adjacency + ?Sized)) -> Graph<SocketAddr> {
     let mut graph = Graph::new();
 
-    for node in nodes {
+    for (idx, node) in nodes.iter().enumerate() {
         let node_addr = node.addr;
+        let connections = adjacency.connections(idx);
 
         // This is a hack to add nodes that are not connected to any other node. That can happen
         // when are found through different network nodes. After filtering out that nodes it could
         // be seen like some nodes are not connected to any other node.
         // This is needed to run some graph algorithms on the graph - like counting betweenness or
         // closeness centrality as well as simple getting degree.
-        if node.connections.is_empty() {
+        if connections.is_empty() {
             graph.insert(Edge::new(node_addr, node_addr));
             continue;
         }
 
-        for i in &node.connections {
+        for i in connections {
             if *i >= nodes.len() {
                 // This should not happen as we check if node has proper connection indexes when
                 // we take the snapshot of the state from external source. However, re-constructing
@@ -113,29 +429,6 @@ pub fn construct_graph(nodes: &[Node]) -> Graph<SocketAddr> {
     graph
 }
 
-/// Removes node from the state and updates all indices in the peerlist
-pub fn remove_node(nodes: &mut Vec<Node>, node_idx: usize) {
-    let node = nodes[node_idx].clone();
-
-    for rnode in nodes.iter_mut() {
-        if let Some(pos) = rnode.connections.iter().position(|x| *x == node_idx) {
-            rnode.connections.remove(pos);
-        }
-    }
-
-    nodes.retain(|x| x.addr != node.addr);
-
-    // Now the tricky part - we need to update all indices in the peerlist
-    // of all nodes that have higher index than the one we removed
-    for node in nodes.iter_mut() {
-        for peer_idx in node.connections.iter_mut() {
-            if *peer_idx > node_idx {
-                *peer_idx -= 1;
-            }
-        }
-    }
-}
-
 /// Find node with lowest betweenness centrality in the provided nodes indexes.
 pub fn find_lowest_betweenness(nodes_idx: &[usize], state: &IpsState) -> usize {
     let mut lowest_betweenness = f64::MAX;
@@ -152,31 +445,300 @@ pub fn find_lowest_betweenness(nodes_idx: &[usize], state: &IpsState) -> usize {
     lowest_betweenness_idx
 }
 
-/// Create new vector with nodes that have common network type.
+/// Create new vector with nodes that have common network type. Nodes keep their order and
+/// connections are reindexed - connections to other nodes are dropped.
 pub fn filter_network(nodes: &[Node], network: NetworkType) -> Vec<Node> {
-    let mut network_nodes = nodes.to_vec();
-
-    // Two step here - first we collect all indices that we want to remove and then we remove them
-    // First step is using `rev` so we don't mess up the order of those found indices once we start removing them
-    let mut indices_to_remove = Vec::with_capacity(network_nodes.len());
-    for idx in (0..network_nodes.len()).rev() {
-        if network_nodes[idx].network_type != network {
-            indices_to_remove.push(idx);
+    // New index of each node of the network - only those nodes are cloned, so filtering a large
+    // state takes a single pass
+    let mut index_map = vec![None; nodes.len()];
+    let mut network_len = 0;
+    for (idx, node) in nodes.iter().enumerate() {
+        if node.network_type == network {
+            index_map[idx] = Some(network_len);
+            network_len += 1;
         }
     }
 
-    for undesired_idx in indices_to_remove {
-        remove_node(&mut network_nodes, undesired_idx);
+    let mut network_nodes = Vec::with_capacity(network_len);
+    for node in nodes.iter().filter(|node| node.network_type == network) {
+        let mut network_node = node.clone();
+        network_node.connections = node
+            .connections
+            .iter()
+            .filter_map(|peer_idx| index_map.get(*peer_idx).copied().flatten())
+            .collect();
+        network_nodes.push(network_node);
     }
 
+    update_degrees(&mut network_nodes);
     network_nodes
 }
 
+// Very simple algorithm to detect islands.
+// Take first vertex and do BFS to find all connected vertices. If there are any unvisited vertices
+// create new island and do BFS one more time. Repeat until all vertices are visited.
+pub fn detect_islands(graph: &(impl Adjacency + ?Sized)) -> Vec<HashSet<usize>> {
+    let mut islands = Vec::new();
+    let mut visited = vec![false; graph.node_count()];
+
+    for i in 0..graph.node_count() {
+        if visited[i] {
+            continue;
+        }
+
+        let mut island = HashSet::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(i);
+
+        while let Some(node_idx) = queue.pop_front() {
+            if visited[node_idx] {
+                continue;
+            }
+
+            island.insert(node_idx);
+
+            visited[node_idx] = true;
+
+            for peer_idx in graph.connections(node_idx) {
+                if !visited[*peer_idx] {
+                    queue.push_back(*peer_idx);
+                }
+            }
+        }
+        islands.push(island);
+    }
+    islands
+}
+
+/// Take nodes of the given island as a separate network. Nodes keep their order and their
+/// connections, given by the graph, are reindexed.
+pub fn extract_island(
+    nodes: &[Node],
+    graph: &(impl Adjacency + ?Sized),
+    island: &HashSet<usize>,
+) -> Vec<Node> {
+    let mut indices = island.iter().copied().collect::<Vec<usize>>();
+    indices.sort_unstable();
+
+    let index_map = indices
+        .iter()
+        .enumerate()
+        .map(|(new_idx, old_idx)| (*old_idx, new_idx))
+        .collect::<HashMap<usize, usize>>();
+
+    let mut island_nodes = indices
+        .iter()
+        .map(|old_idx| {
+            let mut node = nodes[*old_idx].clone();
+            node.connections = graph
+                .connections(*old_idx)
+                .iter()
+                .filter_map(|peer_idx| index_map.get(peer_idx).copied())
+                .collect();
+            node
+        })
+        .collect::<Vec<Node>>();
+    update_degrees(&mut island_nodes);
+    island_nodes
+}
+
+/// Find nodes belonging to components in which any node changed its connections compared to
+/// the previous graph of the same nodes. Returned indices are sorted.
+pub fn changed_components(
+    previous: &(impl Adjacency + ?Sized),
+    graph: &(impl Adjacency + ?Sized),
+) -> Vec<usize> {
+    let connections_of = |connections: &[usize]| {
+        let mut connections = connections.to_vec();
+        connections.sort_unstable();
+        connections.dedup();
+        connections
+    };
+
+    let mut changed = Vec::new();
+    for island in detect_islands(graph) {
+        let is_changed = island.iter().any(|idx| {
+            *idx >= previous.node_count()
+                || connections_of(previous.connections(*idx))
+                    != connections_of(graph.connections(*idx))
+        });
+
+        if is_changed {
+            changed.extend(island);
+        }
+    }
+
+    changed.sort_unstable();
+    changed
+}
+
+/// Distance based measures of the graph computed on its largest connected component.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct GraphDistances {
+    /// Number of nodes in the largest connected component
+    pub component_size: usize,
+    /// Maximum eccentricity in the component
+    pub diameter: u32,
+    /// Minimum eccentricity in the component
+    pub radius: u32,
+    /// Average length of the shortest path between any two nodes in the component
+    pub average_shortest_path_length: f64,
+    /// Eccentricity of each node (None for nodes outside the largest component)
+    #[serde(skip)]
+    pub eccentricities: Vec<Option<u32>>,
+    /// Sum of distances from each node to other nodes in the component (None for nodes outside
+    /// the largest component)
+    #[serde(skip)]
+    pub path_length_sums: Vec<Option<u64>>,
+}
+
+/// Compute diameter, radius, average shortest path length and per-node eccentricity for the
+/// largest connected component of the graph. Each node in the component runs its own BFS, so
+/// the work is split between the given number of threads.
+pub fn compute_distances(graph: &(impl Adjacency + ?Sized), num_threads: usize) -> GraphDistances {
+    let mut distances = GraphDistances {
+        eccentricities: vec![None; graph.node_count()],
+        path_length_sums: vec![None; graph.node_count()],
+        ..Default::default()
+    };
+
+    let component = match detect_islands(graph).into_iter().max_by_key(|i| i.len()) {
+        Some(island) => {
+            let mut component = island.into_iter().collect::<Vec<usize>>();
+            component.sort_unstable();
+            component
+        }
+        None => return distances,
+    };
+
+    let chunk_size = (component.len() / num_threads.max(1)).max(1);
+    let results = thread::scope(|s| {
+        let handles = component
+            .chunks(chunk_size)
+            .map(|sources| {
+                s.spawn(move || {
+                    sources
+                        .iter()
+                        .map(|source| (*source, bfs_eccentricity(graph, *source)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().expect("distance computation thread panicked"))
+            .collect::<Vec<_>>()
+    });
+
+    let mut path_length_sum = 0u64;
+    distances.radius = u32::MAX;
+    for (source, (eccentricity, sum)) in results {
+        distances.eccentricities[source] = Some(eccentricity);
+        distances.path_length_sums[source] = Some(sum);
+        distances.diameter = distances.diameter.max(eccentricity);
+        distances.radius = distances.radius.min(eccentricity);
+        path_length_sum += sum;
+    }
+
+    let size = component.len();
+    distances.component_size = size;
+    if size > 1 {
+        distances.average_shortest_path_length =
+            path_length_sum as f64 / (size * (size - 1)) as f64;
+    }
+
+    distances
+}
+
+/// Harmonic closeness of the source nodes - the sum of inverse distances to all other nodes
+/// (at most `n - 1` for a node connected to all others). Unreachable nodes add nothing, so unlike
+/// closeness it's well defined on disconnected graphs.
+pub fn harmonic_closeness(
+    graph: &(impl Adjacency + ?Sized),
+    sources: &[usize],
+    num_threads: usize,
+) -> HashMap<usize, f64> {
+    let chunk_size = (sources.len() / num_threads.max(1)).max(1);
+    thread::scope(|s| {
+        let handles = sources
+            .chunks(chunk_size)
+            .map(|sources| {
+                s.spawn(move || {
+                    sources
+                        .iter()
+                        .map(|source| (*source, bfs_harmonic(graph, *source)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().expect("harmonic closeness thread panicked"))
+            .collect()
+    })
+}
+
+/// Run BFS from the source node and return the sum of inverse distances to all reachable nodes.
+fn bfs_harmonic(graph: &(impl Adjacency + ?Sized), source: usize) -> f64 {
+    let mut dist = vec![u32::MAX; graph.node_count()];
+    let mut queue = VecDeque::new();
+    let mut sum = 0.0;
+
+    dist[source] = 0;
+    queue.push_back(source);
+
+    while let Some(node_idx) = queue.pop_front() {
+        let d = dist[node_idx];
+        if d > 0 {
+            sum += 1.0 / d as f64;
+        }
+
+        for peer_idx in graph.connections(node_idx) {
+            if *peer_idx < graph.node_count() && dist[*peer_idx] == u32::MAX {
+                dist[*peer_idx] = d + 1;
+                queue.push_back(*peer_idx);
+            }
+        }
+    }
+
+    sum
+}
+
+/// Run BFS from the source node and return its eccentricity together with the sum of distances
+/// to all reachable nodes.
+fn bfs_eccentricity(graph: &(impl Adjacency + ?Sized), source: usize) -> (u32, u64) {
+    let mut dist = vec![u32::MAX; graph.node_count()];
+    let mut queue = VecDeque::new();
+    let mut eccentricity = 0;
+    let mut sum = 0u64;
+
+    dist[source] = 0;
+    queue.push_back(source);
+
+    while let Some(node_idx) = queue.pop_front() {
+        let d = dist[node_idx];
+        eccentricity = eccentricity.max(d);
+        sum += d as u64;
+
+        for peer_idx in graph.connections(node_idx) {
+            if *peer_idx < graph.node_count() && dist[*peer_idx] == u32::MAX {
+                dist[*peer_idx] = d + 1;
+                queue.push_back(*peer_idx);
+            }
+        }
+    }
+
+    (eccentricity, sum)
+}
+
 #[cfg(test)]
 mod tests {
     use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
     use super::*;
+    use crate::csr::CsrGraph;
 
     #[test]
     fn construct_graph_test() {
@@ -198,7 +760,7 @@ mod tests {
             },
         ];
 
-        let mut graph = construct_graph(&nodes);
+        let mut graph = construct_graph(&nodes, &nodes);
         let degrees = graph.degree_centrality();
         assert_eq!(
             degrees
@@ -282,7 +844,7 @@ mod tests {
             },
         ];
 
-        let bridges = find_bridges(&nodes, 1.25);
+        let bridges = find_bridges(&nodes, &nodes, 1.25);
         assert!(bridges.contains_key(&3));
         let peers = bridges.get(&3).unwrap();
         assert_eq!(peers.len(), 1);
@@ -344,6 +906,11 @@ mod tests {
 
         let filtered = filter_network(&nodes, NetworkType::Zcash);
         assert_eq!(filtered.len(), 3);
+        // Connections are reindexed, connections to other networks dropped
+        assert_eq!(filtered[0].connections, vec![1]);
+        assert_eq!(filtered[1].connections, vec![0]);
+        assert!(filtered[2].connections.is_empty());
+        assert_eq!(filtered[2].addr, nodes[6].addr);
         for node in filtered {
             assert!(node.network_type == NetworkType::Zcash);
         }
@@ -357,4 +924,320 @@ mod tests {
             assert!(node.network_type == NetworkType::Unknown);
         }
     }
+
+    #[test]
+    fn compute_distances_test() {
+        // Path 0 - 1 - 2 - 3 plus isolated node 4
+        let nodes = vec![
+            Node {
+                connections: vec![1],
+                ..Default::default()
+            },
+            Node {
+                connections: vec![0, 2],
+                ..Default::default()
+            },
+            Node {
+                connections: vec![1, 3],
+                ..Default::default()
+            },
+            Node {
+                connections: vec![2],
+                ..Default::default()
+            },
+            Node::default(),
+        ];
+
+        let distances = compute_distances(&nodes, 2);
+        assert_eq!(distances.component_size, 4);
+        assert_eq!(distances.diameter, 3);
+        assert_eq!(distances.radius, 2);
+        // Distances: 1+2+3 + 1+1+2 + 2+1+1 + 3+2+1 = 20 over 12 ordered pairs
+        assert!((distances.average_shortest_path_length - 20.0 / 12.0).abs() < 0.0001);
+        assert_eq!(
+            distances.eccentricities,
+            vec![Some(3), Some(2), Some(2), Some(3), None]
+        );
+        assert_eq!(
+            distances.path_length_sums,
+            vec![Some(6), Some(4), Some(4), Some(6), None]
+        );
+
+        // The isolated node has no closeness, yet it doesn't affect the others
+        let harmonic = harmonic_closeness(nodes.as_slice(), &[0, 1, 4], 2);
+        assert_eq!(
+            harmonic,
+            HashMap::from([(0, 1.0 + 0.5 + 1.0 / 3.0), (1, 2.5), (4, 0.0)])
+        );
+        let graph = CsrGraph::from_nodes(&nodes);
+        assert_eq!(harmonic_closeness(&graph, &[0, 1, 4], 2), harmonic);
+    }
+
+    #[test]
+    fn compute_distances_empty_test() {
+        let distances = compute_distances(&CsrGraph::default(), 4);
+        assert_eq!(distances.component_size, 0);
+        assert_eq!(distances.diameter, 0);
+    }
+
+    #[test]
+    fn find_exact_bridges_test() {
+        // Two triangles (0, 1, 2) and (3, 4, 5) joined by the 2 - 3 edge
+        let nodes = vec![
+            Node {
+                connections: vec![1, 2],
+                ..Default::default()
+            },
+            Node {
+                connections: vec![0, 2],
+                ..Default::default()
+            },
+            Node {
+                connections: vec![0, 1, 3],
+                ..Default::default()
+            },
+            Node {
+                connections: vec![2, 4, 5],
+                ..Default::default()
+            },
+            Node {
+                connections: vec![3, 5],
+                ..Default::default()
+            },
+            Node {
+                connections: vec![3, 4],
+                ..Default::default()
+            },
+        ];
+
+        let (bridges, articulation_points) = find_exact_bridges(&nodes);
+        assert_eq!(bridges.len(), 2);
+        assert_eq!(bridges.get(&2).unwrap(), &HashSet::from([3]));
+        assert_eq!(bridges.get(&3).unwrap(), &HashSet::from([2]));
+        assert_eq!(articulation_points, HashSet::from([2, 3]));
+
+        // The CSR form of the same graph gives the same result
+        assert_eq!(
+            find_exact_bridges(&CsrGraph::from_nodes(&nodes)),
+            (bridges, articulation_points)
+        );
+    }
+
+    #[test]
+    fn find_exact_bridges_parallel_edges_test() {
+        // Duplicated connection is not a bridge
+        let nodes = vec![
+            Node {
+                connections: vec![1, 1],
+                ..Default::default()
+            },
+            Node {
+                connections: vec![0, 0],
+                ..Default::default()
+            },
+        ];
+
+        let (bridges, articulation_points) = find_exact_bridges(&nodes);
+        assert!(bridges.is_empty());
+        assert!(articulation_points.is_empty());
+    }
+
+    #[test]
+    fn count_triangles_test() {
+        // Triangle (0, 1, 2) with node 3 hanging on node 2
+        let nodes = vec![
+            Node {
+                connections: vec![1, 2],
+                ..Default::default()
+            },
+            Node {
+                connections: vec![0, 2],
+                ..Default::default()
+            },
+            Node {
+                connections: vec![0, 1, 3, 2],
+                ..Default::default()
+            },
+            Node {
+                connections: vec![2, 2],
+                ..Default::default()
+            },
+        ];
+
+        let (triangles, transitivity) = count_triangles(&nodes);
+        assert_eq!(triangles, 1);
+        // 3 closed triplets out of 1 + 1 + 3 connected ones
+        assert!((transitivity - 0.6).abs() < 0.0001);
+
+        let (triangles, transitivity) = count_triangles(&CsrGraph::default());
+        assert_eq!(triangles, 0);
+        assert_eq!(transitivity, 0.0);
+    }
+
+    #[test]
+    fn rich_club_coefficients_test() {
+        // Hub 0 connected to all others, which form a path 1-2-3-4 with node 5 hanging on 4
+        let connections = [
+            vec![1, 2, 3, 4, 5],
+            vec![0, 2],
+            vec![0, 1, 3],
+            vec![0, 2, 4],
+            vec![0, 3, 5],
+            vec![0, 4],
+        ];
+        let nodes = connections
+            .into_iter()
+            .map(|connections| Node {
+                connections,
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+
+        let coefficients = rich_club_coefficients(&nodes);
+        // All 6 nodes have degree > 1: 9 of 15 possible connections
+        assert!((coefficients[&1] - 0.6).abs() < 0.0001);
+        // Nodes 0, 2, 3 and 4: 5 of 6 possible connections
+        assert!((coefficients[&2] - 5.0 / 6.0).abs() < 0.0001);
+        // Only the hub has degree > 4
+        assert_eq!(coefficients.len(), 2);
+        assert!(rich_club_coefficients(&CsrGraph::default()).is_empty());
+    }
+
+    #[test]
+    fn find_min_cuts_test() {
+        let node = |host: u8, betweenness: f64, connections: Vec<usize>| Node {
+            addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, host)), 1234),
+            betweenness,
+            connections,
+            ..Default::default()
+        };
+        // Two squares with diagonals (0-3 and 4-7) joined by two connections (2-4 and 3-5),
+        // node 8 hangs on node 7 and node 9 is alone
+        let nodes = vec![
+            node(0, 0.1, vec![1, 2, 3]),
+            node(1, 0.1, vec![0, 2, 3]),
+            node(2, 0.5, vec![0, 1, 3, 4, 4]),
+            node(3, 0.4, vec![0, 1, 2, 5]),
+            node(4, 0.6, vec![2, 5, 6, 7]),
+            node(5, 0.3, vec![3, 4, 6, 7]),
+            node(6, 0.0, vec![4, 5, 7]),
+            node(7, 0.2, vec![4, 5, 6, 8]),
+            node(8, 0.0, vec![7]),
+            node(9, 0.0, vec![9]),
+        ];
+
+        let cuts = find_min_cuts(&nodes, &nodes, 3);
+        let sizes = cuts
+            .iter()
+            .map(|cut| (cut.source.ip(), cut.target.ip(), cut.size))
+            .collect::<Vec<_>>();
+        let ip = |host: u8| IpAddr::V4(Ipv4Addr::new(10, 0, 0, host));
+        // The squares are held together by two connections, nodes 2 and 3 by four (one of the
+        // paths goes through the other square)
+        assert_eq!(
+            sizes,
+            vec![(ip(4), ip(2), 2), (ip(4), ip(3), 2), (ip(2), ip(3), 4)]
+        );
+
+        // Node 8 is cut off by its only connection, node 9 is already separated so it's in no
+        // pair
+        let cuts = find_min_cuts(&nodes, &nodes, nodes.len());
+        assert_eq!(cuts.len(), 36);
+        assert_eq!(cuts[0].size, 1);
+        assert!(cuts
+            .iter()
+            .all(|cut| cut.source.ip() != ip(9) && cut.target.ip() != ip(9)));
+        assert!(cuts.iter().all(|cut| cut.size <= 4));
+        assert!(cuts
+            .iter()
+            .filter(|cut| cut.source.ip() == ip(8) || cut.target.ip() == ip(8))
+            .all(|cut| cut.size <= 1));
+
+        assert!(find_min_cuts(&[], &CsrGraph::default(), 5).is_empty());
+    }
+
+    #[test]
+    fn extract_island_test() {
+        // Islands {0, 2} and {1, 3, 4}
+        let nodes = vec![
+            Node {
+                connections: vec![2],
+                ..Default::default()
+            },
+            Node {
+                connections: vec![3, 4],
+                ..Default::default()
+            },
+            Node {
+                connections: vec![0],
+                ..Default::default()
+            },
+            Node {
+                connections: vec![1],
+                ..Default::default()
+            },
+            Node {
+                connections: vec![1],
+                ..Default::default()
+            },
+        ];
+
+        let islands = detect_islands(&nodes);
+        assert_eq!(islands.len(), 2);
+
+        let island = extract_island(&nodes, &nodes, &islands[1]);
+        assert_eq!(island.len(), 3);
+        assert_eq!(island[0].connections, vec![1, 2]);
+        assert_eq!(island[1].connections, vec![0]);
+        assert_eq!(island[2].connections, vec![0]);
+        assert_eq!(detect_islands(&island).len(), 1);
+
+        // Connections can be given separately from the nodes
+        let graph = CsrGraph::take_connections(&mut nodes.clone());
+        let island = extract_island(&nodes, &graph, &islands[1]);
+        assert_eq!(island[0].connections, vec![1, 2]);
+        assert_eq!(island[0].degree, 2);
+    }
+
+    #[test]
+    fn changed_components_test() {
+        let previous = vec![
+            Node {
+                connections: vec![1],
+                ..Default::default()
+            },
+            Node {
+                connections: vec![0],
+                ..Default::default()
+            },
+            Node {
+                connections: vec![3],
+                ..Default::default()
+            },
+            Node {
+                connections: vec![2],
+                ..Default::default()
+            },
+        ];
+
+        // Order and duplicates of connections are not a change
+        let mut nodes = previous.clone();
+        nodes[0].connections = vec![1, 1];
+        assert!(changed_components(&previous, &nodes).is_empty());
+
+        // Connecting node 1 to node 2 changes both components which are now merged
+        nodes[1].connections.push(2);
+        nodes[2].connections.push(1);
+        assert_eq!(changed_components(&previous, &nodes), vec![0, 1, 2, 3]);
+
+        // Only the component with changed node is returned
+        let mut nodes = previous.clone();
+        nodes[3].connections.clear();
+        nodes[2].connections.clear();
+        assert_eq!(changed_components(&previous, &nodes), vec![2, 3]);
+        assert_eq!(
+            changed_components(&CsrGraph::from_nodes(&previous), &nodes),
+            vec![2, 3]
+        );
+    }
 }
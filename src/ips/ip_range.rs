@@ -0,0 +1,171 @@
+// Ranges of IP addresses used in the configuration - either a single address (e.g. "1.2.3.4")
+// or a CIDR range (e.g. "10.0.0.0/8" or "2001:db8::/32"). Ports are not taken into account.
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Range of IP addresses given by the address and the prefix length
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+/// Error returned for an invalid IP range
+#[derive(Debug, PartialEq)]
+pub struct InvalidIpRange(String);
+
+impl fmt::Display for InvalidIpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid IP address or CIDR range: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidIpRange {}
+
+impl IpRange {
+    /// Check if the address is within the range.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.addr, addr) {
+            (IpAddr::V4(range), IpAddr::V4(addr)) => {
+                prefix_matches(u32::from(range), u32::from(*addr), self.prefix_len)
+            }
+            (IpAddr::V6(range), IpAddr::V6(addr)) => {
+                prefix_matches(u128::from(range), u128::from(*addr), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+
+    /// First and last address of the range.
+    pub fn bounds(&self) -> (IpAddr, IpAddr) {
+        let prefix_len = u32::from(self.prefix_len);
+        match self.addr {
+            IpAddr::V4(addr) => {
+                let mask = u32::MAX.checked_shl(32 - prefix_len).unwrap_or(0);
+                let start = u32::from(addr) & mask;
+                (
+                    IpAddr::V4(Ipv4Addr::from(start)),
+                    IpAddr::V4(Ipv4Addr::from(start | !mask)),
+                )
+            }
+            IpAddr::V6(addr) => {
+                let mask = u128::MAX.checked_shl(128 - prefix_len).unwrap_or(0);
+                let start = u128::from(addr) & mask;
+                (
+                    IpAddr::V6(Ipv6Addr::from(start)),
+                    IpAddr::V6(Ipv6Addr::from(start | !mask)),
+                )
+            }
+        }
+    }
+}
+
+/// Check if the first `prefix_len` bits of both values are the same.
+fn prefix_matches<T>(range: T, addr: T, prefix_len: u8) -> bool
+where
+    T: Copy + Eq + std::ops::BitXor<Output = T> + std::ops::Shr<u32, Output = T> + From<u8>,
+{
+    let bits = std::mem::size_of::<T>() as u32 * 8;
+    let prefix_len = u32::from(prefix_len);
+    // Shifting by the full width overflows, so the whole range is handled separately
+    prefix_len == 0 || (range ^ addr) >> (bits - prefix_len) == T::from(0)
+}
+
+impl FromStr for IpRange {
+    type Err = InvalidIpRange;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidIpRange(s.to_owned());
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+
+        let addr = addr.trim().parse::<IpAddr>().map_err(|_| invalid())?;
+        let max_prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.trim().parse::<u8>().map_err(|_| invalid())?,
+            None => max_prefix_len,
+        };
+        if prefix_len > max_prefix_len {
+            return Err(invalid());
+        }
+
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+impl TryFrom<String> for IpRange {
+    type Error = InvalidIpRange;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<IpRange> for String {
+    fn from(range: IpRange) -> String {
+        range.to_string()
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ip_range_test() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        let single = "192.168.0.1".parse::<IpRange>().unwrap();
+        assert!(single.contains(&ip("192.168.0.1")));
+        assert!(!single.contains(&ip("192.168.0.2")));
+
+        let range = "10.1.0.0/16".parse::<IpRange>().unwrap();
+        assert!(range.contains(&ip("10.1.255.7")));
+        assert!(!range.contains(&ip("10.2.0.1")));
+        assert!(!range.contains(&ip("::ffff:10.1.0.1")));
+
+        let all = "0.0.0.0/0".parse::<IpRange>().unwrap();
+        assert!(all.contains(&ip("8.8.8.8")));
+
+        let v6 = "2001:db8::/32".parse::<IpRange>().unwrap();
+        assert!(v6.contains(&ip("2001:db8:1::1")));
+        assert!(!v6.contains(&ip("2001:db9::1")));
+
+        assert_eq!(range.bounds(), (ip("10.1.0.0"), ip("10.1.255.255")));
+        assert_eq!(single.bounds(), (ip("192.168.0.1"), ip("192.168.0.1")));
+        assert_eq!(all.bounds(), (ip("0.0.0.0"), ip("255.255.255.255")));
+        assert_eq!(
+            v6.bounds(),
+            (
+                ip("2001:db8::"),
+                ip("2001:db8:ffff:ffff:ffff:ffff:ffff:ffff")
+            )
+        );
+
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("10.0.0/8".parse::<IpRange>().is_err());
+        assert!("example.com".parse::<IpRange>().is_err());
+        assert_eq!(
+            serde_json::from_str::<Vec<IpRange>>(r#"["10.0.0.0/8", "::1"]"#).unwrap(),
+            vec![
+                "10.0.0.0/8".parse::<IpRange>().unwrap(),
+                "::1/128".parse::<IpRange>().unwrap()
+            ]
+        );
+    }
+}
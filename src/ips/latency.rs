@@ -0,0 +1,43 @@
+// Latency estimation from geolocation. Real RTT between nodes isn't known, but it's dominated by
+// the propagation delay, so it's estimated from the great-circle distance between the nodes plus
+// a constant covering routing and processing on the way.
+
+use crate::Node;
+
+/// Distance a signal travels in fiber within one millisecond (about 2/3 of the speed of light).
+const FIBER_KM_PER_MS: f64 = 200.0;
+
+/// Estimated round-trip time between the nodes in milliseconds, if both nodes are located.
+pub fn estimate_rtt_ms(a: &Node, b: &Node, per_hop_ms: f64) -> Option<f64> {
+    let a = a.geolocation.as_ref()?.coordinates?;
+    let b = b.geolocation.as_ref()?.coordinates?;
+    let distance_km = a.distance_to(b) / 1000.0;
+
+    Some(2.0 * distance_km / FIBER_KM_PER_MS + per_hop_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::geo_at;
+
+    fn node(latitude: f64, longitude: f64) -> Node {
+        Node {
+            geolocation: geo_at(latitude, longitude),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn estimate_rtt_test() {
+        let berlin = node(52.52, 13.405);
+        let new_york = node(40.7128, -74.006);
+        let unknown = Node::default();
+
+        assert_eq!(estimate_rtt_ms(&berlin, &berlin, 5.0), Some(5.0));
+        // Berlin - New York is about 6400 km
+        let rtt = estimate_rtt_ms(&berlin, &new_york, 5.0).unwrap();
+        assert!((rtt - 69.0).abs() < 2.0);
+        assert_eq!(estimate_rtt_ms(&berlin, &unknown, 5.0), None);
+    }
+}
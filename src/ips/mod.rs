@@ -1,6 +1,13 @@
+pub mod adoption;
 pub mod algorithm;
 pub mod config;
-mod graph_utils;
+mod constraints;
+pub mod genetic;
+pub mod graph_utils;
+pub mod ip_range;
+pub mod latency;
 mod normalization;
-mod peer;
-mod statistics;
+pub mod pareto;
+pub mod peer;
+pub mod security;
+pub mod statistics;
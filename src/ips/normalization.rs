@@ -1,6 +1,8 @@
+use serde::Serialize;
+
 /// Structure used to determine min and max values for normalization of any factor (like
 /// betweenness centrality or closeness centrality).
-#[derive(Default, Clone, Copy)]
+#[derive(Default, Clone, Copy, Serialize)]
 pub struct NormalizationFactors {
     /// Minimum value of a factor.
     pub min: f64,
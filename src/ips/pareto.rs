@@ -0,0 +1,208 @@
+// Multi-objective view of the networks evaluated by the genetic optimizer. The optimizer ranks
+// networks by a single fitness score, which hides trade-offs - a network with a shorter diameter
+// may need many more changed connections. Here each network is described by several objectives
+// (all minimized) and only networks not dominated by any other are kept, so the operator can
+// pick the trade-off instead of the fitness weights.
+
+use std::{collections::BTreeSet, net::SocketAddr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    csr::{undirected_edges, Adjacency},
+    Node,
+};
+
+/// Objectives of a network, all of them minimized
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Objectives {
+    pub diameter: u32,
+    pub max_betweenness: f64,
+    /// Average shortest path length
+    pub average_distance: f64,
+    /// Number of connections added or removed
+    pub churn: usize,
+}
+
+impl Objectives {
+    /// Check if these objectives are at least as good as the other ones in all objectives and
+    /// better in at least one of them.
+    pub fn dominates(&self, other: &Objectives) -> bool {
+        let no_worse = self.diameter <= other.diameter
+            && self.max_betweenness <= other.max_betweenness
+            && self.average_distance <= other.average_distance
+            && self.churn <= other.churn;
+        no_worse && self != other
+    }
+}
+
+/// Connections of a network compared with the original network, as pairs of node indices (lower
+/// index first).
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ConnectionChanges {
+    pub added: Vec<(usize, usize)>,
+    pub removed: Vec<(usize, usize)>,
+}
+
+impl ConnectionChanges {
+    /// Connections added to and removed from the original network (the same nodes in the same
+    /// order).
+    pub fn between(
+        original: &(impl Adjacency + ?Sized),
+        network: &(impl Adjacency + ?Sized),
+    ) -> ConnectionChanges {
+        let original = undirected_edges(original).collect::<BTreeSet<_>>();
+        let edges = undirected_edges(network).collect::<BTreeSet<_>>();
+        ConnectionChanges {
+            added: edges.difference(&original).copied().collect(),
+            removed: original.difference(&edges).copied().collect(),
+        }
+    }
+
+    /// Number of changed connections.
+    pub fn churn(&self) -> usize {
+        self.added.len() + self.removed.len()
+    }
+}
+
+/// Networks not dominated by any other network seen so far
+#[derive(Debug, Default, Clone)]
+pub struct ParetoFront {
+    solutions: Vec<(Objectives, ConnectionChanges)>,
+}
+
+impl ParetoFront {
+    /// Add the network unless it's dominated by (or has the same objectives as) a network of the
+    /// front. Networks it dominates are removed. Returns true if it was added.
+    pub fn insert(&mut self, objectives: Objectives, changes: ConnectionChanges) -> bool {
+        if self
+            .solutions
+            .iter()
+            .any(|(kept, _)| kept.dominates(&objectives) || *kept == objectives)
+        {
+            return false;
+        }
+        self.solutions
+            .retain(|(kept, _)| !objectives.dominates(kept));
+        self.solutions.push((objectives, changes));
+        true
+    }
+
+    /// Solutions of the front with connections given by addresses of the nodes, the fewest
+    /// changes first. The solution with the given changes is marked as selected.
+    pub fn solutions(&self, nodes: &[Node], selected: &ConnectionChanges) -> Vec<ParetoSolution> {
+        let addrs = |connections: &[(usize, usize)]| {
+            let mut addrs = connections
+                .iter()
+                .map(|(a, b)| {
+                    let (a, b) = (nodes[*a].addr, nodes[*b].addr);
+                    [a.min(b), a.max(b)]
+                })
+                .collect::<Vec<_>>();
+            addrs.sort_unstable();
+            addrs
+        };
+        let mut solutions = self
+            .solutions
+            .iter()
+            .map(|(objectives, changes)| ParetoSolution {
+                objectives: *objectives,
+                selected: changes == selected,
+                added: addrs(&changes.added),
+                removed: addrs(&changes.removed),
+            })
+            .collect::<Vec<ParetoSolution>>();
+        solutions.sort_by(|a, b| {
+            let key = |solution: &ParetoSolution| {
+                let objectives = solution.objectives;
+                (objectives.churn, objectives.diameter)
+            };
+            key(a).cmp(&key(b)).then_with(|| {
+                a.objectives
+                    .average_distance
+                    .total_cmp(&b.objectives.average_distance)
+            })
+        });
+        solutions
+    }
+
+    pub fn len(&self) -> usize {
+        self.solutions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.solutions.is_empty()
+    }
+}
+
+/// Network of the Pareto front
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParetoSolution {
+    pub objectives: Objectives,
+    /// Indicates if the peer lists were generated from this network
+    pub selected: bool,
+    /// Connections added to the original network (lower address first)
+    pub added: Vec<[SocketAddr; 2]>,
+    /// Connections removed from the original network (lower address first)
+    pub removed: Vec<[SocketAddr; 2]>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn objectives(diameter: u32, max_betweenness: f64, churn: usize) -> Objectives {
+        Objectives {
+            diameter,
+            max_betweenness,
+            average_distance: 1.5,
+            churn,
+        }
+    }
+
+    #[test]
+    fn pareto_front_test() {
+        let nodes = (0..4)
+            .map(|i| Node {
+                addr: SocketAddr::new([10, 0, 0, 4 - i as u8].into(), 1234),
+                connections: vec![(i + 1) % 4, (i + 3) % 4],
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+        let mut changed = nodes.clone();
+        changed[0].connections = vec![1, 2];
+        changed[2].connections.push(0);
+        changed[3].connections.retain(|peer| *peer != 0);
+        let changes = ConnectionChanges::between(&nodes, &changed);
+        assert_eq!(changes.added, vec![(0, 2)]);
+        assert_eq!(changes.removed, vec![(0, 3)]);
+        assert_eq!(changes.churn(), 2);
+        assert_eq!(
+            ConnectionChanges::between(&nodes, &nodes),
+            ConnectionChanges::default()
+        );
+
+        assert!(objectives(3, 0.5, 2).dominates(&objectives(3, 0.6, 2)));
+        assert!(!objectives(3, 0.5, 2).dominates(&objectives(3, 0.5, 2)));
+        assert!(!objectives(2, 0.5, 4).dominates(&objectives(3, 0.5, 2)));
+
+        let mut front = ParetoFront::default();
+        assert!(front.insert(objectives(4, 0.5, 0), ConnectionChanges::default()));
+        assert!(front.insert(objectives(3, 0.6, 2), changes.clone()));
+        // Dominated and duplicate networks are not added
+        assert!(!front.insert(objectives(4, 0.6, 2), changes.clone()));
+        assert!(!front.insert(objectives(3, 0.6, 2), changes.clone()));
+        assert_eq!(front.len(), 2);
+        // Networks dominated by a new one are removed
+        assert!(front.insert(objectives(3, 0.5, 2), changes.clone()));
+        assert_eq!(front.len(), 2);
+
+        let solutions = front.solutions(&nodes, &changes);
+        assert_eq!(solutions[0].objectives.churn, 0);
+        assert!(!solutions[0].selected);
+        assert!(solutions[0].added.is_empty());
+        assert!(solutions[1].selected);
+        assert_eq!(solutions[1].added, vec![[nodes[2].addr, nodes[0].addr]]);
+        assert_eq!(solutions[1].removed, vec![[nodes[3].addr, nodes[0].addr]]);
+    }
+}
@@ -1,8 +1,18 @@
-use std::net::SocketAddr;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    io,
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
 
 use serde::{Deserialize, Serialize};
 
-use crate::Node;
+use crate::{
+    csr::Adjacency,
+    ips::{config::PeerOutputFormat, latency::estimate_rtt_ms},
+    to_json, Node,
+};
 
 /// Peer list structure containing peer list for each node
 #[derive(Clone, Serialize, Deserialize)]
@@ -11,33 +21,312 @@ pub struct Peer {
     pub ip: SocketAddr,
     /// List of peers for the node
     pub list: Vec<SocketAddr>,
+    /// Estimated RTT (in milliseconds) to each peer from the list (if both are located)
+    #[serde(default)]
+    pub estimated_latency_ms: Vec<Option<f64>>,
+    /// Why each peer from the list was recommended (only if peer details are enabled)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<PeerDetails>,
+}
+
+/// Factor of the MCDA rating of peers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RatingFactor {
+    Degree,
+    Betweenness,
+    Closeness,
+    Eigenvector,
+    Uptime,
+    Stability,
+    Location,
+    AsnDiversity,
+    CountryDiversity,
+    Latency,
+}
+
+/// Part of the rating given by a single factor (negative for penalties)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FactorContribution {
+    pub factor: RatingFactor,
+    pub contribution: f64,
+}
+
+/// Rationale of a peer recommended to the node
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerDetails {
+    /// MCDA rating of the peer for the node
+    pub rating: f64,
+    /// Factors which affected the rating the most, the largest absolute contribution first
+    pub dominant_factors: Vec<FactorContribution>,
+    /// Whether the node was already connected to the peer, otherwise the peer is newly suggested
+    pub existing: bool,
+}
+
+/// Version of the JSON peer list format. Version 1 was a bare array of peer lists, still
+/// written with the `json-array` output format.
+pub const PEER_LIST_FORMAT_VERSION: u32 = 2;
+
+fn peer_list_format_version() -> u32 {
+    PEER_LIST_FORMAT_VERSION
+}
+
+/// Peer list output containing peer lists for all nodes together with their validity window
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PeerList {
+    /// Version of the format, so consumers can tell it from other versions
+    #[serde(default = "peer_list_format_version")]
+    pub format_version: u32,
+    /// Time when the peer list was generated
+    pub generated_at: SystemTime,
+    /// Time after which the peer list should be considered stale
+    pub expires_at: SystemTime,
+    /// Seed of pseudo-random choices made while generating the peer lists
+    #[serde(default)]
+    pub seed: u64,
+    /// Peer list for each node
+    pub peers: Vec<Peer>,
+}
+
+/// Change of the node's peers proposed by the peer list
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PeerChange {
+    /// IP address of the node
+    pub ip: SocketAddr,
+    /// Peers the node should connect to
+    pub added: Vec<SocketAddr>,
+    /// Peers the node should disconnect from
+    pub removed: Vec<SocketAddr>,
+}
+
+/// Compare the peer lists with the current connections of the nodes. Only nodes with changed
+/// peers are returned.
+pub fn peer_changes(nodes: &[Node], peers: &[Peer]) -> Vec<PeerChange> {
+    let nodes_by_addr = nodes
+        .iter()
+        .map(|node| (node.addr, node))
+        .collect::<HashMap<SocketAddr, &Node>>();
+
+    peers
+        .iter()
+        .filter_map(|peer| {
+            let current = nodes_by_addr
+                .get(&peer.ip)
+                .map(|node| {
+                    node.connections
+                        .iter()
+                        .filter_map(|idx| nodes.get(*idx).map(|n| n.addr))
+                        .collect::<Vec<SocketAddr>>()
+                })
+                .unwrap_or_default();
+            let current_set = current.iter().collect::<HashSet<_>>();
+            let proposed_set = peer.list.iter().collect::<HashSet<_>>();
+
+            let change = PeerChange {
+                ip: peer.ip,
+                added: peer
+                    .list
+                    .iter()
+                    .filter(|addr| !current_set.contains(addr))
+                    .copied()
+                    .collect(),
+                removed: current
+                    .iter()
+                    .filter(|addr| !proposed_set.contains(addr))
+                    .copied()
+                    .collect(),
+            };
+            (!change.added.is_empty() || !change.removed.is_empty()).then_some(change)
+        })
+        .collect()
+}
+
+impl PeerList {
+    /// Wrap peers into a peer list valid for the given time from now
+    pub fn new(peers: Vec<Peer>, validity: Duration, seed: u64) -> PeerList {
+        PeerList::with_generation_time(peers, SystemTime::now(), validity, seed)
+    }
+
+    /// Wrap peers into a peer list stamped with the given generation time and valid for the given
+    /// time from it - reproducible runs stamp it with the crawl time
+    pub fn with_generation_time(
+        peers: Vec<Peer>,
+        generated_at: SystemTime,
+        validity: Duration,
+        seed: u64,
+    ) -> PeerList {
+        PeerList {
+            format_version: PEER_LIST_FORMAT_VERSION,
+            generated_at,
+            expires_at: generated_at + validity,
+            seed,
+            peers,
+        }
+    }
+
+    /// Time the recommendations are valid for
+    pub fn validity(&self) -> Duration {
+        self.expires_at
+            .duration_since(self.generated_at)
+            .unwrap_or_default()
+    }
+
+    /// Check if the recommendations are no longer valid
+    pub fn is_expired(&self) -> bool {
+        SystemTime::now() > self.expires_at
+    }
+
+    /// Check if the recommendations are no longer valid when their validity starts at the given
+    /// time instead of the generation time (e.g. when a reproduced peer list is published)
+    pub fn is_expired_since(&self, start: SystemTime) -> bool {
+        SystemTime::now() > start + self.validity()
+    }
+
+    /// Render the peer list in the given format (JSON optionally pretty-printed with sorted
+    /// keys). Text formats start with a comment recording the validity window and the seed, as
+    /// they have no other place for them.
+    pub fn render(&self, format: &PeerOutputFormat, pretty: bool) -> String {
+        match format {
+            PeerOutputFormat::Json => return to_json(self, pretty),
+            PeerOutputFormat::JsonArray => return to_json(&self.peers, pretty),
+            _ => (),
+        }
+
+        let unix_secs = |time: SystemTime| {
+            time.duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs())
+        };
+        let mut output = format!(
+            "# generated at {}, expires at {} (unix time), seed {}\n",
+            unix_secs(self.generated_at),
+            unix_secs(self.expires_at),
+            self.seed
+        );
+        for peer in self.peers.iter() {
+            match format {
+                PeerOutputFormat::Addr => {
+                    output.push_str(&peer.ip.to_string());
+                    for addr in peer.list.iter() {
+                        write!(output, " {addr}").unwrap();
+                    }
+                    output.push('\n');
+                }
+                _ => {
+                    writeln!(output, "\n# {}", peer.ip).unwrap();
+                    for addr in peer.list.iter() {
+                        writeln!(output, "addnode={addr}").unwrap();
+                    }
+                }
+            }
+        }
+        output
+    }
+}
+
+/// Parse peer lists written in the given format, e.g. golden peer lists of earlier runs. Text
+/// formats give only the addresses - comments which aren't node addresses are skipped.
+pub fn parse_peers(content: &str, format: &PeerOutputFormat) -> Result<Vec<Peer>, io::Error> {
+    let invalid_data = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+    let parse_addr = |addr: &str| {
+        addr.parse::<SocketAddr>()
+            .map_err(|e| invalid_data(format!("invalid address {addr}: {e}")))
+    };
+
+    match format {
+        PeerOutputFormat::Json => serde_json::from_str::<PeerList>(content)
+            .map(|peerlist| peerlist.peers)
+            .map_err(|e| invalid_data(e.to_string())),
+        PeerOutputFormat::JsonArray => {
+            serde_json::from_str(content).map_err(|e| invalid_data(e.to_string()))
+        }
+        PeerOutputFormat::Addr => content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| -> Result<Peer, io::Error> {
+                let mut addrs = line.split_whitespace().map(&parse_addr);
+                // Lines aren't empty, so there is always the node address
+                let ip = addrs.next().unwrap()?;
+                Ok(Peer {
+                    ip,
+                    list: addrs.collect::<Result<Vec<_>, io::Error>>()?,
+                    estimated_latency_ms: Vec::new(),
+                    details: Vec::new(),
+                })
+            })
+            .collect(),
+        PeerOutputFormat::AddNode => {
+            let mut peers: Vec<Peer> = Vec::new();
+            for line in content.lines().map(str::trim) {
+                if let Some(comment) = line.strip_prefix('#') {
+                    if let Ok(ip) = comment.trim().parse::<SocketAddr>() {
+                        peers.push(Peer {
+                            ip,
+                            list: Vec::new(),
+                            estimated_latency_ms: Vec::new(),
+                            details: Vec::new(),
+                        });
+                    }
+                } else if let Some(addr) = line.strip_prefix("addnode=") {
+                    let peer = peers
+                        .last_mut()
+                        .ok_or_else(|| invalid_data(format!("{line} before any node")))?;
+                    peer.list.push(parse_addr(addr)?);
+                } else if !line.is_empty() {
+                    return Err(invalid_data(format!("invalid line: {line}")));
+                }
+            }
+            Ok(peers)
+        }
+    }
 }
 
 impl Peer {
-    /// Generate peerlist for given nodes based on their connections
-    pub fn generate_all_peerlists(nodes: &[Node]) -> Vec<Peer> {
+    /// Generate peerlist for given nodes based on their connections given by the graph
+    pub fn generate_all_peerlists(
+        nodes: &[Node],
+        graph: &(impl Adjacency + ?Sized),
+        latency_per_hop_ms: f64,
+    ) -> Vec<Peer> {
         let mut peer_list = Vec::with_capacity(nodes.len());
 
-        for node in nodes {
-            peer_list.push(Peer::generate_peerlist(node, nodes));
+        for (idx, node) in nodes.iter().enumerate() {
+            peer_list.push(Peer::generate_peerlist(
+                node,
+                graph.connections(idx),
+                nodes,
+                latency_per_hop_ms,
+            ));
         }
 
         peer_list
     }
 
     /// Generate peerlist for given node based on its connections
-    pub fn generate_peerlist(node: &Node, nodes: &[Node]) -> Peer {
+    pub fn generate_peerlist(
+        node: &Node,
+        connections: &[usize],
+        nodes: &[Node],
+        latency_per_hop_ms: f64,
+    ) -> Peer {
         let mut peer_list_entry = Peer {
             ip: node.addr,
-            list: Vec::with_capacity(node.connections.len()),
+            list: Vec::with_capacity(connections.len()),
+            estimated_latency_ms: Vec::with_capacity(connections.len()),
+            details: Vec::new(),
         };
 
-        for peer in &node.connections {
+        for peer in connections {
             if *peer >= nodes.len() || nodes[*peer].addr == node.addr {
                 continue;
             }
 
             peer_list_entry.list.push(nodes[*peer].addr);
+            peer_list_entry.estimated_latency_ms.push(estimate_rtt_ms(
+                node,
+                &nodes[*peer],
+                latency_per_hop_ms,
+            ));
         }
 
         peer_list_entry
@@ -70,9 +359,162 @@ mod tests {
             },
         ];
 
-        let peer = Peer::generate_peerlist(nodes.get(0).unwrap(), &nodes);
+        let peer = Peer::generate_peerlist(&nodes[0], &nodes[0].connections, &nodes, 5.0);
         assert_eq!(peer.list.len(), 2);
+        assert_eq!(peer.estimated_latency_ms, vec![None, None]);
         assert!(peer.list.contains(&nodes.get(1).unwrap().addr));
         assert!(peer.list.contains(&nodes.get(2).unwrap().addr));
     }
+
+    #[test]
+    fn peer_changes_test() {
+        let addr = |host: u8| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(host, 0, 0, 0)), 1234);
+        // 1 - 2 - 3
+        let nodes = vec![
+            Node {
+                addr: addr(1),
+                connections: vec![1],
+                ..Default::default()
+            },
+            Node {
+                addr: addr(2),
+                connections: vec![0, 2],
+                ..Default::default()
+            },
+            Node {
+                addr: addr(3),
+                connections: vec![1],
+                ..Default::default()
+            },
+        ];
+        // 1 drops 2 and connects to 3 instead
+        let peers = vec![
+            Peer {
+                ip: addr(1),
+                list: vec![addr(3)],
+                estimated_latency_ms: vec![None],
+                details: vec![],
+            },
+            Peer {
+                ip: addr(2),
+                list: vec![addr(1), addr(3)],
+                estimated_latency_ms: vec![None, None],
+                details: vec![],
+            },
+        ];
+
+        let changes = peer_changes(&nodes, &peers);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].ip, addr(1));
+        assert_eq!(changes[0].added, vec![addr(3)]);
+        assert_eq!(changes[0].removed, vec![addr(2)]);
+    }
+
+    #[test]
+    fn render_peer_list_test() {
+        let peer_list = PeerList {
+            format_version: PEER_LIST_FORMAT_VERSION,
+            generated_at: SystemTime::UNIX_EPOCH + Duration::from_secs(100),
+            expires_at: SystemTime::UNIX_EPOCH + Duration::from_secs(200),
+            seed: 7,
+            peers: vec![
+                Peer {
+                    ip: "1.0.0.0:8233".parse().unwrap(),
+                    list: vec![
+                        "2.0.0.0:8233".parse().unwrap(),
+                        "[::1]:8233".parse().unwrap(),
+                    ],
+                    estimated_latency_ms: vec![None, None],
+                    details: vec![],
+                },
+                Peer {
+                    ip: "2.0.0.0:8233".parse().unwrap(),
+                    list: vec!["1.0.0.0:8233".parse().unwrap()],
+                    estimated_latency_ms: vec![None],
+                    details: vec![],
+                },
+            ],
+        };
+
+        let json = peer_list.render(&PeerOutputFormat::Json, false);
+        let parsed: PeerList = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.peers.len(), 2);
+        assert!(!json.contains('\n'));
+
+        assert!(json.contains("\"format_version\":2"));
+
+        // Format version 1 is an array of peer lists
+        let array = peer_list.render(&PeerOutputFormat::JsonArray, false);
+        let parsed: Vec<Peer> = serde_json::from_str(&array).unwrap();
+        assert_eq!(parsed.len(), 2);
+
+        let pretty = peer_list.render(&PeerOutputFormat::Json, true);
+        let parsed: PeerList = serde_json::from_str(&pretty).unwrap();
+        assert_eq!(parsed.peers.len(), 2);
+        assert!(pretty.starts_with("{\n  \"expires_at\": {"));
+        // Keys are sorted, not in the order of fields
+        assert!(pretty.find("\"estimated_latency_ms\"") < pretty.find("\"ip\""));
+        assert!(pretty.find("\"generated_at\"") < pretty.find("\"peers\""));
+
+        assert_eq!(
+            peer_list.render(&PeerOutputFormat::Addr, false),
+            "# generated at 100, expires at 200 (unix time), seed 7\n\
+             1.0.0.0:8233 2.0.0.0:8233 [::1]:8233\n\
+             2.0.0.0:8233 1.0.0.0:8233\n"
+        );
+        assert_eq!(
+            peer_list.render(&PeerOutputFormat::AddNode, false),
+            "# generated at 100, expires at 200 (unix time), seed 7\n\
+             \n# 1.0.0.0:8233\n\
+             addnode=2.0.0.0:8233\n\
+             addnode=[::1]:8233\n\
+             \n# 2.0.0.0:8233\n\
+             addnode=1.0.0.0:8233\n"
+        );
+
+        // Rendered peer lists are parsed back in each format
+        for format in [
+            PeerOutputFormat::Json,
+            PeerOutputFormat::JsonArray,
+            PeerOutputFormat::Addr,
+            PeerOutputFormat::AddNode,
+        ] {
+            let parsed = parse_peers(&peer_list.render(&format, false), &format).unwrap();
+            let lists = |peers: &[Peer]| {
+                peers
+                    .iter()
+                    .map(|peer| (peer.ip, peer.list.clone()))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(lists(&parsed), lists(&peer_list.peers), "{format:?}");
+        }
+        assert!(parse_peers("addnode=1.0.0.0:8233", &PeerOutputFormat::AddNode).is_err());
+        assert!(parse_peers("1.0.0.0 2.0.0.0:8233", &PeerOutputFormat::Addr).is_err());
+    }
+
+    #[test]
+    fn peer_list_expiration_test() {
+        let peer_list = PeerList::new(Vec::new(), Duration::from_secs(3600), 0);
+        assert!(!peer_list.is_expired());
+        assert_eq!(
+            peer_list
+                .expires_at
+                .duration_since(peer_list.generated_at)
+                .unwrap(),
+            Duration::from_secs(3600)
+        );
+
+        let peer_list = PeerList::new(Vec::new(), Duration::ZERO, 0);
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(peer_list.is_expired());
+
+        // A peer list stamped with an old crawl time is still valid from the given start
+        let generated_at = SystemTime::now() - Duration::from_secs(7200);
+        let peer_list =
+            PeerList::with_generation_time(Vec::new(), generated_at, Duration::from_secs(3600), 0);
+        assert!(peer_list.is_expired());
+        assert_eq!(peer_list.validity(), Duration::from_secs(3600));
+        assert!(!peer_list.is_expired_since(SystemTime::now()));
+        assert!(peer_list.is_expired_since(generated_at));
+    }
 }
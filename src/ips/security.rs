@@ -0,0 +1,173 @@
+// Findings of the IPS security phase in a form monitoring can track - the hot nodes whose
+// removal was simulated, bridges, islands and the connections added to fix them. Nodes are
+// identified by their address, as indices differ between networks (and islands analyzed
+// independently).
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Node;
+
+/// Findings and repairs of the security phase
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SecurityReport {
+    /// Nodes with the highest betweenness removed in the fragmentation check
+    pub hot_nodes: Vec<HotNode>,
+    /// Indicates if removing the hot nodes split the network into more massive islands
+    pub fragmentation_possible: bool,
+    /// Connections detected as bridges (each one once, lower address first)
+    pub bridges: Vec<[SocketAddr; 2]>,
+    /// Nodes whose removal disconnects the network (only for exact bridge detection)
+    pub articulation_points: Vec<SocketAddr>,
+    /// Islands of the network, the largest first (none if the network is connected)
+    pub islands: Vec<Island>,
+    /// Connections added by the security phase
+    pub repairs: Vec<Repair>,
+}
+
+impl SecurityReport {
+    /// Add findings of an island analyzed independently.
+    pub fn merge(&mut self, island: SecurityReport) {
+        self.hot_nodes.extend(island.hot_nodes);
+        self.fragmentation_possible |= island.fragmentation_possible;
+        self.bridges.extend(island.bridges);
+        self.articulation_points.extend(island.articulation_points);
+        self.repairs.extend(island.repairs);
+    }
+}
+
+/// Node with high betweenness
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HotNode {
+    pub addr: SocketAddr,
+    pub betweenness: f64,
+}
+
+/// Nodes connected to each other but not to the rest of the network
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Island {
+    /// Number of nodes in the island
+    pub size: usize,
+    /// Indicates if the island contains a significant part of the network
+    pub massive: bool,
+    /// Addresses of the nodes (sorted) - left out for the largest island, which contains all
+    /// nodes not listed in other islands
+    pub nodes: Vec<SocketAddr>,
+}
+
+/// Reason a connection was added
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RepairReason {
+    /// Small island joined to the largest island
+    IslandRepair,
+    /// Neighbours of a hot node connected, so its removal doesn't split the network
+    Fragmentation,
+}
+
+/// Connection added by the security phase
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Repair {
+    pub reason: RepairReason,
+    pub nodes: [SocketAddr; 2],
+}
+
+/// Bridges given as peers of each node, as pairs of addresses (each one once, sorted).
+pub fn bridge_connections(
+    nodes: &[Node],
+    bridges: &HashMap<usize, HashSet<usize>>,
+) -> Vec<[SocketAddr; 2]> {
+    let mut connections = bridges
+        .iter()
+        .flat_map(|(node_idx, peers)| {
+            peers
+                .iter()
+                .filter(move |peer_idx| node_idx < *peer_idx)
+                .map(move |peer_idx| {
+                    let (a, b) = (nodes[*node_idx].addr, nodes[*peer_idx].addr);
+                    [a.min(b), a.max(b)]
+                })
+        })
+        .collect::<Vec<_>>();
+    connections.sort_unstable();
+    connections
+}
+
+/// Islands with their nodes, the largest first. Islands larger than `massive_size` nodes are
+/// massive.
+pub fn island_membership(
+    nodes: &[Node],
+    islands: &[HashSet<usize>],
+    massive_size: usize,
+) -> Vec<Island> {
+    let mut islands = islands
+        .iter()
+        .map(|island| {
+            let mut addrs = island
+                .iter()
+                .map(|idx| nodes[*idx].addr)
+                .collect::<Vec<_>>();
+            addrs.sort_unstable();
+            Island {
+                size: island.len(),
+                massive: island.len() > massive_size,
+                nodes: addrs,
+            }
+        })
+        .collect::<Vec<_>>();
+    // Islands of the same size are ordered by their first node
+    islands.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.nodes.cmp(&b.nodes)));
+    if let Some(largest) = islands.first_mut() {
+        largest.nodes.clear();
+    }
+    islands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn island_membership_test() {
+        let nodes = (0..6)
+            .map(|i| Node {
+                addr: SocketAddr::new([10, 0, 0, 6 - i as u8].into(), 1234),
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+        let islands = [
+            HashSet::from([0]),
+            HashSet::from([1, 2, 3]),
+            HashSet::from([4, 5]),
+        ];
+
+        let membership = island_membership(&nodes, &islands, 1);
+        assert_eq!(
+            membership
+                .iter()
+                .map(|island| (island.size, island.massive))
+                .collect::<Vec<_>>(),
+            vec![(3, true), (2, true), (1, false)]
+        );
+        assert!(membership[0].nodes.is_empty());
+        assert_eq!(membership[1].nodes, vec![nodes[5].addr, nodes[4].addr]);
+        assert_eq!(membership[2].nodes, vec![nodes[0].addr]);
+
+        let bridges = HashMap::from([
+            (1, HashSet::from([2, 4])),
+            (2, HashSet::from([1])),
+            (4, HashSet::from([1])),
+        ]);
+        assert_eq!(
+            bridge_connections(&nodes, &bridges),
+            vec![
+                [nodes[4].addr, nodes[1].addr],
+                [nodes[2].addr, nodes[1].addr]
+            ]
+        );
+    }
+}
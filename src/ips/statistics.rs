@@ -1,8 +1,26 @@
-use std::{collections::HashMap, io::Write, net::SocketAddr};
-
-use crate::ips::algorithm::IpsState;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    net::SocketAddr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ips::{
+        algorithm::IpsState,
+        graph_utils::{
+            compute_distances, count_triangles, find_min_cuts, rich_club_coefficients, MinCut,
+        },
+        pareto::Objectives,
+    },
+    null_model::small_world_coefficients,
+    numeric::{gini, median, percentile, trimmed_mean},
+    Node,
+};
 
 /// This struct is used to store statistics for network at some point in time.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Statistics {
     nodes_count: usize,
     degree_average: f64,
@@ -21,10 +39,160 @@ pub struct Statistics {
     eigenvector_median: f64,
     eigenvector_min: f64,
     eigenvector_max: f64,
+    component_size: usize,
+    diameter: u32,
+    radius: u32,
+    average_shortest_path_length: f64,
+    triangles: u64,
+    transitivity: f64,
+    /// Small-world coefficient sigma - clustering and path length compared with random graphs
+    /// (only in full statistics)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    small_world_sigma: Option<f64>,
+    /// Small-world coefficient omega - path length compared with random graphs and clustering
+    /// compared with a lattice (only in full statistics)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    small_world_omega: Option<f64>,
+    /// Rich-club coefficient for each degree threshold (only in full statistics)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    rich_club: BTreeMap<u32, f64>,
+    /// Gini coefficient of the degree distribution
+    #[serde(default)]
+    degree_gini: f64,
+    /// Freeman degree centralization
+    #[serde(default)]
+    degree_centralization: f64,
+    /// Freeman betweenness centralization
+    #[serde(default)]
+    betweenness_centralization: f64,
+    /// Freeman closeness centralization (in the largest connected component)
+    #[serde(default)]
+    closeness_centralization: f64,
+    /// Size of the smallest cut between the nodes with the highest betweenness (only in full
+    /// statistics)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_cut: Option<usize>,
+    /// Smallest cuts between the nodes with the highest betweenness, the smallest first (only in
+    /// full statistics)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    weakest_cuts: Vec<MinCut>,
+}
+
+impl Statistics {
+    /// Score of the network used to compare networks (higher is better). Connectivity matters
+    /// most - a network split into parts is worse than any connected one. Then shorter paths and
+    /// betweenness spread evenly (no "hot" nodes) are preferred.
+    pub fn fitness(&self) -> f64 {
+        let connected = self.component_size as f64 / self.nodes_count.max(1) as f64;
+        let hot_nodes = self.betweenness_max / self.betweenness_average.max(f64::EPSILON);
+        connected * FITNESS_CONNECTIVITY_WEIGHT - self.average_shortest_path_length - hot_nodes
+    }
+
+    /// Objectives of the network for the Pareto front, given the number of changed connections.
+    pub fn objectives(&self, churn: usize) -> Objectives {
+        Objectives {
+            diameter: self.diameter,
+            max_betweenness: self.betweenness_max,
+            average_distance: self.average_shortest_path_length,
+            churn,
+        }
+    }
+
+    /// Number of nodes in the largest connected component.
+    pub fn component_size(&self) -> usize {
+        self.component_size
+    }
+}
+
+/// Weight of the connected part of the network in the fitness score.
+const FITNESS_CONNECTIVITY_WEIGHT: f64 = 1000.0;
+
+/// Number of nodes with the highest betweenness cuts are computed between (each pair of them)
+const MIN_CUT_NODES: usize = 5;
+
+/// Number of the smallest cuts kept in the statistics
+const WEAKEST_CUTS: usize = 3;
+
+/// Eigenvalues of nodes in the order of nodes.
+fn eigenvalues_in_order(state: &IpsState) -> Vec<f64> {
+    state
+        .nodes
+        .iter()
+        .filter_map(|node| state.eigenvalues.get(&node.addr).copied())
+        .collect()
+}
+
+/// Degrees of nodes in the order of nodes.
+fn degrees_in_order(state: &IpsState) -> Vec<f64> {
+    state
+        .nodes
+        .iter()
+        .filter_map(|node| state.degrees.get(&node.addr).map(|degree| *degree as f64))
+        .collect()
+}
+
+/// Freeman centralization of the values - the sum of differences between the highest value and
+/// the others, relative to the highest possible sum (reached by a star network). 0 means all
+/// nodes are equally central, 1 a single node the network is centered on.
+fn freeman_centralization(values: &[f64], max_sum: f64) -> f64 {
+    if max_sum <= 0.0 {
+        return 0.0;
+    }
+    let max = values.iter().copied().fold(0.0, f64::max);
+    values.iter().map(|value| max - value).sum::<f64>() / max_sum
+}
+
+/// Calculates statistics for given network state, including small-world, rich-club and cut
+/// measures. Those need random graphs to compare with or a max-flow for each pair of nodes, so
+/// they are left out of statistics computed for many networks (fitness of the genetic optimizer,
+/// adoption simulations).
+pub fn generate_full_statistics(state: &IpsState, num_threads: usize) -> Statistics {
+    let mut statistics = generate_statistics(state, num_threads);
+    let (sigma, omega) = small_world_coefficients(
+        &state.graph,
+        statistics.transitivity,
+        statistics.average_shortest_path_length,
+        num_threads,
+    );
+    statistics.small_world_sigma = Some(sigma);
+    statistics.small_world_omega = Some(omega);
+    statistics.rich_club = rich_club_coefficients(&state.graph);
+
+    let mut weakest_cuts = find_min_cuts(&state.nodes, &state.graph, MIN_CUT_NODES);
+    weakest_cuts.truncate(WEAKEST_CUTS);
+    statistics.min_cut = weakest_cuts.first().map(|cut| cut.size);
+    statistics.weakest_cuts = weakest_cuts;
+    statistics
 }
 
 /// Calculates statistics for given network state.
-pub fn generate_statistics(state: &IpsState) -> Statistics {
+pub fn generate_statistics(state: &IpsState, num_threads: usize) -> Statistics {
+    let distances = compute_distances(&state.graph, num_threads);
+    let (triangles, transitivity) = count_triangles(&state.graph);
+
+    // Centralization is relative to a star network of the same size. Closeness is computed from
+    // distances (the inverse of the average distance) rather than taken from nodes, so it's the
+    // same for any closeness variant.
+    let n = state.nodes.len() as f64;
+    let m = distances.component_size as f64;
+    let closeness = distances
+        .path_length_sums
+        .iter()
+        .flatten()
+        .map(|sum| {
+            if *sum > 0 {
+                (m - 1.0) / *sum as f64
+            } else {
+                0.0
+            }
+        })
+        .collect::<Vec<f64>>();
+    let star_closeness_sum = if m > 2.0 {
+        (m - 1.0) * (m - 2.0) / (2.0 * m - 3.0)
+    } else {
+        0.0
+    };
+
     Statistics {
         nodes_count: state.nodes.len(),
 
@@ -70,18 +238,126 @@ pub fn generate_statistics(state: &IpsState) -> Statistics {
         closeness_min: state.closeness_factors.min,
         closeness_max: state.closeness_factors.max,
 
-        eigenvector_average: centrality_avg(
-            &state.eigenvalues.values().copied().collect::<Vec<f64>>(),
-        ),
-        eigenvector_median: median::<f64>(
-            &state.eigenvalues.values().copied().collect::<Vec<f64>>(),
-        )
-        .expect("can't calculate median"),
+        // Values are taken in the node order, as the sum of floats depends on the order
+        eigenvector_average: centrality_avg(&eigenvalues_in_order(state)),
+        eigenvector_median: median::<f64>(&eigenvalues_in_order(state))
+            .expect("can't calculate median"),
         eigenvector_min: state.eigenvector_factors.min,
         eigenvector_max: state.eigenvector_factors.max,
+
+        component_size: distances.component_size,
+        diameter: distances.diameter,
+        radius: distances.radius,
+        average_shortest_path_length: distances.average_shortest_path_length,
+
+        triangles,
+        transitivity,
+
+        small_world_sigma: None,
+        small_world_omega: None,
+        rich_club: BTreeMap::new(),
+
+        degree_gini: gini(&degrees_in_order(state)).unwrap_or_default(),
+        degree_centralization: freeman_centralization(
+            &degrees_in_order(state),
+            (n - 1.0) * (n - 2.0),
+        ),
+        betweenness_centralization: freeman_centralization(
+            &state
+                .nodes
+                .iter()
+                .map(|n| n.betweenness)
+                .collect::<Vec<f64>>(),
+            (n - 1.0) * (n - 1.0) * (n - 2.0) / 2.0,
+        ),
+        closeness_centralization: freeman_centralization(&closeness, star_closeness_sum),
+
+        min_cut: None,
+        weakest_cuts: Vec::new(),
     }
 }
 
+/// Proportion of the lowest and highest values left out of trimmed averages in group statistics.
+const GROUP_TRIM_PROPORTION: f64 = 0.1;
+
+/// Degree and centrality distribution for nodes sharing the same tag or role.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct GroupStatistics {
+    /// Name of the grouping (e.g. "network_type")
+    pub group: String,
+    /// Value shared by all nodes in the group
+    pub value: String,
+    /// Number of nodes in the group
+    pub nodes_count: usize,
+    /// Average degree of nodes in the group
+    pub degree_average: f64,
+    /// Median degree of nodes in the group
+    pub degree_median: f64,
+    /// Average degree of nodes in the group without the lowest and highest 10% of degrees
+    #[serde(default)]
+    pub degree_trimmed_average: f64,
+    /// 90th percentile of degree of nodes in the group
+    #[serde(default)]
+    pub degree_percentile_90: f64,
+    /// Average betweenness of nodes in the group
+    pub betweenness_average: f64,
+    /// Median betweenness of nodes in the group
+    pub betweenness_median: f64,
+    /// 90th percentile of betweenness of nodes in the group
+    #[serde(default)]
+    pub betweenness_percentile_90: f64,
+    /// Average closeness of nodes in the group
+    pub closeness_average: f64,
+    /// Median closeness of nodes in the group
+    pub closeness_median: f64,
+}
+
+/// Breaks down degree and centrality distributions by the value returned for each node.
+/// Nodes for which no value is returned are not taken into account.
+pub fn generate_group_statistics<F>(
+    nodes: &[Node],
+    group: &str,
+    value_of: F,
+) -> Vec<GroupStatistics>
+where
+    F: Fn(&Node) -> Option<String>,
+{
+    let mut groups: BTreeMap<String, Vec<&Node>> = BTreeMap::new();
+    for node in nodes {
+        if let Some(value) = value_of(node) {
+            groups.entry(value).or_default().push(node);
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(value, members)| {
+            let degrees = members
+                .iter()
+                .map(|n| n.degree as f64)
+                .collect::<Vec<f64>>();
+            let betweenness = members.iter().map(|n| n.betweenness).collect::<Vec<f64>>();
+            let closeness = members.iter().map(|n| n.closeness).collect::<Vec<f64>>();
+
+            GroupStatistics {
+                group: group.to_owned(),
+                value,
+                nodes_count: members.len(),
+                degree_average: centrality_avg(&degrees),
+                degree_median: median(&degrees).unwrap_or_default(),
+                degree_trimmed_average: trimmed_mean(&degrees, GROUP_TRIM_PROPORTION)
+                    .unwrap_or_default(),
+                degree_percentile_90: percentile(&degrees, 90.0).unwrap_or_default(),
+                betweenness_average: centrality_avg(&betweenness),
+                betweenness_median: median(&betweenness).unwrap_or_default(),
+                betweenness_percentile_90: percentile(&betweenness, 90.0).unwrap_or_default(),
+                closeness_average: centrality_avg(&closeness),
+                closeness_median: median(&closeness).unwrap_or_default(),
+            }
+        })
+        .collect()
+}
+
 /// Prints statistics to given output.
 pub fn print_statistics(o: &mut Box<dyn Write>, stats: &Statistics) {
     writeln!(o, "----------------------------------------").unwrap();
@@ -134,6 +410,48 @@ pub fn print_statistics(o: &mut Box<dyn Write>, stats: &Statistics) {
     )
     .unwrap();
 
+    writeln!(o, "\nDistance measures (largest connected component):").unwrap();
+    writeln!(o, "Component size: {}", stats.component_size).unwrap();
+    writeln!(o, "Diameter: {}, radius: {}", stats.diameter, stats.radius).unwrap();
+    writeln!(
+        o,
+        "Average shortest path length: {}",
+        stats.average_shortest_path_length
+    )
+    .unwrap();
+
+    writeln!(o, "\nRedundancy measures:").unwrap();
+    writeln!(o, "Triangles: {}", stats.triangles).unwrap();
+    writeln!(o, "Transitivity: {}", stats.transitivity).unwrap();
+
+    if let (Some(sigma), Some(omega)) = (stats.small_world_sigma, stats.small_world_omega) {
+        writeln!(o, "\nSmall-world measures:").unwrap();
+        writeln!(o, "Sigma: {sigma}, omega: {omega}").unwrap();
+        writeln!(o, "Rich-club coefficients (degree > k):").unwrap();
+        for (threshold, coefficient) in stats.rich_club.iter() {
+            writeln!(o, "k = {threshold}: {coefficient}").unwrap();
+        }
+    }
+
+    writeln!(o, "\nCentralization measures:").unwrap();
+    writeln!(o, "Degree Gini coefficient: {}", stats.degree_gini).unwrap();
+    writeln!(
+        o,
+        "Freeman centralization: degree: {}, betweenness: {}, closeness: {}",
+        stats.degree_centralization,
+        stats.betweenness_centralization,
+        stats.closeness_centralization
+    )
+    .unwrap();
+
+    if let Some(min_cut) = stats.min_cut {
+        writeln!(o, "\nCut measures (highest betweenness nodes):").unwrap();
+        writeln!(o, "Minimum cut: {min_cut}").unwrap();
+        for cut in stats.weakest_cuts.iter() {
+            writeln!(o, "{} - {}: {}", cut.source, cut.target, cut.size).unwrap();
+        }
+    }
+
     writeln!(o, "----------------------------------------\n").unwrap();
 }
 
@@ -151,6 +469,68 @@ fn percentage_change(original: f64, new: f64) -> f64 {
     (delta / original) * 100.0
 }
 
+/// Change of a single statistic between two network states.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StatisticDelta {
+    /// Difference between the new and the original value
+    pub delta: f64,
+    /// Percentage change of the value
+    pub percentage: f64,
+}
+
+/// Statistics of the network before and after applying the peer lists, in a machine-readable
+/// form for dashboards.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StatisticsOutput {
+    /// Statistics for the initial network
+    pub initial: Statistics,
+    /// Statistics for the final network
+    pub r#final: Statistics,
+    /// Change of each statistic between the initial and the final network
+    pub delta: BTreeMap<String, StatisticDelta>,
+}
+
+/// Computes delta (value and percentage) for each statistic between two statistics.
+pub fn statistics_delta(
+    stats: &Statistics,
+    stats_original: &Statistics,
+) -> BTreeMap<String, StatisticDelta> {
+    let values = serde_json::to_value(stats).expect("can't serialize statistics");
+    let values_original = serde_json::to_value(stats_original).expect("can't serialize statistics");
+
+    let mut deltas = BTreeMap::new();
+    if let (Some(values), Some(values_original)) = (values.as_object(), values_original.as_object())
+    {
+        for (name, value) in values {
+            if let (Some(new), Some(original)) = (
+                value.as_f64(),
+                values_original.get(name).and_then(|v| v.as_f64()),
+            ) {
+                deltas.insert(
+                    name.clone(),
+                    StatisticDelta {
+                        delta: new - original,
+                        percentage: percentage_change(original, new),
+                    },
+                );
+            }
+        }
+    }
+    // Rich-club coefficients of thresholds present in both statistics
+    for (threshold, new) in stats.rich_club.iter() {
+        if let Some(original) = stats_original.rich_club.get(threshold) {
+            deltas.insert(
+                format!("rich_club_{threshold}"),
+                StatisticDelta {
+                    delta: new - original,
+                    percentage: percentage_change(*original, *new),
+                },
+            );
+        }
+    }
+    deltas
+}
+
 /// Print statistics delta (value and percentage) between two statistics.
 pub fn print_statistics_delta(
     o: &mut Box<dyn Write>,
@@ -300,6 +680,125 @@ pub fn print_statistics_delta(
     )
     .unwrap();
 
+    writeln!(o, "\nDistance measures (largest connected component):").unwrap();
+    writeln!(
+        o,
+        "Component size: {} ({:.3}%)",
+        stats.component_size as i64 - stats_original.component_size as i64,
+        percentage_change(
+            stats_original.component_size as f64,
+            stats.component_size as f64
+        )
+    )
+    .unwrap();
+    writeln!(
+        o,
+        "Diameter: {} ({:.3}%), radius: {} ({:.3}%)",
+        stats.diameter as i64 - stats_original.diameter as i64,
+        percentage_change(stats_original.diameter as f64, stats.diameter as f64),
+        stats.radius as i64 - stats_original.radius as i64,
+        percentage_change(stats_original.radius as f64, stats.radius as f64)
+    )
+    .unwrap();
+    writeln!(
+        o,
+        "Average shortest path length: {} ({:.3}%)",
+        stats.average_shortest_path_length - stats_original.average_shortest_path_length,
+        percentage_change(
+            stats_original.average_shortest_path_length,
+            stats.average_shortest_path_length
+        )
+    )
+    .unwrap();
+
+    writeln!(o, "\nRedundancy measures:").unwrap();
+    writeln!(
+        o,
+        "Triangles: {} ({:.3}%)",
+        stats.triangles as i64 - stats_original.triangles as i64,
+        percentage_change(stats_original.triangles as f64, stats.triangles as f64)
+    )
+    .unwrap();
+    writeln!(
+        o,
+        "Transitivity: {} ({:.3}%)",
+        stats.transitivity - stats_original.transitivity,
+        percentage_change(stats_original.transitivity, stats.transitivity)
+    )
+    .unwrap();
+
+    // Small-world measures are only in full statistics
+    if let (Some(sigma), Some(omega), Some(sigma_original), Some(omega_original)) = (
+        stats.small_world_sigma,
+        stats.small_world_omega,
+        stats_original.small_world_sigma,
+        stats_original.small_world_omega,
+    ) {
+        writeln!(o, "\nSmall-world measures:").unwrap();
+        writeln!(
+            o,
+            "Sigma: {} ({:.3}%), omega: {} ({:.3}%)",
+            sigma - sigma_original,
+            percentage_change(sigma_original, sigma),
+            omega - omega_original,
+            percentage_change(omega_original, omega)
+        )
+        .unwrap();
+        writeln!(o, "Rich-club coefficients (degree > k):").unwrap();
+        for (threshold, coefficient) in stats.rich_club.iter() {
+            if let Some(original) = stats_original.rich_club.get(threshold) {
+                writeln!(
+                    o,
+                    "k = {threshold}: {} ({:.3}%)",
+                    coefficient - original,
+                    percentage_change(*original, *coefficient)
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    writeln!(o, "\nCentralization measures:").unwrap();
+    writeln!(
+        o,
+        "Degree Gini coefficient: {} ({:.3}%)",
+        stats.degree_gini - stats_original.degree_gini,
+        percentage_change(stats_original.degree_gini, stats.degree_gini)
+    )
+    .unwrap();
+    writeln!(
+        o,
+        "Freeman centralization: degree: {} ({:.3}%), betweenness: {} ({:.3}%), closeness: {} ({:.3}%)",
+        stats.degree_centralization - stats_original.degree_centralization,
+        percentage_change(
+            stats_original.degree_centralization,
+            stats.degree_centralization
+        ),
+        stats.betweenness_centralization - stats_original.betweenness_centralization,
+        percentage_change(
+            stats_original.betweenness_centralization,
+            stats.betweenness_centralization
+        ),
+        stats.closeness_centralization - stats_original.closeness_centralization,
+        percentage_change(
+            stats_original.closeness_centralization,
+            stats.closeness_centralization
+        )
+    )
+    .unwrap();
+
+    // Cut measures are only in full statistics
+    if let (Some(min_cut), Some(min_cut_original)) = (stats.min_cut, stats_original.min_cut) {
+        writeln!(o, "\nCut measures:").unwrap();
+        writeln!(
+            o,
+            "Minimum cut: {} ({:.3}%)",
+            min_cut as i64 - min_cut_original as i64,
+            percentage_change(min_cut_original as f64, min_cut as f64)
+        )
+        .unwrap();
+    }
+
     writeln!(o, "----------------------------------------\n").unwrap();
 }
 
@@ -321,26 +820,6 @@ pub fn centrality_avg(values: &[f64]) -> f64 {
     (values.iter().fold(0.0, |acc, &val| acc + val)) / values.len() as f64
 }
 
-/// Computes median of any numeric type convertible to float value.
-pub fn median<T>(list: &[T]) -> Option<f64>
-where
-    T: PartialOrd + Into<f64> + Copy,
-{
-    if list.is_empty() {
-        return None;
-    }
-
-    let mut list = list.to_vec();
-    list.sort_by(|a, b| a.partial_cmp(b).unwrap());
-
-    let mid = list.len() / 2;
-    if list.len() % 2 == 0 {
-        Some((list[mid - 1].into() + list[mid].into()) / 2.0)
-    } else {
-        Some(list[mid].into())
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use std::{
@@ -348,7 +827,10 @@ mod tests {
         str::FromStr,
     };
 
+    use ziggurat_core_crawler::summary::NetworkType;
+
     use super::*;
+    use crate::nodes::update_degrees;
 
     #[test]
     fn percentage_change_test() {
@@ -396,23 +878,55 @@ mod tests {
     }
 
     #[test]
-    fn median_test() {
-        let list = vec![10];
-        assert_eq!(median(&list).unwrap(), 10.0);
-
-        let list = vec![1, 2, 3, 4, 5];
-        assert_eq!(median(&list).unwrap(), 3.0);
-
-        let list = vec![1, 2, 3, 4, 5, 6];
-        assert_eq!(median(&list).unwrap(), 3.5);
-
-        let list = vec![1, 2, 3, 4, 5, 6, 7];
-        assert_eq!(median(&list).unwrap(), 4.0);
+    fn freeman_centralization_test() {
+        // Star with 5 nodes - degrees 4, 1, 1, 1, 1
+        assert_eq!(
+            freeman_centralization(&[4.0, 1.0, 1.0, 1.0, 1.0], 4.0 * 3.0),
+            1.0
+        );
+        // Ring - all nodes equally central
+        assert_eq!(freeman_centralization(&[2.0; 5], 4.0 * 3.0), 0.0);
+        assert_eq!(freeman_centralization(&[1.0, 1.0], 0.0), 0.0);
     }
 
     #[test]
-    fn median_test_empty() {
-        let list = Vec::<f64>::new();
-        assert!(median(&list).is_none());
+    fn generate_group_statistics_test() {
+        let mut nodes = vec![
+            Node {
+                betweenness: 1.0,
+                connections: vec![1, 2],
+                network_type: NetworkType::Zcash,
+                ..Default::default()
+            },
+            Node {
+                betweenness: 3.0,
+                connections: vec![0],
+                network_type: NetworkType::Zcash,
+                ..Default::default()
+            },
+            Node {
+                betweenness: 5.0,
+                connections: vec![0],
+                network_type: NetworkType::Unknown,
+                ..Default::default()
+            },
+        ];
+
+        update_degrees(&mut nodes);
+
+        let groups = generate_group_statistics(&nodes, "network_type", |n| {
+            Some(format!("{:?}", n.network_type))
+        });
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].value, "Unknown");
+        assert_eq!(groups[0].nodes_count, 1);
+        assert_eq!(groups[1].value, "Zcash");
+        assert_eq!(groups[1].nodes_count, 2);
+        assert_eq!(groups[1].degree_average, 1.5);
+        assert_eq!(groups[1].betweenness_median, 2.0);
+        assert_eq!(groups[1].degree_trimmed_average, 1.5);
+        assert!((groups[1].degree_percentile_90 - 1.9).abs() < 0.0001);
+        assert!((groups[1].betweenness_percentile_90 - 2.8).abs() < 0.0001);
     }
 }
@@ -0,0 +1,542 @@
+pub mod archive;
+pub mod asn;
+pub mod atomic_file;
+mod centrality;
+pub mod centrality_cache;
+pub mod config;
+pub mod csr;
+pub mod daemon;
+pub mod geo_cluster;
+pub mod geo_summary;
+pub mod geocache_transfer;
+pub mod geoip_cache;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod heat;
+pub mod histogram;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod input;
+pub mod ip_class;
+pub mod ips;
+pub mod nodes;
+pub mod null_model;
+pub mod numeric;
+pub mod provenance;
+pub mod rankings;
+pub mod regression;
+pub mod report;
+pub mod robustness;
+pub mod rotation;
+pub mod schema;
+pub mod search;
+pub mod stdio;
+#[cfg(feature = "streaming-json")]
+pub mod streaming_json;
+pub mod sweep;
+#[cfg(test)]
+mod test_util;
+pub mod timeseries;
+
+use std::{
+    collections::HashSet,
+    io::{self, Read},
+    net::SocketAddr,
+    path::Path,
+    time::{Instant, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use ziggurat_core_crawler::summary::{NetworkSummary, NetworkType};
+
+use crate::{
+    centrality_cache::CentralityCache,
+    config::CrunchyConfiguration,
+    geo_cluster::{cluster_nodes, GeoCluster},
+    geo_summary::{create_geo_summary, GeoSummary},
+    geoip_cache::GeoIPCache,
+    ips::{
+        algorithm::{Ips, IpsError, IpsReport},
+        graph_utils::{compute_distances, GraphDistances},
+        peer::Peer,
+        statistics::{generate_group_statistics, GroupStatistics},
+    },
+    nodes::{
+        create_histograms, create_nodes, normalize_centralities, sanitize_connections,
+        update_degrees, ConnectionSanitization, HistogramSummary, Node,
+    },
+    provenance::StateMeta,
+    rankings::{create_rankings, Rankings},
+    schema::STATE_SCHEMA_VERSION,
+};
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct CrunchyState {
+    /// Version of the state schema, see [`schema`]
+    #[serde(default)]
+    pub schema_version: u32,
+    pub elapsed: f64,
+    pub nodes: Vec<Node>,
+    /// Left out when written to a separate file (`exclude_histograms_from_state`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub histograms: Vec<HistogramSummary>,
+    #[serde(default)]
+    pub distances: GraphDistances,
+    #[serde(default)]
+    pub group_statistics: Vec<GroupStatistics>,
+    /// Node counts per country, city and ASN
+    #[serde(default)]
+    pub geo_summary: GeoSummary,
+    /// Geographic clusters of nodes, the largest first
+    #[serde(default)]
+    pub geo_clusters: Vec<GeoCluster>,
+    /// Nodes with the highest degree, betweenness and closeness
+    #[serde(default)]
+    pub rankings: Rankings,
+    /// Connections dropped from the input by the sanitization
+    #[serde(default)]
+    pub sanitization: ConnectionSanitization,
+    /// Seed of all pseudo-random choices made for this state
+    #[serde(default)]
+    pub seed: u64,
+    /// Input, version and configuration which produced the state
+    #[serde(default)]
+    pub meta: Option<StateMeta>,
+}
+
+#[derive(Default, Deserialize)]
+pub struct JsonRpcResponse {
+    pub jsonrpc: String,
+    pub result: NetworkSummary,
+    pub id: usize,
+    /// Time of the crawl (unix time in seconds) recorded at the top level of the response or in
+    /// its result, if any
+    #[serde(skip)]
+    pub timestamp: Option<u64>,
+}
+
+impl JsonRpcResponse {
+    /// Parse the crawler response, or the bare network summary as dumped by the crawler - it's
+    /// taken as the result of a response with id 0.
+    pub fn parse(content: &str) -> Result<JsonRpcResponse, io::Error> {
+        let timestamp = timeseries::read_timestamp(content.as_bytes());
+        let response_error = match serde_json::from_str(content) {
+            Ok(response) => {
+                return Ok(JsonRpcResponse {
+                    timestamp,
+                    ..response
+                })
+            }
+            Err(e) => e,
+        };
+        match serde_json::from_str(content) {
+            Ok(result) => Ok(JsonRpcResponse {
+                jsonrpc: "2.0".to_owned(),
+                result,
+                id: 0,
+                timestamp,
+            }),
+            Err(summary_error) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "neither a JSON-RPC response ({response_error}) nor a network summary \
+                     ({summary_error})"
+                ),
+            )),
+        }
+    }
+}
+
+/// Load the response (or the bare network summary) from the file, or from stdin if the path is
+/// `-`.
+pub fn load_response(filepath: &str) -> Result<JsonRpcResponse, io::Error> {
+    read_response(stdio::reader(Path::new(filepath))?)
+}
+
+#[cfg(feature = "streaming-json")]
+pub fn read_response<R: Read>(reader: R) -> Result<JsonRpcResponse, io::Error> {
+    streaming_json::read_response_streaming(reader)
+}
+
+#[cfg(not(feature = "streaming-json"))]
+pub fn read_response<R: Read>(mut reader: R) -> Result<JsonRpcResponse, io::Error> {
+    let mut content = String::new();
+    reader.read_to_string(&mut content)?;
+    JsonRpcResponse::parse(&content)
+}
+
+/// Load the state from the file, or from stdin if the path is `-`.
+pub fn load_state(filepath: &str) -> Result<CrunchyState, io::Error> {
+    read_state(stdio::reader(Path::new(filepath))?)
+}
+
+/// Read the state written by this or an earlier version of crunchy - earlier versions are
+/// upgraded to the current schema. Degrees are computed from the connections again, so they
+/// can't disagree with them.
+pub fn read_state<R: Read>(reader: R) -> Result<CrunchyState, io::Error> {
+    let state = serde_json::from_reader(reader)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut state = schema::parse_state(state)?;
+    update_degrees(&mut state.nodes);
+    Ok(state)
+}
+
+/// Serialize the value as compact JSON, or pretty-printed with keys of all objects sorted, so
+/// that differences between snapshots can be reviewed.
+pub fn to_json<T: Serialize>(value: &T, pretty: bool) -> String {
+    if pretty {
+        // Objects of `serde_json::Value` keep their keys sorted
+        serde_json::to_string_pretty(&serde_json::to_value(value).unwrap()).unwrap()
+    } else {
+        serde_json::to_string(value).unwrap()
+    }
+}
+
+/// Compute the state of the graph (node metrics, histograms and graph measures) from the network
+/// summary. Nothing is read from or written to the filesystem - geolocation is resolved using
+/// the provided cache, which can be backed by any storage, and centralities of an unchanged
+/// graph are taken from the provided centrality cache.
+pub async fn create_state(
+    summary: &NetworkSummary,
+    config: &CrunchyConfiguration,
+    geo_cache: &GeoIPCache,
+    centrality_cache: &CentralityCache,
+) -> CrunchyState {
+    let start = Instant::now();
+
+    // Crawlers can list connections to missing nodes, to the node itself or to the same peer
+    // more than once - nodes are built from clean connection lists
+    let mut indices = summary.nodes_indices.clone();
+    let sanitization = sanitize_connections(&mut indices);
+
+    let mut nodes = create_nodes(
+        config.network_type_filter,
+        &indices,
+        &summary.node_addrs,
+        &summary.node_network_types,
+        geo_cache,
+        centrality_cache,
+        config.closeness_variant,
+        config.num_threads,
+    )
+    .await;
+
+    let distances = compute_distances(&nodes, config.num_threads);
+    for (node, eccentricity) in nodes.iter_mut().zip(distances.eccentricities.iter()) {
+        node.eccentricity = *eccentricity;
+    }
+    // Histograms, group statistics and rankings are made of the values written to the state
+    normalize_centralities(
+        &mut nodes,
+        &config.centrality_output,
+        config.closeness_variant,
+    );
+
+    let histograms =
+        create_histograms(&nodes, &config.histograms, config.weighted_histograms).await;
+
+    let group_statistics = generate_group_statistics(&nodes, "network_type", |node| {
+        Some(format!("{:?}", node.network_type))
+    });
+    let geo_summary = create_geo_summary(&nodes, config.geo_summary_top_cities);
+    let geo_clusters = if config.geo_clustering.enable {
+        cluster_nodes(&mut nodes, &config.geo_clustering)
+    } else {
+        Vec::new()
+    };
+    let rankings = create_rankings(&nodes, config.rankings_top_nodes, config.closeness_variant);
+
+    CrunchyState {
+        schema_version: STATE_SCHEMA_VERSION,
+        elapsed: start.elapsed().as_secs_f64(),
+        nodes,
+        histograms,
+        distances,
+        group_statistics,
+        geo_summary,
+        geo_clusters,
+        rankings,
+        sanitization,
+        seed: config.seed.unwrap_or_default(),
+        meta: None,
+    }
+}
+
+/// Leave out of the state of a reproducible run what differs between runs - the processing time
+/// and the modification time of the input - and return the time its outputs are stamped with:
+/// the crawl time recorded in the input, or the unix epoch if it records none.
+pub fn make_reproducible(state: &mut CrunchyState, crawled_at: Option<SystemTime>) -> SystemTime {
+    state.elapsed = 0.0;
+    if let Some(meta) = state.meta.as_mut() {
+        meta.crawled_at = crawled_at;
+    }
+    crawled_at.unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+/// Generate the Intelligent Peer Sharing peer lists for the given state together with the
+/// report of the IPS run. The report is returned even if peer lists couldn't be generated.
+/// Nodes in `avoided` are never recommended as new peers.
+pub async fn create_peers(
+    state: &CrunchyState,
+    network: NetworkType,
+    config: &CrunchyConfiguration,
+    avoided: HashSet<SocketAddr>,
+) -> (Result<Vec<Peer>, IpsError>, IpsReport) {
+    let mut ips = Ips::new(config.ips_config.clone())
+        .with_seed(config.seed.unwrap_or_default())
+        .with_closeness_variant(config.closeness_variant)
+        .avoiding(avoided);
+    let peers = ips.generate(state, network, config.num_threads).await;
+    (peers, ips.report().clone())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{net::SocketAddr, thread, time::Duration};
+
+    use super::*;
+    use crate::{
+        config::{CentralityOutput, ClosenessVariant, GeoIPConfiguration},
+        geoip_cache::MemoryStorage,
+        input::read_input,
+        ips::{config::PeerOutputFormat, peer::PeerList},
+        provenance::HashingReader,
+    };
+
+    #[tokio::test]
+    async fn create_nodes_unfiltered_test() {
+        let response = load_response("testdata/sample.json").unwrap();
+
+        let config = GeoIPConfiguration::default();
+        let mut geo_cache = GeoIPCache::new(&config).unwrap();
+        geo_cache.configure_providers(&config);
+
+        let num_threads = thread::available_parallelism().unwrap().get();
+        let nodes = create_nodes(
+            None,
+            &response.result.nodes_indices,
+            &response.result.node_addrs,
+            &response.result.node_network_types,
+            &geo_cache,
+            &CentralityCache::default(),
+            ClosenessVariant::Standard,
+            num_threads,
+        )
+        .await;
+
+        assert_eq!(nodes.len(), 6103);
+        assert_eq!(nodes[0].connections.len(), 2478);
+        assert_eq!(nodes[1].connections.len(), 2216);
+        assert_eq!(nodes[2].connections.len(), 1);
+        assert_eq!(nodes[3].connections.len(), 2184);
+        assert_eq!(nodes[3].connections[2], 609);
+        // Node 0 lists itself twice
+        assert_eq!(nodes[0].degree, 2476);
+        assert_eq!(nodes[1].degree, 2216);
+    }
+
+    #[tokio::test]
+    async fn create_nodes_filtered_test1() {
+        let indices = vec![vec![1, 2], vec![0, 2, 3], vec![0, 1, 3], vec![1, 2]];
+        let node_addrs = vec![
+            SocketAddr::from(([127, 0, 0, 1], 1234)),
+            SocketAddr::from(([127, 0, 0, 2], 1234)),
+            SocketAddr::from(([127, 0, 0, 3], 1234)),
+            SocketAddr::from(([127, 0, 0, 4], 1234)),
+        ];
+        let node_network_types = vec![
+            NetworkType::Unknown,
+            NetworkType::Zcash,
+            NetworkType::Unknown,
+            NetworkType::Zcash,
+        ];
+        let config = GeoIPConfiguration::default();
+        let mut geo_cache = GeoIPCache::new(&config).unwrap();
+        geo_cache.configure_providers(&config);
+
+        let num_threads = thread::available_parallelism().unwrap().get();
+        let nodes = create_nodes(
+            Some(NetworkType::Zcash),
+            &indices,
+            &node_addrs,
+            &node_network_types,
+            &geo_cache,
+            &CentralityCache::default(),
+            ClosenessVariant::Standard,
+            num_threads,
+        )
+        .await;
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0].connections, vec![1]);
+        assert_eq!(nodes[1].connections, vec![0]);
+    }
+
+    #[tokio::test]
+    async fn create_nodes_filtered_test2() {
+        let response = load_response("testdata/sample.json").unwrap();
+
+        let config = GeoIPConfiguration::default();
+        let mut geo_cache = GeoIPCache::new(&config).unwrap();
+        geo_cache.configure_providers(&config);
+
+        let num_threads = thread::available_parallelism().unwrap().get();
+        let nodes = create_nodes(
+            Some(NetworkType::Zcash),
+            &response.result.nodes_indices,
+            &response.result.node_addrs,
+            &response.result.node_network_types,
+            &geo_cache,
+            &CentralityCache::default(),
+            ClosenessVariant::Standard,
+            num_threads,
+        )
+        .await;
+        assert_eq!(nodes.len(), 122);
+        assert_eq!(nodes[0].connections.len(), 2);
+        assert_eq!(nodes[1].connections.len(), 0);
+        assert_eq!(nodes[2].connections.len(), 1);
+        assert_eq!(nodes[3].connections.len(), 1);
+        assert_eq!(nodes[3].connections[0], 56);
+
+        let node = nodes[0].clone();
+        assert_eq!(node.addr.to_string(), "3.72.134.66:8233");
+        let epsilon: f64 = 0.0000001;
+        assert!((node.betweenness - 47.525898078529664).abs() < epsilon);
+        assert!((node.closeness - 1.603305785123967).abs() < epsilon);
+    }
+
+    #[tokio::test]
+    async fn create_state_in_memory_test() {
+        let summary = NetworkSummary {
+            node_addrs: vec![
+                SocketAddr::from(([127, 0, 0, 1], 1234)),
+                SocketAddr::from(([127, 0, 0, 2], 1234)),
+                SocketAddr::from(([127, 0, 0, 3], 1234)),
+                SocketAddr::from(([127, 0, 0, 4], 1234)),
+            ],
+            node_network_types: vec![NetworkType::Zcash; 4],
+            nodes_indices: vec![vec![1, 1], vec![0, 2, 1], vec![1, 3, 9], vec![2]],
+            ..Default::default()
+        };
+
+        let config = CrunchyConfiguration::default();
+        let geo_cache = GeoIPCache::with_storage(&config.geoip_config, Box::new(MemoryStorage));
+        geo_cache.load().await.unwrap();
+
+        let state = create_state(&summary, &config, &geo_cache, &CentralityCache::default()).await;
+        assert_eq!(state.nodes.len(), 4);
+        assert_eq!(state.histograms.len(), 4);
+        assert_eq!(state.distances.diameter, 3);
+        assert_eq!(state.nodes[0].connections, vec![1]);
+        assert_eq!(state.nodes[1].connections, vec![0, 2]);
+        assert_eq!(state.nodes[2].connections, vec![1, 3]);
+        assert_eq!(
+            state.sanitization,
+            ConnectionSanitization {
+                out_of_range: 1,
+                self_connections: 1,
+                duplicates: 1,
+            }
+        );
+        assert!(geo_cache.save().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn reproducible_outputs_test() {
+        let config = CrunchyConfiguration {
+            seed: Some(7),
+            reproducible: true,
+            ..Default::default()
+        };
+        let geo_cache = GeoIPCache::with_storage(&config.geoip_config, Box::new(MemoryStorage));
+        geo_cache.load().await.unwrap();
+
+        // Two runs on the shipped sample, which records no crawl time
+        let path = Path::new("testdata/sample.json");
+        let mut outputs = Vec::new();
+        for _ in 0..2 {
+            let mut reader = HashingReader::new(stdio::reader(path).unwrap());
+            let input = read_input(&mut reader, &config.input_format).unwrap();
+            assert_eq!(input.crawled_at, None);
+
+            let mut state = create_state(
+                &input.summary,
+                &config,
+                &geo_cache,
+                &CentralityCache::default(),
+            )
+            .await;
+            state.meta = Some(StateMeta::with_sha256(path, reader.sha256(), &config));
+            let generated_at = make_reproducible(&mut state, input.crawled_at);
+            assert_eq!(generated_at, SystemTime::UNIX_EPOCH);
+
+            let (peers, _) =
+                create_peers(&state, NetworkType::Zcash, &config, HashSet::new()).await;
+            let peer_list = PeerList::with_generation_time(
+                peers.unwrap_or_default(),
+                generated_at,
+                Duration::from_secs(3600),
+                state.seed,
+            );
+            outputs.push((
+                to_json(&state, config.pretty_json),
+                peer_list.render(&PeerOutputFormat::Json, config.pretty_json),
+            ));
+        }
+        assert_eq!(outputs[0].0.as_bytes(), outputs[1].0.as_bytes());
+        assert_eq!(outputs[0].1.as_bytes(), outputs[1].1.as_bytes());
+    }
+
+    #[test]
+    fn normalize_centralities_test() {
+        // Star with 5 nodes - its center is on all 6 shortest paths between the leaves
+        let star = || {
+            let mut nodes = vec![Node::default(); 5];
+            nodes[0].betweenness = 6.0;
+            nodes[0].closeness = 1.0;
+            for leaf in nodes[1..].iter_mut() {
+                leaf.closeness = 1.75;
+            }
+            nodes
+        };
+
+        let standard = ClosenessVariant::Standard;
+
+        let mut nodes = star();
+        normalize_centralities(&mut nodes, &CentralityOutput::Raw, standard);
+        assert_eq!(nodes[0].betweenness, 6.0);
+        assert_eq!(nodes[0].betweenness_normalized, None);
+
+        normalize_centralities(&mut nodes, &CentralityOutput::Normalized, standard);
+        assert_eq!(nodes[0].betweenness, 1.0);
+        assert_eq!(nodes[0].closeness, 0.4);
+        assert_eq!(nodes[1].closeness, 0.7);
+
+        let mut nodes = star();
+        normalize_centralities(&mut nodes, &CentralityOutput::Both, standard);
+        assert_eq!(nodes[0].betweenness, 6.0);
+        assert_eq!(nodes[0].betweenness_normalized, Some(1.0));
+        assert_eq!(nodes[1].closeness_normalized, Some(0.7));
+
+        // An end of a path with 5 nodes is the least central node possible
+        let mut nodes = vec![Node::default(); 5];
+        nodes[0].closeness = (1.0 + 2.0 + 3.0 + 4.0) / 4.0;
+        normalize_centralities(&mut nodes, &CentralityOutput::Normalized, standard);
+        assert_eq!(nodes[0].closeness, 1.0);
+
+        // Harmonic closeness of the center of a star is the highest possible
+        let mut nodes = star();
+        nodes[0].closeness = 4.0;
+        normalize_centralities(
+            &mut nodes,
+            &CentralityOutput::Normalized,
+            ClosenessVariant::Harmonic,
+        );
+        assert_eq!(nodes[0].closeness, 1.0);
+
+        let mut nodes = vec![Node::default()];
+        normalize_centralities(&mut nodes, &CentralityOutput::Normalized, standard);
+        assert_eq!(nodes[0].closeness, 0.0);
+    }
+}
@@ -1,106 +1,772 @@
-mod config;
-mod geoip_cache;
-mod histogram;
-mod ips;
-mod nodes;
-
-use std::{fs, path::PathBuf, time::Instant};
-
-use clap::Parser;
-use serde::{Deserialize, Serialize};
-use ziggurat_core_crawler::summary::{NetworkSummary, NetworkType};
-
-use crate::{
-    config::CrunchyConfiguration,
-    geoip_cache::GeoIPCache,
-    ips::algorithm::Ips,
-    nodes::{create_histograms, create_nodes, HistogramSummary, Node},
+#[cfg(any(feature = "grpc", feature = "http"))]
+use std::net::SocketAddr;
+#[cfg(feature = "http")]
+use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, Read, Write},
+    panic::AssertUnwindSafe,
+    path::{Path, PathBuf},
+    process,
+    time::{Duration, SystemTime},
 };
 
-#[derive(Default, Clone, Serialize, Deserialize)]
-pub struct CrunchyState {
-    elapsed: f64,
-    nodes: Vec<Node>,
-    histograms: Vec<HistogramSummary>,
+use clap::{Parser, Subcommand};
+use futures::FutureExt;
+use ziggurat_core_crawler::summary::NetworkType;
+#[cfg(feature = "http")]
+use ziggurat_crunchy::http::{self, HttpOptions, HttpService, Publication};
+use ziggurat_crunchy::{
+    archive::{observe_stability, observe_uptime, StateArchive},
+    atomic_file,
+    centrality_cache::CentralityCache,
+    config::{
+        default_num_threads, ConfigFormat, CrunchyConfiguration, ENV_PREFIX, ENV_SECTION_SEPARATOR,
+    },
+    create_peers, create_state,
+    daemon::{parse_interval, prune_runs, run_configuration, run_name, Retention},
+    geocache_transfer::{entries_from_csv, entries_to_csv, merge_entries},
+    geoip_cache::{GeoCacheStorage, GeoIPCache, JsonFileStorage},
+    heat::emerging_hot_nodes,
+    input::{apply_metadata, load_summary, read_input},
+    ips::{
+        algorithm::{IpsError, IpsReport},
+        config::{network_name, with_network_suffix, PeerOutputFormat, WeightProfile},
+        graph_utils::filter_network,
+        peer::{parse_peers, peer_changes, Peer, PeerList},
+        statistics::{print_statistics, print_statistics_delta, StatisticsOutput},
+    },
+    make_reproducible,
+    null_model::compare_with_null_models,
+    provenance::{HashingReader, StateMeta},
+    read_state,
+    regression::{compare_peer_lists, compare_states, DEFAULT_CHECK_TOLERANCE},
+    report::{NetworkIps, RunReport},
+    robustness::simulate_attacks,
+    rotation::{expand, is_templated, prune},
+    stdio,
+    sweep::{sweep_csv, sweep_weights},
+    timeseries::{list_samples, TrendPoint},
+    to_json, CrunchyState,
+};
+
+/// Open the GeoIP cache with entries loaded from the storage and providers configured. Fails
+/// only if the storage can't be opened - a missing cache file is created later.
+async fn open_geo_cache(config: &CrunchyConfiguration) -> Result<GeoIPCache, io::Error> {
+    let mut geo_cache = GeoIPCache::new(&config.geoip_config)?;
+
+    let res = geo_cache.load().await;
+    if res.is_err() {
+        writeln!(
+            stdio::messages(),
+            "No cache file to load! Will be created one."
+        )
+        .unwrap();
+    }
+
+    geo_cache.configure_providers(&config.geoip_config);
+    Ok(geo_cache)
 }
 
-#[allow(dead_code)]
-#[derive(Default, Deserialize)]
-pub struct JsonRpcResponse {
-    jsonrpc: String,
-    result: NetworkSummary,
-    id: usize,
+/// Perform all the necessary steps to generate the state file and the peer list. Returns the
+/// generated state, the peer lists written for each network type and the network types IPS
+/// failed for, or why the run failed - the caller decides whether it ends the process.
+async fn write_state(
+    config: &CrunchyConfiguration,
+) -> Result<(CrunchyState, Vec<(String, PeerList)>, Vec<String>), String> {
+    // Templated output paths get the time of this run, so runs don't overwrite each other
+    let templates = config.output_paths();
+    let timestamp = run_name(SystemTime::now());
+    let config = &config.map_output_paths(|path| expand(path, &timestamp));
+
+    let geo_cache = open_geo_cache(config)
+        .await
+        .map_err(|e| format!("Could not open the GeoIP cache: {e}"))?;
+    // The contents are hashed while read, for the provenance of the state
+    let input_file_path = config.input_file_path.as_ref().unwrap();
+    let input_error = |e| {
+        format!(
+            "could not load input file {}: {e}",
+            input_file_path.display()
+        )
+    };
+    let mut reader = HashingReader::new(stdio::reader(input_file_path).map_err(input_error)?);
+    let input = read_input(&mut reader, &config.input_format).map_err(input_error)?;
+    let input_sha256 = reader.sha256();
+    // Reproducible outputs are stamped with the crawl time recorded in the sample, so they don't
+    // depend on when (or from which copy of the sample) they are generated
+    let reproducible_stamp = config.reproducible.then(|| {
+        config
+            .reproducible_timestamp
+            .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+            .or(input.crawled_at)
+    });
+
+    let centrality_cache = config
+        .centrality_cache_file_path
+        .as_ref()
+        .and_then(|path| CentralityCache::load(path).ok())
+        .unwrap_or_default();
+
+    let mut state = create_state(&input.summary, config, &geo_cache, &centrality_cache).await;
+    apply_metadata(&input.metadata, &mut state);
+    state.meta = Some(StateMeta::with_sha256(
+        input_file_path,
+        input_sha256,
+        config,
+    ));
+
+    // Save all changes done to the cache
+    if let Err(res) = geo_cache.save().await {
+        writeln!(stdio::messages(), "Could not save cache file: {}", res).unwrap();
+    }
+    if let Some(path) = &config.centrality_cache_file_path {
+        if let Err(e) = centrality_cache.save(path) {
+            writeln!(stdio::messages(), "Could not save centrality cache: {e}").unwrap();
+        }
+    }
+
+    // Betweenness trends need earlier states, so the archive is loaded before IPS runs
+    let mut archive = match &config.archive_file_path {
+        Some(path) => match StateArchive::load(path) {
+            Ok(archive) => Some(archive),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Some(StateArchive::new(config.archive_metric_threshold))
+            }
+            // Starting a new archive would overwrite the history on the next save
+            Err(e) => {
+                return Err(format!(
+                    "could not load state archive {}: {e}",
+                    path.display()
+                ))
+            }
+        },
+        None => None,
+    };
+    let mut snapshots = Vec::new();
+    for path in config.previous_state_file_paths.iter() {
+        let snapshot = stdio::reader(path)
+            .and_then(read_state)
+            .map_err(|e| format!("could not load previous state {}: {e}", path.display()))?;
+        snapshots.push(snapshot);
+    }
+    let recent_runs = config.heat_window.saturating_sub(1);
+    if let Some(archive) = archive.as_ref() {
+        snapshots.extend(archive.recent(recent_runs));
+    }
+    observe_stability(&snapshots, &mut state);
+
+    // Uptime and betweenness trends are limited to the heat window
+    let older = snapshots.len().saturating_sub(recent_runs);
+    if older > 0 {
+        writeln!(
+            stdio::messages(),
+            "{older} earlier snapshots are outside the heat window of {} runs - they are used only for node stability",
+            config.heat_window
+        )
+        .unwrap();
+    }
+    let history = &snapshots[older..];
+    observe_uptime(history, &mut state);
+    let hot_nodes = emerging_hot_nodes(history, &state, config.emerging_hot_nodes_count);
+    let avoided = if config.ips_config.avoid_emerging_hot_nodes {
+        hot_nodes.iter().map(|node| node.addr).collect()
+    } else {
+        HashSet::new()
+    };
+
+    // Each network type gets its own peer lists - and its own files if there are more of them
+    let networks = config.ips_networks();
+    let mut ips_runs = Vec::new();
+    for network in networks.iter() {
+        let mut network_config = config.clone();
+        if networks.len() > 1 {
+            network_config.ips_config = config.ips_config.for_network(*network);
+        }
+        let (ips_peers, ips_report) =
+            create_peers(&state, *network, &network_config, avoided.clone()).await;
+        ips_runs.push((*network, network_config, ips_peers, ips_report));
+    }
+
+    let mut report = RunReport::new(
+        state.elapsed,
+        state.nodes.len(),
+        state.seed,
+        geo_cache.statistics(),
+        IpsReport::default(),
+    );
+
+    // Processing time and the current time differ for each run - reproducible outputs record
+    // only what's derived from the input (the report still has the real processing time)
+    let generated_at = match reproducible_stamp {
+        Some(crawled_at) => make_reproducible(&mut state, crawled_at),
+        None => SystemTime::now(),
+    };
+    report.emerging_hot_nodes = hot_nodes;
+    report.add_artifact("geoip_cache", &config.geoip_config.geocache_file_path);
+    if let Some(path) = &config.centrality_cache_file_path {
+        report.add_artifact("centrality_cache", path);
+    }
+
+    let mut network_runs = Vec::new();
+    let mut peer_lists = Vec::new();
+    for (network, network_config, ips_peers, ips_report) in ips_runs {
+        let mut run = NetworkIps {
+            network: network_name(network),
+            ips: ips_report,
+            ..Default::default()
+        };
+        if let Some(peerlist) = write_peers(
+            &network_config,
+            &state,
+            network,
+            ips_peers,
+            generated_at,
+            &mut run,
+            &mut report,
+        )? {
+            peer_lists.push((run.network.clone(), peerlist));
+        }
+        network_runs.push(run);
+    }
+    let failed_networks = network_runs
+        .iter()
+        .filter(|run| run.ips_error.is_some())
+        .map(|run| run.network.clone())
+        .collect::<Vec<String>>();
+    // The first network type is reported at the top level, so single network reports stay the
+    // same
+    let first = network_runs[0].clone();
+    report.ips = first.ips;
+    report.ips_error = first.ips_error;
+    report.proposed_changes = first.proposed_changes;
+    if network_runs.len() > 1 {
+        report.networks = network_runs;
+    }
+
+    if let Some(path) = &config.histograms_file_path {
+        stdio::write(path, to_json(&state.histograms, config.pretty_json))
+            .map_err(|e| format!("could not write {}: {e}", path.display()))?;
+        report.add_artifact("histograms", path);
+    }
+
+    // Histograms are taken out only for writing - the returned state (served over HTTP and gRPC)
+    // and the archive still have them
+    let histograms = if config.exclude_histograms_from_state {
+        std::mem::take(&mut state.histograms)
+    } else {
+        Vec::new()
+    };
+    let joutput = to_json(&state, config.pretty_json);
+    let state_file_path = config.state_file_path.as_ref().unwrap();
+    stdio::write(state_file_path, joutput)
+        .map_err(|e| format!("could not write {}: {e}", state_file_path.display()))?;
+    report.add_artifact("state", state_file_path);
+    if config.exclude_histograms_from_state {
+        state.histograms = histograms;
+    }
+
+    if let (Some(path), Some(archive)) = (&config.archive_file_path, archive.as_mut()) {
+        archive.push(&state);
+        match archive.save(path) {
+            Ok(_) => report.add_artifact("archive", path),
+            Err(e) => eprintln!("Could not save state archive: {e}"),
+        }
+    }
+
+    if let Some(path) = &config.report_file_path {
+        stdio::write(path, to_json(&report, config.pretty_json))
+            .map_err(|e| format!("could not write {}: {e}", path.display()))?;
+    }
+
+    if let Some(keep_last) = config.keep_last {
+        for template in templates.iter().filter(|path| is_templated(path)) {
+            match prune(template, keep_last) {
+                Ok(removed) => {
+                    for path in removed {
+                        writeln!(stdio::messages(), "Removed {}", path.display()).unwrap();
+                    }
+                }
+                Err(e) => eprintln!(
+                    "Could not remove old outputs of {}: {e}",
+                    template.display()
+                ),
+            }
+        }
+    }
+
+    Ok((state, peer_lists, failed_networks))
+}
+
+/// Write the peer list generated for a single network type (unless it's a dry run) and record
+/// the outcome. Returns the written peer list, or why an output couldn't be written.
+fn write_peers(
+    config: &CrunchyConfiguration,
+    state: &CrunchyState,
+    network: NetworkType,
+    ips_peers: Result<Vec<Peer>, IpsError>,
+    generated_at: SystemTime,
+    run: &mut NetworkIps,
+    report: &mut RunReport,
+) -> Result<Option<PeerList>, String> {
+    let write_error =
+        |path: &Path, e: io::Error| format!("could not write {}: {e}", path.display());
+
+    if let Some(path) = &config.ips_config.log_path {
+        report.add_artifact("ips_log", path);
+    }
+    for path in run.ips.debug_dumps.iter() {
+        report.add_artifact("debug_dump", path);
+    }
+
+    let validity = Duration::from_secs(config.ips_config.crawl_interval_hours as u64 * 60 * 60);
+    // Peer lists matching the current connections, so consumers can compare them with the
+    // generated ones (the same way they are going to consume them)
+    if let (Some(path), false) = (
+        &config.ips_config.vanilla_peer_file_path,
+        config.ips_dry_run,
+    ) {
+        let network_nodes = filter_network(&state.nodes, network);
+        let vanilla_peers = Peer::generate_all_peerlists(
+            &network_nodes,
+            &network_nodes,
+            config.ips_config.latency_per_hop_ms,
+        );
+        let peerlist =
+            PeerList::with_generation_time(vanilla_peers, generated_at, validity, state.seed)
+                .render(&config.ips_config.peer_output_format, config.pretty_json);
+        atomic_file::write(path, peerlist).map_err(|e| write_error(path, e))?;
+        report.add_artifact("vanilla_peers", path);
+    }
+
+    // Statistics are useful even for dry runs - they show the impact without any peer list
+    if let (Some(path), Some(initial), Some(last)) = (
+        config.ips_config.statistics_path(),
+        &run.ips.initial_statistics,
+        &run.ips.final_statistics,
+    ) {
+        let statistics = StatisticsOutput {
+            initial: initial.clone(),
+            r#final: last.clone(),
+            delta: run.ips.statistics_delta.clone(),
+        };
+        stdio::write(&path, to_json(&statistics, config.pretty_json))
+            .map_err(|e| write_error(&path, e))?;
+        report.add_artifact("statistics", &path);
+    }
+    // Security findings are written even if IPS failed (e.g. on massive islands)
+    if let Some(path) = config.ips_config.security_report_path() {
+        stdio::write(&path, to_json(&run.ips.security, config.pretty_json))
+            .map_err(|e| write_error(&path, e))?;
+        report.add_artifact("security_report", &path);
+    }
+    if let Some(path) = &config.ips_config.pareto_file_path {
+        stdio::write(path, to_json(&run.ips.pareto_front, config.pretty_json))
+            .map_err(|e| write_error(path, e))?;
+        report.add_artifact("pareto_front", path);
+    }
+
+    // The state and the report are still useful if IPS failed - just skip the peer list
+    match ips_peers {
+        Ok(ips_peers) if config.ips_dry_run => {
+            run.proposed_changes = peer_changes(&state.nodes, &ips_peers);
+            print_dry_run(run);
+            Ok(None)
+        }
+        Ok(mut ips_peers) => {
+            // Canary rollout - only canary nodes get their peer lists, the full rollout is kept
+            // to be promoted later
+            if let Some(canary) = &config.ips_config.canary {
+                if let Some(path) = &canary.full_peer_file_path {
+                    let peerlist = PeerList::with_generation_time(
+                        ips_peers.clone(),
+                        generated_at,
+                        validity,
+                        state.seed,
+                    );
+                    atomic_file::write(path, to_json(&peerlist, config.pretty_json))
+                        .map_err(|e| write_error(path, e))?;
+                    report.add_artifact("full_peers", path);
+                }
+                let canary_nodes = run.ips.canary_nodes.iter().collect::<HashSet<_>>();
+                ips_peers.retain(|peer| canary_nodes.contains(&peer.ip));
+            }
+            // Changes only, for operators applying them by hand
+            if let Some(path) = &config.ips_config.peers_delta_file_path {
+                let changes = peer_changes(&state.nodes, &ips_peers);
+                stdio::write(path, to_json(&changes, config.pretty_json))
+                    .map_err(|e| write_error(path, e))?;
+                report.add_artifact("peers_delta", path);
+            }
+            let peerlist =
+                PeerList::with_generation_time(ips_peers, generated_at, validity, state.seed);
+            let peer_file_path = config
+                .ips_config
+                .peer_file_path
+                .as_ref()
+                .ok_or("ips_config.peer_file_path is not set")?;
+            let output = peerlist.render(&config.ips_config.peer_output_format, config.pretty_json);
+            stdio::write(peer_file_path, output).map_err(|e| write_error(peer_file_path, e))?;
+            report.add_artifact("peers", peer_file_path);
+            Ok(Some(peerlist))
+        }
+        Err(e) => {
+            eprintln!("Could not generate peer lists for {}: {e}", run.network);
+            run.ips_error = Some(e.to_string());
+            Ok(None)
+        }
+    }
 }
 
-pub fn load_response(filepath: &str) -> JsonRpcResponse {
-    let jstring = fs::read_to_string(filepath).expect("could not open response file");
-    serde_json::from_str(&jstring).unwrap()
+/// Create the state of every sample in the directory, oldest first, and write their trends.
+/// Samples which can't be loaded are skipped.
+async fn write_timeseries(
+    config: &CrunchyConfiguration,
+    dir: &Path,
+    output: &Path,
+) -> Result<(), io::Error> {
+    let samples = list_samples(dir)?;
+    let geo_cache = open_geo_cache(config).await?;
+    // Samples differ, so centralities of one aren't reused for another
+    let centrality_cache = CentralityCache::default();
+
+    let mut trends = Vec::with_capacity(samples.len());
+    for sample in samples.iter() {
+        let summary = match load_summary(&sample.path, &config.input_format) {
+            Ok(summary) => summary,
+            Err(e) => {
+                eprintln!("Skipping {}: {e}", sample.path.display());
+                continue;
+            }
+        };
+        let state = create_state(&summary, config, &geo_cache, &centrality_cache).await;
+        trends.push(TrendPoint::new(sample, &state));
+        writeln!(stdio::messages(), "Processed {}", sample.path.display()).unwrap();
+    }
+
+    if let Err(e) = geo_cache.save().await {
+        writeln!(stdio::messages(), "Could not save cache file: {e}").unwrap();
+    }
+    stdio::write(output, to_json(&trends, config.pretty_json))
 }
 
-pub fn load_state(filepath: &str) -> CrunchyState {
-    let jstring = fs::read_to_string(filepath).expect("could not open state file");
-    serde_json::from_str(&jstring).unwrap()
+/// Run IPS for each combination of the sweep grid weights and write statistics of the resulting
+/// networks as CSV.
+async fn write_sweep(config: &CrunchyConfiguration, output: &Path) -> Result<(), io::Error> {
+    let geo_cache = open_geo_cache(config).await?;
+    let summary = load_summary(
+        config.input_file_path.as_ref().unwrap(),
+        &config.input_format,
+    )?;
+    let state = create_state(&summary, config, &geo_cache, &CentralityCache::default()).await;
+    if let Err(e) = geo_cache.save().await {
+        writeln!(stdio::messages(), "Could not save cache file: {e}").unwrap();
+    }
+
+    let runs = sweep_weights(&state, config).await;
+    stdio::write(output, sweep_csv(&config.sweep.grid, &runs))
 }
 
-/// Perform all the necessary steps to generate the state file and the peer list.
-async fn write_state(config: &CrunchyConfiguration) {
-    let mut geo_cache = GeoIPCache::new(&config.geoip_config);
-    let response = load_response(config.input_file_path.as_ref().unwrap().to_str().unwrap());
-    let start = Instant::now();
-    let elapsed = start.elapsed();
+/// Export the GeoIP cache as CSV, or merge entries from a CSV file or other caches into it.
+async fn run_geocache_command(
+    config: &CrunchyConfiguration,
+    command: &GeocacheCommand,
+) -> Result<(), String> {
+    let geo_cache = open_geo_cache(config)
+        .await
+        .map_err(|e| format!("Could not open the GeoIP cache: {e}"))?;
+    let entries = match command {
+        GeocacheCommand::Export { output } => {
+            let csv = entries_to_csv(&geo_cache.entries().await);
+            return stdio::write(output, csv)
+                .map_err(|e| format!("could not write {}: {e}", output.display()));
+        }
+        GeocacheCommand::Import { file } => {
+            let mut csv = String::new();
+            stdio::reader(file)
+                .and_then(|mut reader| reader.read_to_string(&mut csv))
+                .map_err(|e| format!("could not read {}: {e}", file.display()))?;
+            entries_from_csv(&csv).map_err(|e| format!("{}: {e}", file.display()))?
+        }
+        GeocacheCommand::Merge { caches } => {
+            let mut entries = HashMap::new();
+            for path in caches {
+                let cache_entries = JsonFileStorage::new(path.clone())
+                    .load()
+                    .map_err(|e| format!("could not load {}: {e}", path.display()))?;
+                // Caches are merged with each other the same way as into the GeoIP cache
+                merge_entries(
+                    &mut entries,
+                    cache_entries,
+                    |_| false,
+                    |ip, previous, info| geo_cache.update_history(ip, previous, info),
+                );
+            }
+            entries
+        }
+    };
 
-    let res = geo_cache.load().await;
-    if res.is_err() {
-        println!("No cache file to load! Will be created one.");
+    let summary = geo_cache
+        .merge(entries)
+        .await
+        .map_err(|e| format!("could not save the cache: {e}"))?;
+    writeln!(
+        stdio::messages(),
+        "GeoIP cache updated: {} entries added, {} updated, {} not newer than cached, {} expired",
+        summary.added,
+        summary.updated,
+        summary.skipped,
+        summary.expired
+    )
+    .unwrap();
+    Ok(())
+}
+
+/// Run the pipeline on the schedule (only once without one) and serve results of the latest
+/// successful run over HTTP until the server stops. A failed run is reported by `/healthz`, while
+/// results of the previous one stay published.
+#[cfg(feature = "http")]
+async fn serve_http(
+    config: &CrunchyConfiguration,
+    listen: SocketAddr,
+    interval: Option<Duration>,
+    allow_stale: bool,
+) -> Result<(), String> {
+    let service = Arc::new(HttpService::new(HttpOptions {
+        interval,
+        allow_stale,
+        peer_output_format: config.ips_config.peer_output_format.clone(),
+        pretty_json: config.pretty_json,
+    }));
+    let server = http::serve(listen, service.clone())
+        .map_err(|e| format!("could not listen on {listen}: {e}"))?;
+    let server = tokio::spawn(server);
+    writeln!(stdio::messages(), "Serving results over HTTP on {listen}").unwrap();
+
+    loop {
+        let run = AssertUnwindSafe(write_state(config))
+            .catch_unwind()
+            .await
+            .map_err(panic_message)
+            .and_then(|result| result);
+        match run {
+            Ok((state, peers, _)) => {
+                let publication = Publication {
+                    state,
+                    peers,
+                    generated_at: SystemTime::now(),
+                };
+                service.publish(publication).await;
+            }
+            Err(error) => {
+                eprintln!("Run failed: {error}");
+                service.fail(error).await;
+            }
+        }
+        match interval {
+            Some(interval) => tokio::time::sleep(interval).await,
+            None => break,
+        }
     }
 
-    geo_cache.configure_providers(&config.geoip_config);
+    match server.await {
+        Ok(result) => result.map_err(|e| format!("server failed: {e}")),
+        Err(e) => Err(format!("server failed: {e}")),
+    }
+}
 
-    let nodes = create_nodes(
-        config.network_type_filter,
-        &response.result.nodes_indices,
-        &response.result.node_addrs,
-        &response.result.node_network_types,
-        &geo_cache,
-        config.num_threads,
+/// Run the pipeline every interval until the process is stopped. Outputs of each run are written
+/// into a new directory of `output_dir` named by the time the run started, and runs not kept by
+/// the retention policy are removed after each run. A failed run is reported and the next one
+/// starts on schedule - runs never overlap.
+async fn run_daemon(
+    config: &CrunchyConfiguration,
+    output_dir: &Path,
+    interval: Duration,
+    #[cfg(feature = "http")] crawler_url: Option<&hyper::Uri>,
+    retention: &Retention,
+) -> Result<(), String> {
+    fs::create_dir_all(output_dir)
+        .map_err(|e| format!("could not create {}: {e}", output_dir.display()))?;
+    writeln!(
+        stdio::messages(),
+        "Running every {}s into {}",
+        interval.as_secs(),
+        output_dir.display()
     )
-    .await;
+    .unwrap();
 
-    let histograms = create_histograms(&nodes).await;
+    loop {
+        let started = SystemTime::now();
+        let run_dir = output_dir.join(run_name(started));
+        let run = run_once(
+            config,
+            &run_dir,
+            #[cfg(feature = "http")]
+            crawler_url,
+        );
+        match run.await {
+            Ok(_) => writeln!(stdio::messages(), "Run written to {}", run_dir.display()).unwrap(),
+            Err(e) => eprintln!("Run failed: {e}"),
+        }
+        match prune_runs(output_dir, retention, SystemTime::now()) {
+            Ok(removed) => {
+                for path in removed {
+                    writeln!(stdio::messages(), "Removed run {}", path.display()).unwrap();
+                }
+            }
+            Err(e) => eprintln!("Could not remove old runs: {e}"),
+        }
+        // The next run starts an interval after this one started
+        let elapsed = started.elapsed().unwrap_or_default();
+        tokio::time::sleep(interval.saturating_sub(elapsed)).await;
+    }
+}
 
-    let state = CrunchyState {
-        elapsed: elapsed.as_secs_f64(),
-        nodes,
-        histograms,
+/// Single run of the daemon writing its outputs into the run directory - the summary fetched
+/// from the crawler (if its URL is set) is written there too, as the input of the run.
+async fn run_once(
+    config: &CrunchyConfiguration,
+    run_dir: &Path,
+    #[cfg(feature = "http")] crawler_url: Option<&hyper::Uri>,
+) -> Result<(), String> {
+    #[cfg(feature = "http")]
+    let response = match crawler_url {
+        Some(url) => Some(
+            ziggurat_crunchy::daemon::fetch_summary(url)
+                .await
+                .map_err(|e| format!("could not fetch the summary from {url}: {e}"))?,
+        ),
+        None => None,
     };
+    fs::create_dir_all(run_dir)
+        .map_err(|e| format!("could not create {}: {e}", run_dir.display()))?;
 
-    // Save all changes done to the cache
-    if let Err(res) = geo_cache.save().await {
-        println!("Could not save cache file: {}", res);
+    let run_config = run_configuration(config, run_dir);
+    #[cfg(feature = "http")]
+    let run_config = match response {
+        Some(response) => {
+            let path = run_dir.join("sample.json");
+            fs::write(&path, response)
+                .map_err(|e| format!("could not write {}: {e}", path.display()))?;
+            CrunchyConfiguration {
+                input_file_path: Some(path),
+                ..run_config
+            }
+        }
+        None => run_config,
+    };
+
+    AssertUnwindSafe(write_state(&run_config))
+        .catch_unwind()
+        .await
+        .map_err(panic_message)?
+        .map(|_| ())
+}
+
+/// Message of a panic caught in a run.
+fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    panic
+        .downcast_ref::<String>()
+        .cloned()
+        .or_else(|| panic.downcast_ref::<&str>().map(|e| e.to_string()))
+        .unwrap_or_else(|| "run panicked".to_owned())
+}
+
+/// Print the impact of the peer lists and the changes they propose, as nothing is written.
+fn print_dry_run(run: &NetworkIps) {
+    let mut o = stdio::messages();
+    writeln!(o, "Dry run for {}:", run.network).unwrap();
+    if let (Some(initial), Some(last)) = (&run.ips.initial_statistics, &run.ips.final_statistics) {
+        writeln!(o, "Dry run - statistics before the peer lists are applied:").unwrap();
+        print_statistics(&mut o, initial);
+        writeln!(o, "Dry run - statistics after the peer lists are applied:").unwrap();
+        print_statistics(&mut o, last);
+        print_statistics_delta(&mut o, last, initial);
     }
 
-    let mut ips = Ips::new(config.ips_config.clone());
-    let ips_peers = ips
-        .generate(&state, NetworkType::Zcash, config.num_threads)
-        .await;
+    writeln!(
+        o,
+        "Dry run - {} nodes would change their peers:",
+        run.proposed_changes.len()
+    )
+    .unwrap();
+    for change in run.proposed_changes.iter() {
+        let added = change.added.iter().map(|addr| format!(" +{addr}"));
+        let removed = change.removed.iter().map(|addr| format!(" -{addr}"));
+        writeln!(
+            o,
+            "{}:{}",
+            change.ip,
+            added.chain(removed).collect::<String>()
+        )
+        .unwrap();
+    }
+}
 
-    let peerlist = serde_json::to_string(&ips_peers).unwrap();
-    fs::write(config.ips_config.peer_file_path.as_ref().unwrap(), peerlist).unwrap();
+/// Promote the canary rollout - peer lists for all nodes written by the canary run become the
+/// peer list file (in the given format). Expired peer lists are refused as they are based on an
+/// outdated crawl. Reproducible peer lists are stamped with the crawl time, so their validity
+/// starts when the file was written instead.
+fn promote(
+    full_peer_file: &Path,
+    peer_file_path: &Path,
+    format: &PeerOutputFormat,
+    pretty: bool,
+    reproducible: bool,
+) -> Result<(), String> {
+    let read_error = |e| format!("could not read {}: {e}", full_peer_file.display());
+    let content = fs::read_to_string(full_peer_file).map_err(read_error)?;
+    let peerlist: PeerList = serde_json::from_str(&content)
+        .map_err(|e| format!("invalid peer list {}: {e}", full_peer_file.display()))?;
+    let valid_from = if reproducible {
+        fs::metadata(full_peer_file)
+            .and_then(|metadata| metadata.modified())
+            .map_err(read_error)?
+    } else {
+        peerlist.generated_at
+    };
+    if peerlist.is_expired_since(valid_from) {
+        return Err(format!(
+            "peer list {} has expired",
+            full_peer_file.display()
+        ));
+    }
 
-    let joutput = serde_json::to_string(&state).unwrap();
-    fs::write(config.state_file_path.as_ref().unwrap(), joutput).unwrap();
+    stdio::write(peer_file_path, peerlist.render(format, pretty))
+        .map_err(|e| format!("could not write {}: {e}", peer_file_path.display()))
 }
 
 #[tokio::main]
 async fn main() {
     let arg_conf = ArgConfiguration::parse();
-    let mut configuration = arg_conf
-        .config_file
-        .map(|path| {
-            CrunchyConfiguration::new(path.to_str().unwrap())
-                .expect("could not load configuration file")
-        })
-        .unwrap_or_default();
+    // The profile is applied while the file is parsed, so weights set in the file override it
+    let profile_override = arg_conf.ips_profile.map(|profile| {
+        (
+            format!("{ENV_PREFIX}IPS_CONFIG{ENV_SECTION_SEPARATOR}PROFILE"),
+            profile.name().to_owned(),
+        )
+    });
+    let loaded = match &arg_conf.config_file {
+        Some(path) => CrunchyConfiguration::new(
+            &path.to_string_lossy(),
+            arg_conf.config_format,
+            profile_override,
+        )
+        .map_err(|e| format!("Could not load configuration file {}: {e:#}", path.display())),
+        // The defaults are layered like a file, so deployments can configure crunchy with the
+        // environment only
+        None => CrunchyConfiguration::from_env(profile_override)
+            .map_err(|e| format!("Could not load configuration: {e:#}")),
+    };
+    let mut configuration = match loaded {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    };
 
     // Override configuration with command line arguments if provided
     if let Some(input_file) = arg_conf.input_sample {
@@ -109,24 +775,190 @@ async fn main() {
     if let Some(state_file) = arg_conf.out_state {
         configuration.state_file_path = Some(state_file);
     }
+    if arg_conf.report_file.is_some() {
+        configuration.report_file_path = arg_conf.report_file;
+    }
     if let Some(geocache_file) = arg_conf.geocache_file {
         configuration.geoip_config.geocache_file_path = geocache_file;
     }
+    if arg_conf.centrality_cache.is_some() {
+        configuration.centrality_cache_file_path = arg_conf.centrality_cache;
+    }
     if arg_conf.ips_file.is_some() {
         configuration.ips_config.peer_file_path = arg_conf.ips_file;
     }
+    if arg_conf.offline {
+        configuration.geoip_config.offline = true;
+    }
+
+    // Promotion only publishes peer lists of an earlier canary run - nothing is computed
+    if let Some(full_peer_file) = arg_conf.promote {
+        let peer_file_path = match configuration.ips_config.peer_file_path.as_ref() {
+            Some(path) => path,
+            None => {
+                eprintln!("Could not promote canary rollout: ips_config.peer_file_path is not set");
+                process::exit(1);
+            }
+        };
+        let format = &configuration.ips_config.peer_output_format;
+        if stdio::is_stdio(peer_file_path) {
+            stdio::reserve_stdout();
+        }
+        if let Err(e) = promote(
+            &full_peer_file,
+            peer_file_path,
+            format,
+            configuration.pretty_json,
+            configuration.reproducible,
+        ) {
+            eprintln!("Could not promote canary rollout: {e}");
+            process::exit(1);
+        }
+        writeln!(
+            stdio::messages(),
+            "Promoted {} to {}",
+            full_peer_file.display(),
+            peer_file_path.display()
+        )
+        .unwrap();
+        return;
+    }
+    if arg_conf.debug_dump_dir.is_some() {
+        configuration.ips_config.debug_dump_dir = arg_conf.debug_dump_dir;
+    }
     if let Some(num_threads) = arg_conf.num_threads {
         configuration.num_threads = num_threads;
     }
+    if configuration.num_threads == 0 {
+        configuration.num_threads = default_num_threads();
+    }
+    if arg_conf.seed.is_some() {
+        configuration.seed = arg_conf.seed;
+    }
+    if arg_conf.reproducible {
+        configuration.reproducible = true;
+    }
+    if arg_conf.ips_dry_run {
+        configuration.ips_dry_run = true;
+    }
 
     // Check if user error setting optional filter type
-    if arg_conf.filter_type.is_some() && arg_conf.filter_type.unwrap() == NetworkType::Invalid {
-        panic!("Invalid network type for filter. Check Readme for possible values.")
+    if arg_conf.filter_type == Some(NetworkType::Invalid) {
+        eprintln!("Invalid network type for filter. Check Readme for possible values.");
+        process::exit(1);
     }
 
     configuration.network_type_filter = arg_conf.filter_type;
 
-    if !configuration.input_file_path.as_ref().unwrap().is_file() {
+    if !arg_conf.ips_network.is_empty() {
+        configuration.ips_config.networks = arg_conf.ips_network;
+    }
+    if !arg_conf.previous_state.is_empty() {
+        configuration.previous_state_file_paths = arg_conf.previous_state;
+    }
+
+    // Printed before the validation, so invalid configurations can be inspected as well
+    if let Some(format) = arg_conf.print_config {
+        println!(
+            "{}",
+            configuration.redacted().render(format).unwrap().trim_end()
+        );
+        return;
+    }
+
+    // All problems are reported at once, before anything is computed
+    if let Err(problems) = configuration.validate() {
+        eprintln!("Invalid configuration:");
+        for problem in problems {
+            eprintln!("  {problem}");
+        }
+        process::exit(1);
+    }
+    // Messages must not get mixed into an output written to stdout
+    let timeseries_to_stdout = matches!(
+        &arg_conf.command,
+        Some(Command::Timeseries { output, .. })
+            | Some(Command::Ips {
+                command: IpsCommand::Sweep { output }
+            })
+            | Some(Command::Geocache {
+                command: GeocacheCommand::Export { output }
+            }) if stdio::is_stdio(output)
+    );
+    // Results of the analyses run after the state can go to stdout as well, if nothing else does
+    let analyses_to_stdout = [&arg_conf.simulate, &arg_conf.null_model]
+        .into_iter()
+        .filter(|path| matches!(path, Some(path) if stdio::is_stdio(path)))
+        .count();
+    if analyses_to_stdout + usize::from(configuration.writes_to_stdout()) > 1 {
+        eprintln!("Only one output can be written to stdout (`-`)");
+        process::exit(1);
+    }
+    if configuration.writes_to_stdout() || timeseries_to_stdout || analyses_to_stdout > 0 {
+        stdio::reserve_stdout();
+    }
+
+    // Without a seed each run makes different choices - the seed used is recorded in all outputs
+    // so the run can be repeated
+    let seed = configuration.seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64)
+    });
+    configuration.seed = Some(seed);
+    writeln!(stdio::messages(), "Using seed {seed}").unwrap();
+
+    if let Some(Command::Timeseries { dir, output }) = &arg_conf.command {
+        if let Err(e) = write_timeseries(&configuration, dir, output).await {
+            eprintln!("Could not write the time series: {e}");
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(Command::Geocache { command }) = &arg_conf.command {
+        if let Err(e) = run_geocache_command(&configuration, command).await {
+            let action = match command {
+                GeocacheCommand::Export { .. } => "export",
+                GeocacheCommand::Import { .. } | GeocacheCommand::Merge { .. } => "update",
+            };
+            eprintln!("Could not {action} the GeoIP cache: {e}");
+            process::exit(1);
+        }
+        return;
+    }
+
+    // The input can be fetched from the crawler or written by it later, so it's not checked
+    if let Some(Command::Daemon {
+        interval,
+        output_dir,
+        #[cfg(feature = "http")]
+        crawler_url,
+        keep_runs,
+        max_age,
+    }) = &arg_conf.command
+    {
+        let retention = Retention {
+            keep_runs: *keep_runs,
+            max_age: *max_age,
+        };
+        let result = run_daemon(
+            &configuration,
+            output_dir,
+            *interval,
+            #[cfg(feature = "http")]
+            crawler_url.as_ref(),
+            &retention,
+        );
+        if let Err(e) = result.await {
+            eprintln!("Could not run the daemon: {e}");
+            process::exit(1);
+        }
+        return;
+    }
+
+    let input_file_path = configuration.input_file_path.as_ref().unwrap();
+    if !stdio::is_stdio(input_file_path) && !input_file_path.is_file() {
         eprintln!(
             "{}: No such file or directory",
             configuration
@@ -138,7 +970,191 @@ async fn main() {
         );
         return;
     }
-    write_state(&configuration).await;
+
+    if let Some(Command::Ips {
+        command: IpsCommand::Sweep { output },
+    }) = &arg_conf.command
+    {
+        if configuration.sweep.grid.is_empty() {
+            eprintln!("Nothing to sweep - sweep.grid of the configuration is empty");
+            process::exit(1);
+        }
+        if let Err(e) = write_sweep(&configuration, output).await {
+            eprintln!("Could not run the weight sweep: {e}");
+            process::exit(1);
+        }
+        return;
+    }
+
+    #[cfg(feature = "http")]
+    if let Some(Command::Serve {
+        listen,
+        interval_minutes,
+        allow_stale,
+    }) = arg_conf.command
+    {
+        let interval = interval_minutes.map(|minutes| Duration::from_secs(minutes * 60));
+        if let Err(e) = serve_http(&configuration, listen, interval, allow_stale).await {
+            eprintln!("Could not serve results over HTTP: {e}");
+            process::exit(1);
+        }
+        return;
+    }
+
+    let (state, peer_lists, failed_networks) = match write_state(&configuration).await {
+        Ok(outputs) => outputs,
+        Err(e) => {
+            eprintln!("Could not generate the state: {e}");
+            process::exit(1);
+        }
+    };
+
+    let tolerance = arg_conf.check_tolerance.unwrap_or(DEFAULT_CHECK_TOLERANCE);
+    if let Some(golden_path) = arg_conf.check_against {
+        let expected = match stdio::reader(&golden_path).and_then(read_state) {
+            Ok(expected) => expected,
+            Err(e) => {
+                eprintln!("Could not load state {}: {e}", golden_path.display());
+                process::exit(1);
+            }
+        };
+        let drifts = compare_states(&expected, &state, tolerance);
+        if !drifts.is_empty() {
+            eprintln!(
+                "State differs from {} (tolerance {tolerance}):",
+                golden_path.display()
+            );
+            for drift in drifts {
+                eprintln!("  {drift}");
+            }
+            process::exit(1);
+        }
+        writeln!(stdio::messages(), "State matches {}", golden_path.display()).unwrap();
+    }
+
+    if let Some(golden_path) = arg_conf.check_peers {
+        // Each network type is checked against its own golden file, named like its peer file
+        let networks = configuration.ips_networks();
+        let format = &configuration.ips_config.peer_output_format;
+        for network in networks.iter().map(|network| network_name(*network)) {
+            let golden_path = if networks.len() > 1 {
+                with_network_suffix(&golden_path, &network)
+            } else {
+                golden_path.clone()
+            };
+            let expected = match fs::read_to_string(&golden_path)
+                .and_then(|content| parse_peers(&content, format))
+            {
+                Ok(expected) => expected,
+                Err(e) => {
+                    eprintln!("Could not load peer list {}: {e}", golden_path.display());
+                    process::exit(1);
+                }
+            };
+            let actual = match peer_lists.iter().find(|(name, _)| *name == network) {
+                Some((_, peerlist)) => peerlist.peers.as_slice(),
+                None => {
+                    eprintln!(
+                        "No peer list was generated for {network} to compare with --check-peers"
+                    );
+                    process::exit(1);
+                }
+            };
+            let drifts = compare_peer_lists(&expected, actual, tolerance);
+            if !drifts.is_empty() {
+                eprintln!(
+                    "Peer lists of {network} differ from {} (tolerance {tolerance}):",
+                    golden_path.display()
+                );
+                for drift in drifts {
+                    eprintln!("  {drift}");
+                }
+                process::exit(1);
+            }
+            writeln!(
+                stdio::messages(),
+                "Peer lists of {network} match {}",
+                golden_path.display()
+            )
+            .unwrap();
+        }
+    }
+
+    if let Some(path) = arg_conf.simulate {
+        let curves = simulate_attacks(
+            &state.nodes,
+            &configuration.simulation,
+            seed,
+            configuration.num_threads,
+        );
+        if let Err(e) = stdio::write(&path, to_json(&curves, configuration.pretty_json)) {
+            eprintln!("Could not write {}: {e}", path.display());
+            process::exit(1);
+        }
+        writeln!(
+            stdio::messages(),
+            "Robustness curves written to {}",
+            path.display()
+        )
+        .unwrap();
+    }
+
+    if let Some(path) = arg_conf.null_model {
+        let comparisons = compare_with_null_models(
+            &state.nodes,
+            &configuration.null_model,
+            seed,
+            configuration.num_threads,
+        );
+        for comparison in comparisons.iter() {
+            for metric in comparison.metrics.iter() {
+                writeln!(
+                    stdio::messages(),
+                    "{:?} {}: {} (random {} ± {}, z-score {})",
+                    comparison.model,
+                    metric.metric,
+                    metric.value,
+                    metric.mean,
+                    metric.std_dev,
+                    metric
+                        .z_score
+                        .map_or_else(|| "-".to_owned(), |z| format!("{z:.2}"))
+                )
+                .unwrap();
+            }
+        }
+        if let Err(e) = stdio::write(&path, to_json(&comparisons, configuration.pretty_json)) {
+            eprintln!("Could not write {}: {e}", path.display());
+            process::exit(1);
+        }
+        writeln!(
+            stdio::messages(),
+            "Null model comparison written to {}",
+            path.display()
+        )
+        .unwrap();
+    }
+
+    #[cfg(feature = "grpc")]
+    if let Some(addr) = arg_conf.grpc_addr {
+        writeln!(stdio::messages(), "Serving gRPC interface on {addr}").unwrap();
+        let geo_cache = match open_geo_cache(&configuration).await {
+            Ok(geo_cache) => geo_cache,
+            Err(e) => {
+                eprintln!("Could not open the GeoIP cache: {e}");
+                process::exit(1);
+            }
+        };
+        if let Err(e) = ziggurat_crunchy::grpc::serve(addr, configuration, geo_cache, state).await {
+            eprintln!("gRPC server failed: {e}");
+        }
+    }
+
+    // All outputs are written, but pipelines must notice the peer lists are missing
+    if !failed_networks.is_empty() {
+        eprintln!("IPS failed for {}", failed_networks.join(", "));
+        process::exit(1);
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -150,121 +1166,178 @@ pub struct ArgConfiguration {
     /// Output file with state of the graph (overrides output from config file)
     #[clap(short, long, value_parser)]
     pub out_state: Option<PathBuf>,
+    /// Output file with run report (overrides report from config file)
+    #[clap(short, long, value_parser)]
+    pub report_file: Option<PathBuf>,
     /// Output file with geolocation cache (overrides cache from config file)
     #[clap(short, long, value_parser)]
     pub geocache_file: Option<PathBuf>,
+    /// Cache of centrality results reused for an unchanged graph (overrides cache from config
+    /// file)
+    #[clap(long, value_parser)]
+    pub centrality_cache: Option<PathBuf>,
     /// Configuration file path (if none defaults will be assumed)
     #[clap(short, long, value_parser)]
     pub config_file: Option<PathBuf>,
+    /// Format of the configuration file: toml, json or yaml (detected by the extension if not set)
+    #[clap(long, value_parser)]
+    pub config_format: Option<ConfigFormat>,
+    /// Print the configuration in effect (after all overrides) as toml, json or yaml and exit
+    #[clap(long, value_parser, num_args = 0..=1, default_missing_value = "toml")]
+    pub print_config: Option<ConfigFormat>,
     /// Intelligent Peer Sharing output file path (overrides ips from config file)
     #[clap(short = 'p', long, value_parser)]
     pub ips_file: Option<PathBuf>,
     /// Number of threads to use for calculations (overrides number of threads from config file)
-    #[clap(short = 'j', long, value_parser)]
+    #[clap(short = 'j', long, visible_alias = "threads", value_parser)]
     pub num_threads: Option<usize>,
+    /// Use only the local GeoIP database and the cache, without any online lookups
+    #[clap(long)]
+    pub offline: bool,
+    /// Directory where intermediate IPS states are written for debugging and research
+    #[clap(long, value_parser)]
+    pub debug_dump_dir: Option<PathBuf>,
+    /// Seed of all pseudo-random choices, recorded in all outputs (random if not set)
+    #[clap(long, value_parser)]
+    pub seed: Option<u64>,
+    /// Run IPS and print its impact and the proposed changes, without writing the peer list
+    #[clap(long)]
+    pub ips_dry_run: bool,
+    /// Predefined MCDA weights: security, performance, geo-diverse or balanced (weights set in
+    /// the config file take precedence)
+    #[clap(long, value_parser)]
+    pub ips_profile: Option<WeightProfile>,
+    /// Make the state and the peer list byte-identical for the same input and seed
+    #[clap(long)]
+    pub reproducible: bool,
+    /// Compare the generated state with this golden state file and exit with an error on drift
+    #[clap(long, value_parser)]
+    pub check_against: Option<PathBuf>,
+    /// Compare the generated peer lists with this golden peer list file (suffixed with the network
+    /// name for more IPS networks) and exit with an error on drift
+    #[clap(long, value_parser)]
+    pub check_peers: Option<PathBuf>,
+    /// Relative tolerance of values compared with --check-against and --check-peers (default 0.01)
+    #[clap(long, value_parser)]
+    pub check_tolerance: Option<f64>,
+    /// Simulate attacks on the network and write its robustness curves to this file
+    #[clap(long, value_parser)]
+    pub simulate: Option<PathBuf>,
+    /// Compare the network with random graphs of the same size and write the z-scores of its
+    /// measures to this file
+    #[clap(long, value_parser)]
+    pub null_model: Option<PathBuf>,
+    /// Publish peer lists for all nodes kept by a canary run as the peer list file and exit
+    #[clap(long, value_parser)]
+    pub promote: Option<PathBuf>,
+    /// Keep serving the latest state (and processing new summaries) over gRPC on this address
+    #[cfg(feature = "grpc")]
+    #[clap(long, value_parser)]
+    pub grpc_addr: Option<SocketAddr>,
     /// Optional node filtering parameter; consult Readme for possible values
     #[clap(short, long, value_parser)]
     pub filter_type: Option<NetworkType>,
+    /// Network type peer lists are generated for (can be repeated, defaults to the filter type)
+    #[clap(long, value_parser)]
+    pub ips_network: Vec<NetworkType>,
+    /// Earlier state file used for node uptime, stability and betweenness trends (can be
+    /// repeated, oldest first)
+    #[clap(long, value_parser)]
+    pub previous_state: Vec<PathBuf>,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
 }
 
-#[cfg(test)]
-mod tests {
-
-    use std::{net::SocketAddr, thread};
-
-    use super::*;
-    use crate::config::GeoIPConfiguration;
-
-    #[tokio::test]
-    async fn create_nodes_unfiltered_test() {
-        let response = load_response("testdata/sample.json");
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Process every sample in the directory in the order of crawling and write the trends
+    /// (node count, average degree, diameter and nodes per country) over time
+    Timeseries {
+        /// Directory with the samples (in the input format of the configuration)
+        #[clap(value_parser)]
+        dir: PathBuf,
+        /// Output file with the trends (stdout if not set)
+        #[clap(short, long, value_parser, default_value = stdio::STDIO_PATH)]
+        output: PathBuf,
+    },
+    /// Run the pipeline (once, or again every interval) and serve results of the latest run over
+    /// HTTP - `/state`, `/peers`, `/histograms`, `/search?q=` and `/healthz`
+    #[cfg(feature = "http")]
+    Serve {
+        /// Address to listen on
+        #[clap(long, value_parser, default_value = "127.0.0.1:8080")]
+        listen: SocketAddr,
+        /// Run the pipeline again every this many minutes (only once if not set)
+        #[clap(long, value_parser = clap::value_parser!(u64).range(1..=u64::MAX / 60))]
+        interval_minutes: Option<u64>,
+        /// Serve peer lists even after they expired
+        #[clap(long)]
+        allow_stale: bool,
+    },
+    /// Run the pipeline every interval, writing outputs of each run into a directory named by
+    /// the time it started (UTC), and remove runs not kept by the retention policy
+    Daemon {
+        /// Time between the starts of runs - a number with a unit: s, m, h or d (e.g. `1h`)
+        #[clap(long, value_parser = parse_interval)]
+        interval: Duration,
+        /// Directory the run directories are created in
+        #[clap(long, value_parser, default_value = "runs")]
+        output_dir: PathBuf,
+        /// JSON-RPC endpoint of the crawler the summary is fetched from for each run (the input
+        /// file is read again if not set)
+        #[cfg(feature = "http")]
+        #[clap(long, value_parser)]
+        crawler_url: Option<hyper::Uri>,
+        /// Number of the latest runs kept
+        #[clap(long, value_parser)]
+        keep_runs: Option<usize>,
+        /// Remove runs started longer ago than this (e.g. `7d`)
+        #[clap(long, value_parser = parse_interval)]
+        max_age: Option<Duration>,
+    },
+    /// Intelligent Peer Sharing analyses
+    Ips {
+        #[clap(subcommand)]
+        command: IpsCommand,
+    },
+    /// Inspect the GeoIP cache and combine it with caches built on other machines
+    Geocache {
+        #[clap(subcommand)]
+        command: GeocacheCommand,
+    },
+}
 
-        let config = GeoIPConfiguration::default();
-        let mut geo_cache = GeoIPCache::new(&config);
-        geo_cache.configure_providers(&config);
+#[derive(Subcommand, Debug)]
+pub enum IpsCommand {
+    /// Run IPS for every combination of the MCDA weights in `sweep.grid` of the configuration
+    /// (on a sampled subgraph) and write statistics of each resulting network as CSV
+    Sweep {
+        /// Output CSV file (stdout if not set)
+        #[clap(short, long, value_parser, default_value = stdio::STDIO_PATH)]
+        output: PathBuf,
+    },
+}
 
-        let num_threads = thread::available_parallelism().unwrap().get();
-        let nodes = create_nodes(
-            None,
-            &response.result.nodes_indices,
-            &response.result.node_addrs,
-            &response.result.node_network_types,
-            &geo_cache,
-            num_threads,
-        )
-        .await;
-
-        assert_eq!(nodes.len(), 6103);
-        assert_eq!(nodes[0].connections.len(), 2478);
-        assert_eq!(nodes[1].connections.len(), 2216);
-        assert_eq!(nodes[2].connections.len(), 1);
-        assert_eq!(nodes[3].connections.len(), 2184);
-        assert_eq!(nodes[3].connections[2], 609);
-    }
-
-    #[tokio::test]
-    async fn create_nodes_filtered_test1() {
-        let indices = vec![vec![1, 2], vec![0, 2, 3], vec![0, 1, 3], vec![1, 2]];
-        let node_addrs = vec![
-            SocketAddr::from(([127, 0, 0, 1], 1234)),
-            SocketAddr::from(([127, 0, 0, 2], 1234)),
-            SocketAddr::from(([127, 0, 0, 3], 1234)),
-            SocketAddr::from(([127, 0, 0, 4], 1234)),
-        ];
-        let node_network_types = vec![
-            NetworkType::Unknown,
-            NetworkType::Zcash,
-            NetworkType::Unknown,
-            NetworkType::Zcash,
-        ];
-        let config = GeoIPConfiguration::default();
-        let mut geo_cache = GeoIPCache::new(&config);
-        geo_cache.configure_providers(&config);
-
-        let num_threads = thread::available_parallelism().unwrap().get();
-        let nodes = create_nodes(
-            Some(NetworkType::Zcash),
-            &indices,
-            &node_addrs,
-            &node_network_types,
-            &geo_cache,
-            num_threads,
-        )
-        .await;
-        assert_eq!(nodes.len(), 2);
-        assert_eq!(nodes[0].connections, vec![1]);
-        assert_eq!(nodes[1].connections, vec![0]);
-    }
-
-    #[tokio::test]
-    async fn create_nodes_filtered_test2() {
-        let response = load_response("testdata/sample.json");
-
-        let config = GeoIPConfiguration::default();
-        let mut geo_cache = GeoIPCache::new(&config);
-        geo_cache.configure_providers(&config);
-
-        let num_threads = thread::available_parallelism().unwrap().get();
-        let nodes = create_nodes(
-            Some(NetworkType::Zcash),
-            &response.result.nodes_indices,
-            &response.result.node_addrs,
-            &response.result.node_network_types,
-            &geo_cache,
-            num_threads,
-        )
-        .await;
-        assert_eq!(nodes.len(), 122);
-        assert_eq!(nodes[0].connections.len(), 2);
-        assert_eq!(nodes[1].connections.len(), 0);
-        assert_eq!(nodes[2].connections.len(), 1);
-        assert_eq!(nodes[3].connections.len(), 1);
-        assert_eq!(nodes[3].connections[0], 56);
-
-        let node = nodes[0].clone();
-        assert_eq!(node.addr.to_string(), "3.72.134.66:8233");
-        let epsilon: f64 = 0.0000001;
-        assert!((node.betweenness - 47.525898078529664).abs() < epsilon);
-        assert!((node.closeness - 1.603305785123967).abs() < epsilon);
-    }
+#[derive(Subcommand, Debug)]
+pub enum GeocacheCommand {
+    /// Write entries of the GeoIP cache as CSV
+    Export {
+        /// Output CSV file (stdout if not set)
+        #[clap(short, long, value_parser, default_value = stdio::STDIO_PATH)]
+        output: PathBuf,
+    },
+    /// Add entries from a CSV file (as written by `geocache export`) to the GeoIP cache, keeping
+    /// the newest entry of each IP
+    Import {
+        /// CSV file with the entries (stdin for `-`)
+        #[clap(value_parser)]
+        file: PathBuf,
+    },
+    /// Add entries of other GeoIP caches (JSON files) to the GeoIP cache, keeping the newest
+    /// entry of each IP
+    Merge {
+        /// Cache files to merge, in any order
+        #[clap(value_parser, required = true)]
+        caches: Vec<PathBuf>,
+    },
 }
@@ -1,13 +1,20 @@
-use std::net::SocketAddr;
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+};
 
 use serde::{Deserialize, Serialize};
-use spectre::{edge::Edge, graph::Graph};
 use ziggurat_core_crawler::summary::{NetworkType, NodesIndices};
 use ziggurat_core_geoip::geoip::GeoInfo;
 
-use crate::{geoip_cache::GeoIPCache, histogram::Histogram};
-
-const HISTOGRAM_COUNTS: usize = 256;
+use crate::{
+    centrality::value_or_fallback,
+    centrality_cache::CentralityCache,
+    config::{CentralityOutput, ClosenessVariant, HistogramConfiguration},
+    csr::{Adjacency, CsrGraph},
+    geoip_cache::GeoIPCache,
+    histogram::{Histogram, HistogramScale},
+};
 
 #[derive(Default, Clone, Serialize, Deserialize)]
 pub struct HistogramSummary {
@@ -17,6 +24,18 @@ pub struct HistogramSummary {
     pub counts: Vec<usize>,
     /// Maximum count for a single slot
     pub max_count: usize,
+    /// Median of the values (weighted for weighted histograms)
+    #[serde(default)]
+    pub median: f64,
+    /// Lower bound of the first slot
+    #[serde(default)]
+    pub min: f64,
+    /// Upper bound of the last slot
+    #[serde(default)]
+    pub max: f64,
+    /// How values are mapped to slots
+    #[serde(default)]
+    pub scale: HistogramScale,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -31,8 +50,55 @@ pub struct Node {
     pub closeness: f64,
     /// indices of all connected nodes
     pub connections: Vec<usize>,
+    /// number of distinct peers - connections without duplicates and the node itself
+    #[serde(default)]
+    pub degree: usize,
     /// used for latitude, longitude, city, country
     pub geolocation: Option<GeoInfo>,
+    /// the computed eccentricity (only for nodes in the largest connected component)
+    #[serde(default)]
+    pub eccentricity: Option<u32>,
+    /// indicates if the node changed its location since the previous lookup
+    #[serde(default)]
+    pub location_changed: bool,
+    /// autonomous system announcing the node's address (if known)
+    #[serde(default)]
+    pub asn: Option<u32>,
+    /// fraction of recent runs in which the node was seen (if earlier runs are known)
+    #[serde(default)]
+    pub uptime: Option<f64>,
+    /// fraction of all the earlier snapshots given in which the node was seen (if any are given)
+    #[serde(default)]
+    pub stability: Option<f64>,
+    /// the computed eigenvector centrality
+    #[serde(default)]
+    pub eigenvector: f64,
+    /// protocol version announced by the node (if the input has it)
+    #[serde(default)]
+    pub protocol_version: Option<u32>,
+    /// user agent announced by the node (if the input has it)
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    /// geographic cluster of the node (none if it's not in any or has no coordinates)
+    #[serde(default)]
+    pub geo_cluster_id: Option<usize>,
+    /// betweenness divided by its maximum for the number of nodes (only if both raw and
+    /// normalized values are written)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub betweenness_normalized: Option<f64>,
+    /// closeness divided by its maximum for the number of nodes (only if both raw and
+    /// normalized values are written)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub closeness_normalized: Option<f64>,
+    /// indicates if the address belongs to a hosting provider (data center)
+    #[serde(default)]
+    pub is_hosting: bool,
+    /// indicates if the address is a Tor exit
+    #[serde(default)]
+    pub is_tor: bool,
+    /// indicates if the address is a VPN exit
+    #[serde(default)]
+    pub is_vpn: bool,
 }
 
 // Implemented it just to make it easier to create a default node for testing
@@ -44,7 +110,22 @@ impl Default for Node {
             betweenness: 0.0,
             closeness: 0.0,
             connections: Vec::new(),
+            degree: 0,
             geolocation: None,
+            eccentricity: None,
+            location_changed: false,
+            asn: None,
+            uptime: None,
+            stability: None,
+            eigenvector: 0.0,
+            protocol_version: None,
+            user_agent: None,
+            geo_cluster_id: None,
+            betweenness_normalized: None,
+            closeness_normalized: None,
+            is_hosting: false,
+            is_tor: false,
+            is_vpn: false,
         }
     }
 }
@@ -54,45 +135,60 @@ pub async fn create_nodes_unfiltered(
     node_addrs: &[SocketAddr],
     node_network_types: &[NetworkType],
     geo_cache: &GeoIPCache,
+    centrality_cache: &CentralityCache,
+    closeness: ClosenessVariant,
     num_threads: usize,
 ) -> Vec<Node> {
-    let mut graph = Graph::new();
-    for (n, node) in indices.iter().enumerate() {
-        node.iter()
-            .filter(|&connection| *connection > n)
-            .for_each(|connection| {
-                graph.insert(Edge::new(n, *connection));
-            });
-    }
+    let graph = CsrGraph::from_adjacency(indices.iter().map(Vec::as_slice));
+    let edges = graph.edges().collect::<Vec<_>>();
 
-    let betweenness = graph.betweenness_centrality(num_threads, false);
-    let closeness = graph.closeness_centrality(num_threads);
+    let centralities = centrality_cache.centralities(&edges, num_threads, closeness);
+    let ips = node_addrs[..indices.len()]
+        .iter()
+        .map(|addr| addr.ip())
+        .collect::<Vec<_>>();
+    let geolocations = geo_cache.lookup_all(&ips).await;
     let mut nodes = Vec::with_capacity(indices.len());
 
     for i in 0..indices.len() {
+        let classes = geo_cache.classify(node_addrs[i].ip());
         let node: Node = Node {
             addr: node_addrs[i],
             network_type: node_network_types[i],
-            betweenness: *betweenness
-                .get(&i)
-                .expect("could not find betweenness value for index}"),
-            closeness: *closeness
-                .get(&i)
-                .expect("could not find closeness value for index"),
-            connections: indices[i].clone(),
-            geolocation: geo_cache.lookup(node_addrs[i].ip()).await,
+            betweenness: value_or_fallback(&centralities.betweenness, &i),
+            closeness: value_or_fallback(&centralities.closeness, &i),
+            connections: graph.neighbors(i).to_vec(),
+            degree: 0,
+            geolocation: geolocations.get(&node_addrs[i].ip()).cloned(),
+            eccentricity: None,
+            location_changed: geo_cache.location_changed(node_addrs[i].ip()),
+            asn: geo_cache.asn(node_addrs[i].ip()),
+            uptime: None,
+            stability: None,
+            eigenvector: value_or_fallback(&centralities.eigenvector, &i),
+            protocol_version: None,
+            user_agent: None,
+            geo_cluster_id: None,
+            betweenness_normalized: None,
+            closeness_normalized: None,
+            is_hosting: classes.hosting,
+            is_tor: classes.tor,
+            is_vpn: classes.vpn,
         };
         nodes.push(node);
     }
     nodes
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn create_nodes_filtered(
     network_type_filter: NetworkType,
     indices: &NodesIndices,
     node_addrs: &[SocketAddr],
     node_network_types: &[NetworkType],
     geo_cache: &GeoIPCache,
+    centrality_cache: &CentralityCache,
+    closeness: ClosenessVariant,
     num_threads: usize,
 ) -> Vec<Node> {
     let num_nodes = indices.len();
@@ -109,15 +205,11 @@ pub async fn create_nodes_filtered(
         }
     }
 
-    // index is the size of our new node indices object,
-    // i.e., the new number of nodes.  Initialize it.
-    let mut new_indices: NodesIndices = vec![Vec::<usize>::new(); index as usize];
-
-    // Create new NodesIndices object using
+    // Collect edges of the new graph using
     //   a) original indices
     //   b) the index map
     // We only keep connections where both nodes are in the index map
-    let mut graph = Graph::new();
+    let mut edges = Vec::new();
     for (n, node) in indices.iter().enumerate() {
         let n_index: i32 = index_map[n];
         if n_index != -1 {
@@ -128,40 +220,56 @@ pub async fn create_nodes_filtered(
                     index_map[*connection] != -1 && index_map[*connection] > n_index
                 })
                 .for_each(|connection| {
-                    graph.insert(Edge::new(n_index as usize, index_map[*connection] as usize));
-                    new_indices[n_index as usize].push(index_map[*connection] as usize);
-                    new_indices[index_map[*connection] as usize].push(n_index as usize);
+                    edges.push((n_index as usize, index_map[*connection] as usize));
                 });
         }
     }
+    // index is the new number of nodes
+    let graph = CsrGraph::from_edges(index as usize, &edges);
 
-    // Our newly create node indices struct might have nodes with zero connections
-    // To those nodes: we add a connection to self.
-    for (n, node) in new_indices.iter().enumerate() {
-        if node.is_empty() {
-            graph.insert(Edge::new(n, n));
+    // Our new graph might have nodes with zero connections
+    // To those nodes: we add a connection to self (for centralities only).
+    for n in 0..graph.len() {
+        if graph.neighbors(n).is_empty() {
+            edges.push((n, n));
         }
     }
 
-    let betweenness = graph.betweenness_centrality(num_threads, false);
-    let closeness = graph.closeness_centrality(num_threads);
+    let centralities = centrality_cache.centralities(&edges, num_threads, closeness);
+    let ips = (0..indices.len())
+        .filter(|i| index_map[*i] != -1)
+        .map(|i| node_addrs[i].ip())
+        .collect::<Vec<_>>();
+    let geolocations = geo_cache.lookup_all(&ips).await;
     let mut nodes = Vec::with_capacity(indices.len());
 
     // here we use the original indexing, because of the node addrs array
     for i in 0..indices.len() {
         let index = index_map[i];
         if index != -1 {
+            let classes = geo_cache.classify(node_addrs[i].ip());
             let node: Node = Node {
                 addr: node_addrs[i],
                 network_type: node_network_types[i],
-                betweenness: *betweenness
-                    .get(&(index as usize))
-                    .expect("could not find betweenness value for index}"),
-                closeness: *closeness
-                    .get(&(index as usize))
-                    .expect("could not find closeness value for index"),
-                connections: new_indices[index as usize].clone(),
-                geolocation: geo_cache.lookup(node_addrs[i].ip()).await,
+                betweenness: value_or_fallback(&centralities.betweenness, &(index as usize)),
+                closeness: value_or_fallback(&centralities.closeness, &(index as usize)),
+                connections: graph.neighbors(index as usize).to_vec(),
+                degree: 0,
+                geolocation: geolocations.get(&node_addrs[i].ip()).cloned(),
+                eccentricity: None,
+                location_changed: geo_cache.location_changed(node_addrs[i].ip()),
+                asn: geo_cache.asn(node_addrs[i].ip()),
+                uptime: None,
+                stability: None,
+                eigenvector: value_or_fallback(&centralities.eigenvector, &(index as usize)),
+                protocol_version: None,
+                user_agent: None,
+                geo_cluster_id: None,
+                betweenness_normalized: None,
+                closeness_normalized: None,
+                is_hosting: classes.hosting,
+                is_tor: classes.tor,
+                is_vpn: classes.vpn,
             };
             nodes.push(node);
         }
@@ -169,15 +277,18 @@ pub async fn create_nodes_filtered(
     nodes
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn create_nodes(
     filter_type: Option<NetworkType>,
     indices: &NodesIndices,
     node_addrs: &[SocketAddr],
     node_network_types: &[NetworkType],
     geo_cache: &GeoIPCache,
+    centrality_cache: &CentralityCache,
+    closeness: ClosenessVariant,
     num_threads: usize,
 ) -> Vec<Node> {
-    match filter_type {
+    let mut nodes = match filter_type {
         Some(network_type) => {
             create_nodes_filtered(
                 network_type,
@@ -185,6 +296,8 @@ pub async fn create_nodes(
                 node_addrs,
                 node_network_types,
                 geo_cache,
+                centrality_cache,
+                closeness,
                 num_threads,
             )
             .await
@@ -195,56 +308,184 @@ pub async fn create_nodes(
                 node_addrs,
                 node_network_types,
                 geo_cache,
+                centrality_cache,
+                closeness,
                 num_threads,
             )
             .await
         }
+    };
+    update_degrees(&mut nodes);
+    nodes
+}
+
+/// Problems found in connection lists of the input, each counted as the number of dropped
+/// connections
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionSanitization {
+    /// Connections to indices of missing nodes
+    pub out_of_range: usize,
+    /// Connections of nodes to themselves
+    pub self_connections: usize,
+    /// Repeated connections to the same peer
+    pub duplicates: usize,
+}
+
+/// Clean connection lists of the input - connections to missing nodes, to the node itself and
+/// repeated ones (all but the first) are dropped.
+pub fn sanitize_connections(indices: &mut NodesIndices) -> ConnectionSanitization {
+    let len = indices.len();
+    let mut sanitization = ConnectionSanitization::default();
+    let mut seen = HashSet::new();
+    for (idx, connections) in indices.iter_mut().enumerate() {
+        seen.clear();
+        connections.retain(|peer| {
+            if *peer >= len {
+                sanitization.out_of_range += 1;
+            } else if *peer == idx {
+                sanitization.self_connections += 1;
+            } else if !seen.insert(*peer) {
+                sanitization.duplicates += 1;
+            } else {
+                return true;
+            }
+            false
+        });
     }
+    sanitization
 }
 
-pub async fn create_histograms(nodes: &[Node]) -> Vec<HistogramSummary> {
-    // Betweenness
-    let mut histogram_b = Histogram {
-        ..Histogram::default()
-    };
+/// Set degree of each node from its connections. Connection lists can contain duplicates and the
+/// node itself, so their length isn't the degree.
+pub fn update_degrees(nodes: &mut [Node]) {
+    for (idx, node) in nodes.iter_mut().enumerate() {
+        node.degree = degree_of(idx, &node.connections);
+    }
+}
 
-    // Closeness
-    let mut histogram_c = Histogram {
-        ..Histogram::default()
-    };
+/// Set degree of each node from its connections given by the graph, as `update_degrees` does.
+pub fn update_degrees_from(nodes: &mut [Node], graph: &(impl Adjacency + ?Sized)) {
+    for (idx, node) in nodes.iter_mut().enumerate() {
+        node.degree = degree_of(idx, graph.connections(idx));
+    }
+}
 
-    // Degree
-    let mut histogram_d = Histogram {
-        ..Histogram::default()
-    };
+fn degree_of(idx: usize, connections: &[usize]) -> usize {
+    connections
+        .iter()
+        .filter(|peer| **peer != idx)
+        .collect::<HashSet<&usize>>()
+        .len()
+}
 
+/// Count nodes sharing their IP address with another node (listening on different ports).
+pub fn shared_ip_nodes(nodes: &[Node]) -> usize {
+    let mut per_ip = HashMap::<IpAddr, usize>::new();
     for node in nodes.iter() {
-        histogram_b.add(node.betweenness);
-        histogram_c.add(node.closeness);
-        histogram_d.add(node.connections.len() as f64);
+        *per_ip.entry(node.addr.ip()).or_default() += 1;
     }
+    per_ip.values().filter(|count| **count > 1).sum()
+}
+
+/// Normalize betweenness and closeness of nodes as requested - each one is divided by its
+/// theoretical maximum for the number of nodes: `(n - 1)(n - 2) / 2` for betweenness (the center
+/// of a star), `n / 2` for closeness, the average distance to other nodes (an end of a path), and
+/// `n - 1` for harmonic closeness (a node connected to all others).
+pub fn normalize_centralities(
+    nodes: &mut [Node],
+    output: &CentralityOutput,
+    closeness_variant: ClosenessVariant,
+) {
+    if *output == CentralityOutput::Raw {
+        return;
+    }
+    let n = nodes.len() as f64;
+    let max_betweenness = (n - 1.0) * (n - 2.0) / 2.0;
+    let max_closeness = match closeness_variant {
+        // An end of a path has the distances 1, 2, ..., n - 1 to the other nodes
+        ClosenessVariant::Standard => n / 2.0,
+        ClosenessVariant::Harmonic => n - 1.0,
+    };
+    // Graphs too small for any path through a node (or to another node) have no centrality
+    let normalized = |value: f64, max: f64| if max > 0.0 { value / max } else { 0.0 };
+
+    for node in nodes.iter_mut() {
+        let betweenness = normalized(node.betweenness, max_betweenness);
+        let closeness = normalized(node.closeness, max_closeness);
+        if *output == CentralityOutput::Both {
+            node.betweenness_normalized = Some(betweenness);
+            node.closeness_normalized = Some(closeness);
+        } else {
+            node.betweenness = betweenness;
+            node.closeness = closeness;
+        }
+    }
+}
+
+/// Value of a node shown in a histogram
+type NodeMetric = fn(&Node) -> f64;
 
+/// Create histograms of node centralities. Degree-weighted histograms (labels with `_weighted`
+/// suffix) are added alongside the plain ones if requested.
+pub async fn create_histograms(
+    nodes: &[Node],
+    config: &HistogramConfiguration,
+    weighted: bool,
+) -> Vec<HistogramSummary> {
     let mut histograms = Vec::new();
-    let (counts, max_count) = histogram_b.compute(HISTOGRAM_COUNTS);
-    histograms.push(HistogramSummary {
-        label: "betweenness".to_owned(),
-        counts,
-        max_count,
-    });
-
-    let (counts, max_count) = histogram_c.compute(HISTOGRAM_COUNTS);
-    histograms.push(HistogramSummary {
-        label: "closeness".to_owned(),
-        counts,
-        max_count,
-    });
-
-    let (counts, max_count) = histogram_d.compute(HISTOGRAM_COUNTS);
-    histograms.push(HistogramSummary {
-        label: "degree".to_owned(),
-        counts,
-        max_count,
-    });
+    create_histogram_summaries(nodes, config, "", |_| 1, &mut histograms);
+
+    // Each node counted as many times as it has connections
+    if weighted {
+        create_histogram_summaries(
+            nodes,
+            config,
+            "_weighted",
+            |node| node.degree,
+            &mut histograms,
+        );
+    }
 
     histograms
 }
+
+/// Create betweenness, closeness, degree and eigenvector histograms where each node adds its
+/// weight to the slot count.
+fn create_histogram_summaries(
+    nodes: &[Node],
+    config: &HistogramConfiguration,
+    label_suffix: &str,
+    weight_of: impl Fn(&Node) -> usize,
+    histograms: &mut Vec<HistogramSummary>,
+) {
+    let metrics: [(&str, NodeMetric); 4] = [
+        ("betweenness", |node| node.betweenness),
+        ("closeness", |node| node.closeness),
+        ("degree", |node| node.degree as f64),
+        ("eigenvector", |node| node.eigenvector),
+    ];
+
+    for (metric, value_of) in metrics {
+        let range = config.ranges.get(metric);
+        let mut histogram = Histogram {
+            fixed_min: range.and_then(|range| range.min),
+            fixed_max: range.and_then(|range| range.max),
+            scale: config.scale,
+            ..Histogram::default()
+        };
+        for node in nodes.iter() {
+            histogram.add_weighted(value_of(node), weight_of(node));
+        }
+
+        let (counts, max_count) = histogram.compute(config.bins);
+        histograms.push(HistogramSummary {
+            label: format!("{metric}{label_suffix}"),
+            counts,
+            max_count,
+            median: histogram.median().unwrap_or_default(),
+            min: histogram.min,
+            max: histogram.max,
+            scale: histogram.scale,
+        });
+    }
+}
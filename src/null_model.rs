@@ -0,0 +1,367 @@
+// Comparison of the network with random graphs of the same size (null models). Raw measures
+// such as a transitivity of 0.12 say little on their own - compared with random graphs having
+// the same number of nodes and connections, they show whether the network is more clustered,
+// has longer paths or more unequal degrees than chance would give. Random graphs are generated
+// from the seed, so the comparison can be repeated.
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::NullModelConfiguration,
+    csr::{undirected_edges, Adjacency},
+    ips::graph_utils::{compute_distances, count_triangles},
+    numeric::Rng,
+    Node,
+};
+
+/// Random graph model
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NullModel {
+    /// Erdős–Rényi graph - the same number of connections, placed uniformly at random
+    ErdosRenyi,
+    /// Barabási–Albert graph - each node added connects to the average degree / 2 nodes, chosen
+    /// with probability proportional to their degree (so the number of connections matches
+    /// only approximately)
+    BarabasiAlbert,
+}
+
+/// Measure of the network compared with the same measure of the random graphs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricComparison {
+    pub metric: String,
+    /// Value for the network
+    pub value: f64,
+    /// Mean value for the random graphs
+    pub mean: f64,
+    /// Standard deviation of the values for the random graphs
+    pub std_dev: f64,
+    /// Number of standard deviations the network is away from the mean (None if all random
+    /// graphs have the same value)
+    pub z_score: Option<f64>,
+}
+
+/// Network compared with random graphs of a single model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NullModelComparison {
+    pub model: NullModel,
+    /// Number of random graphs generated
+    pub samples: usize,
+    /// Average number of connections of the random graphs
+    pub connections: f64,
+    pub metrics: Vec<MetricComparison>,
+}
+
+/// Compare the network with random graphs of each model. Centrality measures, which take most of
+/// the processing time, are not compared.
+pub fn compare_with_null_models(
+    nodes: &[Node],
+    config: &NullModelConfiguration,
+    seed: u64,
+    num_threads: usize,
+) -> Vec<NullModelComparison> {
+    let edge_count = connection_count(nodes);
+    let values = network_metrics(nodes, num_threads);
+
+    [NullModel::ErdosRenyi, NullModel::BarabasiAlbert]
+        .into_iter()
+        .map(|model| {
+            // Each model gets its own sequence, so adding samples doesn't change the other model
+            let mut rng = Rng(seed ^ model as u64);
+            let samples = (0..config.samples)
+                .map(|_| {
+                    let graph = match model {
+                        NullModel::ErdosRenyi => erdos_renyi(nodes.len(), edge_count, &mut rng),
+                        NullModel::BarabasiAlbert => {
+                            barabasi_albert(nodes.len(), edge_count, &mut rng)
+                        }
+                    };
+                    let connections = connection_count(&graph) as f64;
+                    (connections, network_metrics(&graph, num_threads))
+                })
+                .collect::<Vec<_>>();
+
+            let metrics = values
+                .iter()
+                .enumerate()
+                .map(|(idx, (metric, value))| {
+                    let (mean, std_dev) =
+                        mean_std_dev(samples.iter().map(|(_, metrics)| metrics[idx].1));
+                    MetricComparison {
+                        metric: metric.to_string(),
+                        value: *value,
+                        mean,
+                        std_dev,
+                        z_score: (std_dev > 0.0).then(|| (value - mean) / std_dev),
+                    }
+                })
+                .collect();
+
+            NullModelComparison {
+                model,
+                samples: samples.len(),
+                connections: mean_std_dev(samples.iter().map(|(connections, _)| *connections)).0,
+                metrics,
+            }
+        })
+        .collect()
+}
+
+/// Number of random graphs the small-world coefficients are computed against
+const SMALL_WORLD_SAMPLES: usize = 3;
+
+/// Seed of the random graphs the small-world coefficients are computed against - fixed, so
+/// statistics of different networks (and runs) are comparable
+const SMALL_WORLD_SEED: u64 = 0x5eed;
+
+/// Small-world coefficients of the network with the given transitivity and average shortest path
+/// length, as `(sigma, omega)`. Sigma is `(C / C_rand) / (L / L_rand)` and omega is
+/// `L_rand / L - C / C_latt`, where `C` is the transitivity, `L` the average shortest path length,
+/// `rand` refers to Erdős–Rényi graphs and `latt` to a ring lattice with the same number of nodes
+/// and connections. Sigma well above 1 and omega close to 0 mean a small-world network, omega
+/// close to -1 a lattice-like one and close to 1 a random-like one. Ratios with a zero
+/// denominator are taken as 0.
+pub(crate) fn small_world_coefficients(
+    graph: &(impl Adjacency + ?Sized),
+    transitivity: f64,
+    average_shortest_path_length: f64,
+    num_threads: usize,
+) -> (f64, f64) {
+    let ratio = |a: f64, b: f64| if b > 0.0 { a / b } else { 0.0 };
+    let connections = connection_count(graph);
+
+    let mut rng = Rng(SMALL_WORLD_SEED);
+    let (mut random_transitivity, mut random_path_length) = (0.0, 0.0);
+    for _ in 0..SMALL_WORLD_SAMPLES {
+        let random = erdos_renyi(graph.node_count(), connections, &mut rng);
+        random_transitivity += count_triangles(&random).1 / SMALL_WORLD_SAMPLES as f64;
+        random_path_length += compute_distances(&random, num_threads).average_shortest_path_length
+            / SMALL_WORLD_SAMPLES as f64;
+    }
+    let (_, lattice_transitivity) = count_triangles(&ring_lattice(graph.node_count(), connections));
+
+    let sigma = ratio(
+        ratio(transitivity, random_transitivity),
+        ratio(average_shortest_path_length, random_path_length),
+    );
+    let omega = ratio(random_path_length, average_shortest_path_length)
+        - ratio(transitivity, lattice_transitivity);
+    (sigma, omega)
+}
+
+/// Measures compared with the random graphs, by their name
+fn network_metrics(nodes: &[Node], num_threads: usize) -> Vec<(&'static str, f64)> {
+    let distances = compute_distances(nodes, num_threads);
+    let (_, transitivity) = count_triangles(nodes);
+    let degrees = nodes
+        .iter()
+        .enumerate()
+        .map(|(idx, node)| {
+            node.connections
+                .iter()
+                .filter(|peer| **peer != idx && **peer < nodes.len())
+                .collect::<HashSet<_>>()
+                .len() as f64
+        })
+        .collect::<Vec<f64>>();
+    let (degree_mean, degree_std_dev) = mean_std_dev(degrees.iter().copied());
+
+    vec![
+        ("transitivity", transitivity),
+        (
+            "average_shortest_path_length",
+            distances.average_shortest_path_length,
+        ),
+        ("diameter", distances.diameter as f64),
+        ("component_size", distances.component_size as f64),
+        ("degree_max", degrees.iter().copied().fold(0.0, f64::max)),
+        // Coefficient of variation, so networks of different density can be compared
+        (
+            "degree_variation",
+            if degree_mean > 0.0 {
+                degree_std_dev / degree_mean
+            } else {
+                0.0
+            },
+        ),
+    ]
+}
+
+/// Mean and (sample) standard deviation of the values, zeros for no values.
+fn mean_std_dev(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    let values = values.collect::<Vec<f64>>();
+    if values.is_empty() {
+        return (0.0, 0.0);
+    }
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    if values.len() < 2 {
+        return (mean, 0.0);
+    }
+    let variance =
+        values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (values.len() - 1) as f64;
+    (mean, variance.sqrt())
+}
+
+/// Number of connections of the nodes, each one once.
+fn connection_count(graph: &(impl Adjacency + ?Sized)) -> usize {
+    undirected_edges(graph).collect::<HashSet<_>>().len()
+}
+
+/// Nodes of the graph with the edges (addresses are left unset).
+fn graph_nodes(count: usize, edges: &HashSet<(usize, usize)>) -> Vec<Node> {
+    let mut nodes = vec![Node::default(); count];
+    for (a, b) in edges.iter() {
+        nodes[*a].connections.push(*b);
+        nodes[*b].connections.push(*a);
+    }
+    // The order of the set is random, connections are kept in the order of indices
+    for node in nodes.iter_mut() {
+        node.connections.sort_unstable();
+    }
+    nodes
+}
+
+/// Erdős–Rényi graph with `count` nodes and `connections` connections (at most all pairs of
+/// nodes).
+fn erdos_renyi(count: usize, connections: usize, rng: &mut Rng) -> Vec<Node> {
+    let connections = connections.min(count * count.saturating_sub(1) / 2);
+    let mut edges = HashSet::with_capacity(connections);
+    while edges.len() < connections {
+        let (a, b) = (rng.below(count), rng.below(count));
+        if a != b {
+            edges.insert((a.min(b), a.max(b)));
+        }
+    }
+    graph_nodes(count, &edges)
+}
+
+/// Ring lattice with `count` nodes, each connected to the closest nodes on both sides, so that
+/// the graph has about `connections` connections.
+fn ring_lattice(count: usize, connections: usize) -> Vec<Node> {
+    let side = ((connections as f64 / count.max(1) as f64).round() as usize).min(count / 2);
+    let edges = (0..count)
+        .flat_map(|node| {
+            (1..=side).map(move |distance| {
+                let peer = (node + distance) % count;
+                (node.min(peer), node.max(peer))
+            })
+        })
+        .filter(|(a, b)| a != b)
+        .collect::<HashSet<_>>();
+    graph_nodes(count, &edges)
+}
+
+/// Barabási–Albert graph with `count` nodes and about `connections` connections. It starts with
+/// a fully connected core of `m + 1` nodes, then each node added connects to `m` distinct nodes,
+/// where `m` is the number of connections per node (at least one).
+fn barabasi_albert(count: usize, connections: usize, rng: &mut Rng) -> Vec<Node> {
+    let m = ((connections as f64 / count.max(1) as f64).round() as usize)
+        .clamp(1, count.saturating_sub(1).max(1));
+    let core = (m + 1).min(count);
+
+    let mut edges = HashSet::new();
+    // Each node is listed once for each of its connections, so picking a random entry picks a
+    // node with probability proportional to its degree
+    let mut ends = Vec::new();
+    for a in 0..core {
+        for b in a + 1..core {
+            edges.insert((a, b));
+            ends.extend([a, b]);
+        }
+    }
+    for node in core..count {
+        let mut targets = HashSet::with_capacity(m);
+        while targets.len() < m {
+            targets.insert(ends[rng.below(ends.len())]);
+        }
+        // Sorted, so the graph doesn't depend on the order of the set
+        let mut targets = targets.into_iter().collect::<Vec<usize>>();
+        targets.sort_unstable();
+        for target in targets {
+            edges.insert((target, node));
+            ends.extend([target, node]);
+        }
+    }
+    graph_nodes(count, &edges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csr::CsrGraph;
+
+    #[test]
+    fn compare_with_null_models_test() {
+        let mut rng = Rng(3);
+        let graph = erdos_renyi(30, 60, &mut rng);
+        assert_eq!(connection_count(&graph), 60);
+        // No more connections than pairs of nodes
+        assert_eq!(connection_count(&erdos_renyi(4, 10, &mut rng)), 6);
+
+        let graph = barabasi_albert(30, 60, &mut rng);
+        // Core of 3 nodes, then 2 connections for each of the other 27
+        assert_eq!(connection_count(&graph), 3 + 27 * 2);
+        assert!(graph.iter().all(|node| node.connections.len() >= 2));
+
+        // Ring lattice where each node is connected to the two closest nodes on each side -
+        // far more clustered than random graphs with the same number of connections
+        let nodes = (0..30)
+            .map(|idx| Node {
+                connections: [1, 2, 28, 29].iter().map(|d| (idx + d) % 30).collect(),
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+        let config = NullModelConfiguration { samples: 5 };
+        let comparisons = compare_with_null_models(&nodes, &config, 7, 2);
+        assert_eq!(comparisons.len(), 2);
+        assert_eq!(comparisons[0].model, NullModel::ErdosRenyi);
+        assert_eq!(comparisons[0].connections, 60.0);
+        for comparison in comparisons.iter() {
+            assert_eq!(comparison.samples, 5);
+            let metric = |name: &str| {
+                comparison
+                    .metrics
+                    .iter()
+                    .find(|metric| metric.metric == name)
+                    .unwrap()
+            };
+            assert_eq!(metric("transitivity").value, 0.5);
+            assert!(metric("transitivity").z_score.unwrap() > 3.0);
+            assert_eq!(metric("degree_variation").value, 0.0);
+            assert!(
+                metric("average_shortest_path_length").value
+                    > metric("average_shortest_path_length").mean
+            );
+        }
+
+        // The same seed gives the same result
+        let again = compare_with_null_models(&nodes, &config, 7, 1);
+        assert_eq!(
+            serde_json::to_string(&again).unwrap(),
+            serde_json::to_string(&comparisons).unwrap()
+        );
+
+        // Two closest nodes on each side, as in the network above
+        let lattice = ring_lattice(30, 60);
+        assert_eq!(connection_count(&lattice), 60);
+        assert_eq!(lattice[0].connections, vec![1, 2, 28, 29]);
+        let distances = compute_distances(&nodes, 1);
+        let (sigma, omega) =
+            small_world_coefficients(&nodes, 0.5, distances.average_shortest_path_length, 2);
+        // Clustered like a lattice, with longer paths than random graphs
+        assert!(sigma > 1.0);
+        assert!(omega < 0.0);
+        assert_eq!(
+            small_world_coefficients(&CsrGraph::default(), 0.0, 0.0, 1),
+            (0.0, 0.0)
+        );
+
+        assert_eq!(mean_std_dev([2.0, 4.0, 6.0].into_iter()), (4.0, 2.0));
+        assert_eq!(mean_std_dev([5.0].into_iter()), (5.0, 0.0));
+        assert!(compare_with_null_models(&[], &config, 0, 1)
+            .iter()
+            .all(|comparison| comparison.metrics.iter().all(|m| m.z_score.is_none())));
+    }
+}
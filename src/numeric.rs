@@ -0,0 +1,219 @@
+// Numeric helpers shared by statistics, histograms and graph utilities.
+
+/// Sorts the list converted to float values.
+fn sorted<T>(list: &[T]) -> Vec<f64>
+where
+    T: PartialOrd + Into<f64> + Copy,
+{
+    let mut list = list.iter().map(|&v| v.into()).collect::<Vec<f64>>();
+    list.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    list
+}
+
+/// Computes median of any numeric type convertible to float value.
+pub fn median<T>(list: &[T]) -> Option<f64>
+where
+    T: PartialOrd + Into<f64> + Copy,
+{
+    if list.is_empty() {
+        return None;
+    }
+
+    let list = sorted(list);
+    let mid = list.len() / 2;
+    if list.len().is_multiple_of(2) {
+        Some((list[mid - 1] + list[mid]) / 2.0)
+    } else {
+        Some(list[mid])
+    }
+}
+
+/// Computes given percentile (0-100) of the list, interpolating linearly between the closest
+/// values. Percentile outside of the range is clamped, so 0 is the minimum and 100 the maximum.
+pub fn percentile<T>(list: &[T], percentile: f64) -> Option<f64>
+where
+    T: PartialOrd + Into<f64> + Copy,
+{
+    if list.is_empty() {
+        return None;
+    }
+
+    let list = sorted(list);
+    let rank = percentile.clamp(0.0, 100.0) / 100.0 * (list.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    Some(list[lower] + (list[upper] - list[lower]) * (rank - lower as f64))
+}
+
+/// Computes mean of the list after removing given proportion (0.0-0.5) of the lowest and the
+/// highest values. At least one value (two for lists of even length) is always kept.
+pub fn trimmed_mean<T>(list: &[T], proportion: f64) -> Option<f64>
+where
+    T: PartialOrd + Into<f64> + Copy,
+{
+    if list.is_empty() {
+        return None;
+    }
+
+    let list = sorted(list);
+    let cut = ((list.len() as f64 * proportion.clamp(0.0, 0.5)).floor() as usize)
+        .min((list.len() - 1) / 2);
+    let kept = &list[cut..list.len() - cut];
+    Some(kept.iter().sum::<f64>() / kept.len() as f64)
+}
+
+/// Computes median of the list where each value is counted as many times as its weight.
+/// Returns None if the list is empty or all weights are zero.
+pub fn weighted_median<T>(list: &[T], weights: &[usize]) -> Option<f64>
+where
+    T: PartialOrd + Into<f64> + Copy,
+{
+    let mut pairs = list
+        .iter()
+        .zip(weights.iter())
+        .filter(|(_, &weight)| weight > 0)
+        .map(|(&value, &weight)| (value.into(), weight))
+        .collect::<Vec<(f64, usize)>>();
+    pairs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    let total = pairs.iter().map(|(_, weight)| weight).sum::<usize>();
+    if total == 0 {
+        return None;
+    }
+
+    let mut cumulative = 0;
+    for (i, (value, weight)) in pairs.iter().enumerate() {
+        cumulative += weight;
+        if cumulative * 2 > total {
+            return Some(*value);
+        }
+        // Exactly half of the weight is below - take middle of two values like the plain median
+        if cumulative * 2 == total {
+            return Some((value + pairs[i + 1].0) / 2.0);
+        }
+    }
+
+    None
+}
+
+/// Computes Gini coefficient of the values - 0 when all values are equal, approaching 1 when a
+/// single value holds the whole sum. Values are expected to be non-negative; the coefficient is 0
+/// if they sum up to 0.
+pub fn gini<T>(list: &[T]) -> Option<f64>
+where
+    T: PartialOrd + Into<f64> + Copy,
+{
+    if list.is_empty() {
+        return None;
+    }
+
+    let list = sorted(list);
+    let sum = list.iter().sum::<f64>();
+    if sum == 0.0 {
+        return Some(0.0);
+    }
+    let n = list.len() as f64;
+    let weighted = list
+        .iter()
+        .enumerate()
+        .map(|(i, value)| (2.0 * (i + 1) as f64 - n - 1.0) * value)
+        .sum::<f64>();
+    Some(weighted / (n * sum))
+}
+
+/// Small deterministic pseudo-random generator (SplitMix64), so the result depends only on the
+/// seed.
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Random number in range 0..n (n must be greater than 0).
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    pub(crate) fn coin(&mut self) -> bool {
+        self.next_u64() & 1 == 1
+    }
+
+    /// Shuffle the list in place (Fisher-Yates).
+    pub(crate) fn shuffle<T>(&mut self, list: &mut [T]) {
+        for i in (1..list.len()).rev() {
+            list.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn median_test() {
+        let list = vec![1, 2, 3, 4, 5];
+        assert_eq!(median(&list).unwrap(), 3.0);
+
+        let list = vec![1, 2, 3, 4, 5, 6];
+        assert_eq!(median(&list).unwrap(), 3.5);
+
+        let list = vec![1, 7, 3, 4, 5, 6, 2];
+        assert_eq!(median(&list).unwrap(), 4.0);
+    }
+
+    #[test]
+    fn median_test_empty() {
+        let list: Vec<i32> = vec![];
+        assert!(median(&list).is_none());
+    }
+
+    #[test]
+    fn percentile_test() {
+        let list = vec![5.0, 1.0, 4.0, 2.0, 3.0];
+        assert_eq!(percentile(&list, 0.0).unwrap(), 1.0);
+        assert_eq!(percentile(&list, 50.0).unwrap(), median(&list).unwrap());
+        assert!((percentile(&list, 90.0).unwrap() - 4.6).abs() < 0.0001);
+        assert_eq!(percentile(&list, 100.0).unwrap(), 5.0);
+        assert_eq!(percentile(&list, 150.0).unwrap(), 5.0);
+        assert_eq!(percentile(&[7u32], 90.0).unwrap(), 7.0);
+        assert!(percentile::<f64>(&[], 50.0).is_none());
+    }
+
+    #[test]
+    fn trimmed_mean_test() {
+        let list = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 100];
+        assert_eq!(trimmed_mean(&list, 0.0).unwrap(), 14.5);
+        assert_eq!(trimmed_mean(&list, 0.1).unwrap(), 5.5);
+        assert_eq!(trimmed_mean(&list, 0.5).unwrap(), median(&list).unwrap());
+        assert_eq!(trimmed_mean(&[1, 2, 30], 0.5).unwrap(), 2.0);
+        assert!(trimmed_mean::<f64>(&[], 0.1).is_none());
+    }
+
+    #[test]
+    fn weighted_median_test() {
+        let list = vec![1.0, 2.0, 3.0, 4.0];
+        assert_eq!(
+            weighted_median(&list, &[1, 1, 1, 1]).unwrap(),
+            median(&list).unwrap()
+        );
+        assert_eq!(weighted_median(&list, &[1, 1, 1, 10]).unwrap(), 4.0);
+        assert_eq!(weighted_median(&list, &[5, 0, 0, 5]).unwrap(), 2.5);
+        assert!(weighted_median(&list, &[0, 0, 0, 0]).is_none());
+        assert!(weighted_median::<f64>(&[], &[]).is_none());
+    }
+
+    #[test]
+    fn gini_test() {
+        assert_eq!(gini(&[3, 3, 3, 3]).unwrap(), 0.0);
+        assert_eq!(gini(&[0, 4, 0, 0]).unwrap(), 0.75);
+        assert!((gini(&[1.0, 2.0, 3.0]).unwrap() - 2.0 / 9.0).abs() < 0.0001);
+        assert_eq!(gini(&[0.0, 0.0]).unwrap(), 0.0);
+        assert!(gini::<f64>(&[]).is_none());
+    }
+}
@@ -0,0 +1,133 @@
+// Provenance of the state - the crawl and the run of crunchy which produced it, so any published
+// state file can be traced back to its input.
+
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{config::CrunchyConfiguration, stdio};
+
+/// Where the state comes from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateMeta {
+    /// When the crawler wrote the input - the modification time of the input file (unknown for
+    /// stdin), or the crawl time recorded in it for reproducible runs (unknown if it has none)
+    pub crawled_at: Option<SystemTime>,
+    /// Path of the input file (`-` for stdin)
+    pub input: String,
+    /// SHA-256 of the input contents (hex)
+    pub input_sha256: String,
+    /// Version of crunchy which produced the state
+    pub crunchy_version: String,
+    /// Configuration in effect, with API keys redacted
+    pub config: serde_json::Value,
+}
+
+impl StateMeta {
+    /// Describe the state created from the input contents read from the path with the
+    /// configuration.
+    pub fn new(input: &Path, content: &[u8], config: &CrunchyConfiguration) -> StateMeta {
+        StateMeta::with_sha256(input, format!("{:x}", Sha256::digest(content)), config)
+    }
+
+    /// Describe the state created from the input with the SHA-256 (hex) of its contents, e.g.
+    /// computed by [`HashingReader`].
+    pub fn with_sha256(
+        input: &Path,
+        input_sha256: String,
+        config: &CrunchyConfiguration,
+    ) -> StateMeta {
+        let crawled_at = if stdio::is_stdio(input) {
+            None
+        } else {
+            fs::metadata(input)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        };
+
+        StateMeta {
+            crawled_at,
+            input: input.display().to_string(),
+            input_sha256,
+            crunchy_version: env!("CARGO_PKG_VERSION").to_owned(),
+            // States are published, keys must not be
+            config: serde_json::to_value(config.redacted()).unwrap(),
+        }
+    }
+}
+
+/// Reader computing SHA-256 of the contents read through it, so the input is hashed while it's
+/// parsed rather than kept in memory for hashing.
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> HashingReader<R> {
+        HashingReader {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// SHA-256 (hex) of the contents read so far.
+    pub fn sha256(self) -> String {
+        format!("{:x}", self.hasher.finalize())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{GeoIPConfiguration, REDACTED};
+
+    #[test]
+    fn state_meta_test() {
+        let config = CrunchyConfiguration {
+            geoip_config: GeoIPConfiguration {
+                ipapico_api_key: Some("secret".to_owned()),
+                ..Default::default()
+            },
+            seed: Some(7),
+            ..Default::default()
+        };
+
+        let meta = StateMeta::new(Path::new("testdata/sample.json"), b"abc", &config);
+        assert!(meta.crawled_at.is_some());
+        assert_eq!(meta.input, "testdata/sample.json");
+        assert_eq!(
+            meta.input_sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(meta.crunchy_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(meta.config["seed"], 7);
+        assert_eq!(meta.config["geoip_config"]["ipapico_api_key"], REDACTED);
+
+        let meta = StateMeta::new(Path::new(stdio::STDIO_PATH), b"abc", &config);
+        assert!(meta.crawled_at.is_none());
+
+        let mut reader = HashingReader::new("abc".as_bytes());
+        let mut content = String::new();
+        reader.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "abc");
+        assert_eq!(
+            reader.sha256(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
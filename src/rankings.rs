@@ -0,0 +1,116 @@
+// Nodes with the highest centrality measures, so consumers like the explorer's "most important
+// nodes" widget don't have to sort the whole node array themselves.
+
+use std::net::SocketAddr;
+
+use serde::{Deserialize, Serialize};
+use ziggurat_core_geoip::geoip::GeoInfo;
+
+use crate::{config::ClosenessVariant, Node};
+
+/// Node ranked by a measure
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RankedNode {
+    /// Index of the node in the state
+    pub index: usize,
+    pub addr: SocketAddr,
+    /// Value of the measure
+    pub value: f64,
+    pub geolocation: Option<GeoInfo>,
+}
+
+/// Nodes with the highest values of each measure, the highest first (lower indices break ties).
+/// Standard closeness is the average distance to other nodes, so its ranking starts with the
+/// lowest values instead.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Rankings {
+    pub degree: Vec<RankedNode>,
+    pub betweenness: Vec<RankedNode>,
+    pub closeness: Vec<RankedNode>,
+}
+
+/// Rank nodes by each measure, keeping the `top` nodes of each ranking.
+pub fn create_rankings(
+    nodes: &[Node],
+    top: usize,
+    closeness_variant: ClosenessVariant,
+) -> Rankings {
+    let degree = |node: &Node| node.degree as f64;
+    let betweenness = |node: &Node| node.betweenness;
+    let closeness = |node: &Node| node.closeness;
+    Rankings {
+        degree: rank(nodes, top, degree, degree),
+        betweenness: rank(nodes, top, betweenness, betweenness),
+        closeness: match closeness_variant {
+            // Nodes without any connections have no distances, so they come last
+            ClosenessVariant::Standard => rank(nodes, top, closeness, |node| {
+                if node.closeness > 0.0 {
+                    1.0 / node.closeness
+                } else {
+                    0.0
+                }
+            }),
+            ClosenessVariant::Harmonic => rank(nodes, top, closeness, closeness),
+        },
+    }
+}
+
+/// The `top` nodes with the highest keys.
+fn rank(
+    nodes: &[Node],
+    top: usize,
+    value: impl Fn(&Node) -> f64,
+    key: impl Fn(&Node) -> f64,
+) -> Vec<RankedNode> {
+    let mut indices = (0..nodes.len()).collect::<Vec<usize>>();
+    // Stable sort, so nodes with the same key stay in the order of indices
+    indices.sort_by(|a, b| key(&nodes[*b]).total_cmp(&key(&nodes[*a])));
+    indices
+        .into_iter()
+        .take(top)
+        .map(|index| RankedNode {
+            index,
+            addr: nodes[index].addr,
+            value: value(&nodes[index]),
+            geolocation: nodes[index].geolocation.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_rankings_test() {
+        let nodes = [(3, 0.5, 0.1), (5, 0.0, 0.3), (3, 0.9, 0.2), (1, 0.5, 0.4)]
+            .iter()
+            .enumerate()
+            .map(|(idx, (degree, betweenness, closeness))| Node {
+                addr: SocketAddr::new([10, 0, 0, idx as u8].into(), 1234),
+                degree: *degree,
+                betweenness: *betweenness,
+                closeness: *closeness,
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+
+        let rankings = create_rankings(&nodes, 3, ClosenessVariant::Harmonic);
+        let indices =
+            |ranking: &[RankedNode]| ranking.iter().map(|node| node.index).collect::<Vec<_>>();
+        assert_eq!(indices(&rankings.degree), vec![1, 0, 2]);
+        assert_eq!(indices(&rankings.betweenness), vec![2, 0, 3]);
+        assert_eq!(indices(&rankings.closeness), vec![3, 1, 2]);
+        assert_eq!(rankings.degree[0].value, 5.0);
+        assert_eq!(rankings.degree[0].addr, nodes[1].addr);
+
+        // The lowest average distance is the most central
+        let rankings = create_rankings(&nodes, 3, ClosenessVariant::Standard);
+        assert_eq!(indices(&rankings.closeness), vec![0, 2, 1]);
+        assert_eq!(rankings.closeness[0].value, 0.1);
+
+        let standard = ClosenessVariant::Standard;
+        assert_eq!(create_rankings(&nodes, 10, standard).degree.len(), 4);
+        assert!(create_rankings(&[], 3, standard).closeness.is_empty());
+    }
+}
@@ -0,0 +1,274 @@
+// Regression check of the state against a golden (previously accepted) state. Exact values of
+// centrality measures are not compared - only node counts and summaries of metric distributions,
+// which should stay stable as long as the pipeline behaves the same. Peer lists can be checked
+// against golden ones too, allowing a share of them to change.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    net::SocketAddr,
+};
+
+use crate::{
+    ips::peer::Peer,
+    numeric::{median, percentile},
+    CrunchyState,
+};
+
+/// Default relative tolerance of metric distribution summaries.
+pub const DEFAULT_CHECK_TOLERANCE: f64 = 0.01;
+
+/// Value which differs from the golden state.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Drift {
+    /// Name of the compared value
+    pub metric: String,
+    pub expected: f64,
+    pub actual: f64,
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: expected {}, got {}",
+            self.metric, self.expected, self.actual
+        )
+    }
+}
+
+/// Compare the state with the golden one. Counts must match exactly, summaries of metric
+/// distributions (mean, median and 90th percentile) must be within the relative tolerance.
+/// Returns all values which differ.
+pub fn compare_states(
+    expected: &CrunchyState,
+    actual: &CrunchyState,
+    tolerance: f64,
+) -> Vec<Drift> {
+    let mut drifts = Vec::new();
+
+    let counts = |state: &CrunchyState| {
+        [
+            ("nodes", state.nodes.len()),
+            (
+                "connections",
+                state.nodes.iter().map(|node| node.degree).sum::<usize>() / 2,
+            ),
+            ("component_size", state.distances.component_size),
+            ("diameter", state.distances.diameter as usize),
+        ]
+    };
+    for ((metric, expected), (_, actual)) in counts(expected).into_iter().zip(counts(actual)) {
+        if expected != actual {
+            drifts.push(Drift {
+                metric: metric.to_owned(),
+                expected: expected as f64,
+                actual: actual as f64,
+            });
+        }
+    }
+
+    // Peer lists can hold duplicates and the node itself, so their size is compared too
+    for metric in ["degree", "peer_list_size", "betweenness", "closeness"] {
+        let expected = summary(&metric_values(expected, metric));
+        let actual = summary(&metric_values(actual, metric));
+        for ((name, expected), (_, actual)) in expected.into_iter().zip(actual) {
+            if (actual - expected).abs() > tolerance * expected.abs() + f64::EPSILON {
+                drifts.push(Drift {
+                    metric: format!("{metric}_{name}"),
+                    expected,
+                    actual,
+                });
+            }
+        }
+    }
+
+    drifts
+}
+
+/// Compare the peer lists with the golden ones. The number of nodes with a peer list must match
+/// exactly, while the share of nodes whose peers differ (in any order) and the relative change of
+/// the average peer list size must be within the tolerance. Returns all values which differ.
+pub fn compare_peer_lists(expected: &[Peer], actual: &[Peer], tolerance: f64) -> Vec<Drift> {
+    let mut drifts = Vec::new();
+    if expected.len() != actual.len() {
+        drifts.push(Drift {
+            metric: "peer_lists".to_owned(),
+            expected: expected.len() as f64,
+            actual: actual.len() as f64,
+        });
+    }
+
+    let actual_lists = actual
+        .iter()
+        .map(|peer| (peer.ip, peer.list.iter().collect::<HashSet<&SocketAddr>>()))
+        .collect::<HashMap<SocketAddr, HashSet<&SocketAddr>>>();
+    let changed = expected
+        .iter()
+        .filter(|peer| {
+            actual_lists.get(&peer.ip) != Some(&peer.list.iter().collect::<HashSet<&SocketAddr>>())
+        })
+        .count();
+    let changed_share = changed as f64 / expected.len().max(1) as f64;
+    if changed_share > tolerance {
+        drifts.push(Drift {
+            metric: "changed_peer_lists".to_owned(),
+            expected: 0.0,
+            actual: changed_share,
+        });
+    }
+
+    let mean_size = |peers: &[Peer]| {
+        peers.iter().map(|peer| peer.list.len()).sum::<usize>() as f64 / peers.len().max(1) as f64
+    };
+    let (expected_size, actual_size) = (mean_size(expected), mean_size(actual));
+    if (actual_size - expected_size).abs() > tolerance * expected_size + f64::EPSILON {
+        drifts.push(Drift {
+            metric: "peer_list_size_mean".to_owned(),
+            expected: expected_size,
+            actual: actual_size,
+        });
+    }
+
+    drifts
+}
+
+/// Values of the given metric for all nodes.
+fn metric_values(state: &CrunchyState, metric: &str) -> Vec<f64> {
+    state
+        .nodes
+        .iter()
+        .map(|node| match metric {
+            "degree" => node.degree as f64,
+            "peer_list_size" => node.connections.len() as f64,
+            "betweenness" => node.betweenness,
+            _ => node.closeness,
+        })
+        .collect()
+}
+
+/// Mean, median and 90th percentile of the values (zeros for an empty list).
+fn summary(values: &[f64]) -> [(&'static str, f64); 3] {
+    let mean = if values.is_empty() {
+        0.0
+    } else {
+        values.iter().sum::<f64>() / values.len() as f64
+    };
+    [
+        ("mean", mean),
+        ("median", median(values).unwrap_or_default()),
+        (
+            "percentile_90",
+            percentile(values, 90.0).unwrap_or_default(),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Node;
+
+    fn state(betweenness: &[f64]) -> CrunchyState {
+        let count = betweenness.len();
+        CrunchyState {
+            nodes: betweenness
+                .iter()
+                .enumerate()
+                .map(|(idx, betweenness)| Node {
+                    betweenness: *betweenness,
+                    connections: vec![(idx + 1) % count, (idx + count - 1) % count],
+                    degree: 2,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn compare_states_test() {
+        let expected = state(&[1.0, 2.0, 3.0, 4.0]);
+
+        assert!(compare_states(&expected, &expected, DEFAULT_CHECK_TOLERANCE).is_empty());
+        let within_tolerance = state(&[1.0, 2.0, 3.0, 4.01]);
+        assert!(compare_states(&expected, &within_tolerance, DEFAULT_CHECK_TOLERANCE).is_empty());
+
+        let drifted = state(&[1.0, 2.0, 3.0, 5.0]);
+        let drifts = compare_states(&expected, &drifted, DEFAULT_CHECK_TOLERANCE);
+        let metrics = drifts.iter().map(|d| d.metric.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            metrics,
+            vec!["betweenness_mean", "betweenness_percentile_90"]
+        );
+
+        let fewer_nodes = state(&[1.0, 2.0, 3.0]);
+        let drifts = compare_states(&expected, &fewer_nodes, DEFAULT_CHECK_TOLERANCE);
+        assert_eq!(
+            drifts[0],
+            Drift {
+                metric: String::from("nodes"),
+                expected: 4.0,
+                actual: 3.0
+            }
+        );
+        assert_eq!(drifts[1].metric, "connections");
+
+        // Duplicate connections don't change the degree
+        let mut duplicated = expected.clone();
+        duplicated.nodes[0].connections.push(1);
+        let drifts = compare_states(&expected, &duplicated, DEFAULT_CHECK_TOLERANCE);
+        let metrics = drifts.iter().map(|d| d.metric.as_str()).collect::<Vec<_>>();
+        assert!(metrics.contains(&"peer_list_size_mean"));
+        assert!(!metrics.iter().any(|metric| metric.starts_with("degree")));
+    }
+
+    #[test]
+    fn compare_peer_lists_test() {
+        let addr = |i: u8| SocketAddr::new([10, 0, 0, i].into(), 8233);
+        let peer = |i: u8, list: &[u8]| Peer {
+            ip: addr(i),
+            list: list.iter().map(|j| addr(*j)).collect(),
+            estimated_latency_ms: Vec::new(),
+            details: Vec::new(),
+        };
+        let expected = (0..100)
+            .map(|i| peer(i, &[(i + 1) % 100, (i + 99) % 100]))
+            .collect::<Vec<Peer>>();
+
+        // The order of peers doesn't matter
+        let mut actual = expected.clone();
+        actual[0].list.reverse();
+        assert!(compare_peer_lists(&expected, &actual, DEFAULT_CHECK_TOLERANCE).is_empty());
+
+        // One changed peer list is within the tolerance, two aren't
+        actual[1] = peer(1, &[2, 5]);
+        assert!(compare_peer_lists(&expected, &actual, DEFAULT_CHECK_TOLERANCE).is_empty());
+        actual[2] = peer(2, &[3, 6]);
+        let drifts = compare_peer_lists(&expected, &actual, DEFAULT_CHECK_TOLERANCE);
+        assert_eq!(
+            drifts,
+            vec![Drift {
+                metric: String::from("changed_peer_lists"),
+                expected: 0.0,
+                actual: 0.02
+            }]
+        );
+
+        actual.pop();
+        actual[3].list.push(addr(50));
+        let metrics = compare_peer_lists(&expected, &actual, 0.1)
+            .into_iter()
+            .map(|drift| drift.metric)
+            .collect::<Vec<String>>();
+        assert_eq!(metrics, vec!["peer_lists"]);
+        let metrics = compare_peer_lists(&expected, &actual, 0.0)
+            .into_iter()
+            .map(|drift| drift.metric)
+            .collect::<Vec<String>>();
+        assert_eq!(
+            metrics,
+            vec!["peer_lists", "changed_peer_lists", "peer_list_size_mean"]
+        );
+    }
+}
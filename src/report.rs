@@ -0,0 +1,101 @@
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    geoip_cache::GeoIPStatistics,
+    heat::HotNode,
+    ips::{algorithm::IpsReport, peer::PeerChange},
+};
+
+/// File produced by the run
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Artifact {
+    /// What the file contains (e.g. "state", "peers")
+    pub kind: String,
+    /// Where the file was written
+    pub path: PathBuf,
+}
+
+/// Outcome of IPS for a single network type
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct NetworkIps {
+    /// Network type the peer lists were generated for
+    pub network: String,
+    /// Findings, guardrail outcomes and statistics of the IPS run
+    pub ips: IpsReport,
+    /// Reason why peer lists were not generated
+    #[serde(default)]
+    pub ips_error: Option<String>,
+    /// Changes of peers proposed for each node (only for dry runs, which write no peer list)
+    #[serde(default)]
+    pub proposed_changes: Vec<PeerChange>,
+}
+
+/// Machine-readable record of a single run
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RunReport {
+    /// Time when the report was generated
+    pub generated_at: SystemTime,
+    /// Time spent computing the state (in seconds)
+    pub elapsed: f64,
+    /// Number of nodes in the state
+    pub nodes_count: usize,
+    /// Seed of all pseudo-random choices made during the run
+    #[serde(default)]
+    pub seed: u64,
+    /// How geolocation lookups were resolved
+    pub geoip: GeoIPStatistics,
+    /// Findings, guardrail outcomes and statistics of the IPS run (for the first network type if
+    /// there are more)
+    pub ips: IpsReport,
+    /// Reason why peer lists were not generated
+    #[serde(default)]
+    pub ips_error: Option<String>,
+    /// IPS outcomes for each network type (only if peer lists are generated for more than one)
+    #[serde(default)]
+    pub networks: Vec<NetworkIps>,
+    /// Nodes with the fastest rising betweenness across recent runs
+    #[serde(default)]
+    pub emerging_hot_nodes: Vec<HotNode>,
+    /// Changes of peers proposed for each node (only for dry runs, which write no peer list)
+    #[serde(default)]
+    pub proposed_changes: Vec<PeerChange>,
+    /// Files written during the run
+    pub artifacts: Vec<Artifact>,
+}
+
+impl RunReport {
+    pub fn new(
+        elapsed: f64,
+        nodes_count: usize,
+        seed: u64,
+        geoip: GeoIPStatistics,
+        ips: IpsReport,
+    ) -> Self {
+        Self {
+            generated_at: SystemTime::now(),
+            elapsed,
+            nodes_count,
+            seed,
+            geoip,
+            ips,
+            ips_error: None,
+            networks: Vec::new(),
+            emerging_hot_nodes: Vec::new(),
+            proposed_changes: Vec::new(),
+            artifacts: Vec::new(),
+        }
+    }
+
+    /// Record a file written during the run
+    pub fn add_artifact(&mut self, kind: &str, path: &Path) {
+        self.artifacts.push(Artifact {
+            kind: kind.to_owned(),
+            path: path.to_path_buf(),
+        });
+    }
+}
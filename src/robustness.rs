@@ -0,0 +1,167 @@
+// Attack simulation - how the network falls apart when nodes are removed step by step. Targeted
+// attacks remove the most central nodes first (ranked once on the intact network), while random
+// removal is the baseline a robust network should not be much more sensitive to.
+
+use std::{cmp::Reverse, collections::HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::SimulationConfiguration,
+    ips::graph_utils::{compute_distances, detect_islands, extract_island},
+    numeric::Rng,
+    Node,
+};
+
+/// Order in which nodes are removed
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttackStrategy {
+    /// Nodes with the highest betweenness first
+    Betweenness,
+    /// Nodes with the highest degree first
+    Degree,
+    /// Pseudo-random order depending only on the seed
+    Random,
+}
+
+/// Network after a number of nodes were removed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RobustnessStep {
+    /// Number of nodes removed so far
+    pub removed_nodes: usize,
+    /// Number of nodes in the largest connected component
+    pub largest_component: usize,
+    /// Number of connected components (islands)
+    pub islands: usize,
+    /// Diameter of the largest connected component
+    pub diameter: u32,
+}
+
+/// Robustness curve of the network for a single attack strategy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RobustnessCurve {
+    pub strategy: AttackStrategy,
+    /// The intact network first, then the network after each step
+    pub steps: Vec<RobustnessStep>,
+}
+
+/// Simulate attacks with each strategy - the configured percentage of nodes is removed in each
+/// step.
+pub fn simulate_attacks(
+    nodes: &[Node],
+    config: &SimulationConfiguration,
+    seed: u64,
+    num_threads: usize,
+) -> Vec<RobustnessCurve> {
+    let nodes_per_step =
+        ((nodes.len() as f64 * config.step_percentage / 100.0).round() as usize).max(1);
+
+    [
+        AttackStrategy::Betweenness,
+        AttackStrategy::Degree,
+        AttackStrategy::Random,
+    ]
+    .into_iter()
+    .map(|strategy| {
+        simulate_attack(
+            nodes,
+            strategy,
+            nodes_per_step,
+            config.steps,
+            seed,
+            num_threads,
+        )
+    })
+    .collect()
+}
+
+/// Remove `nodes_per_step` nodes in the order given by the strategy in each of `steps` steps and
+/// record the network after each of them.
+pub fn simulate_attack(
+    nodes: &[Node],
+    strategy: AttackStrategy,
+    nodes_per_step: usize,
+    steps: usize,
+    seed: u64,
+    num_threads: usize,
+) -> RobustnessCurve {
+    let mut order = (0..nodes.len()).collect::<Vec<usize>>();
+    match strategy {
+        AttackStrategy::Betweenness => {
+            order.sort_by(|a, b| nodes[*b].betweenness.total_cmp(&nodes[*a].betweenness))
+        }
+        AttackStrategy::Degree => order.sort_by_key(|idx| Reverse(nodes[*idx].degree)),
+        AttackStrategy::Random => Rng(seed).shuffle(&mut order),
+    }
+
+    let steps = (0..=steps)
+        .map(|step| (step * nodes_per_step).min(nodes.len()))
+        .map(|removed_nodes| {
+            let kept = order[removed_nodes..]
+                .iter()
+                .copied()
+                .collect::<HashSet<usize>>();
+            let remaining = extract_island(nodes, nodes, &kept);
+            let islands = detect_islands(&remaining);
+
+            RobustnessStep {
+                removed_nodes,
+                largest_component: islands.iter().map(|island| island.len()).max().unwrap_or(0),
+                islands: islands.len(),
+                diameter: compute_distances(&remaining, num_threads).diameter,
+            }
+        })
+        .collect();
+
+    RobustnessCurve { strategy, steps }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+
+    #[test]
+    fn simulate_attack_test() {
+        // Star with 5 leaves, leaves 1 and 2 are connected too
+        let mut nodes = (0..6)
+            .map(|i| Node {
+                addr: SocketAddr::new([10, 0, 0, i as u8].into(), 1234),
+                connections: if i == 0 { (1..6).collect() } else { vec![0] },
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+        nodes[1].connections.push(2);
+        nodes[2].connections.push(1);
+        nodes[0].betweenness = 0.9;
+
+        let curve = simulate_attack(&nodes, AttackStrategy::Degree, 1, 2, 0, 2);
+        assert_eq!(curve.steps.len(), 3);
+        assert_eq!(curve.steps[0].largest_component, 6);
+        assert_eq!(curve.steps[0].islands, 1);
+        assert_eq!(curve.steps[0].diameter, 2);
+        // Removing the hub splits the network
+        assert_eq!(curve.steps[1].removed_nodes, 1);
+        assert_eq!(curve.steps[1].largest_component, 2);
+        assert_eq!(curve.steps[1].islands, 4);
+
+        let curve = simulate_attack(&nodes, AttackStrategy::Betweenness, 1, 1, 0, 2);
+        assert_eq!(curve.steps[1].islands, 4);
+
+        // Steps can't remove more nodes than there are
+        let curve = simulate_attack(&nodes, AttackStrategy::Random, 4, 3, 7, 2);
+        assert_eq!(curve.steps[3].removed_nodes, 6);
+        assert_eq!(curve.steps[3].largest_component, 0);
+
+        let config = SimulationConfiguration {
+            step_percentage: 20.0,
+            steps: 2,
+        };
+        let curves = simulate_attacks(&nodes, &config, 0, 2);
+        assert_eq!(curves.len(), 3);
+        assert_eq!(curves[2].strategy, AttackStrategy::Random);
+        assert_eq!(curves[2].steps[2].removed_nodes, 2);
+    }
+}
@@ -0,0 +1,127 @@
+// Rotation of outputs written repeatedly (e.g. by the `daemon` command or cron jobs). The file
+// name of any output path can contain the `{timestamp}` placeholder, replaced with the UTC time of
+// the run - `state-{timestamp}.json` becomes `state-20261016T120000Z.json` - so runs don't
+// overwrite each other, and files of older runs are removed according to `keep_last`.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::daemon::is_run_name;
+
+/// Placeholder of the run time in output paths
+pub const TIMESTAMP_PLACEHOLDER: &str = "{timestamp}";
+
+/// Check if the path contains the timestamp placeholder.
+pub fn is_templated(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.contains(TIMESTAMP_PLACEHOLDER))
+}
+
+/// Path with the timestamp placeholder replaced with the timestamp (the same path if it has no
+/// placeholder).
+pub fn expand(path: &Path, timestamp: &str) -> PathBuf {
+    match path.to_str() {
+        Some(path) if path.contains(TIMESTAMP_PLACEHOLDER) => {
+            PathBuf::from(path.replace(TIMESTAMP_PLACEHOLDER, timestamp))
+        }
+        _ => path.to_owned(),
+    }
+}
+
+/// Remove files (or directories) the template was expanded to, except for the latest
+/// `keep_last`. Returns the removed paths.
+pub fn prune(template: &Path, keep_last: usize) -> io::Result<Vec<PathBuf>> {
+    let template_name = template
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.split_once(TIMESTAMP_PLACEHOLDER));
+    let (prefix, suffix) = match template_name {
+        Some(parts) => parts,
+        None => return Ok(Vec::new()),
+    };
+    let parent = template.parent().unwrap_or(Path::new(""));
+    let dir = if parent.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        parent
+    };
+
+    let mut names = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| {
+            name.strip_prefix(prefix)
+                .and_then(|rest| rest.strip_suffix(suffix))
+                .is_some_and(is_run_name)
+        })
+        .collect::<Vec<String>>();
+    // Timestamps sort in the order of runs, the latest first
+    names.sort_unstable_by(|a, b| b.cmp(a));
+
+    let mut removed = Vec::new();
+    for name in names.iter().skip(keep_last) {
+        let path = parent.join(name);
+        if path.is_dir() {
+            fs::remove_dir_all(&path)?;
+        } else {
+            fs::remove_file(&path)?;
+        }
+        removed.push(path);
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prune_test() {
+        let dir = std::env::temp_dir().join(format!("crunchy-rotation-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let template = dir.join("state-{timestamp}.json");
+        assert!(is_templated(&template));
+        assert_eq!(
+            expand(&template, "20261016T120000Z"),
+            dir.join("state-20261016T120000Z.json")
+        );
+        assert_eq!(expand(&dir, "20261016T120000Z"), dir);
+
+        for hour in 10..15 {
+            let path = expand(&template, &format!("20261016T{hour}0000Z"));
+            fs::write(path, "{}").unwrap();
+        }
+        // Files not written by a templated output are left alone
+        for name in [
+            "state.json",
+            "state-latest.json",
+            "state-20261016T100000Z-zcash.json",
+        ] {
+            fs::write(dir.join(name), "{}").unwrap();
+        }
+
+        let removed = prune(&template, 2).unwrap();
+        assert_eq!(removed.len(), 3);
+        assert!(removed.contains(&dir.join("state-20261016T100000Z.json")));
+        let mut left = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<String>>();
+        left.sort_unstable();
+        assert_eq!(
+            left,
+            vec![
+                "state-20261016T100000Z-zcash.json",
+                "state-20261016T130000Z.json",
+                "state-20261016T140000Z.json",
+                "state-latest.json",
+                "state.json"
+            ]
+        );
+        assert!(prune(&dir.join("state.json"), 1).unwrap().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
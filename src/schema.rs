@@ -0,0 +1,113 @@
+// Versions of the state file schema. Every state records the version it was written with, and
+// states written by earlier versions are upgraded step by step when loaded, so old snapshots
+// stay readable after the state structure changes.
+
+use std::io;
+
+use serde_json::Value;
+
+use crate::CrunchyState;
+
+/// Version of the state schema written by this version of crunchy. States without the version
+/// are version 1.
+pub const STATE_SCHEMA_VERSION: u32 = 2;
+
+/// Steps upgrading the state by one version - the first one upgrades version 1.
+const UPGRADES: [fn(&mut Value); (STATE_SCHEMA_VERSION - 1) as usize] = [upgrade_v1];
+
+/// Parse the state of any supported schema version, upgrading it to the current one.
+pub fn parse_state(mut state: Value) -> Result<CrunchyState, io::Error> {
+    let invalid_data = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let version = match state.get("schema_version") {
+        None => 1,
+        Some(version) => version
+            .as_u64()
+            .filter(|version| *version >= 1)
+            .ok_or_else(|| invalid_data(format!("invalid state schema version {version}")))?,
+    };
+    if version > u64::from(STATE_SCHEMA_VERSION) {
+        return Err(invalid_data(format!(
+            "state schema version {version} is newer than supported ({STATE_SCHEMA_VERSION})"
+        )));
+    }
+
+    for upgrade in UPGRADES.iter().skip(version as usize - 1) {
+        upgrade(&mut state);
+    }
+    if let Some(fields) = state.as_object_mut() {
+        fields.insert("schema_version".to_owned(), STATE_SCHEMA_VERSION.into());
+    }
+    serde_json::from_value(state).map_err(|e| invalid_data(e.to_string()))
+}
+
+/// Version 1 nodes have no precomputed degree - it's the number of distinct connections,
+/// excluding the node itself.
+fn upgrade_v1(state: &mut Value) {
+    let nodes = state
+        .get_mut("nodes")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten();
+    for (idx, fields) in nodes
+        .enumerate()
+        .filter_map(|(idx, node)| Some((idx, node.as_object_mut()?)))
+    {
+        let mut connections = fields
+            .get("connections")
+            .and_then(Value::as_array)
+            .map(|connections| {
+                connections
+                    .iter()
+                    .filter_map(Value::as_u64)
+                    .filter(|peer| *peer != idx as u64)
+                    .collect::<Vec<u64>>()
+            })
+            .unwrap_or_default();
+        connections.sort_unstable();
+        connections.dedup();
+        fields.insert("degree".to_owned(), connections.len().into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parse_state_test() {
+        let node = |connections: Value| {
+            json!({
+                "addr": "1.1.1.1:8233",
+                "network_type": "Zcash",
+                "betweenness": 0.0,
+                "closeness": 0.0,
+                "connections": connections,
+                "geolocation": null,
+            })
+        };
+        let v1 = json!({
+            "elapsed": 1.0,
+            "nodes": [node(json!([1, 1, 0])), node(json!([0]))],
+            "histograms": [],
+        });
+
+        let state = parse_state(v1.clone()).unwrap();
+        assert_eq!(state.schema_version, STATE_SCHEMA_VERSION);
+        assert_eq!(state.nodes[0].degree, 1);
+        assert_eq!(state.nodes[1].degree, 1);
+
+        // Current states are taken as they are
+        let current = serde_json::to_value(&state).unwrap();
+        let reloaded = parse_state(current).unwrap();
+        assert_eq!(reloaded.nodes[0].degree, 1);
+
+        let mut newer = v1.clone();
+        newer["schema_version"] = (STATE_SCHEMA_VERSION + 1).into();
+        assert!(parse_state(newer).is_err());
+        let mut invalid = v1;
+        invalid["schema_version"] = "two".into();
+        assert!(parse_state(invalid).is_err());
+    }
+}
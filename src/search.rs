@@ -0,0 +1,263 @@
+// Search over nodes of the state, so operators can find nodes without downloading and grepping
+// the whole state JSON.
+//
+// Query is a list of whitespace separated terms and a node must match all of them:
+//   addr:<prefix>       address starting with the prefix (also a term without any field)
+//   country:<name>      country (case insensitive)
+//   city:<name>         city (case insensitive)
+//   asn:<number>        autonomous system number
+//   tag:<value>         node role - currently the network type (case insensitive)
+//   <metric>:<range>    metric within the range, where metric is one of degree, betweenness,
+//                       closeness, eccentricity or uptime and range is one of <x, <=x, >x, >=x,
+//                       x..y (inclusive) or x (exact value)
+
+use std::fmt;
+
+use crate::{CrunchyState, Node};
+
+/// Errors in the search query
+#[derive(Debug, PartialEq)]
+pub enum SearchError {
+    /// Field of the term is not known
+    UnknownField(String),
+    /// Value of the term can't be parsed
+    InvalidValue(String),
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchError::UnknownField(field) => write!(f, "unknown search field: {field}"),
+            SearchError::InvalidValue(term) => write!(f, "invalid search value: {term}"),
+        }
+    }
+}
+
+impl std::error::Error for SearchError {}
+
+/// Metric which can be searched by range
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Metric {
+    Degree,
+    Betweenness,
+    Closeness,
+    Eccentricity,
+    Uptime,
+}
+
+impl Metric {
+    fn value(&self, node: &Node) -> Option<f64> {
+        match self {
+            Metric::Degree => Some(node.degree as f64),
+            Metric::Betweenness => Some(node.betweenness),
+            Metric::Closeness => Some(node.closeness),
+            Metric::Eccentricity => node.eccentricity.map(f64::from),
+            Metric::Uptime => node.uptime,
+        }
+    }
+}
+
+/// Single term of the query
+#[derive(Debug, PartialEq)]
+enum Term {
+    AddrPrefix(String),
+    Country(String),
+    City(String),
+    Asn(u32),
+    Tag(String),
+    Range(Metric, f64, f64),
+}
+
+/// Parse the query into terms.
+fn parse_query(query: &str) -> Result<Vec<Term>, SearchError> {
+    query
+        .split_whitespace()
+        .map(|term| {
+            let (field, value) = match term.split_once(':') {
+                // IPv6 addresses contain colons too
+                Some((field, value)) if field.chars().all(|c| c.is_ascii_alphabetic()) => {
+                    (field.to_lowercase(), value)
+                }
+                _ => return Ok(Term::AddrPrefix(term.to_owned())),
+            };
+            let invalid = || SearchError::InvalidValue(term.to_owned());
+
+            let metric = match field.as_str() {
+                "addr" => return Ok(Term::AddrPrefix(value.to_owned())),
+                "country" => return Ok(Term::Country(value.to_lowercase())),
+                "city" => return Ok(Term::City(value.to_lowercase())),
+                "asn" => return value.parse().map(Term::Asn).map_err(|_| invalid()),
+                "tag" => return Ok(Term::Tag(value.to_lowercase())),
+                "degree" => Metric::Degree,
+                "betweenness" => Metric::Betweenness,
+                "closeness" => Metric::Closeness,
+                "eccentricity" => Metric::Eccentricity,
+                "uptime" => Metric::Uptime,
+                _ => return Err(SearchError::UnknownField(field)),
+            };
+            let (min, max) = parse_range(value).ok_or_else(invalid)?;
+            Ok(Term::Range(metric, min, max))
+        })
+        .collect()
+}
+
+/// Parse the range into inclusive bounds. Exclusive bounds are moved to the closest float.
+fn parse_range(range: &str) -> Option<(f64, f64)> {
+    let number = |s: &str| s.trim().parse::<f64>().ok();
+    if let Some(value) = range.strip_prefix(">=") {
+        return Some((number(value)?, f64::INFINITY));
+    }
+    if let Some(value) = range.strip_prefix("<=") {
+        return Some((f64::NEG_INFINITY, number(value)?));
+    }
+    if let Some(value) = range.strip_prefix('>') {
+        return Some((next_up(number(value)?), f64::INFINITY));
+    }
+    if let Some(value) = range.strip_prefix('<') {
+        return Some((f64::NEG_INFINITY, next_down(number(value)?)));
+    }
+    if let Some((min, max)) = range.split_once("..") {
+        return Some((number(min)?, number(max)?));
+    }
+    let value = number(range)?;
+    Some((value, value))
+}
+
+fn next_up(value: f64) -> f64 {
+    if value == 0.0 {
+        f64::MIN_POSITIVE
+    } else if value > 0.0 {
+        f64::from_bits(value.to_bits() + 1)
+    } else {
+        f64::from_bits(value.to_bits() - 1)
+    }
+}
+
+fn next_down(value: f64) -> f64 {
+    -next_up(-value)
+}
+
+/// Searchable keys of a single node
+struct IndexEntry {
+    addr: String,
+    country: Option<String>,
+    city: Option<String>,
+    tag: String,
+}
+
+/// Search index over nodes of the state. Keys are normalized once, so the index is built for
+/// each new state and then used for all searches.
+pub struct SearchIndex {
+    entries: Vec<IndexEntry>,
+}
+
+impl SearchIndex {
+    pub fn new(state: &CrunchyState) -> Self {
+        let entries = state
+            .nodes
+            .iter()
+            .map(|node| {
+                let geolocation = node.geolocation.as_ref();
+                IndexEntry {
+                    addr: node.addr.to_string(),
+                    country: geolocation
+                        .and_then(|geo| geo.country.as_ref())
+                        .map(|country| country.to_lowercase()),
+                    city: geolocation
+                        .and_then(|geo| geo.city.as_ref())
+                        .map(|city| city.to_lowercase()),
+                    tag: format!("{:?}", node.network_type).to_lowercase(),
+                }
+            })
+            .collect();
+
+        Self { entries }
+    }
+
+    /// Find indices of nodes (in the state the index was built for) matching the query.
+    pub fn search(&self, state: &CrunchyState, query: &str) -> Result<Vec<usize>, SearchError> {
+        let terms = parse_query(query)?;
+
+        Ok(self
+            .entries
+            .iter()
+            .zip(state.nodes.iter())
+            .enumerate()
+            .filter(|(_, (entry, node))| terms.iter().all(|term| matches(term, entry, node)))
+            .map(|(idx, _)| idx)
+            .collect())
+    }
+}
+
+fn matches(term: &Term, entry: &IndexEntry, node: &Node) -> bool {
+    match term {
+        Term::AddrPrefix(prefix) => entry.addr.starts_with(prefix.as_str()),
+        Term::Country(country) => entry.country.as_ref() == Some(country),
+        Term::City(city) => entry.city.as_ref() == Some(city),
+        Term::Asn(asn) => node.asn == Some(*asn),
+        Term::Tag(tag) => entry.tag == *tag,
+        Term::Range(metric, min, max) => {
+            matches!(metric.value(node), Some(value) if *min <= value && value <= *max)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use ziggurat_core_crawler::summary::NetworkType;
+
+    use super::*;
+    use crate::test_util::geo;
+
+    fn node(addr: &str, country: &str, asn: u32, betweenness: f64, degree: usize) -> Node {
+        Node {
+            addr: addr.parse::<SocketAddr>().unwrap(),
+            network_type: NetworkType::Zcash,
+            betweenness,
+            degree,
+            geolocation: geo(country),
+            asn: Some(asn),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn search_test() {
+        let state = CrunchyState {
+            nodes: vec![
+                node("10.0.0.1:8233", "Germany", 24940, 0.5, 8),
+                node("10.0.1.1:8233", "Germany", 16509, 0.1, 3),
+                node("192.168.0.1:8233", "United States", 16509, 0.9, 12),
+                node("[2001:db8::1]:8233", "Japan", 2500, 0.0, 1),
+            ],
+            ..Default::default()
+        };
+        let index = SearchIndex::new(&state);
+        let search = |query| index.search(&state, query).unwrap();
+
+        assert_eq!(search("10.0."), vec![0, 1]);
+        assert_eq!(search("addr:10.0.0"), vec![0]);
+        assert_eq!(search("[2001:db8"), vec![3]);
+        assert_eq!(search("country:germany"), vec![0, 1]);
+        assert_eq!(search("asn:16509"), vec![1, 2]);
+        assert_eq!(search("tag:zcash").len(), 4);
+        assert_eq!(search("betweenness:>0.1"), vec![0, 2]);
+        assert_eq!(search("betweenness:>=0.1"), vec![0, 1, 2]);
+        assert_eq!(search("degree:3..8"), vec![0, 1]);
+        assert_eq!(search("degree:<3"), vec![3]);
+        assert_eq!(search("country:Germany asn:16509"), vec![1]);
+        assert_eq!(search("eccentricity:>0"), Vec::<usize>::new());
+        assert_eq!(search(""), vec![0, 1, 2, 3]);
+
+        assert_eq!(
+            index.search(&state, "color:red"),
+            Err(SearchError::UnknownField(String::from("color")))
+        );
+        assert_eq!(
+            index.search(&state, "degree:a..b"),
+            Err(SearchError::InvalidValue(String::from("degree:a..b")))
+        );
+    }
+}
@@ -0,0 +1,77 @@
+// Standard input and output in place of files - the input is read from stdin and an output is
+// written to stdout if its path is `-`, so crunchy can be used in a pipeline. Messages which
+// would otherwise go to stdout are then moved to stderr.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Write},
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::atomic_file;
+
+/// Path standing for stdin (inputs) or stdout (outputs)
+pub const STDIO_PATH: &str = "-";
+
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+
+/// Check if the path stands for stdin or stdout.
+pub fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == STDIO_PATH
+}
+
+/// Open the file for reading, or stdin for `-`.
+pub fn reader(path: &Path) -> io::Result<Box<dyn Read>> {
+    if is_stdio(path) {
+        return Ok(Box::new(io::stdin().lock()));
+    }
+    Ok(Box::new(BufReader::new(File::open(path)?)))
+}
+
+/// Write the contents to the file (replaced atomically), or to stdout for `-`.
+pub fn write(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    if is_stdio(path) {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(contents.as_ref())?;
+        return stdout.flush();
+    }
+    atomic_file::write(path, contents)
+}
+
+/// Reserve stdout for an output - messages are written to stderr from now on.
+pub fn reserve_stdout() {
+    STDOUT_RESERVED.store(true, Ordering::Relaxed);
+}
+
+/// Destination of progress and informational messages - stdout unless it's reserved for an
+/// output.
+pub fn messages() -> Box<dyn Write> {
+    if STDOUT_RESERVED.load(Ordering::Relaxed) {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn reader_test() {
+        assert!(is_stdio(Path::new("-")));
+        assert!(!is_stdio(Path::new("./-")));
+        assert!(!is_stdio(Path::new("testdata/sample.json")));
+
+        let mut content = String::new();
+        reader(Path::new("testdata/sample.json"))
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, fs::read_to_string("testdata/sample.json").unwrap());
+        assert!(reader(Path::new("testdata/missing.json")).is_err());
+    }
+}
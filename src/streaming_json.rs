@@ -0,0 +1,217 @@
+// Streaming parser of the crawler input (the `streaming-json` feature). The response is parsed
+// while it's read, so the document - hundreds of MB for large crawls - is never held in memory
+// next to the summary built from it.
+
+use std::{
+    fmt,
+    io::{self, BufReader, Read},
+};
+
+use serde::{
+    de::{
+        self, value::MapAccessDeserializer, DeserializeSeed, IgnoredAny, IntoDeserializer,
+        MapAccess, Visitor,
+    },
+    Deserialize, Deserializer,
+};
+use ziggurat_core_crawler::summary::NetworkSummary;
+
+use crate::JsonRpcResponse;
+
+/// Read the crawler response, or the bare network summary (taken as the result of a response
+/// with id 0), parsing it as it's read.
+pub fn read_response_streaming<R: Read>(reader: R) -> Result<JsonRpcResponse, io::Error> {
+    let invalid_data = |e: serde_json::Error| io::Error::new(io::ErrorKind::InvalidData, e);
+    let mut deserializer = serde_json::Deserializer::from_reader(BufReader::new(reader));
+    let response = deserializer
+        .deserialize_map(ResponseVisitor)
+        .map_err(invalid_data)?;
+    deserializer.end().map_err(invalid_data)?;
+    Ok(response)
+}
+
+/// Tells the response from the bare summary by its first key - keys of the response never
+/// appear in the summary
+struct ResponseVisitor;
+
+impl<'de> Visitor<'de> for ResponseVisitor {
+    type Value = JsonRpcResponse;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a JSON-RPC response or a network summary")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<JsonRpcResponse, A::Error> {
+        let first_key = match map.next_key::<String>()? {
+            Some(key) => key,
+            None => return Err(de::Error::invalid_length(0, &self)),
+        };
+        let mut timestamp = None;
+        if !matches!(first_key.as_str(), "jsonrpc" | "result" | "id") {
+            let result = NetworkSummary::deserialize(MapAccessDeserializer::new(Timestamped {
+                map: Replayed {
+                    key: Some(first_key),
+                    map,
+                },
+                timestamp: &mut timestamp,
+            }))?;
+            return Ok(JsonRpcResponse {
+                jsonrpc: "2.0".to_owned(),
+                result,
+                id: 0,
+                timestamp,
+            });
+        }
+
+        let (mut jsonrpc, mut result, mut id) = (None, None, None);
+        let mut key = Some(first_key);
+        while let Some(name) = key {
+            match name.as_str() {
+                "jsonrpc" => jsonrpc = Some(map.next_value()?),
+                "result" => result = Some(map.next_value_seed(SummarySeed(&mut timestamp))?),
+                "id" => id = Some(map.next_value()?),
+                // The crawl time at the top level wins over the one in the result
+                "timestamp" => {
+                    if let Some(secs) = map.next_value::<Option<u64>>()? {
+                        timestamp = Some(secs);
+                    }
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+            key = map.next_key()?;
+        }
+        Ok(JsonRpcResponse {
+            jsonrpc: jsonrpc.ok_or_else(|| de::Error::missing_field("jsonrpc"))?,
+            result: result.ok_or_else(|| de::Error::missing_field("result"))?,
+            id: id.ok_or_else(|| de::Error::missing_field("id"))?,
+            timestamp,
+        })
+    }
+}
+
+/// Network summary whose crawl time is taken out of it while it's parsed
+struct SummarySeed<'t>(&'t mut Option<u64>);
+
+impl<'de> DeserializeSeed<'de> for SummarySeed<'_> {
+    type Value = NetworkSummary;
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<NetworkSummary, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for SummarySeed<'_> {
+    type Value = NetworkSummary;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a network summary")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<NetworkSummary, A::Error> {
+        NetworkSummary::deserialize(MapAccessDeserializer::new(Timestamped {
+            map,
+            timestamp: self.0,
+        }))
+    }
+}
+
+/// Map whose `timestamp` entry is read into `timestamp` (unless one was read already) instead of
+/// being given out
+struct Timestamped<'t, A> {
+    map: A,
+    timestamp: &'t mut Option<u64>,
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for Timestamped<'_, A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, A::Error> {
+        while let Some(key) = self.map.next_key::<String>()? {
+            if key != "timestamp" {
+                return seed.deserialize(key.into_deserializer()).map(Some);
+            }
+            let secs = self.map.next_value::<Option<u64>>()?;
+            if self.timestamp.is_none() {
+                *self.timestamp = secs;
+            }
+        }
+        Ok(None)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, A::Error> {
+        self.map.next_value_seed(seed)
+    }
+}
+
+/// Map whose first key was already read - it's given out again before the remaining entries
+struct Replayed<A> {
+    key: Option<String>,
+    map: A,
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for Replayed<A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, A::Error> {
+        match self.key.take() {
+            Some(key) => seed.deserialize(key.into_deserializer()).map(Some),
+            None => self.map.next_key_seed(seed),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, A::Error> {
+        self.map.next_value_seed(seed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn read_response_streaming_test() {
+        let content = fs::read_to_string("testdata/sample.json").unwrap();
+        let expected = JsonRpcResponse::parse(&content).unwrap();
+
+        let response = read_response_streaming(content.as_bytes()).unwrap();
+        assert_eq!(response.id, expected.id);
+        assert_eq!(response.result.node_addrs, expected.result.node_addrs);
+        assert_eq!(response.result.nodes_indices, expected.result.nodes_indices);
+
+        let bare = serde_json::to_string(&expected.result).unwrap();
+        let response = read_response_streaming(bare.as_bytes()).unwrap();
+        assert_eq!(response.id, 0);
+        assert_eq!(response.result.nodes_indices, expected.result.nodes_indices);
+
+        // The crawl time is read at the top level or from the result, as by the parser
+        let timestamped = format!(r#"{{"timestamp":200,{}"#, &bare[1..]);
+        for content in [
+            format!(r#"{{"jsonrpc":"2.0","id":1,"result":{bare},"timestamp":100}}"#),
+            format!(r#"{{"jsonrpc":"2.0","id":1,"result":{timestamped}}}"#),
+            timestamped.clone(),
+        ] {
+            let expected = JsonRpcResponse::parse(&content).unwrap();
+            let streamed = read_response_streaming(content.as_bytes()).unwrap();
+            assert!(expected.timestamp.is_some());
+            assert_eq!(streamed.timestamp, expected.timestamp);
+        }
+        assert_eq!(response.timestamp, None);
+
+        assert!(read_response_streaming(r#"{"jsonrpc": "2.0", "id": 1}"#.as_bytes()).is_err());
+        assert!(read_response_streaming("{}".as_bytes()).is_err());
+        assert!(read_response_streaming(format!("{bare} {{}}").as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,268 @@
+// Sensitivity of IPS results to the MCDA weights (`ips sweep`). IPS runs for every combination
+// of the weight values in the configured grid, and statistics of each resulting network are
+// written as a CSV table, so the default weights can be justified by how the network changes
+// with them. As that's many IPS runs, they are done on a connected subgraph sampled from the
+// network.
+
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    io::Write,
+};
+
+use crate::{
+    centrality::{compute_centralities, value_or_fallback},
+    config::{ClosenessVariant, CrunchyConfiguration},
+    create_peers,
+    ips::{
+        graph_utils::{detect_islands, extract_island},
+        statistics::Statistics,
+    },
+    numeric::Rng,
+    stdio, CrunchyState, Node,
+};
+
+/// IPS run with a single combination of weights
+#[derive(Debug, Clone)]
+pub struct SweepRun {
+    /// Values of the swept weights, in the order of the grid
+    pub weights: Vec<f64>,
+    /// Numeric statistics of the network with the generated peer lists by their name (empty if
+    /// IPS failed)
+    pub statistics: BTreeMap<String, f64>,
+}
+
+/// All combinations of the grid values, in the order of the grid - the last weight changes
+/// fastest.
+pub fn weight_combinations(grid: &BTreeMap<String, Vec<f64>>) -> Vec<Vec<f64>> {
+    grid.values()
+        .fold(vec![Vec::new()], |combinations, values| {
+            combinations
+                .iter()
+                .flat_map(|combination| {
+                    values.iter().map(move |value| {
+                        let mut combination = combination.clone();
+                        combination.push(*value);
+                        combination
+                    })
+                })
+                .collect()
+        })
+}
+
+/// Connected subgraph of (at most) `count` nodes, collected breadth-first from a node of the
+/// largest island chosen by the seed. Centrality measures are computed for the subgraph.
+pub fn sample_subgraph(
+    nodes: &[Node],
+    count: usize,
+    seed: u64,
+    closeness: ClosenessVariant,
+    num_threads: usize,
+) -> Vec<Node> {
+    let largest = detect_islands(nodes)
+        .into_iter()
+        .max_by_key(|island| island.len())
+        .unwrap_or_default();
+    let mut starts = largest.into_iter().collect::<Vec<usize>>();
+    if starts.is_empty() {
+        return Vec::new();
+    }
+    starts.sort_unstable();
+    let start = starts[Rng(seed).below(starts.len())];
+
+    let mut sampled = HashSet::from([start]);
+    let mut queue = VecDeque::from([start]);
+    while let Some(idx) = queue.pop_front() {
+        for peer in nodes[idx].connections.iter() {
+            if sampled.len() == count {
+                break;
+            }
+            if sampled.insert(*peer) {
+                queue.push_back(*peer);
+            }
+        }
+    }
+
+    let mut sample = extract_island(nodes, nodes, &sampled);
+    let edges = sample
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, node)| {
+            node.connections
+                .iter()
+                .filter(move |peer| idx < **peer)
+                .map(move |peer| (idx, *peer))
+        })
+        .collect::<Vec<(usize, usize)>>();
+    let centralities = compute_centralities(&edges, num_threads, closeness);
+    for (idx, node) in sample.iter_mut().enumerate() {
+        node.betweenness = value_or_fallback(&centralities.betweenness, &idx);
+        node.closeness = value_or_fallback(&centralities.closeness, &idx);
+    }
+    sample
+}
+
+/// Run IPS for each combination of the grid weights on the sampled subgraph (the whole network
+/// if `sample_nodes` is 0). Peer lists are generated for the first IPS network type.
+pub async fn sweep_weights(state: &CrunchyState, config: &CrunchyConfiguration) -> Vec<SweepRun> {
+    let sample_nodes = config.sweep.sample_nodes;
+    let sample = if sample_nodes == 0 || sample_nodes >= state.nodes.len() {
+        state.clone()
+    } else {
+        CrunchyState {
+            nodes: sample_subgraph(
+                &state.nodes,
+                sample_nodes,
+                config.seed.unwrap_or_default(),
+                config.closeness_variant,
+                config.num_threads,
+            ),
+            ..Default::default()
+        }
+    };
+    let network = config.ips_networks()[0];
+
+    let combinations = weight_combinations(&config.sweep.grid);
+    let total = combinations.len();
+    let mut runs = Vec::with_capacity(total);
+    for (run, weights) in combinations.into_iter().enumerate() {
+        writeln!(
+            stdio::messages(),
+            "Sweep run {} of {} on {} nodes",
+            run + 1,
+            total,
+            sample.nodes.len()
+        )
+        .unwrap();
+
+        let mut run_config = config.clone();
+        for (name, value) in config.sweep.grid.keys().zip(weights.iter()) {
+            if let Some(weight) = run_config.ips_config.mcda_weights.weight_mut(name) {
+                *weight = *value;
+            }
+        }
+        let (peers, report) = create_peers(&sample, network, &run_config, HashSet::new()).await;
+        if let Err(e) = &peers {
+            writeln!(stdio::messages(), "Sweep run {} failed: {e}", run + 1).unwrap();
+        }
+        runs.push(SweepRun {
+            weights,
+            statistics: peers
+                .ok()
+                .and(report.final_statistics)
+                .map(|stats| numeric_statistics(&stats))
+                .unwrap_or_default(),
+        });
+    }
+    runs
+}
+
+/// Statistics with a numeric value by their name.
+fn numeric_statistics(stats: &Statistics) -> BTreeMap<String, f64> {
+    let values = serde_json::to_value(stats).expect("can't serialize statistics");
+    values
+        .as_object()
+        .map(|values| {
+            values
+                .iter()
+                .filter_map(|(name, value)| value.as_f64().map(|value| (name.clone(), value)))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// CSV table of the runs - a column for each swept weight followed by a column for each numeric
+/// statistic. Statistics of failed runs are left empty.
+pub fn sweep_csv(grid: &BTreeMap<String, Vec<f64>>, runs: &[SweepRun]) -> String {
+    let mut columns = runs
+        .iter()
+        .flat_map(|run| run.statistics.keys())
+        .collect::<Vec<&String>>();
+    columns.sort_unstable();
+    columns.dedup();
+
+    let header = grid.keys().chain(columns.iter().copied());
+    let mut csv = header.cloned().collect::<Vec<String>>().join(",");
+    csv.push('\n');
+    for run in runs.iter() {
+        let weights = run.weights.iter().map(|value| value.to_string());
+        let values = columns.iter().map(|name| {
+            run.statistics
+                .get(*name)
+                .map_or_else(String::new, f64::to_string)
+        });
+        csv.push_str(&weights.chain(values).collect::<Vec<String>>().join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+
+    #[test]
+    fn sweep_test() {
+        let grid = BTreeMap::from([
+            ("degree".to_owned(), vec![-0.5, 0.0]),
+            ("location".to_owned(), vec![0.1, 0.2, 0.3]),
+        ]);
+        let combinations = weight_combinations(&grid);
+        assert_eq!(combinations.len(), 6);
+        assert_eq!(combinations[0], vec![-0.5, 0.1]);
+        assert_eq!(combinations[1], vec![-0.5, 0.2]);
+        assert_eq!(combinations[5], vec![0.0, 0.3]);
+        assert_eq!(
+            weight_combinations(&BTreeMap::new()),
+            vec![Vec::<f64>::new()]
+        );
+
+        // Path of 6 nodes and a separate pair
+        let mut nodes = (0..8)
+            .map(|i| Node {
+                addr: SocketAddr::new([10, 0, 0, i as u8].into(), 1234),
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+        for (a, b) in [(0, 1), (1, 2), (2, 3), (3, 4), (4, 5), (6, 7)] {
+            nodes[a].connections.push(b);
+            nodes[b].connections.push(a);
+        }
+        for seed in 0..4 {
+            let sample = sample_subgraph(&nodes, 3, seed, ClosenessVariant::Standard, 1);
+            assert_eq!(sample.len(), 3);
+            assert!(sample
+                .iter()
+                .all(|node| node.addr.ip() < nodes[6].addr.ip()));
+            // The sample is a path too
+            assert_eq!(detect_islands(&sample).len(), 1);
+            assert_eq!(sample.iter().map(|node| node.degree).sum::<usize>(), 4);
+        }
+        assert_eq!(
+            sample_subgraph(&nodes, 10, 0, ClosenessVariant::Standard, 1).len(),
+            6
+        );
+
+        let runs = [
+            SweepRun {
+                weights: vec![-0.5, 0.1],
+                statistics: BTreeMap::from([
+                    ("diameter".to_owned(), 4.0),
+                    ("nodes_count".to_owned(), 6.0),
+                ]),
+            },
+            SweepRun {
+                weights: vec![0.0, 0.1],
+                statistics: BTreeMap::new(),
+            },
+        ];
+        let csv = sweep_csv(&grid, &runs);
+        let lines = csv.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "degree,location,diameter,nodes_count");
+        assert_eq!(lines[1], "-0.5,0.1,4,6");
+        // Statistics of failed runs are left empty
+        assert_eq!(lines[2], "0,0.1,,");
+    }
+}
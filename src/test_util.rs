@@ -0,0 +1,62 @@
+// Fixtures shared by the unit tests - small networks and locations which would otherwise be
+// pasted into every module testing with them.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use ziggurat_core_crawler::summary::NetworkType;
+use ziggurat_core_geoip::geoip::GeoInfo;
+
+use crate::{
+    nodes::{update_degrees, Node},
+    CrunchyState,
+};
+
+/// Address of the test node with the given index.
+pub(crate) fn addr(idx: usize) -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, idx as u8)), 1234)
+}
+
+/// State of `count` Zcash nodes connected in a ring - each to the previous and the next one.
+pub(crate) fn ring_state(count: usize) -> CrunchyState {
+    let mut nodes = (0..count)
+        .map(|idx| Node {
+            addr: addr(idx),
+            network_type: NetworkType::Zcash,
+            connections: vec![(idx + 1) % count, (idx + count - 1) % count],
+            ..Default::default()
+        })
+        .collect::<Vec<Node>>();
+    update_degrees(&mut nodes);
+    CrunchyState {
+        nodes,
+        ..Default::default()
+    }
+}
+
+/// Location in the given country, nothing else known.
+pub(crate) fn geo(country: &str) -> Option<GeoInfo> {
+    geo_info(Some(country), None, None)
+}
+
+/// Location at the given latitude and longitude, nothing else known.
+pub(crate) fn geo_at(latitude: f64, longitude: f64) -> Option<GeoInfo> {
+    geo_info(None, None, Some((latitude, longitude)))
+}
+
+/// Location with the given country, city and coordinates (latitude and longitude).
+pub(crate) fn geo_info(
+    country: Option<&str>,
+    city: Option<&str>,
+    coordinates: Option<(f64, f64)>,
+) -> Option<GeoInfo> {
+    let quoted = |value: Option<&str>| value.map_or("null".to_owned(), |v| format!("\"{v}\""));
+    let coordinates = coordinates.map_or("null".to_owned(), |(latitude, longitude)| {
+        format!(r#"{{"latitude":{latitude},"longitude":{longitude}}}"#)
+    });
+    serde_json::from_str(&format!(
+        r#"{{"country":{},"city":{},"coordinates":{coordinates},"timezone":null,"isp":null}}"#,
+        quoted(country),
+        quoted(city)
+    ))
+    .unwrap()
+}
@@ -0,0 +1,196 @@
+// Trends over a series of crawls - each sample in a directory is processed in the order of
+// crawling and summarized in one point of the time series.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{geo_summary::LocationCount, CrunchyState};
+
+/// Sample file of one crawl
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub path: PathBuf,
+    /// Time of the crawl recorded in the sample, or the modification time of the file if the
+    /// sample has none
+    pub crawled_at: SystemTime,
+}
+
+/// Time of the crawl (unix time in seconds) recorded at the top level of the sample or in the
+/// result of the JSON-RPC response. Other fields are skipped.
+#[derive(Deserialize)]
+struct SampleTimestamp {
+    #[serde(default)]
+    timestamp: Option<u64>,
+    #[serde(default)]
+    result: Option<ResultTimestamp>,
+}
+
+#[derive(Deserialize)]
+struct ResultTimestamp {
+    #[serde(default)]
+    timestamp: Option<u64>,
+}
+
+/// Read the time of the crawl recorded in the sample (none for samples without it, including
+/// samples which aren't JSON).
+pub fn recorded_crawl_time(path: &Path) -> Option<SystemTime> {
+    let file = File::open(path).ok()?;
+    let secs = read_timestamp(BufReader::new(file))?;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Read the time of the crawl (unix time in seconds) recorded in the sample content.
+pub(crate) fn read_timestamp<R: Read>(reader: R) -> Option<u64> {
+    let sample: SampleTimestamp = serde_json::from_reader(reader).ok()?;
+    sample
+        .timestamp
+        .or_else(|| sample.result.and_then(|result| result.timestamp))
+}
+
+/// Summary of one crawl in the time series
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrendPoint {
+    pub crawled_at: SystemTime,
+    /// Path of the sample file
+    pub input: String,
+    /// Number of nodes
+    pub nodes: usize,
+    /// Average degree of nodes
+    pub degree_average: f64,
+    /// Diameter of the largest connected component
+    pub diameter: u32,
+    /// Nodes per country
+    pub countries: Vec<LocationCount>,
+}
+
+impl TrendPoint {
+    /// Summarize the state created from the sample.
+    pub fn new(sample: &Sample, state: &CrunchyState) -> TrendPoint {
+        let degree_average = if state.nodes.is_empty() {
+            0.0
+        } else {
+            state.nodes.iter().map(|node| node.degree).sum::<usize>() as f64
+                / state.nodes.len() as f64
+        };
+
+        TrendPoint {
+            crawled_at: sample.crawled_at,
+            input: sample.path.display().to_string(),
+            nodes: state.nodes.len(),
+            degree_average,
+            diameter: state.distances.diameter,
+            countries: state.geo_summary.countries.clone(),
+        }
+    }
+}
+
+/// List sample files in the directory (hidden files and subdirectories are skipped), the oldest
+/// crawl first - samples crawled at the same time are ordered by name.
+pub fn list_samples(dir: &Path) -> Result<Vec<Sample>, io::Error> {
+    let mut samples = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() || entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        samples.push(Sample {
+            path: entry.path(),
+            crawled_at: match recorded_crawl_time(&entry.path()) {
+                Some(crawled_at) => crawled_at,
+                None => metadata.modified()?,
+            },
+        });
+    }
+    samples.sort_by(|a, b| {
+        a.crawled_at
+            .cmp(&b.crawled_at)
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+    use crate::{geo_summary::GeoSummary, Node};
+
+    #[test]
+    fn list_samples_test() {
+        let dir = env::temp_dir().join(format!("crunchy-timeseries-{}", process::id()));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        for name in ["b.json", "a.json", ".hidden.json"] {
+            fs::write(dir.join(name), "{}").unwrap();
+        }
+
+        let samples = list_samples(&dir).unwrap();
+        let names = samples
+            .iter()
+            .map(|sample| sample.path.file_name().unwrap().to_string_lossy())
+            .collect::<Vec<_>>();
+        assert_eq!(names.len(), 2);
+        assert!(names.contains(&"a.json".into()) && names.contains(&"b.json".into()));
+        assert!(samples[0].crawled_at <= samples[1].crawled_at);
+
+        // The time recorded in the sample wins over the modification time
+        fs::write(dir.join("c.json"), r#"{"result":{"timestamp":200}}"#).unwrap();
+        fs::write(dir.join("d.json"), r#"{"timestamp":100,"result":{}}"#).unwrap();
+        let samples = list_samples(&dir).unwrap();
+        let names = samples
+            .iter()
+            .map(|sample| sample.path.file_name().unwrap().to_string_lossy())
+            .collect::<Vec<_>>();
+        assert_eq!(names[..2], ["d.json", "c.json"]);
+        assert_eq!(
+            samples[1].crawled_at,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(200)
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(list_samples(&dir).is_err());
+    }
+
+    #[test]
+    fn trend_point_test() {
+        let node = |degree| Node {
+            degree,
+            ..Default::default()
+        };
+        let mut state = CrunchyState {
+            nodes: vec![node(1), node(2), node(3)],
+            geo_summary: GeoSummary {
+                countries: vec![LocationCount {
+                    name: "Japan".to_owned(),
+                    nodes: 3,
+                }],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        state.distances.diameter = 4;
+        let sample = Sample {
+            path: PathBuf::from("samples/1.json"),
+            crawled_at: SystemTime::UNIX_EPOCH,
+        };
+
+        let point = TrendPoint::new(&sample, &state);
+        assert_eq!(point.input, "samples/1.json");
+        assert_eq!(point.nodes, 3);
+        assert_eq!(point.degree_average, 2.0);
+        assert_eq!(point.diameter, 4);
+        assert_eq!(point.countries, state.geo_summary.countries);
+
+        assert_eq!(
+            TrendPoint::new(&sample, &CrunchyState::default()).degree_average,
+            0.0
+        );
+    }
+}